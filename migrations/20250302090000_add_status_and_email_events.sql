@@ -0,0 +1,13 @@
+-- Track whether we may still email a subscriber.
+-- There is no confirmation flow yet: everyone who signs up starts out confirmed.
+ALTER TABLE subscriptions ADD COLUMN status TEXT NOT NULL DEFAULT 'confirmed';
+
+-- Audit trail of every event our email provider reports back to us
+CREATE TABLE email_events(
+                             id uuid NOT NULL,
+                             PRIMARY KEY (id),
+                             record_type TEXT NOT NULL,
+                             email TEXT,
+                             payload jsonb NOT NULL,
+                             received_at timestamptz NOT NULL
+);
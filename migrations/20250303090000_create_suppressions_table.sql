@@ -0,0 +1,7 @@
+-- Addresses we must never email, keyed by trimmed, lowercased email
+CREATE TABLE suppressions(
+                             email TEXT NOT NULL,
+                             PRIMARY KEY (email),
+                             reason TEXT NOT NULL,
+                             created_at timestamptz NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Create Newsletter Issues Table
+CREATE TABLE newsletter_issues(
+                                  id uuid NOT NULL,
+                                  PRIMARY KEY (id),
+                                  title TEXT NOT NULL,
+                                  text_content TEXT NOT NULL,
+                                  html_content TEXT NOT NULL,
+                                  -- name of the configured sender identity the issue goes out as
+                                  sender TEXT NOT NULL,
+                                  published_at timestamptz NOT NULL
+);
@@ -0,0 +1,42 @@
+-- How many subscribers an issue was queued for, to report delivery progress
+ALTER TABLE newsletter_issues ADD COLUMN n_recipients INT NOT NULL DEFAULT 0;
+
+-- Emails waiting to be sent by the delivery worker
+CREATE TABLE email_delivery_queue(
+                                     id uuid NOT NULL,
+                                     PRIMARY KEY (id),
+                                     recipient_email TEXT NOT NULL,
+                                     stream TEXT NOT NULL,
+                                     -- higher goes first: transactional mail jumps ahead of broadcasts
+                                     priority SMALLINT NOT NULL,
+                                     -- broadcasts take their content from the issue...
+                                     newsletter_issue_id uuid REFERENCES newsletter_issues (id),
+                                     -- ...while transactional emails carry their own
+                                     sender TEXT,
+                                     subject TEXT,
+                                     html_content TEXT,
+                                     text_content TEXT,
+                                     n_retries SMALLINT NOT NULL DEFAULT 0,
+                                     execute_after timestamptz NOT NULL,
+                                     enqueued_at timestamptz NOT NULL,
+                                     CHECK (
+                                         newsletter_issue_id IS NOT NULL
+                                         OR (sender IS NOT NULL AND subject IS NOT NULL
+                                             AND html_content IS NOT NULL AND text_content IS NOT NULL)
+                                     )
+);
+CREATE INDEX email_delivery_queue_next_idx ON email_delivery_queue (priority DESC, execute_after);
+
+-- Every delivery attempt that reached a final outcome
+CREATE TABLE email_deliveries(
+                                 id uuid NOT NULL,
+                                 PRIMARY KEY (id),
+                                 recipient_email TEXT NOT NULL,
+                                 stream TEXT NOT NULL,
+                                 newsletter_issue_id uuid REFERENCES newsletter_issues (id),
+                                 subject TEXT NOT NULL,
+                                 -- 'sent', 'suppressed' or 'failed'
+                                 outcome TEXT NOT NULL,
+                                 attempted_at timestamptz NOT NULL
+);
+CREATE INDEX email_deliveries_issue_idx ON email_deliveries (newsletter_issue_id);
@@ -0,0 +1,19 @@
+-- Administrators, with Argon2id password hashes in PHC string format
+CREATE TABLE users(
+                      user_id uuid NOT NULL,
+                      PRIMARY KEY (user_id),
+                      username TEXT NOT NULL UNIQUE,
+                      password_hash TEXT NOT NULL,
+                      created_at timestamptz NOT NULL
+);
+
+-- Server-side sessions, keyed by the SHA-256 of the cookie token
+CREATE TABLE sessions(
+                         session_hash TEXT NOT NULL,
+                         PRIMARY KEY (session_hash),
+                         user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+                         created_at timestamptz NOT NULL,
+                         expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,13 @@
+-- Keys for machine clients; only the SHA-256 of each key is kept
+CREATE TABLE api_keys(
+                         id uuid NOT NULL,
+                         PRIMARY KEY (id),
+                         name TEXT NOT NULL,
+                         key_prefix TEXT NOT NULL,
+                         key_hash TEXT NOT NULL UNIQUE,
+                         scopes TEXT[] NOT NULL,
+                         created_by uuid REFERENCES users (user_id) ON DELETE SET NULL,
+                         created_at timestamptz NOT NULL,
+                         last_used_at timestamptz,
+                         revoked_at timestamptz
+);
@@ -0,0 +1,4 @@
+-- Roles for administrators: owner, editor or viewer
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer';
+-- Everyone could do everything so far
+UPDATE users SET role = 'owner';
@@ -0,0 +1,7 @@
+-- Each session carries the token its forms must echo back
+ALTER TABLE sessions ADD COLUMN csrf_token TEXT NOT NULL DEFAULT md5(random()::text);
+ALTER TABLE sessions ALTER COLUMN csrf_token DROP DEFAULT;
+
+-- A one-off message shown on the next page after a redirect
+ALTER TABLE sessions ADD COLUMN flash_level TEXT;
+ALTER TABLE sessions ADD COLUMN flash_message TEXT;
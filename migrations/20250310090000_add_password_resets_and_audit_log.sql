@@ -0,0 +1,23 @@
+-- Where administrators receive password reset links
+ALTER TABLE users ADD COLUMN email TEXT UNIQUE;
+
+-- Single-use password reset links, keyed by the SHA-256 of the token
+CREATE TABLE password_reset_tokens(
+                                      token_hash TEXT NOT NULL,
+                                      PRIMARY KEY (token_hash),
+                                      user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+                                      created_at timestamptz NOT NULL,
+                                      expires_at timestamptz NOT NULL,
+                                      used_at timestamptz
+);
+
+-- Security-relevant events, never updated or deleted
+CREATE TABLE audit_log(
+                          id BIGSERIAL NOT NULL,
+                          PRIMARY KEY (id),
+                          occurred_at timestamptz NOT NULL,
+                          actor_user_id uuid,
+                          action TEXT NOT NULL,
+                          ip TEXT,
+                          details jsonb NOT NULL
+);
@@ -0,0 +1,31 @@
+-- RFC 6238 secrets: `totp_pending_secret` until the first code is confirmed
+ALTER TABLE users ADD COLUMN totp_secret BYTEA;
+ALTER TABLE users ADD COLUMN totp_pending_secret BYTEA;
+-- The last time step a code was accepted for, so that codes cannot be replayed
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+ALTER TABLE users ADD COLUMN totp_enabled_at timestamptz;
+
+-- Single-use codes for a lost authenticator, keyed by their SHA-256
+CREATE TABLE recovery_codes(
+                               code_hash TEXT NOT NULL,
+                               PRIMARY KEY (code_hash),
+                               user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+                               used_at timestamptz
+);
+
+-- Logins that passed the password step and wait for the second factor
+CREATE TABLE pending_logins(
+                               token_hash TEXT NOT NULL,
+                               PRIMARY KEY (token_hash),
+                               user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+                               expires_at timestamptz NOT NULL,
+                               failed_attempts INT NOT NULL DEFAULT 0
+);
+
+-- Settings owners can change from the admin area; always exactly one row
+CREATE TABLE security_policy(
+                                id BOOLEAN NOT NULL DEFAULT TRUE CHECK (id),
+                                PRIMARY KEY (id),
+                                require_two_factor BOOLEAN NOT NULL
+);
+INSERT INTO security_policy (require_two_factor) VALUES (FALSE);
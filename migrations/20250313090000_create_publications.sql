@@ -0,0 +1,52 @@
+-- Newsletters hosted by this deployment; subscriptions, issues and API keys
+-- belong to exactly one of them
+CREATE TABLE publications(
+                             id uuid NOT NULL,
+                             PRIMARY KEY (id),
+                             -- signups can be posted to /p/{slug}/subscriptions
+                             slug TEXT NOT NULL UNIQUE,
+                             name TEXT NOT NULL,
+                             -- ...or to /subscriptions on this host, without port
+                             host TEXT UNIQUE,
+                             -- configured sender identities it may send as; any of them if empty
+                             senders TEXT[] NOT NULL DEFAULT '{}',
+                             -- the configured default sender if NULL
+                             default_sender TEXT,
+                             created_at timestamptz NOT NULL
+);
+
+-- Administrators only see the publications they belong to
+CREATE TABLE publication_members(
+                                    publication_id uuid NOT NULL REFERENCES publications (id) ON DELETE CASCADE,
+                                    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+                                    PRIMARY KEY (publication_id, user_id),
+                                    added_at timestamptz NOT NULL
+);
+CREATE INDEX publication_members_user_id_idx ON publication_members (user_id);
+
+-- Everything so far belongs to a default publication, run by everyone
+INSERT INTO publications (id, slug, name, created_at)
+VALUES (gen_random_uuid(), 'default', 'Default', now());
+INSERT INTO publication_members (publication_id, user_id, added_at)
+SELECT p.id, u.user_id, now() FROM publications p CROSS JOIN users u;
+
+ALTER TABLE subscriptions ADD COLUMN publication_id uuid REFERENCES publications (id);
+UPDATE subscriptions SET publication_id = (SELECT id FROM publications);
+ALTER TABLE subscriptions ALTER COLUMN publication_id SET NOT NULL;
+-- The same address may subscribe to several publications, once to each
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+ALTER TABLE subscriptions ADD UNIQUE (publication_id, email);
+
+ALTER TABLE newsletter_issues ADD COLUMN publication_id uuid REFERENCES publications (id);
+UPDATE newsletter_issues SET publication_id = (SELECT id FROM publications);
+ALTER TABLE newsletter_issues ALTER COLUMN publication_id SET NOT NULL;
+CREATE INDEX newsletter_issues_publication_id_idx ON newsletter_issues (publication_id);
+
+-- A key acts on behalf of a single publication
+ALTER TABLE api_keys ADD COLUMN publication_id uuid REFERENCES publications (id) ON DELETE CASCADE;
+UPDATE api_keys SET publication_id = (SELECT id FROM publications);
+ALTER TABLE api_keys ALTER COLUMN publication_id SET NOT NULL;
+
+-- The publication a browser session is currently working on
+ALTER TABLE sessions ADD COLUMN publication_id uuid REFERENCES publications (id) ON DELETE SET NULL;
+UPDATE sessions SET publication_id = (SELECT id FROM publications);
@@ -0,0 +1,7 @@
+-- Free-form labels administrators put on subscribers to filter them
+ALTER TABLE subscriptions ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+CREATE INDEX subscriptions_tags_idx ON subscriptions USING GIN (tags);
+
+-- Admin listings page through a publication's subscribers, newest first
+CREATE INDEX subscriptions_publication_id_subscribed_at_idx
+    ON subscriptions (publication_id, subscribed_at DESC, id DESC);
@@ -0,0 +1,40 @@
+-- CSV files of subscribers, imported in the background
+CREATE TABLE subscriber_imports(
+                                   id uuid NOT NULL,
+                                   PRIMARY KEY (id),
+                                   publication_id uuid NOT NULL REFERENCES publications (id) ON DELETE CASCADE,
+                                   -- 'pending', 'running' or 'completed'
+                                   status TEXT NOT NULL,
+                                   -- validate and report, but do not touch subscriptions
+                                   dry_run BOOLEAN NOT NULL,
+                                   -- 'skip', 'update' or 'error'
+                                   on_duplicate TEXT NOT NULL,
+                                   -- which header holds what: {"email": ..., "name": ..., "tags": ...}
+                                   columns JSONB NOT NULL,
+                                   -- the file, dropped once it has been imported
+                                   content TEXT,
+                                   total_rows INT NOT NULL,
+                                   processed_rows INT NOT NULL DEFAULT 0,
+                                   imported INT NOT NULL DEFAULT 0,
+                                   updated INT NOT NULL DEFAULT 0,
+                                   skipped INT NOT NULL DEFAULT 0,
+                                   rejected INT NOT NULL DEFAULT 0,
+                                   created_at timestamptz NOT NULL,
+                                   -- running imports not heard from in a while are picked up again
+                                   heartbeat_at timestamptz,
+                                   finished_at timestamptz
+);
+CREATE INDEX subscriber_imports_unfinished_idx ON subscriber_imports (created_at)
+    WHERE status <> 'completed';
+
+-- Lines of an import that were not imported, and why
+CREATE TABLE subscriber_import_rejections(
+                                             import_id uuid NOT NULL REFERENCES subscriber_imports (id) ON DELETE CASCADE,
+                                             line INT NOT NULL,
+                                             PRIMARY KEY (import_id, line),
+                                             reason TEXT NOT NULL
+);
+
+-- Imports look duplicates up regardless of case
+CREATE INDEX subscriptions_publication_id_lower_email_idx
+    ON subscriptions (publication_id, lower(email));
@@ -0,0 +1,2 @@
+-- 'csv', 'mailchimp' or 'substack'
+ALTER TABLE subscriber_imports ADD COLUMN format TEXT NOT NULL DEFAULT 'csv';
@@ -0,0 +1,16 @@
+-- Archives of everything we hold about an address, requested by its owner
+CREATE TABLE data_exports(
+                             id uuid NOT NULL,
+                             PRIMARY KEY (id),
+                             -- trimmed and lowercased
+                             email TEXT NOT NULL,
+                             -- 'pending' or 'ready'
+                             status TEXT NOT NULL,
+                             archive jsonb,
+                             requested_at timestamptz NOT NULL,
+                             ready_at timestamptz,
+                             -- the archive is deleted then
+                             expires_at timestamptz
+);
+CREATE INDEX data_exports_pending_idx ON data_exports (requested_at) WHERE status = 'pending';
+CREATE INDEX data_exports_email_idx ON data_exports (email);
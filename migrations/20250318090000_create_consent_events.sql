@@ -0,0 +1,45 @@
+-- Proof of consent: every subscribe, confirm and unsubscribe as it happened.
+-- No foreign keys, the record outlives the subscription.
+CREATE TABLE consent_events(
+                               id BIGSERIAL NOT NULL,
+                               PRIMARY KEY (id),
+                               occurred_at timestamptz NOT NULL,
+                               publication_id uuid NOT NULL,
+                               subscriber_id uuid NOT NULL,
+                               email TEXT NOT NULL,
+                               -- subscribe, confirm or unsubscribe
+                               action TEXT NOT NULL,
+                               -- the form or process it came through, e.g. signup_form or admin
+                               source TEXT NOT NULL,
+                               -- of the wording the subscriber agreed to, when a form sends it
+                               consent_text_version TEXT,
+                               ip TEXT,
+                               user_agent TEXT
+);
+CREATE INDEX consent_events_subscriber_id_idx ON consent_events (subscriber_id);
+CREATE INDEX consent_events_email_idx ON consent_events (lower(btrim(email)));
+
+-- Append-only. The one change allowed is an erasure: the address is replaced
+-- with its hash and where the request came from is forgotten.
+CREATE FUNCTION reject_consent_event_changes() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE'
+        AND NEW.email LIKE 'sha256:%' AND NEW.ip IS NULL AND NEW.user_agent IS NULL
+        AND (NEW.id, NEW.occurred_at, NEW.publication_id, NEW.subscriber_id, NEW.action,
+             NEW.source, NEW.consent_text_version)
+            IS NOT DISTINCT FROM (OLD.id, OLD.occurred_at, OLD.publication_id, OLD.subscriber_id,
+                                  OLD.action, OLD.source, OLD.consent_text_version)
+    THEN
+        RETURN NEW;
+    END IF;
+    RAISE EXCEPTION 'consent_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER consent_events_append_only
+    BEFORE UPDATE OR DELETE ON consent_events
+    FOR EACH ROW EXECUTE FUNCTION reject_consent_event_changes();
+
+CREATE TRIGGER consent_events_no_truncate
+    BEFORE TRUNCATE ON consent_events
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_consent_event_changes();
@@ -0,0 +1,12 @@
+-- Token buckets shared by all replicas, when rate limits are kept in Postgres
+CREATE TABLE rate_limit_buckets(
+                                   key TEXT NOT NULL,
+                                   PRIMARY KEY (key),
+                                   tokens DOUBLE PRECISION NOT NULL,
+                                   -- whether the last attempt took a token
+                                   allowed BOOLEAN NOT NULL,
+                                   updated_at timestamptz NOT NULL,
+                                   -- full buckets carry no information and are deleted
+                                   full_at timestamptz NOT NULL
+);
+CREATE INDEX rate_limit_buckets_full_at_idx ON rate_limit_buckets (full_at);
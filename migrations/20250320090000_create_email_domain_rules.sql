@@ -0,0 +1,7 @@
+-- Administrators' allow and deny lists of email domains, for signups
+CREATE TABLE email_domain_rules(
+                                   domain TEXT NOT NULL,
+                                   PRIMARY KEY (domain),
+                                   rule TEXT NOT NULL CHECK (rule IN ('allow', 'deny')),
+                                   created_at timestamptz NOT NULL
+);
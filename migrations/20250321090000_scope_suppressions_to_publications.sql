@@ -0,0 +1,10 @@
+-- Administrators and imports suppress addresses for their own publication
+-- only; bounces, complaints and erasures keep applying to every publication
+-- (NULL)
+ALTER TABLE suppressions
+    ADD COLUMN publication_id uuid REFERENCES publications (id) ON DELETE CASCADE;
+ALTER TABLE suppressions DROP CONSTRAINT suppressions_pkey;
+ALTER TABLE suppressions
+    ADD CONSTRAINT suppressions_email_publication_id_key
+        UNIQUE NULLS NOT DISTINCT (email, publication_id);
+CREATE INDEX suppressions_publication_id_idx ON suppressions (publication_id);
@@ -0,0 +1,4 @@
+-- The publication an entry belongs to, for the API keys of one publication
+-- to only read its entries; NULL for events outside any publication
+ALTER TABLE audit_log ADD COLUMN publication_id uuid;
+CREATE INDEX audit_log_publication_id_idx ON audit_log (publication_id, id);
@@ -0,0 +1,7 @@
+-- Deliveries, opens and clicks used to be stored as Unsupported with no
+-- address, out of reach of data exports and erasure
+UPDATE email_events
+SET record_type = payload ->> 'RecordType',
+    email = payload ->> 'Recipient'
+WHERE record_type = 'Unsupported'
+  AND payload ->> 'RecordType' IN ('Delivery', 'Open', 'Click');
@@ -0,0 +1,35 @@
+-- Signups looked addresses up case-insensitively but the constraint did not,
+-- so `Ada@example.com` could subscribe next to `ada@example.com`. Of each
+-- address, the confirmed subscription is kept, then the one consented to
+-- last, then the newest. Consent events are never deleted, so those of the
+-- dropped rows remain as proof of what happened.
+DO $$
+DECLARE
+    dropped bigint;
+BEGIN
+    WITH ranked AS (
+        SELECT s.id,
+               row_number() OVER (
+                   PARTITION BY s.publication_id, lower(s.email)
+                   ORDER BY s.status = 'confirmed' DESC,
+                            (SELECT max(c.occurred_at) FROM consent_events c
+                             WHERE c.subscriber_id = s.id
+                               AND c.action IN ('subscribe', 'confirm')) DESC NULLS LAST,
+                            s.subscribed_at DESC,
+                            s.id
+               ) AS rank
+        FROM subscriptions s
+    )
+    DELETE FROM subscriptions
+    WHERE id IN (SELECT id FROM ranked WHERE rank > 1);
+    GET DIAGNOSTICS dropped = ROW_COUNT;
+    IF dropped > 0 THEN
+        RAISE WARNING 'Dropped % subscriptions differing from another of their publication only by the case of their address', dropped;
+    END IF;
+END
+$$;
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_publication_id_email_key;
+CREATE UNIQUE INDEX subscriptions_publication_id_lower_email_key
+    ON subscriptions (publication_id, lower(email));
+-- Lookups by address use the unique index from now on
+DROP INDEX subscriptions_publication_id_lower_email_idx;
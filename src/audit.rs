@@ -0,0 +1,450 @@
+use actix_web::http::header::USER_AGENT;
+use actix_web::{HttpMessage, HttpRequest};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{GenericClient, Pool};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::authentication::Principal;
+use crate::publication::PublicationId;
+use crate::rate_limit::client_ip;
+
+/// What the first entry of the chain points back to.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A security-relevant event, e.g. `password_change.failed`.
+pub struct AuditEvent<'a> {
+    pub action: &'a str,
+    /// The administrator who acted, when known.
+    pub actor: Option<Uuid>,
+    /// The API key that acted, for machine clients.
+    pub api_key: Option<Uuid>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// What was acted upon, e.g. `user:<id>`.
+    pub target: Option<String>,
+    /// What changed, e.g. `{"role": {"from": "editor", "to": "viewer"}}`.
+    pub details: serde_json::Value,
+    /// The publication acted upon, if any.
+    pub publication_id: Option<Uuid>,
+}
+
+impl<'a> AuditEvent<'a> {
+    pub fn new(action: &'a str) -> Self {
+        Self {
+            action,
+            actor: None,
+            api_key: None,
+            ip: None,
+            user_agent: None,
+            target: None,
+            details: serde_json::json!({}),
+            publication_id: None,
+        }
+    }
+
+    pub fn actor(mut self, user_id: Uuid) -> Self {
+        self.actor = Some(user_id);
+        self
+    }
+
+    pub fn principal(mut self, principal: &Principal) -> Self {
+        match principal {
+            Principal::User { user_id, .. } => self.actor = Some(user_id.0),
+            Principal::ApiKey { id, .. } => self.api_key = Some(*id),
+        }
+        self
+    }
+
+    /// Record where `request` came from, and the publication it works on.
+    pub fn request(mut self, request: &HttpRequest) -> Self {
+        if let Some(publication_id) = request.extensions().get::<PublicationId>() {
+            self.publication_id = Some(publication_id.0);
+        }
+        self.ip = client_ip(request).map(|ip| ip.to_string());
+        self.user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        self
+    }
+
+    pub fn publication(mut self, publication_id: PublicationId) -> Self {
+        self.publication_id = Some(publication_id.0);
+        self
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// An entry as stored.
+#[derive(Debug, serde::Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor_user_id: Option<Uuid>,
+    pub actor_api_key_id: Option<Uuid>,
+    pub action: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub target: Option<String>,
+    pub details: serde_json::Value,
+    pub publication_id: Option<Uuid>,
+    pub prev_hash: Option<String>,
+    pub entry_hash: Option<String>,
+}
+
+/// The hashed content of an entry. Fields serialize in declaration order
+/// and `serde_json` sorts object keys, so the encoding is stable.
+#[derive(serde::Serialize)]
+struct ChainedFields<'a> {
+    prev_hash: &'a str,
+    occurred_at_micros: i64,
+    actor_user_id: Option<Uuid>,
+    actor_api_key_id: Option<Uuid>,
+    action: &'a str,
+    ip: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    target: Option<&'a str>,
+    details: &'a serde_json::Value,
+    /// Left out when `None`, so entries from before it existed still match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publication_id: Option<Uuid>,
+}
+
+impl ChainedFields<'_> {
+    fn hash(&self) -> String {
+        let encoded = serde_json::to_vec(self).expect("Audit entries always serialize");
+        hex::encode(Sha256::digest(encoded))
+    }
+}
+
+impl AuditEntry {
+    fn chained_fields<'a>(&'a self, prev_hash: &'a str) -> ChainedFields<'a> {
+        ChainedFields {
+            prev_hash,
+            occurred_at_micros: self.occurred_at.timestamp_micros(),
+            actor_user_id: self.actor_user_id,
+            actor_api_key_id: self.actor_api_key_id,
+            action: &self.action,
+            ip: self.ip.as_deref(),
+            user_agent: self.user_agent.as_deref(),
+            target: self.target.as_deref(),
+            details: &self.details,
+            publication_id: self.publication_id,
+        }
+    }
+}
+
+/// Append an event to the audit log, chained to the entry before it.
+///
+/// Takes a transaction: the table stays locked against other writers until
+/// it commits, so that no two entries can claim the same predecessor.
+#[tracing::instrument(name = "Writing to the audit log", skip_all, fields(action = %event.action))]
+pub async fn record(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    event: AuditEvent<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    transaction
+        .execute("LOCK TABLE audit_log IN SHARE ROW EXCLUSIVE MODE", &[])
+        .await?;
+    let prev_hash = transaction
+        .query_opt(
+            "SELECT entry_hash FROM audit_log ORDER BY id DESC LIMIT 1",
+            &[],
+        )
+        .await?
+        .and_then(|row| row.get::<_, Option<String>>("entry_hash"))
+        .unwrap_or_else(|| GENESIS_HASH.to_owned());
+
+    // Postgres keeps microseconds: hash what will be read back
+    let occurred_at = DateTime::from_timestamp_micros(Utc::now().timestamp_micros())
+        .expect("The current time is a valid timestamp");
+    let entry_hash = ChainedFields {
+        prev_hash: &prev_hash,
+        occurred_at_micros: occurred_at.timestamp_micros(),
+        actor_user_id: event.actor,
+        actor_api_key_id: event.api_key,
+        action: event.action,
+        ip: event.ip.as_deref(),
+        user_agent: event.user_agent.as_deref(),
+        target: event.target.as_deref(),
+        details: &event.details,
+        publication_id: event.publication_id,
+    }
+    .hash();
+
+    transaction
+        .execute(
+            r#"
+    INSERT INTO audit_log
+        (occurred_at, actor_user_id, actor_api_key_id, action, ip, user_agent, target, details,
+         publication_id, prev_hash, entry_hash)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+    "#,
+            &[
+                &occurred_at,
+                &event.actor,
+                &event.api_key,
+                &event.action,
+                &event.ip,
+                &event.user_agent,
+                &event.target,
+                &event.details,
+                &event.publication_id,
+                &prev_hash,
+                &entry_hash,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Append an event from a request handler: a failure to write it is logged
+/// but does not fail the request. Only for attempts and accesses, such as
+/// logins and downloads: changes to data `record` their entry in their own
+/// transaction, so that neither is kept without the other.
+pub async fn record_from_handler(pool: &Pool, event: AuditEvent<'_>) {
+    let outcome = match pool.get().await {
+        Ok(mut client) => match client.transaction().await {
+            Ok(transaction) => match record(&transaction, event).await {
+                Ok(()) => transaction.commit().await.map_err(Into::into),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = outcome {
+        tracing::error!("Failed to write to the audit log: {:?}", e);
+    }
+}
+
+fn entry_from_row(row: &tokio_postgres::Row) -> AuditEntry {
+    AuditEntry {
+        id: row.get("id"),
+        occurred_at: row.get("occurred_at"),
+        actor_user_id: row.get("actor_user_id"),
+        actor_api_key_id: row.get("actor_api_key_id"),
+        action: row.get("action"),
+        ip: row.get("ip"),
+        user_agent: row.get("user_agent"),
+        target: row.get("target"),
+        details: row.get("details"),
+        publication_id: row.get("publication_id"),
+        prev_hash: row.get("prev_hash"),
+        entry_hash: row.get("entry_hash"),
+    }
+}
+
+/// Entries with an id above `after_id`, oldest first: of one publication,
+/// or with `None` all of them.
+pub async fn list_audit_entries<C: GenericClient>(
+    client: &C,
+    after_id: i64,
+    limit: i64,
+    publication_id: Option<PublicationId>,
+) -> Result<Vec<AuditEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(
+            r#"
+    SELECT id, occurred_at, actor_user_id, actor_api_key_id, action, ip, user_agent, target,
+           details, publication_id, prev_hash, entry_hash
+    FROM audit_log
+    WHERE id > $1 AND ($3::uuid IS NULL OR publication_id = $3)
+    ORDER BY id
+    LIMIT $2
+    "#,
+            &[&after_id, &limit, &publication_id.map(|id| id.0)],
+        )
+        .await?;
+    Ok(rows.iter().map(entry_from_row).collect())
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ChainVerification {
+    /// Entries whose hash was recomputed.
+    pub verified_entries: u64,
+    /// Entries from before the chain existed, which cannot be verified.
+    pub unchained_entries: u64,
+    /// The first entry that does not match its hash or predecessor.
+    pub first_broken_id: Option<i64>,
+}
+
+impl ChainVerification {
+    pub fn is_intact(&self) -> bool {
+        self.first_broken_id.is_none()
+    }
+}
+
+/// Walk the whole log, recomputing every hash.
+#[tracing::instrument(name = "Verifying the audit log", skip_all)]
+pub async fn verify_audit_chain<C: GenericClient>(
+    client: &C,
+) -> Result<ChainVerification, Box<dyn std::error::Error + Send + Sync>> {
+    const BATCH_SIZE: i64 = 1000;
+    let mut verification = ChainVerification {
+        verified_entries: 0,
+        unchained_entries: 0,
+        first_broken_id: None,
+    };
+    let mut last_hash: Option<String> = None;
+    let mut after_id = 0;
+    loop {
+        let entries = list_audit_entries(client, after_id, BATCH_SIZE, None).await?;
+        let Some(last) = entries.last() else {
+            return Ok(verification);
+        };
+        after_id = last.id;
+        for entry in &entries {
+            if let Err(id) = check_link(&mut last_hash, entry, &mut verification) {
+                verification.first_broken_id = Some(id);
+                return Ok(verification);
+            }
+        }
+    }
+}
+
+/// Check `entry` against the hash of the one before it, if chained.
+fn check_link(
+    last_hash: &mut Option<String>,
+    entry: &AuditEntry,
+    verification: &mut ChainVerification,
+) -> Result<(), i64> {
+    match (&entry.prev_hash, &entry.entry_hash) {
+        // Unchained entries may only precede the chain
+        (None, None) if last_hash.is_none() => {
+            verification.unchained_entries += 1;
+            Ok(())
+        }
+        (Some(prev_hash), Some(entry_hash)) => {
+            let expected_prev = last_hash.as_deref().unwrap_or(GENESIS_HASH);
+            if prev_hash != expected_prev || &entry.chained_fields(prev_hash).hash() != entry_hash {
+                return Err(entry.id);
+            }
+            *last_hash = Some(entry_hash.clone());
+            verification.verified_entries += 1;
+            Ok(())
+        }
+        _ => Err(entry.id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_link, AuditEntry, ChainVerification, ChainedFields, GENESIS_HASH};
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+
+    /// Entries chained the way `record` chains them.
+    fn chain(n: usize) -> Vec<AuditEntry> {
+        let mut prev_hash = GENESIS_HASH.to_owned();
+        (0..n)
+            .map(|i| {
+                let mut entry = AuditEntry {
+                    id: i as i64 + 1,
+                    occurred_at: DateTime::from_timestamp_micros(Utc::now().timestamp_micros())
+                        .unwrap(),
+                    actor_user_id: Some(Uuid::new_v4()),
+                    actor_api_key_id: None,
+                    action: "user.role_changed".into(),
+                    ip: Some("127.0.0.1".into()),
+                    user_agent: None,
+                    target: Some(format!("user:{}", i)),
+                    details: serde_json::json!({ "role": { "to": "viewer", "from": "editor" } }),
+                    publication_id: (i % 2 == 0).then(Uuid::new_v4),
+                    prev_hash: Some(prev_hash.clone()),
+                    entry_hash: None,
+                };
+                let hash = entry.chained_fields(&prev_hash).hash();
+                entry.entry_hash = Some(hash.clone());
+                prev_hash = hash;
+                entry
+            })
+            .collect()
+    }
+
+    fn verify(entries: &[AuditEntry]) -> ChainVerification {
+        let mut verification = ChainVerification {
+            verified_entries: 0,
+            unchained_entries: 0,
+            first_broken_id: None,
+        };
+        let mut last_hash = None;
+        for entry in entries {
+            if let Err(id) = check_link(&mut last_hash, entry, &mut verification) {
+                verification.first_broken_id = Some(id);
+                break;
+            }
+        }
+        verification
+    }
+
+    #[test]
+    fn an_untouched_chain_verifies() {
+        let verification = verify(&chain(3));
+        assert!(verification.is_intact());
+        assert_eq!(verification.verified_entries, 3);
+    }
+
+    #[test]
+    fn an_edited_entry_breaks_the_chain() {
+        let mut entries = chain(3);
+        entries[1].details = serde_json::json!({ "role": { "to": "owner" } });
+        assert_eq!(verify(&entries).first_broken_id, Some(2));
+    }
+
+    #[test]
+    fn a_removed_entry_breaks_the_chain() {
+        let mut entries = chain(3);
+        entries.remove(1);
+        assert_eq!(verify(&entries).first_broken_id, Some(3));
+    }
+
+    #[test]
+    fn the_hash_ignores_how_details_were_written() {
+        let details = serde_json::from_str(r#"{"b": 1, "a": 2}"#).unwrap();
+        let reordered = serde_json::from_str(r#"{"a": 2, "b": 1}"#).unwrap();
+        let fields = |details| ChainedFields {
+            prev_hash: GENESIS_HASH,
+            occurred_at_micros: 0,
+            actor_user_id: None,
+            actor_api_key_id: None,
+            action: "login.succeeded",
+            ip: None,
+            user_agent: None,
+            target: None,
+            details,
+            publication_id: None,
+        };
+        assert_eq!(fields(&details).hash(), fields(&reordered).hash());
+    }
+
+    #[test]
+    fn entries_without_a_publication_hash_as_before_it_was_recorded() {
+        let details = serde_json::json!({});
+        let fields = ChainedFields {
+            prev_hash: GENESIS_HASH,
+            occurred_at_micros: 0,
+            actor_user_id: None,
+            actor_api_key_id: None,
+            action: "login.succeeded",
+            ip: None,
+            user_agent: None,
+            target: None,
+            details: &details,
+            publication_id: None,
+        };
+        let encoded = serde_json::to_string(&fields).unwrap();
+        assert!(!encoded.contains("publication_id"));
+    }
+}
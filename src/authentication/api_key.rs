@@ -0,0 +1,252 @@
+use actix_web::http::header::HeaderMap;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::publication::PublicationId;
+
+/// Every key starts with this, so leaked keys are easy to spot in logs and
+/// by secret scanners.
+const KEY_PREFIX: &str = "z2p_";
+
+/// What an API key is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Scope {
+    #[serde(rename = "subscribers:read")]
+    SubscribersRead,
+    #[serde(rename = "subscribers:write")]
+    SubscribersWrite,
+    #[serde(rename = "newsletters:publish")]
+    NewslettersPublish,
+    #[serde(rename = "audit_log:read")]
+    AuditLogRead,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [
+        Scope::SubscribersRead,
+        Scope::SubscribersWrite,
+        Scope::NewslettersPublish,
+        Scope::AuditLogRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::SubscribersRead => "subscribers:read",
+            Scope::SubscribersWrite => "subscribers:write",
+            Scope::NewslettersPublish => "newsletters:publish",
+            Scope::AuditLogRead => "audit_log:read",
+        }
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid scope.", s))
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    /// The publication the key acts on.
+    pub publication_id: Uuid,
+    pub name: String,
+    /// The first characters of the key, to tell keys apart.
+    pub key_prefix: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+fn key_hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Extract a Bearer token from the `Authorization` header.
+/// `Ok(None)` means the request did not try to use one.
+pub fn bearer_token(headers: &HeaderMap) -> Result<Option<Secret<String>>, String> {
+    let Some(header_value) = headers.get("Authorization") else {
+        return Ok(None);
+    };
+    let header_value = header_value
+        .to_str()
+        .map_err(|_| "The 'Authorization' header was not a valid UTF8 string.")?;
+    match header_value.strip_prefix("Bearer ") {
+        Some(token) => Ok(Some(Secret::new(token.trim().to_owned()))),
+        None => Err("The authorization scheme was not 'Bearer'.".into()),
+    }
+}
+
+/// Create a key, returning it alongside the only copy of its secret.
+#[tracing::instrument(name = "Creating an API key", skip(client))]
+pub async fn create_api_key<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    name: &str,
+    scopes: &[Scope],
+    created_by: Option<Uuid>,
+) -> Result<(ApiKey, Secret<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let key = format!("{}{}", KEY_PREFIX, hex::encode(bytes));
+
+    let api_key = ApiKey {
+        id: Uuid::new_v4(),
+        publication_id: publication_id.0,
+        name: name.to_owned(),
+        key_prefix: key[..KEY_PREFIX.len() + 8].to_owned(),
+        scopes: scopes.to_vec(),
+        created_at: Utc::now(),
+        last_used_at: None,
+    };
+    let scope_names: Vec<&str> = scopes.iter().map(Scope::as_str).collect();
+    client
+        .execute(
+            r#"
+    INSERT INTO api_keys
+        (id, publication_id, name, key_prefix, key_hash, scopes, created_by, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    "#,
+            &[
+                &api_key.id,
+                &api_key.publication_id,
+                &api_key.name,
+                &api_key.key_prefix,
+                &key_hash(&key),
+                &scope_names,
+                &created_by,
+                &api_key.created_at,
+            ],
+        )
+        .await?;
+    Ok((api_key, Secret::new(key)))
+}
+
+/// Returns `false` if the publication has no active key with this id.
+#[tracing::instrument(name = "Revoking an API key", skip(client))]
+pub async fn revoke_api_key<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    id: Uuid,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let n_updated = client
+        .execute(
+            r#"
+    UPDATE api_keys SET revoked_at = now()
+    WHERE id = $1 AND publication_id = $2 AND revoked_at IS NULL
+    "#,
+            &[&id, &publication_id.0],
+        )
+        .await?;
+    Ok(n_updated > 0)
+}
+
+/// Active keys of a publication, most recent first.
+pub async fn list_api_keys<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+) -> Result<Vec<ApiKey>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(
+            r#"
+    SELECT id, publication_id, name, key_prefix, scopes, created_at, last_used_at
+    FROM api_keys
+    WHERE publication_id = $1 AND revoked_at IS NULL
+    ORDER BY created_at DESC
+    "#,
+            &[&publication_id.0],
+        )
+        .await?;
+    rows.into_iter()
+        .map(|row| {
+            let scopes = row
+                .get::<_, Vec<String>>("scopes")
+                .into_iter()
+                .map(Scope::try_from)
+                .collect::<Result<_, _>>()?;
+            Ok(ApiKey {
+                id: row.get("id"),
+                publication_id: row.get("publication_id"),
+                name: row.get("name"),
+                key_prefix: row.get("key_prefix"),
+                scopes,
+                created_at: row.get("created_at"),
+                last_used_at: row.get("last_used_at"),
+            })
+        })
+        .collect()
+}
+
+/// Look up an active key, recording that it was just used.
+/// Returns its id, scopes and publication.
+pub async fn authenticate_api_key<C: GenericClient>(
+    client: &C,
+    key: &Secret<String>,
+) -> Result<Option<(Uuid, Vec<Scope>, PublicationId)>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    UPDATE api_keys SET last_used_at = now()
+    WHERE key_hash = $1 AND revoked_at IS NULL
+    RETURNING id, scopes, publication_id
+    "#,
+            &[&key_hash(key.expose_secret())],
+        )
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    // Scopes we no longer recognise grant nothing.
+    let scopes = row
+        .get::<_, Vec<String>>("scopes")
+        .into_iter()
+        .filter_map(|scope| Scope::try_from(scope).ok())
+        .collect();
+    Ok(Some((
+        row.get("id"),
+        scopes,
+        PublicationId(row.get("publication_id")),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bearer_token, Scope};
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use claims::{assert_err, assert_none, assert_ok};
+    use secrecy::ExposeSecret;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("authorization"),
+            HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in Scope::ALL {
+            assert_eq!(Scope::try_from(scope.as_str().to_owned()), Ok(scope));
+        }
+        assert_err!(Scope::try_from("subscribers:*".to_owned()));
+    }
+
+    #[test]
+    fn bearer_tokens_are_extracted() {
+        let token = assert_ok!(bearer_token(&headers("Bearer z2p_abc"))).unwrap();
+        assert_eq!(token.expose_secret(), "z2p_abc");
+        assert_none!(assert_ok!(bearer_token(&HeaderMap::new())));
+        assert_err!(bearer_token(&headers("Basic cG9zdG1hcms6c2VjcmV0")));
+    }
+}
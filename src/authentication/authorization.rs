@@ -0,0 +1,229 @@
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+
+use actix_web::dev::Payload;
+use actix_web::error::{ErrorForbidden, ErrorUnauthorized};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+
+use super::{Principal, Scope, UserId};
+
+/// What an administrator may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Everything, including managing users, API keys and publications.
+    Owner,
+    /// Drafting and publishing issues, on top of what viewers can do.
+    Editor,
+    /// Read-only access to analytics and subscribers.
+    Viewer,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Owner, Role::Editor, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Editor => matches!(
+                permission,
+                Permission::PublishNewsletters
+                    | Permission::ReadSubscribers
+                    | Permission::ViewAnalytics
+            ),
+            Role::Viewer => matches!(
+                permission,
+                Permission::ReadSubscribers | Permission::ViewAnalytics
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid role.", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ManageUsers,
+    ManageApiKeys,
+    ManagePublications,
+    PublishNewsletters,
+    ReadSubscribers,
+    WriteSubscribers,
+    ViewAnalytics,
+    ViewAuditLog,
+}
+
+impl Permission {
+    /// The API key scope granting this permission, if keys can have it at all.
+    fn scope(&self) -> Option<Scope> {
+        match self {
+            Permission::PublishNewsletters => Some(Scope::NewslettersPublish),
+            Permission::ReadSubscribers => Some(Scope::SubscribersRead),
+            Permission::WriteSubscribers => Some(Scope::SubscribersWrite),
+            Permission::ViewAuditLog => Some(Scope::AuditLogRead),
+            Permission::ManageUsers
+            | Permission::ManageApiKeys
+            | Permission::ManagePublications
+            | Permission::ViewAnalytics => None,
+        }
+    }
+}
+
+impl Principal {
+    pub fn can(&self, permission: Permission) -> bool {
+        match self {
+            Principal::User { role, .. } => role.can(permission),
+            Principal::ApiKey { scopes, .. } => permission
+                .scope()
+                .is_some_and(|scope| scopes.contains(&scope)),
+        }
+    }
+}
+
+/// Ties a marker type to the permission it stands for, so that handlers can
+/// state what they need in their signature: `_: Authorized<PublishNewsletters>`.
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+macro_rules! required_permissions {
+    ($($name:ident),* $(,)?) => {
+        /// Markers for `Authorized`, one per `Permission`.
+        pub mod permissions {
+            $(
+                pub struct $name;
+
+                impl super::RequiredPermission for $name {
+                    const PERMISSION: super::Permission = super::Permission::$name;
+                }
+            )*
+        }
+    };
+}
+
+required_permissions!(
+    ManageUsers,
+    ManageApiKeys,
+    ManagePublications,
+    PublishNewsletters,
+    ReadSubscribers,
+    WriteSubscribers,
+    ViewAnalytics,
+    ViewAuditLog,
+);
+
+/// Extractor for the principal behind a request, rejecting it with a 403
+/// unless it holds `P`'s permission.
+///
+/// It is the only way for a handler to learn who is calling, so a route
+/// cannot act on behalf of someone without also checking what they may do.
+pub struct Authorized<P> {
+    principal: Principal,
+    _permission: PhantomData<P>,
+}
+
+impl<P> Authorized<P> {
+    pub fn principal(&self) -> &Principal {
+        &self.principal
+    }
+
+    /// The logged in user, or `None` for API keys.
+    pub fn user_id(&self) -> Option<UserId> {
+        match &self.principal {
+            Principal::User { user_id, .. } => Some(*user_id),
+            Principal::ApiKey { .. } => None,
+        }
+    }
+}
+
+impl<P: RequiredPermission> FromRequest for Authorized<P> {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let principal = req.extensions().get::<Principal>().cloned();
+        ready(match principal {
+            Some(principal) if principal.can(P::PERMISSION) => Ok(Authorized {
+                principal,
+                _permission: PhantomData,
+            }),
+            Some(principal) => {
+                tracing::warn!(?principal, permission = ?P::PERMISSION, "Forbidden request.");
+                Err(ErrorForbidden("You are not allowed to do this."))
+            }
+            None => Err(ErrorUnauthorized("The request is not authenticated.")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+    use crate::authentication::{Principal, Scope, UserId};
+    use uuid::Uuid;
+
+    fn user(role: Role) -> Principal {
+        Principal::User {
+            user_id: UserId(Uuid::new_v4()),
+            role,
+        }
+    }
+
+    #[test]
+    fn only_owners_manage_users_api_keys_and_publications_and_see_the_audit_log() {
+        for permission in [
+            Permission::ManageUsers,
+            Permission::ManageApiKeys,
+            Permission::ManagePublications,
+            Permission::ViewAuditLog,
+        ] {
+            assert!(user(Role::Owner).can(permission));
+            assert!(!user(Role::Editor).can(permission));
+            assert!(!user(Role::Viewer).can(permission));
+        }
+    }
+
+    #[test]
+    fn editors_publish_and_viewers_only_read() {
+        assert!(user(Role::Editor).can(Permission::PublishNewsletters));
+        assert!(!user(Role::Viewer).can(Permission::PublishNewsletters));
+        assert!(user(Role::Viewer).can(Permission::ViewAnalytics));
+        assert!(user(Role::Viewer).can(Permission::ReadSubscribers));
+        assert!(!user(Role::Viewer).can(Permission::WriteSubscribers));
+    }
+
+    #[test]
+    fn api_keys_are_limited_to_their_scopes() {
+        let key = Principal::ApiKey {
+            id: Uuid::new_v4(),
+            scopes: vec![Scope::NewslettersPublish],
+        };
+        assert!(key.can(Permission::PublishNewsletters));
+        assert!(!key.can(Permission::ReadSubscribers));
+        assert!(!key.can(Permission::ManageApiKeys));
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in Role::ALL {
+            assert_eq!(Role::try_from(role.as_str().to_owned()), Ok(role));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use actix_web::http::header::HeaderMap;
+use base64::Engine;
+use secrecy::{ExposeSecret, Secret};
+use subtle::ConstantTimeEq;
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+impl Credentials {
+    /// Compare against the expected credentials in constant time, so that
+    /// response latency does not leak how much of a guess was correct.
+    pub fn matches(&self, username: &str, password: &Secret<String>) -> bool {
+        let username_matches = self.username.as_bytes().ct_eq(username.as_bytes());
+        let password_matches = self
+            .password
+            .expose_secret()
+            .as_bytes()
+            .ct_eq(password.expose_secret().as_bytes());
+        (username_matches & password_matches).into()
+    }
+}
+
+/// Extract HTTP Basic credentials from the `Authorization` header.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, String> {
+    let header_value = headers
+        .get("Authorization")
+        .ok_or("The 'Authorization' header was missing")?
+        .to_str()
+        .map_err(|_| "The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .ok_or("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .map_err(|_| "Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .map_err(|_| "The decoded credential string is not valid UTF8.")?;
+
+    // Split into two segments, using ':' as delimiter
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .ok_or("A password must be provided in 'Basic' auth.")?;
+
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::basic_authentication;
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use claims::{assert_err, assert_ok};
+    use secrecy::{ExposeSecret, Secret};
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("authorization"),
+            HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn valid_basic_credentials_are_parsed() {
+        // "postmark:secret"
+        let credentials = assert_ok!(basic_authentication(&headers("Basic cG9zdG1hcms6c2VjcmV0")));
+        assert_eq!(credentials.username, "postmark");
+        assert_eq!(credentials.password.expose_secret(), "secret");
+    }
+
+    #[test]
+    fn a_missing_header_is_rejected() {
+        assert_err!(basic_authentication(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn a_non_basic_scheme_is_rejected() {
+        assert_err!(basic_authentication(&headers(
+            "Bearer cG9zdG1hcms6c2VjcmV0"
+        )));
+    }
+
+    #[test]
+    fn credentials_match_only_when_both_fields_are_equal() {
+        // "postmark:secret"
+        let credentials = basic_authentication(&headers("Basic cG9zdG1hcms6c2VjcmV0")).unwrap();
+        assert!(credentials.matches("postmark", &Secret::new("secret".into())));
+        assert!(!credentials.matches("postmark", &Secret::new("secrets".into())));
+        assert!(!credentials.matches("mailgun", &Secret::new("secret".into())));
+    }
+
+    #[test]
+    fn credentials_without_a_password_are_rejected() {
+        // "postmark"
+        assert_err!(basic_authentication(&headers("Basic cG9zdG1hcms=")));
+    }
+}
@@ -0,0 +1,25 @@
+mod api_key;
+mod authorization;
+mod basic;
+mod password;
+mod password_reset;
+mod principal;
+mod session;
+mod signed_link;
+mod totp;
+mod two_factor;
+mod users;
+mod webhook;
+
+pub use api_key::*;
+pub use authorization::*;
+pub use basic::*;
+pub use password::*;
+pub use password_reset::*;
+pub use principal::*;
+pub use session::*;
+pub use signed_link::*;
+pub use totp::*;
+pub use two_factor::*;
+pub use users::*;
+pub use webhook::*;
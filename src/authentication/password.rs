@@ -0,0 +1,189 @@
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use deadpool_postgres::Pool;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use super::Credentials;
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidCredentials,
+    Unexpected(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "Invalid username or password."),
+            AuthError::Unexpected(e) => write!(f, "Authentication failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl AuthError {
+    fn unexpected(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        AuthError::Unexpected(e.into())
+    }
+}
+
+/// Verified against when the username is unknown, so that a missing user
+/// takes as long to reject as a wrong password.
+const FALLBACK_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$\
+gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+/// Run CPU-heavy work on the blocking thread pool, keeping the current span.
+pub async fn spawn_blocking_with_tracing<F, R>(f: F) -> Result<R, tokio::task::JoinError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f)).await
+}
+
+/// Returns the id of the user if the credentials are valid.
+#[tracing::instrument(name = "Validating credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &Pool,
+) -> Result<Uuid, AuthError> {
+    let client = pool.get().await.map_err(AuthError::unexpected)?;
+    let row = client
+        .query_opt(
+            "SELECT user_id, password_hash FROM users WHERE username = $1",
+            &[&credentials.username],
+        )
+        .await
+        .map_err(AuthError::unexpected)?;
+
+    let (user_id, expected_password_hash) = match row {
+        Some(row) => (
+            Some(row.get::<_, Uuid>("user_id")),
+            Secret::new(row.get::<_, String>("password_hash")),
+        ),
+        None => (None, Secret::new(FALLBACK_PASSWORD_HASH.to_owned())),
+    };
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .map_err(AuthError::unexpected)??;
+
+    user_id.ok_or(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Verifying password hash", skip_all)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash =
+        PasswordHash::new(expected_password_hash.expose_secret()).map_err(AuthError::unexpected)?;
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(|_| AuthError::InvalidCredentials)
+}
+
+/// Hash a password with Argon2id, as a PHC string.
+pub fn compute_password_hash(
+    password: Secret<String>,
+) -> Result<Secret<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(19456, 2, 1, None)?,
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+/// Passwords everybody tries first; they would pass every other rule.
+const COMMON_PASSWORDS: &[&str] = &[
+    "password1234",
+    "passwordpassword",
+    "123456789012",
+    "qwertyuiopas",
+    "letmeinletmein",
+    "administrator",
+    "iloveyou1234",
+];
+
+/// Strength rules for new passwords: 12 to 128 characters, not made of a
+/// single repeated character, unrelated to the username and not a
+/// well-known password.
+pub fn check_password_strength(password: &Secret<String>, username: &str) -> Result<(), String> {
+    let password = password.expose_secret();
+    let length = password.chars().count();
+    if length < 12 {
+        return Err("The password must be at least 12 characters long.".into());
+    }
+    if length > 128 {
+        return Err("The password must be at most 128 characters long.".into());
+    }
+    let mut chars = password.chars();
+    let first = chars.next();
+    if chars.all(|c| Some(c) == first) {
+        return Err("The password must not repeat a single character.".into());
+    }
+    let lowercase = password.to_lowercase();
+    if !username.is_empty() && lowercase.contains(&username.to_lowercase()) {
+        return Err("The password must not contain the username.".into());
+    }
+    if COMMON_PASSWORDS.contains(&lowercase.as_str()) {
+        return Err("The password is too common.".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_password_strength, compute_password_hash, verify_password_hash,
+        FALLBACK_PASSWORD_HASH,
+    };
+    use argon2::PasswordHash;
+    use claims::{assert_err, assert_ok};
+    use secrecy::{ExposeSecret, Secret};
+
+    #[test]
+    fn hashes_are_argon2id_phc_strings_that_verify() {
+        let hash = compute_password_hash(Secret::new("correct horse".into())).unwrap();
+        assert!(hash.expose_secret().starts_with("$argon2id$v=19$"));
+
+        assert_ok!(verify_password_hash(
+            hash.clone(),
+            Secret::new("correct horse".into())
+        ));
+        assert_err!(verify_password_hash(
+            hash,
+            Secret::new("battery staple".into())
+        ));
+    }
+
+    #[test]
+    fn the_fallback_hash_is_well_formed() {
+        assert_ok!(PasswordHash::new(FALLBACK_PASSWORD_HASH));
+    }
+
+    #[test]
+    fn weak_passwords_are_rejected() {
+        let check =
+            |password: &str| check_password_strength(&Secret::new(password.into()), "ursula");
+        assert_err!(check("short"));
+        assert_err!(check(&"a".repeat(129)));
+        assert_err!(check("aaaaaaaaaaaaaaaa"));
+        assert_err!(check("my name is Ursula!"));
+        assert_err!(check("Password1234"));
+        assert_ok!(check("correct horse battery staple"));
+    }
+}
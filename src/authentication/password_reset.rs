@@ -0,0 +1,93 @@
+use chrono::{Duration, Utc};
+use deadpool_postgres::GenericClient;
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// How long a reset link stays valid.
+pub const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+
+/// Like sessions, only a digest of the token is stored.
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Issue a reset token for `user_id`, to be sent as part of a link.
+#[tracing::instrument(name = "Creating a password reset token", skip(client))]
+pub async fn create_password_reset_token<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Secret<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let now = Utc::now();
+    client
+        .execute(
+            "DELETE FROM password_reset_tokens WHERE expires_at < now()",
+            &[],
+        )
+        .await?;
+    client
+        .execute(
+            r#"
+    INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+    VALUES ($1, $2, $3, $4)
+    "#,
+            &[
+                &token_hash(&token),
+                &user_id,
+                &now,
+                &(now + Duration::minutes(PASSWORD_RESET_TTL_MINUTES)),
+            ],
+        )
+        .await?;
+    Ok(Secret::new(token))
+}
+
+/// Whom an unused, unexpired token belongs to, without using it up.
+pub async fn check_password_reset_token<C: GenericClient>(
+    client: &C,
+    token: &Secret<String>,
+) -> Result<Option<Uuid>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT user_id FROM password_reset_tokens
+    WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+    "#,
+            &[&token_hash(token.expose_secret())],
+        )
+        .await?;
+    Ok(row.map(|row| row.get("user_id")))
+}
+
+/// Use a token up. Returns whom it belonged to, or `None` if it was unknown,
+/// expired or already used; every other token of that user is used up too.
+#[tracing::instrument(name = "Consuming a password reset token", skip_all)]
+pub async fn consume_password_reset_token<C: GenericClient>(
+    client: &C,
+    token: &Secret<String>,
+) -> Result<Option<Uuid>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    UPDATE password_reset_tokens SET used_at = now()
+    WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+    RETURNING user_id
+    "#,
+            &[&token_hash(token.expose_secret())],
+        )
+        .await?;
+    let Some(user_id) = row.map(|row| row.get::<_, Uuid>("user_id")) else {
+        return Ok(None);
+    };
+    client
+        .execute(
+            "UPDATE password_reset_tokens SET used_at = now() WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    Ok(Some(user_id))
+}
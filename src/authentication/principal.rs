@@ -0,0 +1,199 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{ErrorForbidden, InternalError};
+use actix_web::http::header::LOCATION;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpResponse};
+use deadpool_postgres::Pool;
+use uuid::Uuid;
+
+use super::{
+    authenticate_api_key, bearer_token, load_session, Role, Scope, Session, SESSION_COOKIE,
+};
+use crate::publication::PublicationId;
+
+/// A logged in administrator.
+#[derive(Clone, Copy, Debug)]
+pub struct UserId(pub Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Who is making an `/admin` request, set by `reject_anonymous_users`.
+/// Handlers get hold of it through `Authorized`.
+#[derive(Clone, Debug)]
+pub enum Principal {
+    User { user_id: UserId, role: Role },
+    ApiKey { id: Uuid, scopes: Vec<Scope> },
+}
+
+/// Form field or header carrying the CSRF token. Never the query string:
+/// URLs end up in logs and Referer headers.
+pub const CSRF_FIELD: &str = "csrf_token";
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+enum Authenticated {
+    ApiKey(Principal, PublicationId),
+    Session(Session),
+}
+
+/// Middleware authenticating requests with a Bearer API key or a session
+/// cookie. Browsers without a session are sent to the login form, and
+/// browsers with one must prove that their forms came from us.
+///
+/// Also sets the `PublicationId` the request works on.
+pub async fn reject_anonymous_users(
+    mut request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let pool = request
+        .app_data::<web::Data<Pool>>()
+        .expect("The database pool is not registered as application data.")
+        .clone();
+
+    let token = match bearer_token(request.headers()) {
+        Ok(token) => token,
+        Err(e) => return Err(unauthorized_api_client(e)),
+    };
+
+    let authenticated = match pool.get().await {
+        Ok(client) => match (&token, request.cookie(SESSION_COOKIE)) {
+            (Some(token), _) => authenticate_api_key(&client, token).await.map(|key| {
+                key.map(|(id, scopes, publication_id)| {
+                    Authenticated::ApiKey(Principal::ApiKey { id, scopes }, publication_id)
+                })
+            }),
+            (None, Some(cookie)) => load_session(&client, cookie.value())
+                .await
+                .map(|session| session.map(Authenticated::Session)),
+            (None, None) => Ok(None),
+        },
+        Err(e) => Err(e.into()),
+    };
+
+    match authenticated {
+        Ok(Some(Authenticated::ApiKey(principal, publication_id))) => {
+            request.extensions_mut().insert(principal);
+            request.extensions_mut().insert(publication_id);
+            next.call(request).await
+        }
+        Ok(Some(Authenticated::Session(session))) => {
+            if !request.method().is_safe() {
+                verify_csrf_token(&mut request, &session).await?;
+            }
+            if session.must_enroll_two_factor && !allowed_before_enrollment(request.path()) {
+                let response = HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/admin/two_factor"))
+                    .finish();
+                return Err(InternalError::from_response(
+                    "The user must set up two-factor authentication.",
+                    response,
+                )
+                .into());
+            }
+            request.extensions_mut().insert(Principal::User {
+                user_id: UserId(session.user_id),
+                role: session.role,
+            });
+            if let Some(publication_id) = session.publication_id {
+                request.extensions_mut().insert(publication_id);
+            }
+            request.extensions_mut().insert(session);
+            next.call(request).await
+        }
+        Ok(None) if token.is_some() => Err(unauthorized_api_client(
+            "Unknown or revoked API key.".into(),
+        )),
+        Ok(None) => {
+            let response = HttpResponse::SeeOther()
+                .insert_header((LOCATION, "/login"))
+                .finish();
+            Err(InternalError::from_response("The user has not logged in.", response).into())
+        }
+        Err(e) => {
+            tracing::error!("Failed to authenticate the request: {:?}", e);
+            let response = HttpResponse::InternalServerError().finish();
+            Err(
+                InternalError::from_response("Failed to authenticate the request.", response)
+                    .into(),
+            )
+        }
+    }
+}
+
+/// Pages a user who must set up two-factor authentication can still reach.
+fn allowed_before_enrollment(path: &str) -> bool {
+    path == "/admin/logout" || path == "/admin/two_factor" || path.starts_with("/admin/two_factor/")
+}
+
+/// Look for the session's CSRF token in a header or an url-encoded form
+/// body, putting the body back for the handler to read.
+///
+/// JSON bodies are exempt: browsers cannot send them cross-site without a
+/// CORS preflight, which we never allow.
+async fn verify_csrf_token(
+    request: &mut ServiceRequest,
+    session: &Session,
+) -> Result<(), actix_web::Error> {
+    let content_type = request.content_type().to_owned();
+    if content_type == "application/json" {
+        return Ok(());
+    }
+
+    let mut candidates: Vec<String> = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| vec![value.to_owned()])
+        .unwrap_or_default();
+
+    if content_type == "application/x-www-form-urlencoded" {
+        let body = request.extract::<web::Bytes>().await?;
+        candidates.extend(form_field(&body, CSRF_FIELD));
+        let (_, mut payload) = actix_http::h1::Payload::create(true);
+        payload.unread_data(body);
+        request.set_payload(payload.into());
+    }
+
+    if candidates
+        .iter()
+        .any(|candidate| session.csrf_token_matches(candidate))
+    {
+        Ok(())
+    } else {
+        tracing::warn!("Rejected a form without a valid CSRF token.");
+        Err(ErrorForbidden("Invalid or missing CSRF token."))
+    }
+}
+
+fn form_field(encoded: &[u8], name: &str) -> Option<String> {
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(encoded)
+        .ok()?
+        .into_iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value)
+}
+
+fn unauthorized_api_client(reason: String) -> actix_web::Error {
+    tracing::warn!("Rejected API request: {}", reason);
+    let response = HttpResponse::Unauthorized()
+        .append_header(("WWW-Authenticate", "Bearer"))
+        .finish();
+    InternalError::from_response(reason, response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::form_field;
+
+    #[test]
+    fn form_fields_are_found_among_others() {
+        let body = b"title=Hello%20world&csrf_token=abc123&html=%3Cp%3E";
+        assert_eq!(form_field(body, "csrf_token").as_deref(), Some("abc123"));
+        assert_eq!(form_field(body, "missing"), None);
+        assert_eq!(form_field(b"", "csrf_token"), None);
+    }
+}
@@ -0,0 +1,292 @@
+use std::future::{ready, Ready};
+
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::Payload;
+use actix_web::error::ErrorForbidden;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use chrono::Utc;
+use deadpool_postgres::GenericClient;
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use super::Role;
+use crate::configuration::AdminSettings;
+use crate::publication::PublicationId;
+
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// Only a digest of the token is stored, so a leaked `sessions` table
+/// cannot be replayed as cookies.
+fn session_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// A logged in browser, set next to the `Principal` by `reject_anonymous_users`.
+#[derive(Clone)]
+pub struct Session {
+    pub hash: String,
+    pub user_id: Uuid,
+    pub role: Role,
+    /// Forms posted within this session must echo it back.
+    pub csrf_token: String,
+    /// Two-factor authentication is mandatory and this user has not set it up.
+    pub must_enroll_two_factor: bool,
+    /// The publication being worked on, if the user belongs to any.
+    pub publication_id: Option<PublicationId>,
+}
+
+impl Session {
+    /// Compare in constant time, like any other secret.
+    pub fn csrf_token_matches(&self, candidate: &str) -> bool {
+        self.csrf_token
+            .as_bytes()
+            .ct_eq(candidate.as_bytes())
+            .into()
+    }
+}
+
+impl FromRequest for Session {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Session>()
+                .cloned()
+                .ok_or_else(|| ErrorForbidden("This page needs a browser session.")),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashLevel {
+    Info,
+    Error,
+}
+
+impl FlashLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlashLevel::Info => "info",
+            FlashLevel::Error => "error",
+        }
+    }
+}
+
+/// A message to show once, on the page a redirect leads to.
+#[derive(Debug, Clone)]
+pub struct FlashMessage {
+    pub level: FlashLevel,
+    pub message: String,
+}
+
+impl FlashMessage {
+    pub fn info(message: impl Into<String>) -> Self {
+        Self {
+            level: FlashLevel::Info,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            level: FlashLevel::Error,
+            message: message.into(),
+        }
+    }
+}
+
+/// Start a session for `user_id`, returning the token for the cookie.
+/// It works on the first of the user's publications by name.
+#[tracing::instrument(name = "Creating a session", skip(client, ttl))]
+pub async fn create_session<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    ttl: chrono::Duration,
+) -> Result<Secret<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let token = random_token();
+
+    let now = Utc::now();
+    // Expired sessions are only ever read to be rejected; clear them here.
+    client
+        .execute(
+            "DELETE FROM sessions WHERE user_id = $1 AND expires_at <= $2",
+            &[&user_id, &now],
+        )
+        .await?;
+    client
+        .execute(
+            r#"
+    INSERT INTO sessions
+        (session_hash, user_id, csrf_token, created_at, expires_at, publication_id)
+    SELECT $1, $2, $3, $4, $5,
+           (SELECT m.publication_id FROM publication_members m
+            JOIN publications p ON p.id = m.publication_id
+            WHERE m.user_id = $2
+            ORDER BY p.name, p.slug
+            LIMIT 1)
+    "#,
+            &[
+                &session_hash(&token),
+                &user_id,
+                &random_token(),
+                &now,
+                &(now + ttl),
+            ],
+        )
+        .await?;
+    Ok(Secret::new(token))
+}
+
+/// The session a token belongs to, if it is known and not expired.
+pub async fn load_session<C: GenericClient>(
+    client: &C,
+    token: &str,
+) -> Result<Option<Session>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT s.session_hash, s.csrf_token, u.user_id, u.role,
+           p.require_two_factor AND u.totp_secret IS NULL AS must_enroll_two_factor,
+           m.publication_id
+    FROM sessions s
+    JOIN users u ON u.user_id = s.user_id
+    CROSS JOIN security_policy p
+    -- members removed from a publication stop working on it
+    LEFT JOIN publication_members m
+        ON m.publication_id = s.publication_id AND m.user_id = s.user_id
+    WHERE s.session_hash = $1 AND s.expires_at > now()
+    "#,
+            &[&session_hash(token)],
+        )
+        .await?;
+    match row {
+        Some(row) => Ok(Some(Session {
+            hash: row.get("session_hash"),
+            user_id: row.get("user_id"),
+            role: row.get::<_, String>("role").try_into()?,
+            csrf_token: row.get("csrf_token"),
+            must_enroll_two_factor: row.get("must_enroll_two_factor"),
+            publication_id: row
+                .get::<_, Option<Uuid>>("publication_id")
+                .map(PublicationId),
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Work on another publication. Returns `false` if the user does not
+/// belong to it.
+pub async fn switch_publication<C: GenericClient>(
+    client: &C,
+    session: &Session,
+    publication_id: PublicationId,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let n_updated = client
+        .execute(
+            r#"
+    UPDATE sessions s SET publication_id = $2
+    FROM publication_members m
+    WHERE s.session_hash = $1 AND m.publication_id = $2 AND m.user_id = s.user_id
+    "#,
+            &[&session.hash, &publication_id.0],
+        )
+        .await?;
+    Ok(n_updated > 0)
+}
+
+pub async fn set_flash<C: GenericClient>(
+    client: &C,
+    session: &Session,
+    flash: &FlashMessage,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            "UPDATE sessions SET flash_level = $2, flash_message = $3 WHERE session_hash = $1",
+            &[&session.hash, &flash.level.as_str(), &flash.message],
+        )
+        .await?;
+    Ok(())
+}
+
+/// The pending flash message, if any; it will not be returned again.
+pub async fn take_flash<C: GenericClient>(
+    client: &C,
+    session: &Session,
+) -> Result<Option<FlashMessage>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    UPDATE sessions s SET flash_level = NULL, flash_message = NULL
+    FROM (SELECT session_hash, flash_level, flash_message FROM sessions
+          WHERE session_hash = $1 FOR UPDATE) old
+    WHERE s.session_hash = old.session_hash AND old.flash_message IS NOT NULL
+    RETURNING old.flash_level, old.flash_message
+    "#,
+            &[&session.hash],
+        )
+        .await?;
+    Ok(row.map(|row| FlashMessage {
+        level: match row.get::<_, Option<String>>("flash_level").as_deref() {
+            Some("error") => FlashLevel::Error,
+            _ => FlashLevel::Info,
+        },
+        message: row.get("flash_message"),
+    }))
+}
+
+pub async fn delete_session<C: GenericClient>(
+    client: &C,
+    token: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            "DELETE FROM sessions WHERE session_hash = $1",
+            &[&session_hash(token)],
+        )
+        .await?;
+    Ok(())
+}
+
+/// The session cookie carrying `token`.
+pub fn session_cookie(token: &Secret<String>, settings: &AdminSettings) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, token.expose_secret().clone())
+        .path("/")
+        .http_only(true)
+        .secure(settings.secure_cookies)
+        .same_site(SameSite::Lax)
+        .max_age(actix_web::cookie::time::Duration::minutes(
+            settings.session_ttl_minutes,
+        ))
+        .finish()
+}
+
+/// A cookie that makes the browser forget its session.
+pub fn removal_cookie() -> Cookie<'static> {
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+    cookie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::session_hash;
+
+    #[test]
+    fn sessions_are_stored_as_digests() {
+        let hash = session_hash("token");
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, "token");
+        assert_eq!(hash, session_hash("token"));
+    }
+}
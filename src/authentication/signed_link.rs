@@ -0,0 +1,98 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+/// Signs the tokens of links we email to subscribers, who have no account
+/// to log in with: a token names its subject and expiry, and is only valid
+/// for the purpose it was signed for.
+pub struct LinkSigner {
+    secret: Secret<String>,
+}
+
+impl LinkSigner {
+    pub fn new(secret: Secret<String>) -> Self {
+        Self { secret }
+    }
+
+    /// `<base64url of expiry.subject>.<base64url of HMAC-SHA256>`
+    pub fn sign(&self, purpose: &str, subject: &str, expires_at: DateTime<Utc>) -> String {
+        let payload = format!("{}.{}", expires_at.timestamp(), subject);
+        let signature = self.mac(purpose, &payload).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    /// The subject of a token signed for `purpose` that has not expired by
+    /// `now`, or `None` for anything else.
+    pub fn verify(&self, purpose: &str, token: &str, now: DateTime<Utc>) -> Option<String> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = String::from_utf8(URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        // `verify_slice` compares in constant time.
+        self.mac(purpose, &payload).verify_slice(&signature).ok()?;
+        let (expires_at, subject) = payload.split_once('.')?;
+        if expires_at.parse::<i64>().ok()? < now.timestamp() {
+            return None;
+        }
+        Some(subject.to_owned())
+    }
+
+    fn mac(&self, purpose: &str, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.expose_secret().as_bytes())
+            .expect("HMAC can take keys of any size");
+        mac.update(purpose.as_bytes());
+        mac.update(b".");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinkSigner;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use chrono::{Duration, Utc};
+    use claims::{assert_none, assert_some_eq};
+    use secrecy::Secret;
+
+    fn signer(secret: &str) -> LinkSigner {
+        LinkSigner::new(Secret::new(secret.into()))
+    }
+
+    #[test]
+    fn tokens_verify_for_their_purpose_until_they_expire() {
+        let now = Utc::now();
+        let token =
+            signer("s3cret").sign("erasure", "ursula@example.com", now + Duration::hours(1));
+        assert_some_eq!(
+            signer("s3cret").verify("erasure", &token, now),
+            "ursula@example.com"
+        );
+        assert_none!(signer("s3cret").verify("data_export", &token, now));
+        assert_none!(signer("other").verify("erasure", &token, now));
+        assert_none!(signer("s3cret").verify("erasure", &token, now + Duration::hours(2)));
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let now = Utc::now();
+        let signer = signer("s3cret");
+        let token = signer.sign("erasure", "ursula@example.com", now + Duration::hours(1));
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(format!("{}.octavia@example.com", now.timestamp() + 60)),
+            signature
+        );
+        assert_none!(signer.verify("erasure", &forged, now));
+        assert_none!(signer.verify("erasure", "garbage", now));
+        assert_none!(signer.verify("erasure", "", now));
+    }
+}
@@ -0,0 +1,144 @@
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::RngCore;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+/// RFC 6238 defaults, the only parameters authenticator apps reliably support.
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from the previous and the next step are accepted too, for clocks
+/// that drift and users that type slowly.
+const ALLOWED_DRIFT_STEPS: u64 = 1;
+
+const ISSUER: &str = "zero2prod";
+
+/// A new 160-bit secret, the size RFC 4226 recommends for HMAC-SHA1.
+pub fn generate_totp_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// The `otpauth://` URI authenticator apps enroll from, usually as a QR code.
+pub fn provisioning_uri(account: &str, secret: &[u8]) -> String {
+    let label = format!("{}:{}", ISSUER, account);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        utf8_percent_encode(&label, NON_ALPHANUMERIC),
+        base32_encode(secret),
+        utf8_percent_encode(ISSUER, NON_ALPHANUMERIC),
+        DIGITS,
+        STEP_SECONDS,
+    )
+}
+
+/// RFC 4648 base32 without padding, the form secrets are typed in as.
+pub fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// The time step `unix_time` falls in.
+pub fn totp_step(unix_time: u64) -> u64 {
+    unix_time / STEP_SECONDS
+}
+
+/// The code for a time step (RFC 4226 HOTP with the step as counter), as
+/// authenticator apps show it.
+pub fn totp_code(secret: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// The time step `code` is valid for around `current_step`, if any.
+pub fn verify_totp_code(secret: &[u8], code: &str, current_step: u64) -> Option<u64> {
+    let code = code.trim();
+    (current_step.saturating_sub(ALLOWED_DRIFT_STEPS)..=current_step + ALLOWED_DRIFT_STEPS).find(
+        |&step| {
+            totp_code(secret, step)
+                .as_bytes()
+                .ct_eq(code.as_bytes())
+                .into()
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base32_encode, provisioning_uri, totp_code, totp_step, verify_totp_code};
+
+    // The SHA-1 seed of RFC 6238, appendix B
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        // The RFC lists 8 digits; ours are the last 6 of them.
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(totp_code(SECRET, totp_step(time)), code);
+        }
+    }
+
+    #[test]
+    fn codes_from_neighbouring_steps_are_accepted() {
+        let step = totp_step(1111111111);
+        let code = totp_code(SECRET, step);
+        assert_eq!(verify_totp_code(SECRET, &code, step), Some(step));
+        assert_eq!(verify_totp_code(SECRET, &code, step + 1), Some(step));
+        assert_eq!(verify_totp_code(SECRET, &code, step - 1), Some(step));
+        assert_eq!(verify_totp_code(SECRET, &code, step + 2), None);
+        assert_eq!(verify_totp_code(SECRET, "123", step), None);
+    }
+
+    #[test]
+    fn secrets_are_base32_encoded() {
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_encode(SECRET), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+
+    #[test]
+    fn the_provisioning_uri_names_issuer_and_account() {
+        let uri = provisioning_uri("ursula le guin", SECRET);
+        assert_eq!(
+            uri,
+            "otpauth://totp/zero2prod%3Aursula%20le%20guin\
+             ?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=zero2prod\
+             &algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use chrono::{Duration, Utc};
+use deadpool_postgres::GenericClient;
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::{base32_encode, generate_totp_secret, totp_step, verify_totp_code};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Time to type in a code between the two login steps.
+pub const PENDING_LOGIN_TTL_MINUTES: i64 = 5;
+/// Wrong codes allowed per pending login before the password is asked again.
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+
+fn digest(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+/// Recovery codes are typed in by hand: case, spaces and dashes do not matter.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 7];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = base32_encode(&bytes).to_lowercase();
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+/// Which second factor a login was completed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecondFactor {
+    Totp,
+    RecoveryCode,
+}
+
+fn current_step() -> u64 {
+    totp_step(Utc::now().timestamp().max(0) as u64)
+}
+
+pub async fn two_factor_enabled<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            "SELECT totp_secret IS NOT NULL AS enabled FROM users WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+    Ok(row.map(|row| row.get("enabled")).unwrap_or(false))
+}
+
+/// Generate a secret for `user_id` to enroll with; it only protects logins
+/// once `confirm_totp_enrollment` has seen a code for it.
+#[tracing::instrument(name = "Starting TOTP enrollment", skip(client))]
+pub async fn start_totp_enrollment<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let secret = generate_totp_secret();
+    client
+        .execute(
+            "UPDATE users SET totp_pending_secret = $2 WHERE user_id = $1",
+            &[&user_id, &secret],
+        )
+        .await?;
+    Ok(secret)
+}
+
+/// Turn two-factor authentication on if `code` matches the pending secret.
+/// Returns the recovery codes to show, once, or `None` for a wrong code.
+#[tracing::instrument(name = "Confirming TOTP enrollment", skip(transaction, code))]
+pub async fn confirm_totp_enrollment(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Option<Vec<Secret<String>>>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = transaction
+        .query_opt(
+            "SELECT totp_pending_secret FROM users WHERE user_id = $1 FOR UPDATE",
+            &[&user_id],
+        )
+        .await?;
+    let Some(secret) = row.and_then(|row| row.get::<_, Option<Vec<u8>>>("totp_pending_secret"))
+    else {
+        return Ok(None);
+    };
+    let Some(step) = verify_totp_code(&secret, code, current_step()) else {
+        return Ok(None);
+    };
+    transaction
+        .execute(
+            r#"
+    UPDATE users
+    SET totp_secret = totp_pending_secret, totp_pending_secret = NULL,
+        totp_last_step = $2, totp_enabled_at = now()
+    WHERE user_id = $1
+    "#,
+            &[&user_id, &(step as i64)],
+        )
+        .await?;
+    Ok(Some(replace_recovery_codes(transaction, user_id).await?))
+}
+
+/// Issue a fresh set of recovery codes, voiding the previous ones.
+pub async fn replace_recovery_codes<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Vec<Secret<String>>, Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute("DELETE FROM recovery_codes WHERE user_id = $1", &[&user_id])
+        .await?;
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code = generate_recovery_code();
+        client
+            .execute(
+                "INSERT INTO recovery_codes (code_hash, user_id) VALUES ($1, $2)",
+                &[&digest(&normalize_recovery_code(&code)), &user_id],
+            )
+            .await?;
+        codes.push(Secret::new(code));
+    }
+    Ok(codes)
+}
+
+/// Where a user stands with two-factor authentication.
+pub enum TwoFactorStatus {
+    Disabled,
+    /// A secret was generated but no code confirmed yet.
+    Enrolling {
+        secret: Vec<u8>,
+    },
+    Enabled {
+        unused_recovery_codes: i64,
+    },
+}
+
+pub async fn two_factor_status<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<TwoFactorStatus, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_one(
+            r#"
+    SELECT totp_secret IS NOT NULL AS enabled, totp_pending_secret,
+           (SELECT count(*) FROM recovery_codes r
+            WHERE r.user_id = u.user_id AND r.used_at IS NULL) AS unused_recovery_codes
+    FROM users u
+    WHERE user_id = $1
+    "#,
+            &[&user_id],
+        )
+        .await?;
+    if row.get("enabled") {
+        return Ok(TwoFactorStatus::Enabled {
+            unused_recovery_codes: row.get("unused_recovery_codes"),
+        });
+    }
+    Ok(match row.get::<_, Option<Vec<u8>>>("totp_pending_secret") {
+        Some(secret) => TwoFactorStatus::Enrolling { secret },
+        None => TwoFactorStatus::Disabled,
+    })
+}
+
+#[tracing::instrument(name = "Disabling two-factor authentication", skip(client))]
+pub async fn disable_two_factor<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            r#"
+    UPDATE users
+    SET totp_secret = NULL, totp_pending_secret = NULL, totp_last_step = NULL,
+        totp_enabled_at = NULL
+    WHERE user_id = $1
+    "#,
+            &[&user_id],
+        )
+        .await?;
+    client
+        .execute("DELETE FROM recovery_codes WHERE user_id = $1", &[&user_id])
+        .await?;
+    Ok(())
+}
+
+/// Check a code from an authenticator app or, failing that, an unused
+/// recovery code. Either can only be used once.
+#[tracing::instrument(name = "Verifying a second factor", skip(client, code))]
+pub async fn verify_second_factor<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Option<SecondFactor>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            "SELECT totp_secret FROM users WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+    let Some(secret) = row.and_then(|row| row.get::<_, Option<Vec<u8>>>("totp_secret")) else {
+        return Ok(None);
+    };
+
+    if let Some(step) = verify_totp_code(&secret, code, current_step()) {
+        // Only the first use of a code counts: it may have been shoulder-surfed.
+        let n_updated = client
+            .execute(
+                r#"
+    UPDATE users SET totp_last_step = $2
+    WHERE user_id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+    "#,
+                &[&user_id, &(step as i64)],
+            )
+            .await?;
+        return Ok((n_updated == 1).then_some(SecondFactor::Totp));
+    }
+
+    let n_updated = client
+        .execute(
+            r#"
+    UPDATE recovery_codes SET used_at = now()
+    WHERE code_hash = $1 AND user_id = $2 AND used_at IS NULL
+    "#,
+            &[&digest(&normalize_recovery_code(code)), &user_id],
+        )
+        .await?;
+    Ok((n_updated == 1).then_some(SecondFactor::RecoveryCode))
+}
+
+/// Whether every administrator must use two-factor authentication.
+pub async fn two_factor_required<C: GenericClient>(
+    client: &C,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_one("SELECT require_two_factor FROM security_policy", &[])
+        .await?;
+    Ok(row.get("require_two_factor"))
+}
+
+#[tracing::instrument(name = "Changing the two-factor policy", skip(client))]
+pub async fn set_two_factor_required<C: GenericClient>(
+    client: &C,
+    required: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            "UPDATE security_policy SET require_two_factor = $1",
+            &[&required],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Remember that `user_id` got the password right; the returned token
+/// goes in a cookie until the second factor is in.
+pub async fn create_pending_login<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Secret<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    client
+        .execute("DELETE FROM pending_logins WHERE expires_at <= now()", &[])
+        .await?;
+    client
+        .execute(
+            "INSERT INTO pending_logins (token_hash, user_id, expires_at) VALUES ($1, $2, $3)",
+            &[
+                &digest(&token),
+                &user_id,
+                &(Utc::now() + Duration::minutes(PENDING_LOGIN_TTL_MINUTES)),
+            ],
+        )
+        .await?;
+    Ok(Secret::new(token))
+}
+
+/// Whose password step `token` stands for, if it is still pending.
+pub async fn load_pending_login<C: GenericClient>(
+    client: &C,
+    token: &Secret<String>,
+) -> Result<Option<Uuid>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT user_id FROM pending_logins
+    WHERE token_hash = $1 AND expires_at > now() AND failed_attempts < $2
+    "#,
+            &[&digest(token.expose_secret()), &MAX_FAILED_ATTEMPTS],
+        )
+        .await?;
+    Ok(row.map(|row| row.get("user_id")))
+}
+
+/// Count a wrong code. Returns whether the pending login may be retried.
+pub async fn record_failed_second_factor<C: GenericClient>(
+    client: &C,
+    token: &Secret<String>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    UPDATE pending_logins SET failed_attempts = failed_attempts + 1
+    WHERE token_hash = $1
+    RETURNING failed_attempts
+    "#,
+            &[&digest(token.expose_secret())],
+        )
+        .await?;
+    Ok(row.is_some_and(|row| row.get::<_, i32>("failed_attempts") < MAX_FAILED_ATTEMPTS))
+}
+
+pub async fn delete_pending_login<C: GenericClient>(
+    client: &C,
+    token: &Secret<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            "DELETE FROM pending_logins WHERE token_hash = $1",
+            &[&digest(token.expose_secret())],
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_recovery_code, normalize_recovery_code};
+
+    #[test]
+    fn recovery_codes_survive_sloppy_typing() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(
+            normalize_recovery_code(&code),
+            normalize_recovery_code(&format!(" {} ", code.to_uppercase().replace('-', " ")))
+        );
+        assert_ne!(code, generate_recovery_code());
+    }
+}
@@ -0,0 +1,259 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{GenericClient, Pool};
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use super::{compute_password_hash, spawn_blocking_with_tracing, Role};
+
+#[derive(Debug, serde::Serialize)]
+pub struct User {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub role: Role,
+    pub two_factor_enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Creating a user", skip(client, password))]
+pub async fn create_user<C: GenericClient>(
+    client: &C,
+    username: &str,
+    email: Option<&str>,
+    password: Secret<String>,
+    role: Role,
+) -> Result<Uuid, Box<dyn std::error::Error + Send + Sync>> {
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password)).await??;
+    let user_id = Uuid::new_v4();
+    client
+        .execute(
+            r#"
+    INSERT INTO users (user_id, username, email, password_hash, role, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    "#,
+            &[
+                &user_id,
+                &username,
+                &email,
+                &password_hash.expose_secret(),
+                &role.as_str(),
+                &Utc::now(),
+            ],
+        )
+        .await?;
+    Ok(user_id)
+}
+
+/// Create the configured administrator if nobody can log in yet, as a
+/// member of every publication.
+pub async fn ensure_admin_user(
+    pool: &Pool,
+    username: &str,
+    email: Option<&str>,
+    password: &Secret<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = pool.get().await?;
+    let row = client.query_one("SELECT count(*) FROM users", &[]).await?;
+    if row.get::<_, i64>(0) == 0 {
+        let user_id = create_user(&client, username, email, password.clone(), Role::Owner).await?;
+        client
+            .execute(
+                r#"
+    INSERT INTO publication_members (publication_id, user_id, added_at)
+    SELECT id, $1, now() FROM publications
+    "#,
+                &[&user_id],
+            )
+            .await?;
+        tracing::info!(%username, "Created the initial administrator.");
+    }
+    Ok(())
+}
+
+pub async fn list_users<C: GenericClient>(
+    client: &C,
+) -> Result<Vec<User>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(
+            r#"
+    SELECT user_id, username, email, role, totp_secret IS NOT NULL AS two_factor_enabled,
+           created_at
+    FROM users
+    ORDER BY username
+    "#,
+            &[],
+        )
+        .await?;
+    rows.into_iter()
+        .map(|row| {
+            Ok(User {
+                user_id: row.get("user_id"),
+                username: row.get("username"),
+                email: row.get("email"),
+                role: row.get::<_, String>("role").try_into()?,
+                two_factor_enabled: row.get("two_factor_enabled"),
+                created_at: row.get("created_at"),
+            })
+        })
+        .collect()
+}
+
+/// Look a user up by username or, failing that, by email address.
+pub async fn find_user_by_login<C: GenericClient>(
+    client: &C,
+    login: &str,
+) -> Result<Option<User>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT user_id, username, email, role, totp_secret IS NOT NULL AS two_factor_enabled,
+           created_at
+    FROM users
+    WHERE username = $1 OR lower(email) = lower($1)
+    ORDER BY username = $1 DESC
+    LIMIT 1
+    "#,
+            &[&login],
+        )
+        .await?;
+    row.map(|row| {
+        Ok(User {
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            email: row.get("email"),
+            role: row.get::<_, String>("role").try_into()?,
+            two_factor_enabled: row.get("two_factor_enabled"),
+            created_at: row.get("created_at"),
+        })
+    })
+    .transpose()
+}
+
+pub async fn get_username<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt("SELECT username FROM users WHERE user_id = $1", &[&user_id])
+        .await?;
+    Ok(row.map(|row| row.get("username")))
+}
+
+/// Set a new password and log the user out everywhere: whoever held an old
+/// session may be the reason for the change.
+#[tracing::instrument(name = "Changing a password", skip(client, password))]
+pub async fn change_password<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    password: Secret<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password)).await??;
+    client
+        .execute(
+            "UPDATE users SET password_hash = $2 WHERE user_id = $1",
+            &[&user_id, &password_hash.expose_secret()],
+        )
+        .await?;
+    client
+        .execute("DELETE FROM sessions WHERE user_id = $1", &[&user_id])
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum UserChangeError {
+    NotFound,
+    /// Nobody would be left to manage users.
+    LastOwner,
+    Unexpected(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl std::fmt::Display for UserChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserChangeError::NotFound => write!(f, "There is no such user."),
+            UserChangeError::LastOwner => write!(f, "The last owner cannot be demoted or removed."),
+            UserChangeError::Unexpected(e) => write!(f, "Failed to change the user: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UserChangeError {}
+
+impl From<tokio_postgres::Error> for UserChangeError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        UserChangeError::Unexpected(e.into())
+    }
+}
+
+/// Change the role of a user, returning the previous one. Takes a
+/// transaction, which locks the owners so that two demotions cannot leave
+/// nobody in charge.
+#[tracing::instrument(name = "Changing the role of a user", skip(transaction))]
+pub async fn set_user_role(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    user_id: Uuid,
+    role: Role,
+) -> Result<Role, UserChangeError> {
+    if role != Role::Owner {
+        ensure_other_owner_remains(transaction, user_id).await?;
+    }
+    let row = transaction
+        .query_opt(
+            "SELECT role FROM users WHERE user_id = $1 FOR UPDATE",
+            &[&user_id],
+        )
+        .await?;
+    let Some(row) = row else {
+        return Err(UserChangeError::NotFound);
+    };
+    let previous_role = row
+        .get::<_, String>("role")
+        .try_into()
+        .map_err(|e: String| UserChangeError::Unexpected(e.into()))?;
+    transaction
+        .execute(
+            "UPDATE users SET role = $2 WHERE user_id = $1",
+            &[&user_id, &role.as_str()],
+        )
+        .await?;
+    Ok(previous_role)
+}
+
+/// Delete a user; their sessions go with them.
+#[tracing::instrument(name = "Deleting a user", skip(transaction))]
+pub async fn delete_user(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    user_id: Uuid,
+) -> Result<(), UserChangeError> {
+    ensure_other_owner_remains(transaction, user_id).await?;
+    let n_deleted = transaction
+        .execute("DELETE FROM users WHERE user_id = $1", &[&user_id])
+        .await?;
+    if n_deleted == 0 {
+        return Err(UserChangeError::NotFound);
+    }
+    Ok(())
+}
+
+async fn ensure_other_owner_remains(
+    transaction: &deadpool_postgres::Transaction<'_>,
+    user_id: Uuid,
+) -> Result<(), UserChangeError> {
+    let rows = transaction
+        .query(
+            "SELECT user_id FROM users WHERE role = 'owner' FOR UPDATE",
+            &[],
+        )
+        .await?;
+    let other_owners = rows
+        .iter()
+        .filter(|row| row.get::<_, Uuid>("user_id") != user_id)
+        .count();
+    if other_owners == 0 {
+        return Err(UserChangeError::LastOwner);
+    }
+    Ok(())
+}
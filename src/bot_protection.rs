@@ -0,0 +1,332 @@
+//! Telling people from spam bots on the public signup form, in layers: a
+//! honeypot field people cannot see, a minimum time to fill the form in,
+//! and an optional CAPTCHA.
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::authentication::LinkSigner;
+use crate::configuration::{BotProtectionSettings, CaptchaSettings};
+
+const FORM_TOKEN_PURPOSE: &str = "signup_form";
+/// Past this, a form left open in a tab has to be reloaded. Kept short: a
+/// token can be sent with any number of submissions until it expires.
+pub const FORM_TOKEN_TTL_MINUTES: i64 = 30;
+
+/// Why a submission looks like a bot's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotSignal {
+    /// The hidden field was filled in.
+    Honeypot,
+    /// No form token, or one we did not sign or that expired.
+    InvalidFormToken,
+    /// Sent sooner after the form was loaded than anybody can type.
+    TooFast,
+    CaptchaFailed,
+}
+
+impl BotSignal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BotSignal::Honeypot => "honeypot",
+            BotSignal::InvalidFormToken => "invalid_form_token",
+            BotSignal::TooFast => "too_fast",
+            BotSignal::CaptchaFailed => "captcha_failed",
+        }
+    }
+}
+
+/// What a submission brings to prove it was sent by a person.
+#[derive(Debug, Default)]
+pub struct SignupChallenge<'a> {
+    pub honeypot: Option<&'a str>,
+    pub form_token: Option<&'a str>,
+    pub captcha_response: Option<&'a str>,
+}
+
+pub struct BotProtection {
+    signer: LinkSigner,
+    /// `None` accepts forms without a token.
+    min_fill_time: Option<Duration>,
+    captcha: Option<CaptchaVerifier>,
+}
+
+impl BotProtection {
+    /// Errors if the HTTP client for the CAPTCHA provider cannot be built.
+    pub fn new(
+        settings: &BotProtectionSettings,
+        link_signing_secret: Secret<String>,
+    ) -> Result<Self, reqwest::Error> {
+        Ok(Self {
+            signer: LinkSigner::new(link_signing_secret),
+            min_fill_time: settings
+                .min_fill_time_seconds
+                .map(|seconds| Duration::seconds(seconds as i64)),
+            captcha: settings
+                .captcha
+                .as_ref()
+                .map(CaptchaVerifier::new)
+                .transpose()?,
+        })
+    }
+
+    /// For the form to post back, recording when it was loaded.
+    pub fn issue_form_token(&self, now: DateTime<Utc>) -> String {
+        self.signer.sign(
+            FORM_TOKEN_PURPOSE,
+            &now.timestamp().to_string(),
+            now + Duration::minutes(FORM_TOKEN_TTL_MINUTES),
+        )
+    }
+
+    /// The first signal the submission trips, cheapest checks first. Errors
+    /// are failures to reach the CAPTCHA provider.
+    pub async fn check(
+        &self,
+        challenge: &SignupChallenge<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<BotSignal>, reqwest::Error> {
+        if challenge
+            .honeypot
+            .is_some_and(|value| !value.trim().is_empty())
+        {
+            return Ok(Some(BotSignal::Honeypot));
+        }
+        if let Some(min_fill_time) = self.min_fill_time {
+            if let Err(signal) = self.check_form_token(challenge.form_token, min_fill_time, now) {
+                return Ok(Some(signal));
+            }
+        }
+        if let Some(captcha) = &self.captcha {
+            let response = challenge.captcha_response.unwrap_or_default().trim();
+            if response.is_empty() || !captcha.verify(response).await? {
+                return Ok(Some(BotSignal::CaptchaFailed));
+            }
+        }
+        Ok(None)
+    }
+
+    fn check_form_token(
+        &self,
+        token: Option<&str>,
+        min_fill_time: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<(), BotSignal> {
+        let issued_at = token
+            .and_then(|token| self.signer.verify(FORM_TOKEN_PURPOSE, token, now))
+            .and_then(|issued_at| issued_at.parse::<i64>().ok())
+            .and_then(|issued_at| DateTime::from_timestamp(issued_at, 0))
+            .ok_or(BotSignal::InvalidFormToken)?;
+        if now - issued_at < min_fill_time {
+            return Err(BotSignal::TooFast);
+        }
+        Ok(())
+    }
+}
+
+/// Checks CAPTCHA answers with the provider's verify endpoint. hCaptcha,
+/// reCAPTCHA and Turnstile share its shape: the secret and the answer are
+/// posted as a form, and a JSON `success` flag comes back.
+struct CaptchaVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+}
+
+impl CaptchaVerifier {
+    fn new(settings: &CaptchaSettings) -> Result<Self, reqwest::Error> {
+        let http_client = Client::builder()
+            .timeout(std::time::Duration::from_millis(
+                settings.timeout_milliseconds,
+            ))
+            .build()?;
+        Ok(Self {
+            http_client,
+            verify_url: settings.verify_url.clone(),
+            secret: settings.secret.clone(),
+        })
+    }
+
+    async fn verify(&self, response: &str) -> Result<bool, reqwest::Error> {
+        let outcome: VerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&[
+                ("secret", self.secret.expose_secret().as_str()),
+                ("response", response),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(outcome.success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BotProtection, BotSignal, SignupChallenge};
+    use crate::configuration::{BotProtectionSettings, CaptchaSettings};
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_none, assert_some_eq};
+    use secrecy::Secret;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn protection(captcha: Option<CaptchaSettings>) -> BotProtection {
+        let settings = BotProtectionSettings {
+            min_fill_time_seconds: Some(3),
+            captcha,
+        };
+        BotProtection::new(&settings, Secret::new("s3cret".into())).unwrap()
+    }
+
+    fn captcha(server: &MockServer) -> CaptchaSettings {
+        CaptchaSettings {
+            verify_url: format!("{}/siteverify", server.uri()),
+            secret: Secret::new("captcha-secret".into()),
+            timeout_milliseconds: 200,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_filled_in_honeypot_gives_bots_away() {
+        let protection = protection(None);
+        let now = Utc::now();
+        let token = protection.issue_form_token(now - Duration::minutes(1));
+        let mut challenge = SignupChallenge {
+            honeypot: Some("https://cheap-pills.example"),
+            form_token: Some(&token),
+            captcha_response: None,
+        };
+        assert_some_eq!(
+            protection.check(&challenge, now).await.unwrap(),
+            BotSignal::Honeypot
+        );
+
+        challenge.honeypot = Some("");
+        assert_none!(protection.check(&challenge, now).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn forms_need_a_token_at_least_as_old_as_the_minimum_fill_time() {
+        let protection = protection(None);
+        let now = Utc::now();
+        let check = |token: Option<String>| {
+            let protection = &protection;
+            async move {
+                let challenge = SignupChallenge {
+                    form_token: token.as_deref(),
+                    ..Default::default()
+                };
+                protection.check(&challenge, now).await.unwrap()
+            }
+        };
+
+        assert_none!(
+            check(Some(
+                protection.issue_form_token(now - Duration::seconds(3))
+            ))
+            .await
+        );
+        assert_some_eq!(
+            check(Some(
+                protection.issue_form_token(now - Duration::seconds(1))
+            ))
+            .await,
+            BotSignal::TooFast
+        );
+        assert_some_eq!(check(None).await, BotSignal::InvalidFormToken);
+        assert_some_eq!(
+            check(Some(protection.issue_form_token(now - Duration::hours(1)))).await,
+            BotSignal::InvalidFormToken
+        );
+        let other_signer = BotProtection::new(
+            &BotProtectionSettings {
+                min_fill_time_seconds: Some(3),
+                captcha: None,
+            },
+            Secret::new("another-secret".into()),
+        )
+        .unwrap();
+        assert_some_eq!(
+            check(Some(
+                other_signer.issue_form_token(now - Duration::minutes(1))
+            ))
+            .await,
+            BotSignal::InvalidFormToken
+        );
+    }
+
+    #[tokio::test]
+    async fn captcha_answers_are_checked_with_the_verify_url() {
+        let server = MockServer::start().await;
+        let protection = protection(Some(captcha(&server)));
+        let now = Utc::now();
+        let token = protection.issue_form_token(now - Duration::minutes(1));
+
+        Mock::given(method("POST"))
+            .and(path("/siteverify"))
+            .and(body_string_contains("secret=captcha-secret"))
+            .and(body_string_contains("response=good-answer"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/siteverify"))
+            .and(body_string_contains("response=bad-answer"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false,
+                "error-codes": ["invalid-input-response"]
+            })))
+            .mount(&server)
+            .await;
+
+        let mut challenge = SignupChallenge {
+            honeypot: None,
+            form_token: Some(&token),
+            captcha_response: Some("good-answer"),
+        };
+        assert_none!(protection.check(&challenge, now).await.unwrap());
+        challenge.captcha_response = Some("bad-answer");
+        assert_some_eq!(
+            protection.check(&challenge, now).await.unwrap(),
+            BotSignal::CaptchaFailed
+        );
+        // unanswered: the provider is not even asked
+        challenge.captcha_response = None;
+        assert_some_eq!(
+            protection.check(&challenge, now).await.unwrap(),
+            BotSignal::CaptchaFailed
+        );
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_captcha_provider_is_an_error() {
+        let server = MockServer::start().await;
+        let protection = protection(Some(captcha(&server)));
+        let now = Utc::now();
+        let token = protection.issue_form_token(now - Duration::minutes(1));
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let challenge = SignupChallenge {
+            honeypot: None,
+            form_token: Some(&token),
+            captcha_response: Some("good-answer"),
+        };
+        assert_err!(protection.check(&challenge, now).await);
+    }
+}
@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use deadpool_postgres::Pool;
+use uuid::Uuid;
+
+use crate::audit::{record, AuditEvent};
+use crate::authentication::{create_api_key, list_api_keys, revoke_api_key, Scope};
+use crate::configuration::Settings;
+use crate::publication::{find_publication_by_slug, PublicationId};
+use crate::startup::get_connection_pool;
+use crate::subscriber_import::{
+    create_import, get_import, inspect, list_rejections, process_import, ColumnMapping,
+    DuplicatePolicy, ImportFormat, NewImport,
+};
+
+/// Without a command, the server is started.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Manage API keys for machine clients.
+    ApiKeys {
+        /// The slug of the publication the keys act on.
+        #[arg(long, default_value = "default")]
+        publication: String,
+        #[command(subcommand)]
+        command: ApiKeyCommand,
+    },
+    /// Import subscribers from a CSV file with a header line, ours or
+    /// exported from Mailchimp or Substack, then list the lines that were
+    /// not imported.
+    ImportSubscribers {
+        /// The slug of the publication to import into.
+        #[arg(long, default_value = "default")]
+        publication: String,
+        file: PathBuf,
+        /// Validate and report without changing any subscriber.
+        #[arg(long)]
+        dry_run: bool,
+        /// What to do with addresses already subscribed: skip, update or error.
+        #[arg(long, default_value = "skip", value_parser = parse_duplicate_policy)]
+        on_duplicate: DuplicatePolicy,
+        /// csv, mailchimp or substack; the column options only apply to csv.
+        #[arg(long, default_value = "csv", value_parser = parse_import_format)]
+        format: ImportFormat,
+        #[arg(long, default_value = "email")]
+        email_column: String,
+        #[arg(long, default_value = "name")]
+        name_column: String,
+        /// The column holding `;` separated tags, if any.
+        #[arg(long)]
+        tags_column: Option<String>,
+        /// The column holding confirmed, unsubscribed, bounced or complained.
+        #[arg(long)]
+        status_column: Option<String>,
+        #[arg(long)]
+        subscribed_at_column: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ApiKeyCommand {
+    /// Create a key and print it; it cannot be shown again.
+    Create {
+        #[arg(long)]
+        name: String,
+        /// May be repeated, e.g. `--scope newsletters:publish`.
+        #[arg(long = "scope", value_parser = parse_scope)]
+        scopes: Vec<Scope>,
+    },
+    /// List active keys.
+    List,
+    /// Revoke a key by id.
+    Revoke { id: Uuid },
+}
+
+fn parse_scope(s: &str) -> Result<Scope, String> {
+    Scope::try_from(s.to_owned())
+}
+
+fn parse_duplicate_policy(s: &str) -> Result<DuplicatePolicy, String> {
+    DuplicatePolicy::try_from(s.to_owned())
+}
+
+fn parse_import_format(s: &str) -> Result<ImportFormat, String> {
+    ImportFormat::try_from(s.to_owned())
+}
+
+pub async fn run(
+    command: Command,
+    configuration: Settings,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let pool = get_connection_pool(&configuration.database).map_err(|e| e.to_string())?;
+
+    match command {
+        Command::ApiKeys {
+            publication,
+            command,
+        } => manage_api_keys(&pool, &publication, command).await,
+        Command::ImportSubscribers {
+            publication,
+            file,
+            dry_run,
+            on_duplicate,
+            format,
+            email_column,
+            name_column,
+            tags_column,
+            status_column,
+            subscribed_at_column,
+        } => {
+            let columns = ColumnMapping {
+                email: email_column,
+                name: name_column,
+                tags: tags_column,
+                status: status_column,
+                subscribed_at: subscribed_at_column,
+            };
+            import_subscribers(
+                &pool,
+                &publication,
+                &file,
+                dry_run,
+                on_duplicate,
+                format,
+                &columns,
+            )
+            .await
+        }
+    }
+}
+
+async fn find_publication(
+    client: &deadpool_postgres::Client,
+    slug: &str,
+) -> Result<PublicationId, Box<dyn std::error::Error + Send + Sync>> {
+    match find_publication_by_slug(client, slug).await? {
+        Some(publication) => Ok(PublicationId(publication.id)),
+        None => Err(format!("There is no publication {}", slug).into()),
+    }
+}
+
+async fn manage_api_keys(
+    pool: &Pool,
+    publication: &str,
+    command: ApiKeyCommand,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let publication_id = find_publication(&client, publication).await?;
+    match command {
+        ApiKeyCommand::Create { name, scopes } => {
+            let transaction = client.transaction().await?;
+            let (api_key, secret) =
+                create_api_key(&transaction, publication_id, &name, &scopes, None).await?;
+            let audit = AuditEvent::new("api_key.created")
+                .publication(publication_id)
+                .target(format!("api_key:{}", api_key.id))
+                .details(serde_json::json!({
+                    "name": api_key.name,
+                    "scopes": api_key.scopes,
+                    "publication_id": api_key.publication_id,
+                    "via": "cli",
+                }));
+            record(&transaction, audit).await?;
+            transaction.commit().await?;
+            println!("Created API key {} ({})", api_key.id, api_key.name);
+            println!("{}", secrecy::ExposeSecret::expose_secret(&secret));
+        }
+        ApiKeyCommand::List => {
+            for api_key in list_api_keys(&client, publication_id).await? {
+                let scopes: Vec<&str> = api_key.scopes.iter().map(Scope::as_str).collect();
+                println!(
+                    "{}\t{}\t{}…\t{}\tlast used: {}",
+                    api_key.id,
+                    api_key.name,
+                    api_key.key_prefix,
+                    scopes.join(","),
+                    api_key
+                        .last_used_at
+                        .map(|t| t.to_rfc3339())
+                        .unwrap_or_else(|| "never".into()),
+                );
+            }
+        }
+        ApiKeyCommand::Revoke { id } => {
+            let transaction = client.transaction().await?;
+            if revoke_api_key(&transaction, publication_id, id).await? {
+                let audit = AuditEvent::new("api_key.revoked")
+                    .publication(publication_id)
+                    .target(format!("api_key:{}", id))
+                    .details(serde_json::json!({ "via": "cli" }));
+                record(&transaction, audit).await?;
+                transaction.commit().await?;
+                println!("Revoked API key {}", id);
+            } else {
+                return Err(format!("{} has no active API key with id {}", publication, id).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn import_subscribers(
+    pool: &Pool,
+    publication: &str,
+    file: &Path,
+    dry_run: bool,
+    on_duplicate: DuplicatePolicy,
+    format: ImportFormat,
+    columns: &ColumnMapping,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let content = std::fs::read_to_string(file)
+        .map_err(|e| format!("Cannot read {}: {}", file.display(), e))?;
+    let total_rows = inspect(&content, format, columns)?;
+    let import = NewImport {
+        content: &content,
+        format,
+        columns,
+        on_duplicate,
+        dry_run,
+        total_rows,
+    };
+
+    let mut client = pool.get().await?;
+    let publication_id = find_publication(&client, publication).await?;
+    let transaction = client.transaction().await?;
+    // the import is ours to process, the server's workers leave it alone
+    let import_id = create_import(&transaction, publication_id, import, true).await?;
+    let audit = AuditEvent::new("subscribers.import_started")
+        .publication(publication_id)
+        .target(format!("subscriber_import:{}", import_id))
+        .details(serde_json::json!({
+            "dry_run": dry_run,
+            "on_duplicate": on_duplicate,
+            "format": format,
+            "columns": columns,
+            "total_rows": total_rows,
+            "via": "cli",
+        }));
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+
+    process_import(pool, import_id).await?;
+
+    let import = get_import(&client, publication_id, import_id)
+        .await?
+        .ok_or("The import vanished.")?;
+    println!(
+        "{}{} imported, {} updated, {} skipped, {} rejected out of {} rows",
+        if dry_run { "Dry run: " } else { "" },
+        import.imported,
+        import.updated,
+        import.skipped,
+        import.rejected,
+        import.total_rows,
+    );
+    let mut after_line = 0;
+    loop {
+        let rejections = list_rejections(&client, import_id, after_line, 1000).await?;
+        let Some(last) = rejections.last() else {
+            break;
+        };
+        after_line = last.line;
+        for rejection in &rejections {
+            println!("line {}: {}", rejection.line, rejection.reason);
+        }
+    }
+    Ok(())
+}
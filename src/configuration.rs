@@ -1,12 +1,19 @@
+use std::collections::HashMap;
+
 use secrecy::{ExposeSecret, Secret};
 
-use crate::domain::SubscriberEmail;
+use crate::domain::SenderIdentity;
+use crate::email_client::{MessageStream, SenderIdentities};
+use crate::outbox::Outbox;
+use crate::rate_limit::IpNetwork;
 
 #[derive(serde::Deserialize)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub webhooks: WebhookSettings,
+    pub admin: AdminSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -35,23 +42,195 @@ impl DatabaseSettings {
 pub struct ApplicationSettings {
     pub host: String,
     pub port: u16,
+    /// Where users reach us, for links in emails.
+    pub base_url: String,
+    /// The slug of the publication signups go to when their host is not
+    /// claimed by any publication.
+    pub default_publication: String,
+    /// Signs the links we email to subscribers, see `LinkSigner`.
+    pub link_signing_secret: Secret<String>,
+    /// The proxies in front of us, whose `X-Forwarded-For` headers name
+    /// the client.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNetwork>,
+    pub signup_rate_limits: SignupRateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub email_policy: EmailPolicySettings,
+}
+
+/// See `EmailPolicy`.
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailPolicySettings {
+    /// Disposable email providers, one domain per line. `None` blocks none.
+    pub disposable_domains_file: Option<String>,
+    /// Local parts of addresses that reach a function rather than a person.
+    #[serde(default)]
+    pub role_addresses: Vec<String>,
+}
+
+/// See `BotProtection`.
+#[derive(serde::Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    /// Forms sent sooner after being loaded are a bot's. `None` accepts
+    /// forms without a token.
+    pub min_fill_time_seconds: Option<u64>,
+    pub captcha: Option<CaptchaSettings>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct CaptchaSettings {
+    pub verify_url: String,
+    pub secret: Secret<String>,
+    pub timeout_milliseconds: u64,
+}
+
+/// Limits on signups, against people signing others up: per client, and
+/// per address signed up.
+#[derive(serde::Deserialize, Clone)]
+pub struct SignupRateLimitSettings {
+    pub backend: RateLimitBackend,
+    pub per_ip: TokenBucketSettings,
+    pub per_email: TokenBucketSettings,
+}
+
+/// A burst of `capacity` attempts, then one every refill interval.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct TokenBucketSettings {
+    pub capacity: u32,
+    pub refill_interval_seconds: u64,
+}
+
+impl TokenBucketSettings {
+    pub fn refill_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.refill_interval_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    /// Each replica counts on its own.
+    Memory,
+    /// Replicas share their counters.
+    Postgres,
 }
 
 #[derive(serde::Deserialize)]
 pub struct EmailClientSettings {
     pub base_url: String,
-    pub sender_email: String,
+    /// Named identities we may send email as.
+    pub senders: HashMap<String, SenderSettings>,
+    pub default_sender: String,
+    /// Which identity each kind of transactional email is sent as,
+    /// e.g. `password_reset: "accounts"`. Unlisted kinds use `default_sender`.
+    #[serde(default)]
+    pub transactional_senders: HashMap<String, String>,
+    pub message_streams: MessageStreamSettings,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub backend: EmailBackend,
+    /// Where the capture backend keeps emails; in memory if unset.
+    pub capture_directory: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailBackend {
+    Postmark,
+    /// Store outgoing emails for the `/_dev/mailbox` viewer instead of sending them.
+    Capture,
+}
+
+/// The provider's identifier of the stream each kind of message goes through.
+/// Pointing both at the same stream merges them.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct MessageStreamSettings {
+    pub transactional: String,
+    pub broadcast: String,
+}
+
+impl MessageStreamSettings {
+    pub fn id(&self, stream: MessageStream) -> &str {
+        match stream {
+            MessageStream::Transactional => &self.transactional,
+            MessageStream::Broadcast => &self.broadcast,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SenderSettings {
+    pub display_name: String,
+    pub email: String,
+    pub reply_to: Option<String>,
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
-        SubscriberEmail::parse(self.sender_email.clone())
+    pub fn senders(&self) -> Result<SenderIdentities, String> {
+        let identities = self
+            .senders
+            .iter()
+            .map(|(name, sender)| {
+                let identity = SenderIdentity::parse(
+                    sender.display_name.clone(),
+                    sender.email.clone(),
+                    sender.reply_to.clone(),
+                )
+                .map_err(|e| format!("Invalid sender `{}`: {}", name, e))?;
+                Ok((name.clone(), identity))
+            })
+            .collect::<Result<_, String>>()?;
+        SenderIdentities::new(
+            identities,
+            self.default_sender.clone(),
+            self.transactional_senders.clone(),
+        )
     }
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+    pub fn outbox(&self) -> Result<Outbox, std::io::Error> {
+        match &self.capture_directory {
+            Some(directory) => Outbox::in_directory(directory.into()),
+            None => Ok(Outbox::in_memory()),
+        }
+    }
+}
+
+/// How inbound provider webhooks prove where they come from.
+/// Every configured method must pass; at least one has to be configured.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub basic_auth: Option<WebhookCredentials>,
+    /// Shared secret for HMAC-SHA256 body signatures.
+    pub signing_secret: Option<Secret<String>>,
+    /// How far a signature timestamp may drift from our clock.
+    pub timestamp_tolerance_seconds: i64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookCredentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// Administrator accounts and their sessions.
+#[derive(serde::Deserialize, Clone)]
+pub struct AdminSettings {
+    /// The first administrator, created when the `users` table is empty.
+    pub username: String,
+    pub password: Secret<String>,
+    /// Where the first administrator receives password reset links.
+    pub email: Option<String>,
+    pub session_ttl_minutes: i64,
+    /// Only send the session cookie over HTTPS.
+    pub secure_cookies: bool,
+}
+
+impl AdminSettings {
+    pub fn session_ttl(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.session_ttl_minutes)
+    }
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -71,9 +250,46 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .add_source(config::File::from(
             configuration_directory.join(environment_filename),
         ))
+        // E.g. `APP_ADMIN__PASSWORD=...` sets `admin.password`
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__"),
+        )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+    if !matches!(environment, Environment::Local) {
+        let placeholders = placeholder_secrets(&settings);
+        if !placeholders.is_empty() {
+            return Err(config::ConfigError::Message(format!(
+                "Refusing to start with the example secrets from base.yaml, set {}.",
+                placeholders.join(", ")
+            )));
+        }
+    }
+    Ok(settings)
+}
+
+/// The environment variables of secrets still set to base.yaml's examples,
+/// which anybody reading the repository knows.
+fn placeholder_secrets(settings: &Settings) -> Vec<&'static str> {
+    let mut placeholders = Vec::new();
+    if settings.admin.password.expose_secret() == "my-admin-password" {
+        placeholders.push("APP_ADMIN__PASSWORD");
+    }
+    if settings.application.link_signing_secret.expose_secret() == "my-link-signing-secret" {
+        placeholders.push("APP_APPLICATION__LINK_SIGNING_SECRET");
+    }
+    if settings
+        .webhooks
+        .basic_auth
+        .as_ref()
+        .is_some_and(|credentials| credentials.password.expose_secret() == "my-webhook-secret")
+    {
+        placeholders.push("APP_WEBHOOKS__BASIC_AUTH__PASSWORD");
+    }
+    placeholders
 }
 
 /// The possible runtime environment for our application.
@@ -0,0 +1,244 @@
+//! Proof of consent: who agreed to be emailed, or stopped agreeing, when,
+//! through what and to which wording. Records are never changed, except
+//! for the address being hashed on erasure.
+
+use actix_web::http::header::USER_AGENT;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use uuid::Uuid;
+
+use crate::publication::PublicationId;
+use crate::rate_limit::client_ip;
+use crate::suppression::normalize_email;
+
+/// Longer form fields are cut, the forms posting them are public.
+const MAX_FIELD_LENGTH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsentAction {
+    Subscribe,
+    Confirm,
+    Unsubscribe,
+}
+
+impl ConsentAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentAction::Subscribe => "subscribe",
+            ConsentAction::Confirm => "confirm",
+            ConsentAction::Unsubscribe => "unsubscribe",
+        }
+    }
+}
+
+impl TryFrom<String> for ConsentAction {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "subscribe" => Ok(Self::Subscribe),
+            "confirm" => Ok(Self::Confirm),
+            "unsubscribe" => Ok(Self::Unsubscribe),
+            other => Err(format!("{} is not a valid consent action.", other)),
+        }
+    }
+}
+
+/// Where and how consent was given or withdrawn.
+#[derive(Debug, Clone)]
+pub struct ConsentContext {
+    /// The form or process, e.g. `signup_form` or `admin`.
+    pub source: String,
+    pub consent_text_version: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ConsentContext {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            consent_text_version: None,
+            ip: None,
+            user_agent: None,
+        }
+    }
+
+    /// Record where `request` came from.
+    pub fn request(mut self, request: &HttpRequest) -> Self {
+        self.ip = client_ip(request).map(|ip| ip.to_string());
+        self.user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        self
+    }
+
+    pub fn consent_text_version(mut self, version: Option<String>) -> Self {
+        self.consent_text_version = version;
+        self
+    }
+}
+
+/// A form field naming a source or a consent text version: `None` if blank.
+pub fn parse_form_field(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| {
+            value
+                .trim()
+                .chars()
+                .take(MAX_FIELD_LENGTH)
+                .collect::<String>()
+        })
+        .filter(|value| !value.is_empty())
+}
+
+/// A record as stored.
+#[derive(Debug, serde::Serialize)]
+pub struct ConsentEvent {
+    pub occurred_at: DateTime<Utc>,
+    pub publication_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub action: ConsentAction,
+    pub source: String,
+    pub consent_text_version: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+const CONSENT_EVENT_COLUMNS: &str = "occurred_at, publication_id, subscriber_id, email, action, \
+     source, consent_text_version, ip, user_agent";
+
+fn from_row(
+    row: &tokio_postgres::Row,
+) -> Result<ConsentEvent, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(ConsentEvent {
+        occurred_at: row.get("occurred_at"),
+        publication_id: row.get("publication_id"),
+        subscriber_id: row.get("subscriber_id"),
+        email: row.get("email"),
+        action: ConsentAction::try_from(row.get::<_, String>("action"))?,
+        source: row.get("source"),
+        consent_text_version: row.get("consent_text_version"),
+        ip: row.get("ip"),
+        user_agent: row.get("user_agent"),
+    })
+}
+
+/// Run in the transaction changing the subscription.
+#[tracing::instrument(name = "Recording a consent event", skip(client, email, context))]
+pub async fn record_consent<C: GenericClient>(
+    client: &C,
+    action: ConsentAction,
+    publication_id: PublicationId,
+    subscriber_id: Uuid,
+    email: &str,
+    context: &ConsentContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            r#"
+    INSERT INTO consent_events (occurred_at, publication_id, subscriber_id, email, action,
+                                source, consent_text_version, ip, user_agent)
+    VALUES (now(), $1, $2, $3, $4, $5, $6, $7, $8)
+    "#,
+            &[
+                &publication_id.0,
+                &subscriber_id,
+                &email,
+                &action.as_str(),
+                &context.source,
+                &context.consent_text_version,
+                &context.ip,
+                &context.user_agent,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Oldest first. Records outlive the subscription, so there may be some
+/// for a deleted subscriber.
+pub async fn list_consent_events<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    subscriber_id: Uuid,
+) -> Result<Vec<ConsentEvent>, Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .query(
+            &format!(
+                r#"
+    SELECT {} FROM consent_events
+    WHERE publication_id = $1 AND subscriber_id = $2
+    ORDER BY id
+    "#,
+                CONSENT_EVENT_COLUMNS
+            ),
+            &[&publication_id.0, &subscriber_id],
+        )
+        .await?
+        .iter()
+        .map(from_row)
+        .collect()
+}
+
+/// In every publication, oldest first.
+pub async fn consent_events_for_address<C: GenericClient>(
+    client: &C,
+    email: &str,
+) -> Result<Vec<ConsentEvent>, Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .query(
+            &format!(
+                r#"
+    SELECT {} FROM consent_events
+    WHERE lower(btrim(email)) = $1
+    ORDER BY id
+    "#,
+                CONSENT_EVENT_COLUMNS
+            ),
+            &[&normalize_email(email)],
+        )
+        .await?
+        .iter()
+        .map(from_row)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_form_field, ConsentAction};
+
+    #[test]
+    fn consent_actions_round_trip() {
+        for action in [
+            ConsentAction::Subscribe,
+            ConsentAction::Confirm,
+            ConsentAction::Unsubscribe,
+        ] {
+            assert_eq!(
+                ConsentAction::try_from(action.as_str().to_owned()),
+                Ok(action)
+            );
+        }
+        assert!(ConsentAction::try_from("resubscribe".to_owned()).is_err());
+    }
+
+    #[test]
+    fn blank_form_fields_are_left_out_and_long_ones_cut() {
+        assert_eq!(parse_form_field(None), None);
+        assert_eq!(parse_form_field(Some("  ".into())), None);
+        assert_eq!(
+            parse_form_field(Some(" footer ".into())),
+            Some("footer".into())
+        );
+        assert_eq!(
+            parse_form_field(Some("x".repeat(500))).map(|field| field.len()),
+            Some(100)
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Just enough RFC 4180 to read the CSV files other newsletter tools export:
+//! comma separated, fields optionally quoted with `"`, quotes doubled inside
+//! quoted fields, which may span lines. `\n` and `\r\n` both end a record.
+
+/// A record and the line it starts on, counting from 1.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CsvRecord {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+impl CsvRecord {
+    pub fn get(&self, column: usize) -> Option<&str> {
+        self.fields.get(column).map(String::as_str)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CsvError {
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl std::fmt::Display for CsvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+/// Yields records one at a time, skipping blank lines. A byte order mark at
+/// the start is ignored.
+pub struct CsvReader<'a> {
+    input: &'a str,
+    position: usize,
+    line: usize,
+}
+
+impl<'a> CsvReader<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self {
+            input: input.strip_prefix('\u{feff}').unwrap_or(input),
+            position: 0,
+            line: 1,
+        }
+    }
+
+    fn read_record(&mut self) -> Result<CsvRecord, CsvError> {
+        let start_line = self.line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut is_quoted = false;
+        let mut in_quotes = false;
+        let mut chars = self.input[self.position..].char_indices().peekable();
+        let mut end = self.input.len();
+
+        while let Some((offset, c)) = chars.next() {
+            if in_quotes {
+                match c {
+                    '"' if chars.peek().map(|(_, c)| *c) == Some('"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' => in_quotes = false,
+                    '\n' => {
+                        self.line += 1;
+                        field.push(c);
+                    }
+                    _ => field.push(c),
+                }
+                continue;
+            }
+            match c {
+                ',' => {
+                    fields.push(std::mem::take(&mut field));
+                    is_quoted = false;
+                }
+                '"' if field.is_empty() && !is_quoted => {
+                    is_quoted = true;
+                    in_quotes = true;
+                }
+                '\r' if chars.peek().map(|(_, c)| *c) == Some('\n') => {}
+                '\n' => {
+                    end = self.position + offset + 1;
+                    break;
+                }
+                _ => field.push(c),
+            }
+        }
+
+        self.position = end;
+        self.line += 1;
+        if in_quotes {
+            return Err(CsvError {
+                line: start_line,
+                message: "A quoted field is never closed.",
+            });
+        }
+        fields.push(field);
+        Ok(CsvRecord {
+            line: start_line,
+            fields,
+        })
+    }
+}
+
+impl Iterator for CsvReader<'_> {
+    type Item = Result<CsvRecord, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position < self.input.len() {
+            match self.read_record() {
+                Ok(record) if record.fields.len() == 1 && record.fields[0].trim().is_empty() => {
+                    continue
+                }
+                outcome => return Some(outcome),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvError, CsvReader, CsvRecord};
+
+    fn read(input: &str) -> Vec<Result<CsvRecord, CsvError>> {
+        CsvReader::new(input).collect()
+    }
+
+    fn record(line: usize, fields: &[&str]) -> Result<CsvRecord, CsvError> {
+        Ok(CsvRecord {
+            line,
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn plain_fields_are_split_on_commas() {
+        assert_eq!(
+            read("email,name\nursula@example.com,Ursula\n"),
+            vec![
+                record(1, &["email", "name"]),
+                record(2, &["ursula@example.com", "Ursula"])
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_fields_keep_commas_quotes_and_newlines() {
+        assert_eq!(
+            read("a,b\r\n\"Le Guin, Ursula\",\"say \"\"hi\"\"\"\r\n\"two\nlines\",x\r\nlast,\"\""),
+            vec![
+                record(1, &["a", "b"]),
+                record(2, &["Le Guin, Ursula", "say \"hi\""]),
+                record(3, &["two\nlines", "x"]),
+                record(5, &["last", ""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_lines_and_byte_order_marks_are_ignored() {
+        assert_eq!(
+            read("\u{feff}email\n\n  \nursula@example.com"),
+            vec![record(1, &["email"]), record(4, &["ursula@example.com"])]
+        );
+        assert!(read("").is_empty());
+    }
+
+    #[test]
+    fn unterminated_quotes_are_an_error() {
+        assert_eq!(
+            read("email\n\"ursula@example.com,Ursula\n"),
+            vec![
+                record(1, &["email"]),
+                Err(CsvError {
+                    line: 2,
+                    message: "A quoted field is never closed."
+                })
+            ]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! The right of access: subscribers download an archive of everything we
+//! hold about their address. Archives are put together in the background
+//! and emailed as a link once ready.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{GenericClient, Pool};
+use htmlescape::encode_attribute;
+use uuid::Uuid;
+
+use crate::authentication::LinkSigner;
+use crate::consent::{consent_events_for_address, ConsentEvent};
+use crate::delivery_worker::enqueue_transactional_email;
+use crate::domain::SubscriberEmail;
+use crate::suppression::{email_hash, normalize_email, suppressions_of_address, Suppression};
+
+/// How long an emailed link to request an archive stays valid.
+pub const DATA_EXPORT_LINK_TTL_HOURS: i64 = 24;
+/// How long an archive can be downloaded before it is deleted.
+pub const ARCHIVE_TTL_DAYS: i64 = 7;
+
+/// The purposes the links of each step are signed for.
+pub const DATA_EXPORT_LINK_PURPOSE: &str = "data_export";
+pub const ARCHIVE_LINK_PURPOSE: &str = "data_export_download";
+
+#[derive(serde::Serialize)]
+pub struct DataArchive {
+    pub email: String,
+    pub generated_at: DateTime<Utc>,
+    pub subscriptions: Vec<ArchivedSubscription>,
+    /// Why we do not email the address, from every publication or some.
+    pub suppressions: Vec<Suppression>,
+    /// When and how the address subscribed, confirmed and unsubscribed.
+    pub consent_events: Vec<ConsentEvent>,
+    pub deliveries: Vec<ArchivedDelivery>,
+    /// Opens, clicks, bounces and complaints, as our email provider
+    /// reported them.
+    pub email_events: Vec<ArchivedEmailEvent>,
+    /// Changes to the subscriptions and requests about the address, from
+    /// the audit log.
+    pub changes: Vec<ArchivedChange>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ArchivedSubscription {
+    pub id: Uuid,
+    pub publication: String,
+    pub name: String,
+    pub status: String,
+    pub tags: Vec<String>,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ArchivedDelivery {
+    /// `None` for transactional emails.
+    pub publication: Option<String>,
+    pub stream: String,
+    pub subject: String,
+    pub outcome: String,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ArchivedEmailEvent {
+    pub record_type: String,
+    pub received_at: DateTime<Utc>,
+    pub payload: serde_json::Value,
+}
+
+/// Who made a change is left out: it names our staff, not the subscriber.
+#[derive(serde::Serialize)]
+pub struct ArchivedChange {
+    pub action: String,
+    pub occurred_at: DateTime<Utc>,
+    pub details: serde_json::Value,
+}
+
+/// Queue an archive for the worker, unless one is already waiting for it.
+/// Returns `false` if one was.
+#[tracing::instrument(name = "Queuing a data export", skip_all)]
+pub async fn queue_data_export<C: GenericClient>(
+    client: &C,
+    email: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let inserted = client
+        .execute(
+            r#"
+    INSERT INTO data_exports (id, email, status, requested_at)
+    SELECT $1, $2, 'pending', now()
+    WHERE NOT EXISTS (SELECT 1 FROM data_exports WHERE email = $2 AND status = 'pending')
+    "#,
+            &[&Uuid::new_v4(), &normalize_email(email)],
+        )
+        .await?;
+    Ok(inserted > 0)
+}
+
+/// A ready archive that has not expired.
+pub async fn get_archive<C: GenericClient>(
+    client: &C,
+    id: Uuid,
+) -> Result<Option<serde_json::Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT archive FROM data_exports
+    WHERE id = $1 AND status = 'ready' AND expires_at > now()
+    "#,
+            &[&id],
+        )
+        .await?;
+    Ok(row.map(|row| row.get("archive")))
+}
+
+#[tracing::instrument(name = "Gathering a data archive", skip_all)]
+pub async fn build_archive<C: GenericClient>(
+    client: &C,
+    email: &str,
+) -> Result<DataArchive, Box<dyn std::error::Error + Send + Sync>> {
+    let address = normalize_email(email);
+    let subscriptions: Vec<ArchivedSubscription> = client
+        .query(
+            r#"
+    SELECT s.id, p.slug, s.name, s.status, s.tags, s.subscribed_at
+    FROM subscriptions s JOIN publications p ON p.id = s.publication_id
+    WHERE lower(btrim(s.email)) = $1
+    ORDER BY s.subscribed_at
+    "#,
+            &[&address],
+        )
+        .await?
+        .iter()
+        .map(|row| ArchivedSubscription {
+            id: row.get("id"),
+            publication: row.get("slug"),
+            name: row.get("name"),
+            status: row.get("status"),
+            tags: row.get("tags"),
+            subscribed_at: row.get("subscribed_at"),
+        })
+        .collect();
+    let deliveries = client
+        .query(
+            r#"
+    SELECT p.slug, d.stream, d.subject, d.outcome, d.attempted_at
+    FROM email_deliveries d
+    LEFT JOIN newsletter_issues i ON i.id = d.newsletter_issue_id
+    LEFT JOIN publications p ON p.id = i.publication_id
+    WHERE lower(btrim(d.recipient_email)) = $1
+    ORDER BY d.attempted_at
+    "#,
+            &[&address],
+        )
+        .await?
+        .iter()
+        .map(|row| ArchivedDelivery {
+            publication: row.get("slug"),
+            stream: row.get("stream"),
+            subject: row.get("subject"),
+            outcome: row.get("outcome"),
+            attempted_at: row.get("attempted_at"),
+        })
+        .collect();
+    let email_events = client
+        .query(
+            r#"
+    SELECT record_type, received_at, payload FROM email_events
+    WHERE lower(btrim(email)) = $1
+    ORDER BY received_at
+    "#,
+            &[&address],
+        )
+        .await?
+        .iter()
+        .map(|row| ArchivedEmailEvent {
+            record_type: row.get("record_type"),
+            received_at: row.get("received_at"),
+            payload: row.get("payload"),
+        })
+        .collect();
+    let mut targets: Vec<String> = subscriptions
+        .iter()
+        .map(|subscription| format!("subscriber:{}", subscription.id))
+        .collect();
+    targets.push(format!("email:{}", email_hash(&address)));
+    let changes = client
+        .query(
+            r#"
+    SELECT action, occurred_at, details FROM audit_log
+    WHERE target = ANY($1)
+    ORDER BY id
+    "#,
+            &[&targets],
+        )
+        .await?
+        .iter()
+        .map(|row| ArchivedChange {
+            action: row.get("action"),
+            occurred_at: row.get("occurred_at"),
+            details: row.get("details"),
+        })
+        .collect();
+
+    Ok(DataArchive {
+        suppressions: suppressions_of_address(client, &address).await?,
+        consent_events: consent_events_for_address(client, &address).await?,
+        email: address,
+        generated_at: Utc::now(),
+        subscriptions,
+        deliveries,
+        email_events,
+        changes,
+    })
+}
+
+/// How the worker lets subscribers know their archive is ready.
+pub struct ArchiveNotifier {
+    pub signer: LinkSigner,
+    /// Where users reach us.
+    pub base_url: String,
+    /// The sender identity the emails go out as.
+    pub sender: String,
+}
+
+/// Put archives together forever, one at a time, and delete expired ones.
+pub async fn worker_loop(pool: Pool, notifier: ArchiveNotifier) {
+    loop {
+        match try_export_next(&pool, &notifier).await {
+            Ok(true) => {}
+            Ok(false) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Err(e) => {
+                tracing::error!("Failed to export subscriber data: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// The archive, and the email linking to it, commit together. Returns
+/// `false` if there was nothing to export.
+pub async fn try_export_next(
+    pool: &Pool,
+    notifier: &ArchiveNotifier,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let row = transaction
+        .query_opt(
+            r#"
+    SELECT id, email FROM data_exports
+    WHERE status = 'pending'
+    ORDER BY requested_at
+    FOR UPDATE SKIP LOCKED
+    LIMIT 1
+    "#,
+            &[],
+        )
+        .await?;
+    let Some(row) = row else {
+        transaction
+            .execute("DELETE FROM data_exports WHERE expires_at < now()", &[])
+            .await?;
+        transaction.commit().await?;
+        return Ok(false);
+    };
+    let id: Uuid = row.get("id");
+    let recipient = SubscriberEmail::parse(row.get("email"))?;
+
+    let archive = build_archive(&transaction, recipient.as_ref()).await?;
+    let expires_at = Utc::now() + chrono::Duration::days(ARCHIVE_TTL_DAYS);
+    transaction
+        .execute(
+            r#"
+    UPDATE data_exports
+    SET status = 'ready', archive = $2, ready_at = now(), expires_at = $3
+    WHERE id = $1
+    "#,
+            &[&id, &serde_json::to_value(&archive)?, &expires_at],
+        )
+        .await?;
+
+    let token = notifier
+        .signer
+        .sign(ARCHIVE_LINK_PURPOSE, &id.to_string(), expires_at);
+    let link = format!("{}/data_export/download/{}", notifier.base_url, token);
+    enqueue_transactional_email(
+        &transaction,
+        &notifier.sender,
+        &recipient,
+        "Your data is ready to download",
+        &format!(
+            "Hi,<br />The archive of everything we hold about you is ready. \
+             <a href=\"{}\">Download it</a> within {} days, after which it is deleted.",
+            encode_attribute(&link),
+            ARCHIVE_TTL_DAYS
+        ),
+        &format!(
+            "Hi,\nThe archive of everything we hold about you is ready. \
+             Download it from {} within {} days, after which it is deleted.",
+            link, ARCHIVE_TTL_DAYS
+        ),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(true)
+}
@@ -0,0 +1,239 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use deadpool_postgres::{GenericClient, Pool};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::domain::{SubscriberEmail, SubscriptionStatus};
+use crate::email_client::{EmailClient, MessageStream, Recipient, SendOutcome};
+use crate::publication::PublicationId;
+
+/// Attempts made before an email is given up on.
+const MAX_RETRIES: i16 = 5;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Drain the delivery queue forever.
+pub async fn worker_loop(pool: Pool, email_client: Arc<EmailClient>) {
+    loop {
+        match try_execute_task(&pool, &email_client).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to execute delivery task: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(task_id = tracing::field::Empty, stream = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &Pool,
+    email_client: &EmailClient,
+) -> Result<ExecutionOutcome, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            r#"
+    SELECT q.id, q.recipient_email, q.stream, q.n_retries, q.newsletter_issue_id,
+           i.publication_id,
+           COALESCE(q.sender, i.sender) AS sender,
+           COALESCE(q.subject, i.title) AS subject,
+           COALESCE(q.html_content, i.html_content) AS html_content,
+           COALESCE(q.text_content, i.text_content) AS text_content
+    FROM email_delivery_queue q
+    LEFT JOIN newsletter_issues i ON i.id = q.newsletter_issue_id
+    WHERE q.execute_after <= now()
+    ORDER BY q.priority DESC, q.execute_after
+    FOR UPDATE OF q SKIP LOCKED
+    LIMIT 1
+    "#,
+            &[],
+        )
+        .await?;
+    let Some(row) = row else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    let task_id: Uuid = row.get("id");
+    let stream: MessageStream = row.get::<_, String>("stream").try_into()?;
+    let n_retries: i16 = row.get("n_retries");
+    let issue_id: Option<Uuid> = row.get("newsletter_issue_id");
+    let recipient: String = row.get("recipient_email");
+    let subject: String = row.get("subject");
+    Span::current()
+        .record("task_id", display(task_id))
+        .record("stream", display(stream.as_str()));
+
+    let outcome = match SubscriberEmail::parse(recipient.clone()) {
+        Ok(email) => {
+            let sender_name: String = row.get("sender");
+            let sender = email_client.senders().get(&sender_name).unwrap_or_else(|| {
+                tracing::warn!(
+                    sender = %sender_name,
+                    "Unknown sender identity, falling back to the default sender."
+                );
+                email_client.senders().default_sender()
+            });
+            email_client
+                .send_email(
+                    sender,
+                    Recipient {
+                        email,
+                        publication_id: row
+                            .get::<_, Option<Uuid>>("publication_id")
+                            .map(PublicationId),
+                    },
+                    stream,
+                    &subject,
+                    &row.get::<_, String>("html_content"),
+                    &row.get::<_, String>("text_content"),
+                )
+                .await
+        }
+        Err(e) => Err(e.into()),
+    };
+
+    let final_outcome = match outcome {
+        Ok(SendOutcome::Sent) => Some("sent"),
+        Ok(SendOutcome::Suppressed) => Some("suppressed"),
+        Err(e) if n_retries + 1 >= MAX_RETRIES => {
+            tracing::error!("Giving up on email delivery: {:?}", e);
+            Some("failed")
+        }
+        Err(e) => {
+            tracing::warn!("Email delivery failed, will retry: {:?}", e);
+            None
+        }
+    };
+
+    match final_outcome {
+        Some(final_outcome) => {
+            transaction
+                .execute(
+                    "DELETE FROM email_delivery_queue WHERE id = $1",
+                    &[&task_id],
+                )
+                .await?;
+            transaction
+                .execute(
+                    r#"
+    INSERT INTO email_deliveries
+        (id, recipient_email, stream, newsletter_issue_id, subject, outcome, attempted_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    "#,
+                    &[
+                        &Uuid::new_v4(),
+                        &recipient,
+                        &stream.as_str(),
+                        &issue_id,
+                        &subject,
+                        &final_outcome,
+                        &Utc::now(),
+                    ],
+                )
+                .await?;
+        }
+        None => {
+            // Exponential backoff: 30s, 1m, 2m, 4m...
+            let backoff = chrono::Duration::seconds(30 * 2i64.pow(n_retries as u32));
+            transaction
+                .execute(
+                    r#"
+    UPDATE email_delivery_queue
+    SET n_retries = n_retries + 1, execute_after = $2
+    WHERE id = $1
+    "#,
+                    &[&task_id, &(Utc::now() + backoff)],
+                )
+                .await?;
+        }
+    }
+
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Queue a transactional email, sent ahead of any pending broadcast.
+/// `sender` is the name of a configured sender identity.
+#[tracing::instrument(name = "Enqueueing a transactional email", skip_all)]
+pub async fn enqueue_transactional_email<C: GenericClient>(
+    client: &C,
+    sender: &str,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let stream = MessageStream::Transactional;
+    client
+        .execute(
+            r#"
+    INSERT INTO email_delivery_queue
+        (id, recipient_email, stream, priority, sender, subject, html_content, text_content,
+         execute_after, enqueued_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now(), now())
+    "#,
+            &[
+                &Uuid::new_v4(),
+                &recipient.as_ref(),
+                &stream.as_str(),
+                &stream.priority(),
+                &sender,
+                &subject,
+                &html_content,
+                &text_content,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Queue a newsletter issue for every confirmed subscriber of its publication.
+/// Returns the number of recipients.
+#[tracing::instrument(name = "Enqueueing newsletter delivery tasks", skip(client))]
+pub async fn enqueue_newsletter_issue<C: GenericClient>(
+    client: &C,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let stream = MessageStream::Broadcast;
+    let n_recipients = client
+        .execute(
+            r#"
+    INSERT INTO email_delivery_queue
+        (id, recipient_email, stream, priority, newsletter_issue_id, execute_after, enqueued_at)
+    SELECT gen_random_uuid(), s.email, $2, $3, $1, now(), now()
+    FROM subscriptions s
+    JOIN newsletter_issues i ON i.publication_id = s.publication_id
+    WHERE i.id = $1 AND s.status = $4
+    "#,
+            &[
+                &newsletter_issue_id,
+                &stream.as_str(),
+                &stream.priority(),
+                &SubscriptionStatus::Confirmed.as_str(),
+            ],
+        )
+        .await?;
+    client
+        .execute(
+            "UPDATE newsletter_issues SET n_recipients = $2 WHERE id = $1",
+            &[&newsletter_issue_id, &(n_recipients as i32)],
+        )
+        .await?;
+    Ok(n_recipients)
+}
@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+
+use crate::domain::SubscriberEmail;
+
+/// Why an address was turned away on signup. Each has a code for clients
+/// to tell them apart by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailRejection {
+    /// A throwaway inbox provider, see `EmailPolicy`.
+    DisposableDomain,
+    /// On the deny list kept by administrators.
+    DeniedDomain,
+    /// An address for a function rather than a person, e.g. `postmaster@`.
+    RoleAddress,
+}
+
+impl EmailRejection {
+    pub fn code(&self) -> &'static str {
+        match self {
+            EmailRejection::DisposableDomain => "disposable_domain",
+            EmailRejection::DeniedDomain => "denied_domain",
+            EmailRejection::RoleAddress => "role_address",
+        }
+    }
+}
+
+impl std::fmt::Display for EmailRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmailRejection::DisposableDomain => {
+                write!(
+                    f,
+                    "Addresses from disposable email providers are not accepted."
+                )
+            }
+            EmailRejection::DeniedDomain => {
+                write!(f, "Addresses from this domain are not accepted.")
+            }
+            EmailRejection::RoleAddress => write!(
+                f,
+                "Role addresses such as postmaster@ are not accepted, use your own."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmailRejection {}
+
+/// What administrators decided about a domain, overriding the blocklist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainRule {
+    Allow,
+    Deny,
+}
+
+impl DomainRule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainRule::Allow => "allow",
+            DomainRule::Deny => "deny",
+        }
+    }
+}
+
+impl TryFrom<String> for DomainRule {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            other => Err(format!("{} is not a valid domain rule.", other)),
+        }
+    }
+}
+
+/// Which addresses we accept signups from, beyond them being well formed.
+/// A domain covers its subdomains, both on the blocklist and in rules.
+#[derive(Debug, Default)]
+pub struct EmailPolicy {
+    disposable_domains: HashSet<String>,
+    role_addresses: HashSet<String>,
+}
+
+impl EmailPolicy {
+    pub fn new(
+        disposable_domains: impl IntoIterator<Item = String>,
+        role_addresses: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self {
+            disposable_domains: disposable_domains
+                .into_iter()
+                .map(|domain| domain.trim().to_lowercase())
+                .collect(),
+            role_addresses: role_addresses
+                .into_iter()
+                .map(|local_part| local_part.trim().to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// `rule` is the administrators' rule for the address's domain, see
+    /// `email_domain_candidates`. Allowing a domain lets its disposable
+    /// addresses in, not its role addresses.
+    pub fn check(
+        &self,
+        email: &SubscriberEmail,
+        rule: Option<DomainRule>,
+    ) -> Result<(), EmailRejection> {
+        let (local_part, _) = split_address(email.as_ref());
+        // `noreply+newsletter@` is still nobody
+        let local_part = local_part.split('+').next().unwrap_or_default();
+        if self.role_addresses.contains(local_part) {
+            return Err(EmailRejection::RoleAddress);
+        }
+        match rule {
+            Some(DomainRule::Deny) => Err(EmailRejection::DeniedDomain),
+            Some(DomainRule::Allow) => Ok(()),
+            None => {
+                let is_disposable = email_domain_candidates(email)
+                    .iter()
+                    .any(|domain| self.disposable_domains.contains(domain));
+                if is_disposable {
+                    Err(EmailRejection::DisposableDomain)
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+/// The address's domain and the domains above it, most specific first,
+/// stopping short of the top level one.
+pub fn email_domain_candidates(email: &SubscriberEmail) -> Vec<String> {
+    let (_, domain) = split_address(email.as_ref());
+    let mut candidates = Vec::new();
+    let mut rest = domain.as_str();
+    while let Some((_, parent)) = rest.split_once('.') {
+        candidates.push(rest.to_owned());
+        rest = parent;
+    }
+    candidates
+}
+
+/// A domain for a rule or the blocklist, lowercased.
+pub fn parse_email_domain(s: &str) -> Result<String, String> {
+    let domain = s.trim().trim_end_matches('.').to_lowercase();
+    let is_valid = domain.contains('.')
+        && domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if is_valid {
+        Ok(domain)
+    } else {
+        Err(format!("{} is not a valid domain.", s.trim()))
+    }
+}
+
+/// One domain per line, `#` starting a comment.
+pub fn parse_domain_list(text: &str) -> Result<Vec<String>, String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(parse_email_domain)
+        .collect()
+}
+
+fn split_address(email: &str) -> (String, String) {
+    let email = email.trim().to_lowercase();
+    match email.rsplit_once('@') {
+        Some((local_part, domain)) => (local_part.to_owned(), domain.to_owned()),
+        None => (email, String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        email_domain_candidates, parse_domain_list, parse_email_domain, DomainRule, EmailPolicy,
+        EmailRejection,
+    };
+    use crate::domain::SubscriberEmail;
+    use claims::{assert_err, assert_ok};
+
+    fn policy() -> EmailPolicy {
+        EmailPolicy::new(
+            ["mailinator.com".to_owned()],
+            ["noreply".to_owned(), "postmaster".to_owned()],
+        )
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        let policy = policy();
+        assert_ok!(policy.check(&email("ursula@example.com"), None));
+        assert_eq!(
+            policy.check(&email("ursula@Mailinator.com"), None),
+            Err(EmailRejection::DisposableDomain)
+        );
+        assert_eq!(
+            policy.check(&email("ursula@eu.mailinator.com"), None),
+            Err(EmailRejection::DisposableDomain)
+        );
+    }
+
+    #[test]
+    fn role_addresses_are_rejected_whatever_the_domain_rule() {
+        let policy = policy();
+        for address in [
+            "noreply@example.com",
+            "Postmaster@example.com",
+            "noreply+news@example.com",
+        ] {
+            assert_eq!(
+                policy.check(&email(address), Some(DomainRule::Allow)),
+                Err(EmailRejection::RoleAddress)
+            );
+        }
+    }
+
+    #[test]
+    fn administrators_rules_override_the_blocklist() {
+        let policy = policy();
+        assert_ok!(policy.check(&email("ursula@mailinator.com"), Some(DomainRule::Allow)));
+        assert_eq!(
+            policy.check(&email("ursula@example.com"), Some(DomainRule::Deny)),
+            Err(EmailRejection::DeniedDomain)
+        );
+    }
+
+    #[test]
+    fn rejections_have_distinct_codes() {
+        let codes = [
+            EmailRejection::DisposableDomain,
+            EmailRejection::DeniedDomain,
+            EmailRejection::RoleAddress,
+        ]
+        .map(|rejection| rejection.code());
+        assert_eq!(
+            codes.iter().collect::<std::collections::HashSet<_>>().len(),
+            codes.len()
+        );
+    }
+
+    #[test]
+    fn candidates_run_from_the_domain_up_to_before_the_top_level() {
+        assert_eq!(
+            email_domain_candidates(&email("ursula@mail.eu.Example.com")),
+            vec!["mail.eu.example.com", "eu.example.com", "example.com"]
+        );
+    }
+
+    #[test]
+    fn domain_lists_skip_comments_and_reject_nonsense() {
+        assert_eq!(
+            parse_domain_list(
+                "# throwaway inboxes\nMailinator.com\n\n10minutemail.com # and more\n"
+            ),
+            Ok(vec![
+                "mailinator.com".to_owned(),
+                "10minutemail.com".to_owned()
+            ])
+        );
+        assert_err!(parse_email_domain("ursula@example.com"));
+        assert_err!(parse_email_domain("localhost"));
+        assert_err!(parse_email_domain("-bad-.com"));
+    }
+}
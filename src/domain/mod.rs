@@ -1,7 +1,16 @@
+mod email_policy;
 mod new_subscriber;
+mod sender_identity;
+mod subscriber;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use email_policy::{
+    email_domain_candidates, parse_domain_list, parse_email_domain, DomainRule, EmailPolicy,
+    EmailRejection,
+};
 pub use new_subscriber::NewSubscriber;
+pub use sender_identity::SenderIdentity;
+pub use subscriber::{Subscriber, SubscriberEvent, SubscriptionStatus, TransitionError};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -0,0 +1,151 @@
+use base64::Engine;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::SubscriberEmail;
+
+/// A named "From" we send email as, e.g. `Zero To Production <news@example.com>`.
+#[derive(Debug)]
+pub struct SenderIdentity {
+    display_name: String,
+    email: SubscriberEmail,
+    reply_to: Option<SubscriberEmail>,
+}
+
+impl SenderIdentity {
+    /// Returns an instance of `SenderIdentity` if the display name is safe to
+    /// put in a header and every address is valid.
+    pub fn parse(
+        display_name: String,
+        email: String,
+        reply_to: Option<String>,
+    ) -> Result<SenderIdentity, String> {
+        let is_empty_or_whitespace = display_name.trim().is_empty();
+        let is_too_long = display_name.graphemes(true).count() > 78;
+        // Line breaks would let the name inject headers of its own.
+        let contains_control_characters = display_name.chars().any(|c| c.is_control());
+        if is_empty_or_whitespace || is_too_long || contains_control_characters {
+            return Err(format!(
+                "{} is not a valid sender display name.",
+                display_name
+            ));
+        }
+
+        let email = SubscriberEmail::parse(email)?;
+        let reply_to = reply_to.map(SubscriberEmail::parse).transpose()?;
+        Ok(Self {
+            display_name,
+            email,
+            reply_to,
+        })
+    }
+
+    pub fn email(&self) -> &SubscriberEmail {
+        &self.email
+    }
+
+    pub fn reply_to(&self) -> Option<&SubscriberEmail> {
+        self.reply_to.as_ref()
+    }
+
+    /// The identity as an RFC 5322 `name-addr`, ready for the `From` header.
+    ///
+    /// Names made of atoms are used verbatim, names containing specials are
+    /// sent as a quoted-string and non-ASCII names as an RFC 2047 encoded-word.
+    pub fn mailbox(&self) -> String {
+        let name = &self.display_name;
+        let phrase = if !name.is_ascii() {
+            format!(
+                "=?UTF-8?B?{}?=",
+                base64::engine::general_purpose::STANDARD.encode(name)
+            )
+        } else if name.split(' ').all(is_atom) {
+            name.to_owned()
+        } else {
+            format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+        };
+        format!("{} <{}>", phrase, self.email.as_ref())
+    }
+}
+
+/// `atext` from RFC 5322, section 3.2.3.
+fn is_atom(word: &str) -> bool {
+    !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SenderIdentity;
+    use claims::{assert_err, assert_ok};
+
+    fn identity(display_name: &str) -> SenderIdentity {
+        SenderIdentity::parse(display_name.into(), "news@example.com".into(), None).unwrap()
+    }
+
+    #[test]
+    fn atom_only_names_are_used_verbatim() {
+        assert_eq!(
+            identity("Zero To Production").mailbox(),
+            "Zero To Production <news@example.com>"
+        );
+    }
+
+    #[test]
+    fn names_with_specials_are_quoted_and_escaped() {
+        assert_eq!(
+            identity("Le Guin, Ursula").mailbox(),
+            r#""Le Guin, Ursula" <news@example.com>"#
+        );
+        assert_eq!(
+            identity(r#"The "Weekly" \ Digest"#).mailbox(),
+            r#""The \"Weekly\" \\ Digest" <news@example.com>"#
+        );
+    }
+
+    #[test]
+    fn non_ascii_names_are_encoded() {
+        assert_eq!(
+            identity("Café").mailbox(),
+            "=?UTF-8?B?Q2Fmw6k=?= <news@example.com>"
+        );
+    }
+
+    #[test]
+    fn names_with_line_breaks_are_rejected() {
+        assert_err!(SenderIdentity::parse(
+            "News\r\nBcc: everyone@example.com".into(),
+            "news@example.com".into(),
+            None
+        ));
+    }
+
+    #[test]
+    fn empty_names_are_rejected() {
+        assert_err!(SenderIdentity::parse(
+            " ".into(),
+            "news@example.com".into(),
+            None
+        ));
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        assert_err!(SenderIdentity::parse(
+            "News".into(),
+            "not-an-email".into(),
+            None
+        ));
+        assert_err!(SenderIdentity::parse(
+            "News".into(),
+            "news@example.com".into(),
+            Some("not-an-email".into())
+        ));
+        assert_ok!(SenderIdentity::parse(
+            "News".into(),
+            "news@example.com".into(),
+            Some("editor@example.com".into())
+        ));
+    }
+}
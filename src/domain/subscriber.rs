@@ -0,0 +1,334 @@
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::publication::PublicationId;
+
+/// Where a subscription stands. Only confirmed subscriptions get newsletters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    Pending,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+    /// Never stored: the subscription is deleted.
+    Erased,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::Pending => "pending",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+            SubscriptionStatus::Bounced => "bounced",
+            SubscriptionStatus::Complained => "complained",
+            SubscriptionStatus::Erased => "erased",
+        }
+    }
+}
+
+impl TryFrom<String> for SubscriptionStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "pending" => Ok(Self::Pending),
+            "confirmed" => Ok(Self::Confirmed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            "bounced" => Ok(Self::Bounced),
+            "complained" => Ok(Self::Complained),
+            "erased" => Ok(Self::Erased),
+            other => Err(format!("{} is not a valid subscription status.", other)),
+        }
+    }
+}
+
+/// What happened to a subscription, for whoever stores it to act upon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriberEvent {
+    Subscribed,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+    Erased,
+}
+
+/// A change the subscription's status does not allow.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TransitionError {
+    pub from: SubscriptionStatus,
+    pub to: SubscriptionStatus,
+}
+
+impl std::fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.from {
+            SubscriptionStatus::Unsubscribed | SubscriptionStatus::Complained => write!(
+                f,
+                "The subscriber is {}: only they can subscribe again.",
+                self.from.as_str()
+            ),
+            _ => write!(
+                f,
+                "A subscriber cannot go from {} to {}.",
+                self.from.as_str(),
+                self.to.as_str()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+/// The lifecycle of a subscription. Its status only changes through the
+/// methods below, each of them recording an event; a change to the status
+/// it already has is allowed and records nothing, since providers and
+/// administrators repeat themselves.
+#[derive(Debug)]
+pub struct Subscriber {
+    id: Uuid,
+    publication_id: PublicationId,
+    email: String,
+    status: SubscriptionStatus,
+    events: Vec<SubscriberEvent>,
+}
+
+impl Subscriber {
+    /// A new subscription, pending until confirmed.
+    pub fn subscribe(publication_id: PublicationId, email: &SubscriberEmail) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            publication_id,
+            email: email.as_ref().to_owned(),
+            status: SubscriptionStatus::Pending,
+            events: vec![SubscriberEvent::Subscribed],
+        }
+    }
+
+    /// A subscription as stored.
+    pub fn restore(
+        id: Uuid,
+        publication_id: PublicationId,
+        email: String,
+        status: SubscriptionStatus,
+    ) -> Self {
+        Self {
+            id,
+            publication_id,
+            email,
+            status,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn publication_id(&self) -> PublicationId {
+        self.publication_id
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn status(&self) -> SubscriptionStatus {
+        self.status
+    }
+
+    /// A bounced address can be confirmed again once it works; people who
+    /// left have to come back themselves, see `resubscribe`.
+    pub fn confirm(&mut self) -> Result<(), TransitionError> {
+        use SubscriptionStatus::*;
+        match self.status {
+            Pending | Bounced => self.change(Confirmed, SubscriberEvent::Confirmed),
+            Confirmed => Ok(()),
+            Unsubscribed | Complained | Erased => Err(self.refuse(Confirmed)),
+        }
+    }
+
+    /// A fresh opt-in by the subscriber, pending until confirmed.
+    pub fn resubscribe(&mut self) -> Result<(), TransitionError> {
+        use SubscriptionStatus::*;
+        match self.status {
+            Unsubscribed | Bounced | Complained => {
+                self.change(Pending, SubscriberEvent::Subscribed)
+            }
+            Pending | Confirmed => Ok(()),
+            Erased => Err(self.refuse(Pending)),
+        }
+    }
+
+    pub fn unsubscribe(&mut self) -> Result<(), TransitionError> {
+        use SubscriptionStatus::*;
+        match self.status {
+            Pending | Confirmed | Bounced => {
+                self.change(Unsubscribed, SubscriberEvent::Unsubscribed)
+            }
+            // a complaint stopped the emails already, and says more
+            Unsubscribed | Complained => Ok(()),
+            Erased => Err(self.refuse(Unsubscribed)),
+        }
+    }
+
+    /// Bounces of emails to people who left do not change why they left.
+    pub fn bounce(&mut self) -> Result<(), TransitionError> {
+        use SubscriptionStatus::*;
+        match self.status {
+            Pending | Confirmed => self.change(Bounced, SubscriberEvent::Bounced),
+            Bounced | Unsubscribed | Complained => Ok(()),
+            Erased => Err(self.refuse(Bounced)),
+        }
+    }
+
+    pub fn complain(&mut self) -> Result<(), TransitionError> {
+        use SubscriptionStatus::*;
+        match self.status {
+            Pending | Confirmed | Unsubscribed | Bounced => {
+                self.change(Complained, SubscriberEvent::Complained)
+            }
+            Complained => Ok(()),
+            Erased => Err(self.refuse(Complained)),
+        }
+    }
+
+    pub fn erase(&mut self) {
+        if self.status != SubscriptionStatus::Erased {
+            self.status = SubscriptionStatus::Erased;
+            self.events.push(SubscriberEvent::Erased);
+        }
+    }
+
+    /// The events since the subscription was created, restored or last
+    /// drained, oldest first.
+    pub fn take_events(&mut self) -> Vec<SubscriberEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn change(
+        &mut self,
+        status: SubscriptionStatus,
+        event: SubscriberEvent,
+    ) -> Result<(), TransitionError> {
+        self.status = status;
+        self.events.push(event);
+        Ok(())
+    }
+
+    fn refuse(&self, to: SubscriptionStatus) -> TransitionError {
+        TransitionError {
+            from: self.status,
+            to,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Subscriber, SubscriberEvent, SubscriptionStatus};
+    use crate::domain::SubscriberEmail;
+    use crate::publication::PublicationId;
+    use claims::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    fn subscriber(status: SubscriptionStatus) -> Subscriber {
+        Subscriber::restore(
+            Uuid::new_v4(),
+            PublicationId(Uuid::new_v4()),
+            "ursula@example.com".into(),
+            status,
+        )
+    }
+
+    #[test]
+    fn a_new_subscription_is_pending_until_confirmed() {
+        let email = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+        let mut subscriber = Subscriber::subscribe(PublicationId(Uuid::new_v4()), &email);
+        assert_eq!(subscriber.status(), SubscriptionStatus::Pending);
+
+        assert_ok!(subscriber.confirm());
+        assert_eq!(subscriber.status(), SubscriptionStatus::Confirmed);
+        assert_eq!(
+            subscriber.take_events(),
+            vec![SubscriberEvent::Subscribed, SubscriberEvent::Confirmed]
+        );
+        assert!(subscriber.take_events().is_empty());
+    }
+
+    #[test]
+    fn people_who_left_cannot_be_confirmed_back() {
+        for status in [
+            SubscriptionStatus::Unsubscribed,
+            SubscriptionStatus::Complained,
+            SubscriptionStatus::Erased,
+        ] {
+            let mut subscriber = subscriber(status);
+            assert_err!(subscriber.confirm());
+            assert_eq!(subscriber.status(), status);
+            assert!(subscriber.take_events().is_empty());
+        }
+    }
+
+    #[test]
+    fn people_who_left_can_subscribe_again() {
+        let mut subscriber = subscriber(SubscriptionStatus::Complained);
+        assert_ok!(subscriber.resubscribe());
+        assert_ok!(subscriber.confirm());
+        assert_eq!(subscriber.status(), SubscriptionStatus::Confirmed);
+        assert_eq!(
+            subscriber.take_events(),
+            vec![SubscriberEvent::Subscribed, SubscriberEvent::Confirmed]
+        );
+    }
+
+    #[test]
+    fn repeated_changes_record_nothing() {
+        let mut subscriber = subscriber(SubscriptionStatus::Unsubscribed);
+        assert_ok!(subscriber.unsubscribe());
+        assert_ok!(subscriber.bounce());
+        assert_eq!(subscriber.status(), SubscriptionStatus::Unsubscribed);
+        assert!(subscriber.take_events().is_empty());
+    }
+
+    #[test]
+    fn a_complaint_overrides_an_unsubscribe_but_not_the_other_way_around() {
+        let mut subscriber = subscriber(SubscriptionStatus::Unsubscribed);
+        assert_ok!(subscriber.complain());
+        assert_ok!(subscriber.unsubscribe());
+        assert_eq!(subscriber.status(), SubscriptionStatus::Complained);
+        assert_eq!(subscriber.take_events(), vec![SubscriberEvent::Complained]);
+    }
+
+    #[test]
+    fn nothing_happens_to_an_erased_subscriber() {
+        let mut subscriber = subscriber(SubscriptionStatus::Confirmed);
+        subscriber.erase();
+        subscriber.erase();
+        assert_err!(subscriber.resubscribe());
+        assert_err!(subscriber.unsubscribe());
+        assert_err!(subscriber.bounce());
+        assert_err!(subscriber.complain());
+        assert_eq!(subscriber.take_events(), vec![SubscriberEvent::Erased]);
+    }
+
+    #[test]
+    fn statuses_round_trip() {
+        for status in [
+            SubscriptionStatus::Pending,
+            SubscriptionStatus::Confirmed,
+            SubscriptionStatus::Unsubscribed,
+            SubscriptionStatus::Bounced,
+            SubscriptionStatus::Complained,
+            SubscriptionStatus::Erased,
+        ] {
+            assert_eq!(
+                SubscriptionStatus::try_from(status.as_str().to_owned()),
+                Ok(status)
+            );
+        }
+    }
+}
@@ -1,62 +1,280 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use deadpool_postgres::Pool;
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
 
-use crate::domain::SubscriberEmail;
+use crate::configuration::MessageStreamSettings;
+use crate::domain::{SenderIdentity, SubscriberEmail};
+use crate::outbox::{CapturedEmail, Outbox};
+use crate::publication::PublicationId;
+use crate::suppression::{find_suppression, Suppression, SuppressionReason};
 
 pub struct EmailClient {
-    http_client: Client,
-    base_url: String,
-    sender: SubscriberEmail,
-    authorization_token: Secret<String>,
+    transport: Transport,
+    senders: SenderIdentities,
+    suppression_list: Option<Pool>,
+}
+
+/// The identities we may send email as, indexed by name.
+#[derive(Debug)]
+pub struct SenderIdentities {
+    identities: HashMap<String, SenderIdentity>,
+    default: String,
+    transactional: HashMap<String, String>,
+}
+
+impl SenderIdentities {
+    /// Fails if `default` or any transactional mapping names an unknown identity.
+    pub fn new(
+        identities: HashMap<String, SenderIdentity>,
+        default: String,
+        transactional: HashMap<String, String>,
+    ) -> Result<Self, String> {
+        for name in std::iter::once(&default).chain(transactional.values()) {
+            if !identities.contains_key(name) {
+                return Err(format!("{} is not a configured sender identity.", name));
+            }
+        }
+        Ok(Self {
+            identities,
+            default,
+            transactional,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SenderIdentity> {
+        self.identities.get(name)
+    }
+
+    pub fn default_name(&self) -> &str {
+        &self.default
+    }
+
+    /// The names of all identities, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.identities.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn default_sender(&self) -> &SenderIdentity {
+        &self.identities[&self.default]
+    }
+
+    /// The identity a kind of transactional email, e.g. `password_reset`, is sent as.
+    pub fn transactional(&self, kind: &str) -> &SenderIdentity {
+        &self.identities[self.transactional_name(kind)]
+    }
+
+    /// The name of that identity, for emails going through the delivery queue.
+    pub fn transactional_name(&self, kind: &str) -> &str {
+        self.transactional.get(kind).unwrap_or(&self.default)
+    }
+}
+
+/// Transactional emails (confirmations, password resets, ...) and broadcasts
+/// (newsletter issues) are sent through separate streams, so that complaints
+/// about bulk mail cannot hurt the deliverability of transactional mail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStream {
+    Transactional,
+    Broadcast,
+}
+
+impl MessageStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageStream::Transactional => "transactional",
+            MessageStream::Broadcast => "broadcast",
+        }
+    }
+
+    /// Queued transactional mail is delivered before any broadcast.
+    pub fn priority(&self) -> i16 {
+        match self {
+            MessageStream::Transactional => 1,
+            MessageStream::Broadcast => 0,
+        }
+    }
+}
+
+impl TryFrom<String> for MessageStream {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "transactional" => Ok(Self::Transactional),
+            "broadcast" => Ok(Self::Broadcast),
+            other => Err(format!("{} is not a valid message stream.", other)),
+        }
+    }
+}
+
+/// Who an email goes to, and for which publication: the publication's own
+/// suppressions apply on top of those of every publication.
+pub struct Recipient {
+    pub email: SubscriberEmail,
+    /// `None` for emails belonging to no publication, e.g. password resets.
+    pub publication_id: Option<PublicationId>,
+}
+
+impl From<SubscriberEmail> for Recipient {
+    fn from(email: SubscriberEmail) -> Self {
+        Self {
+            email,
+            publication_id: None,
+        }
+    }
+}
+
+/// What happened to an email handed to `EmailClient::send_email`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOutcome {
+    Sent,
+    /// The recipient is on the suppression list: nothing was sent.
+    Suppressed,
+}
+
+enum Transport {
+    Postmark {
+        http_client: Client,
+        base_url: String,
+        authorization_token: Secret<String>,
+        message_streams: MessageStreamSettings,
+    },
+    /// Keep emails in an `Outbox` instead of sending them, for local development.
+    Capture(Outbox),
 }
 
 impl EmailClient {
     pub fn new(
         base_url: String,
-        sender: SubscriberEmail,
+        senders: SenderIdentities,
+        message_streams: MessageStreamSettings,
         authorization_token: Secret<String>,
         timeout: std::time::Duration,
     ) -> Self {
         let http_client = Client::builder().timeout(timeout).build().unwrap();
 
         Self {
-            http_client,
-            base_url,
-            sender,
-            authorization_token,
+            transport: Transport::Postmark {
+                http_client,
+                base_url,
+                authorization_token,
+                message_streams,
+            },
+            senders,
+            suppression_list: None,
         }
     }
 
+    /// An `EmailClient` that stores every email in `outbox` rather than sending it.
+    pub fn capture(senders: SenderIdentities, outbox: Outbox) -> Self {
+        Self {
+            transport: Transport::Capture(outbox),
+            senders,
+            suppression_list: None,
+        }
+    }
+
+    /// Check every recipient against the suppression list stored in `pool`
+    /// before sending, silently skipping suppressed addresses. Unsubscribed
+    /// addresses only skip broadcasts.
+    pub fn with_suppression_list(mut self, pool: Pool) -> Self {
+        self.suppression_list = Some(pool);
+        self
+    }
+
+    pub fn senders(&self) -> &SenderIdentities {
+        &self.senders
+    }
+
     pub async fn send_email(
         &self,
-        recipient: SubscriberEmail,
+        sender: &SenderIdentity,
+        recipient: impl Into<Recipient>,
+        stream: MessageStream,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/email", self.base_url);
+    ) -> Result<SendOutcome, Box<dyn std::error::Error + Send + Sync>> {
+        let Recipient {
+            email: recipient,
+            publication_id,
+        } = recipient.into();
+        if let Some(pool) = &self.suppression_list {
+            let client = pool.get().await?;
+            // links people ask for, e.g. to erase their data, still reach them
+            let applies = |suppression: &Suppression| {
+                stream == MessageStream::Broadcast
+                    || suppression.reason != SuppressionReason::Unsubscribe
+            };
+            if let Some(suppression) = find_suppression(&client, recipient.as_ref(), publication_id)
+                .await?
+                .filter(applies)
+            {
+                tracing::info!(
+                    reason = suppression.reason.as_str(),
+                    "Skipping email to a suppressed address."
+                );
+                return Ok(SendOutcome::Suppressed);
+            }
+        }
+
+        let (http_client, base_url, authorization_token, message_streams) = match &self.transport {
+            Transport::Postmark {
+                http_client,
+                base_url,
+                authorization_token,
+                message_streams,
+            } => (http_client, base_url, authorization_token, message_streams),
+            Transport::Capture(outbox) => {
+                let email = CapturedEmail {
+                    id: Uuid::new_v4(),
+                    captured_at: Utc::now(),
+                    from: sender.mailbox(),
+                    reply_to: sender.reply_to().map(|r| r.as_ref().to_owned()),
+                    to: recipient.as_ref().to_owned(),
+                    message_stream: Some(stream),
+                    subject: subject.to_owned(),
+                    html_body: html_content.to_owned(),
+                    text_body: text_content.to_owned(),
+                };
+                tracing::info!(email_id = %email.id, "Captured email in the development outbox.");
+                outbox.store(email).await?;
+                return Ok(SendOutcome::Sent);
+            }
+        };
 
+        let url = format!("{}/email", base_url);
+
+        let from = sender.mailbox();
         let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
+            from: &from,
+            reply_to: sender.reply_to().map(|r| r.as_ref()),
             to: recipient.as_ref(),
             subject,
             html_body: html_content,
             text_body: text_content,
+            message_stream: message_streams.id(stream),
         };
 
-        let _builder = self
-            .http_client
+        let _builder = http_client
             .post(&url)
             .header(
                 "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
+                authorization_token.expose_secret(),
             )
             .json(&request_body)
             .send()
             .await?
             .error_for_status()?;
 
-        Ok(())
+        Ok(SendOutcome::Sent)
     }
 }
 
@@ -64,21 +282,27 @@ impl EmailClient {
 #[serde(rename_all = "PascalCase")]
 struct SendEmailRequest<'a> {
     from: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<&'a str>,
     to: &'a str,
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    message_stream: &'a str,
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::configuration::MessageStreamSettings;
+    use crate::domain::{SenderIdentity, SubscriberEmail};
+    use crate::email_client::{EmailClient, MessageStream, SenderIdentities};
+    use crate::outbox::Outbox;
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
     use secrecy::Secret;
+    use std::collections::HashMap;
     use wiremock::matchers::{any, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -94,6 +318,7 @@ mod tests {
                     && body.get("Subject").is_some()
                     && body.get("HtmlBody").is_some()
                     && body.get("TextBody").is_some()
+                    && body.get("MessageStream").is_some()
             } else {
                 false
             }
@@ -112,10 +337,25 @@ mod tests {
         SubscriberEmail::parse(SafeEmail().fake()).unwrap()
     }
 
+    fn senders() -> SenderIdentities {
+        let identity =
+            SenderIdentity::parse("Newsletter".into(), SafeEmail().fake(), None).unwrap();
+        SenderIdentities::new(
+            HashMap::from([("newsletter".to_string(), identity)]),
+            "newsletter".into(),
+            HashMap::new(),
+        )
+        .unwrap()
+    }
+
     fn email_client(base_url: String) -> EmailClient {
         EmailClient::new(
             base_url,
-            email(),
+            senders(),
+            MessageStreamSettings {
+                transactional: "outbound".into(),
+                broadcast: "broadcast".into(),
+            },
             Secret::new(Faker.fake()),
             std::time::Duration::from_millis(200),
         )
@@ -141,7 +381,14 @@ mod tests {
 
         // act
         let _ = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                email_client.senders().default_sender(),
+                email(),
+                MessageStream::Transactional,
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         // assert
@@ -162,7 +409,14 @@ mod tests {
 
         // act
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                email_client.senders().default_sender(),
+                email(),
+                MessageStream::Transactional,
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         assert_ok!(outcome);
@@ -182,12 +436,48 @@ mod tests {
 
         // act
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                email_client.senders().default_sender(),
+                email(),
+                MessageStream::Transactional,
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         assert_err!(outcome);
     }
 
+    #[tokio::test]
+    async fn send_email_stores_the_email_in_the_outbox_when_capturing() {
+        // arrange
+        let outbox = Outbox::in_memory();
+        let email_client = EmailClient::capture(senders(), outbox.clone());
+        let recipient = email();
+        let subject = subject();
+
+        // act
+        let outcome = email_client
+            .send_email(
+                email_client.senders().default_sender(),
+                SubscriberEmail::parse(recipient.as_ref().to_owned()).unwrap(),
+                MessageStream::Broadcast,
+                &subject,
+                &content(),
+                &content(),
+            )
+            .await;
+
+        // assert
+        assert_ok!(outcome);
+        let captured = outbox.list().await.unwrap();
+        assert_eq!(captured.len(), 1);
+        assert_eq!(captured[0].to, recipient.as_ref());
+        assert_eq!(captured[0].subject, subject);
+        assert_eq!(captured[0].message_stream, Some(MessageStream::Broadcast));
+    }
+
     #[tokio::test]
     async fn send_email_times_out_if_the_server_takes_too_long() {
         // arrange
@@ -203,9 +493,67 @@ mod tests {
 
         // act
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                email_client.senders().default_sender(),
+                email(),
+                MessageStream::Transactional,
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         assert_err!(outcome);
     }
+
+    #[test]
+    fn sender_identities_must_reference_configured_identities() {
+        let identities = || {
+            HashMap::from([(
+                "newsletter".to_string(),
+                SenderIdentity::parse("Newsletter".into(), SafeEmail().fake(), None).unwrap(),
+            )])
+        };
+
+        assert_err!(SenderIdentities::new(
+            identities(),
+            "accounts".into(),
+            HashMap::new()
+        ));
+        assert_err!(SenderIdentities::new(
+            identities(),
+            "newsletter".into(),
+            HashMap::from([("password_reset".to_string(), "accounts".to_string())])
+        ));
+    }
+
+    #[test]
+    fn transactional_emails_fall_back_to_the_default_sender() {
+        let senders = SenderIdentities::new(
+            HashMap::from([
+                (
+                    "newsletter".to_string(),
+                    SenderIdentity::parse("Newsletter".into(), "news@example.com".into(), None)
+                        .unwrap(),
+                ),
+                (
+                    "accounts".to_string(),
+                    SenderIdentity::parse("Accounts".into(), "accounts@example.com".into(), None)
+                        .unwrap(),
+                ),
+            ]),
+            "newsletter".into(),
+            HashMap::from([("password_reset".to_string(), "accounts".to_string())]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            senders.transactional("password_reset").email().as_ref(),
+            "accounts@example.com"
+        );
+        assert_eq!(
+            senders.transactional("confirmation").email().as_ref(),
+            "news@example.com"
+        );
+    }
 }
@@ -0,0 +1,98 @@
+//! The allow and deny lists of email domains kept by administrators, see
+//! `EmailPolicy`.
+
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+
+use crate::domain::{email_domain_candidates, DomainRule, SubscriberEmail};
+
+#[derive(Debug, serde::Serialize)]
+pub struct EmailDomainRule {
+    pub domain: String,
+    pub rule: DomainRule,
+    pub created_at: DateTime<Utc>,
+}
+
+fn from_row(
+    row: &tokio_postgres::Row,
+) -> Result<EmailDomainRule, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(EmailDomainRule {
+        domain: row.get("domain"),
+        rule: DomainRule::try_from(row.get::<_, String>("rule"))?,
+        created_at: row.get("created_at"),
+    })
+}
+
+/// The rule for the address's domain, or for the closest domain above it
+/// that has one.
+#[tracing::instrument(name = "Looking up the email domain rules", skip(client))]
+pub async fn find_domain_rule<C: GenericClient>(
+    client: &C,
+    email: &SubscriberEmail,
+) -> Result<Option<DomainRule>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT rule FROM email_domain_rules
+    WHERE domain = ANY($1)
+    ORDER BY length(domain) DESC
+    LIMIT 1
+    "#,
+            &[&email_domain_candidates(email)],
+        )
+        .await?;
+    row.map(|row| DomainRule::try_from(row.get::<_, String>("rule")))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// Replaces any rule the domain had. `domain` is already parsed, see
+/// `parse_email_domain`.
+#[tracing::instrument(name = "Setting an email domain rule", skip(client))]
+pub async fn set_domain_rule<C: GenericClient>(
+    client: &C,
+    domain: &str,
+    rule: DomainRule,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            r#"
+    INSERT INTO email_domain_rules (domain, rule, created_at)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (domain) DO UPDATE SET rule = EXCLUDED.rule, created_at = EXCLUDED.created_at
+    "#,
+            &[&domain, &rule.as_str(), &Utc::now()],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Returns `false` if the domain had no rule.
+#[tracing::instrument(name = "Removing an email domain rule", skip(client))]
+pub async fn delete_domain_rule<C: GenericClient>(
+    client: &C,
+    domain: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let deleted = client
+        .execute(
+            "DELETE FROM email_domain_rules WHERE domain = $1",
+            &[&domain.trim().to_lowercase()],
+        )
+        .await?;
+    Ok(deleted > 0)
+}
+
+#[tracing::instrument(name = "Listing the email domain rules", skip(client))]
+pub async fn list_domain_rules<C: GenericClient>(
+    client: &C,
+) -> Result<Vec<EmailDomainRule>, Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .query(
+            "SELECT domain, rule, created_at FROM email_domain_rules ORDER BY domain",
+            &[],
+        )
+        .await?
+        .iter()
+        .map(from_row)
+        .collect()
+}
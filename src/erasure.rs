@@ -0,0 +1,134 @@
+//! The right to erasure: what we hold about an address is deleted, or
+//! anonymized where the record itself is still needed, and only a hash of
+//! the address is kept on the suppression list so that it is not imported
+//! again.
+
+use deadpool_postgres::GenericClient;
+use uuid::Uuid;
+
+use crate::consent::ConsentContext;
+use crate::publication::PublicationId;
+use crate::subscribers::{
+    is_subscribed_anywhere, load_subscriptions_of_address, save_subscription,
+};
+use crate::suppression::{email_hash, normalize_email, suppress, SuppressionReason};
+
+/// How long an emailed erasure link stays valid.
+pub const ERASURE_LINK_TTL_HOURS: i64 = 24;
+
+/// The purpose erasure links are signed for.
+pub const ERASURE_LINK_PURPOSE: &str = "erasure";
+
+pub enum ErasureScope {
+    /// An administrator erases a subscriber of their publication; records
+    /// shared with other publications go once the address is gone from all.
+    Publication(PublicationId),
+    /// The subscriber erases themselves.
+    Everywhere,
+}
+
+/// What was erased, for the audit log.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Erasure {
+    pub subscriptions: u64,
+    /// Emails waiting to be sent, dropped.
+    pub queued_emails: u64,
+    /// Delivery records, kept for issue statistics with a hashed recipient.
+    pub deliveries: u64,
+    /// Provider webhook events, kept with a hashed address and no payload.
+    pub email_events: u64,
+    /// Consent records, kept with a hashed address and no IP or user agent.
+    pub consent_events: u64,
+    /// Archives the subscriber asked for, see `data_export`.
+    pub data_exports: u64,
+}
+
+/// Run in a transaction, with the audit entry.
+#[tracing::instrument(name = "Erasing a subscriber", skip_all)]
+pub async fn erase_address<C: GenericClient>(
+    client: &C,
+    email: &str,
+    scope: ErasureScope,
+) -> Result<Erasure, Box<dyn std::error::Error + Send + Sync>> {
+    let address = normalize_email(email);
+    let hash = email_hash(email);
+    let publication_id: Option<Uuid> = match scope {
+        ErasureScope::Publication(publication_id) => Some(publication_id.0),
+        ErasureScope::Everywhere => None,
+    };
+
+    let scope = publication_id.map(PublicationId);
+    let subscriptions = load_subscriptions_of_address(client, &address, scope).await?;
+    let mut erasure = Erasure {
+        subscriptions: subscriptions.len() as u64,
+        ..Default::default()
+    };
+    // an erasure is no consent record, the audit log has it
+    let consent = ConsentContext::new("erasure");
+    for mut subscription in subscriptions {
+        subscription.erase();
+        save_subscription(client, &mut subscription, &consent).await?;
+    }
+    let is_gone = !is_subscribed_anywhere(client, &address).await?;
+    // transactional emails belong to no publication: they go with the
+    // address's last subscription
+    let publication_id = if is_gone { None } else { publication_id };
+
+    erasure.queued_emails = client
+        .execute(
+            r#"
+    DELETE FROM email_delivery_queue
+    WHERE lower(btrim(recipient_email)) = $1
+      AND ($2::uuid IS NULL OR newsletter_issue_id IN (
+          SELECT id FROM newsletter_issues WHERE publication_id = $2
+      ))
+    "#,
+            &[&address, &publication_id],
+        )
+        .await?;
+    erasure.deliveries = client
+        .execute(
+            r#"
+    UPDATE email_deliveries SET recipient_email = $3
+    WHERE lower(btrim(recipient_email)) = $1
+      AND ($2::uuid IS NULL OR newsletter_issue_id IN (
+          SELECT id FROM newsletter_issues WHERE publication_id = $2
+      ))
+    "#,
+            &[&address, &publication_id, &hash],
+        )
+        .await?;
+    erasure.consent_events = client
+        .execute(
+            r#"
+    UPDATE consent_events SET email = $3, ip = NULL, user_agent = NULL
+    WHERE lower(btrim(email)) = $1 AND ($2::uuid IS NULL OR publication_id = $2)
+    "#,
+            &[&address, &publication_id, &hash],
+        )
+        .await?;
+    if is_gone {
+        erasure.email_events = client
+            .execute(
+                r#"
+    UPDATE email_events SET email = $2, payload = '{}'
+    WHERE lower(btrim(email)) = $1
+    "#,
+                &[&address, &hash],
+            )
+            .await?;
+        erasure.data_exports = client
+            .execute("DELETE FROM data_exports WHERE email = $1", &[&address])
+            .await?;
+        // while other publications still have the address, it must keep
+        // getting their emails
+        suppress(client, &hash, SuppressionReason::Erased, None).await?;
+    }
+    client
+        .execute(
+            "DELETE FROM suppressions WHERE email = $1 AND ($2::uuid IS NULL OR publication_id = $2)",
+            &[&address, &publication_id],
+        )
+        .await?;
+    Ok(erasure)
+}
@@ -1,6 +1,22 @@
+pub mod audit;
+pub mod authentication;
+pub mod bot_protection;
+pub mod cli;
 pub mod configuration;
+pub mod consent;
+pub mod csv;
+pub mod data_export;
+pub mod delivery_worker;
 pub mod domain;
 pub mod email_client;
+pub mod email_domains;
+pub mod erasure;
+pub mod outbox;
+pub mod publication;
+pub mod rate_limit;
 pub mod routes;
 pub mod startup;
+pub mod subscriber_import;
+pub mod subscribers;
+pub mod suppression;
 pub mod telemetry;
@@ -1,14 +1,27 @@
+use clap::Parser;
+use zero2prod::cli::Cli;
 use zero2prod::configuration::get_configuration;
 use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
+    let cli = Cli::parse();
     // Panic if configuration unreadable
     let configuration = get_configuration().expect("Failed to read configuration.");
 
+    if let Some(command) = cli.command {
+        // Commands print their results; keep stdout free of server logs.
+        let subscriber = get_subscriber("zero2prod".into(), "warn".into(), std::io::stderr);
+        init_subscriber(subscriber);
+        return zero2prod::cli::run(command, configuration)
+            .await
+            .map_err(|e| e.to_string().into());
+    }
+
+    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+    init_subscriber(subscriber);
+
     let application = Application::build(configuration).await?;
     application.run_until_stopped().await?;
 
@@ -0,0 +1,143 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::email_client::MessageStream;
+
+/// An email that was captured instead of being handed to a provider.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct CapturedEmail {
+    pub id: Uuid,
+    pub captured_at: DateTime<Utc>,
+    pub from: String,
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    pub to: String,
+    #[serde(default)]
+    pub message_stream: Option<MessageStream>,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+/// Where the capture backend of `EmailClient` stores outgoing emails during
+/// development. Cloning an `Outbox` yields a handle to the same storage.
+#[derive(Clone)]
+pub enum Outbox {
+    Memory(Arc<Mutex<Vec<CapturedEmail>>>),
+    /// One JSON file per email, so captures survive restarts.
+    Directory(PathBuf),
+}
+
+impl Outbox {
+    pub fn in_memory() -> Self {
+        Outbox::Memory(Arc::new(Mutex::new(Vec::new())))
+    }
+
+    pub fn in_directory(path: PathBuf) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&path)?;
+        Ok(Outbox::Directory(path))
+    }
+
+    pub async fn store(
+        &self,
+        email: CapturedEmail,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Outbox::Memory(emails) => emails.lock().unwrap().push(email),
+            Outbox::Directory(path) => {
+                let file = path.join(format!("{}.json", email.id));
+                tokio::fs::write(file, serde_json::to_vec_pretty(&email)?).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// All captured emails, most recent first.
+    pub async fn list(
+        &self,
+    ) -> Result<Vec<CapturedEmail>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut emails = match self {
+            Outbox::Memory(emails) => emails.lock().unwrap().clone(),
+            Outbox::Directory(path) => {
+                let mut emails = Vec::new();
+                let mut entries = tokio::fs::read_dir(path).await?;
+                while let Some(entry) = entries.next_entry().await? {
+                    if entry.path().extension().is_some_and(|e| e == "json") {
+                        let contents = tokio::fs::read(entry.path()).await?;
+                        emails.push(serde_json::from_slice(&contents)?);
+                    }
+                }
+                emails
+            }
+        };
+        emails.sort_by_key(|email| std::cmp::Reverse(email.captured_at));
+        Ok(emails)
+    }
+
+    pub async fn get(
+        &self,
+        id: Uuid,
+    ) -> Result<Option<CapturedEmail>, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Outbox::Memory(emails) => {
+                Ok(emails.lock().unwrap().iter().find(|e| e.id == id).cloned())
+            }
+            Outbox::Directory(path) => {
+                match tokio::fs::read(path.join(format!("{}.json", id))).await {
+                    Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CapturedEmail, Outbox};
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    fn email(minutes_ago: i64) -> CapturedEmail {
+        CapturedEmail {
+            id: Uuid::new_v4(),
+            captured_at: Utc::now() - Duration::minutes(minutes_ago),
+            from: "newsletter@example.com".into(),
+            reply_to: None,
+            to: "ursula@example.com".into(),
+            message_stream: None,
+            subject: "Welcome!".into(),
+            html_body: "<p>Hi</p>".into(),
+            text_body: "Hi".into(),
+        }
+    }
+
+    async fn stores_and_lists_most_recent_first(outbox: Outbox) {
+        let older = email(10);
+        let newer = email(1);
+        outbox.store(older.clone()).await.unwrap();
+        outbox.store(newer.clone()).await.unwrap();
+
+        let listed: Vec<_> = outbox.list().await.unwrap().iter().map(|e| e.id).collect();
+        assert_eq!(listed, vec![newer.id, older.id]);
+
+        assert_eq!(outbox.get(older.id).await.unwrap().unwrap().id, older.id);
+        assert!(outbox.get(Uuid::new_v4()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn the_in_memory_outbox_keeps_captured_emails() {
+        stores_and_lists_most_recent_first(Outbox::in_memory()).await;
+    }
+
+    #[tokio::test]
+    async fn the_directory_outbox_keeps_captured_emails() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        stores_and_lists_most_recent_first(Outbox::in_directory(path.clone()).unwrap()).await;
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+use std::future::{ready, Ready};
+
+use actix_web::dev::Payload;
+use actix_web::error::ErrorForbidden;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::email_client::SenderIdentities;
+
+/// One of the newsletters hosted by this deployment.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Publication {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+    /// Signups posted to `/subscriptions` on this host belong here.
+    pub host: Option<String>,
+    /// Configured sender identities it may send as; any of them if empty.
+    pub senders: Vec<String>,
+    pub default_sender: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Publication {
+    /// The configured identities this publication may send as, sorted.
+    pub fn sender_names<'a>(&'a self, identities: &'a SenderIdentities) -> Vec<&'a str> {
+        if self.senders.is_empty() {
+            return identities.names();
+        }
+        let mut names: Vec<&str> = self
+            .senders
+            .iter()
+            .map(String::as_str)
+            .filter(|name| identities.get(name).is_some())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn may_send_as(&self, identities: &SenderIdentities, name: &str) -> bool {
+        self.sender_names(identities).contains(&name)
+    }
+
+    /// What issues are sent as unless told otherwise: the publication's
+    /// default sender, else the configured one, else any it may send as.
+    pub fn default_sender_name<'a>(&'a self, identities: &'a SenderIdentities) -> &'a str {
+        self.default_sender
+            .as_deref()
+            .into_iter()
+            .chain(std::iter::once(identities.default_name()))
+            .find(|name| self.may_send_as(identities, name))
+            .or_else(|| self.sender_names(identities).first().copied())
+            .unwrap_or(identities.default_name())
+    }
+}
+
+/// The publication an `/admin` request works on, set by
+/// `reject_anonymous_users`: the one picked in the browser session, or the
+/// one the API key belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicationId(pub Uuid);
+
+impl std::fmt::Display for PublicationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromRequest for PublicationId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<PublicationId>()
+                .copied()
+                .ok_or_else(|| {
+                    ErrorForbidden(
+                        "You do not belong to any publication yet: ask an owner to add you.",
+                    )
+                }),
+        )
+    }
+}
+
+/// Slugs end up in URLs: lowercase ASCII letters, digits and inner dashes.
+pub fn parse_slug(slug: &str) -> Result<String, String> {
+    let slug = slug.trim();
+    let is_valid = !slug.is_empty()
+        && slug.len() <= 63
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if is_valid {
+        Ok(slug.to_owned())
+    } else {
+        Err(format!(
+            "{} is not a valid slug: use lowercase letters, digits and dashes.",
+            slug
+        ))
+    }
+}
+
+/// Host names are compared without port, case or trailing dot.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    // IPv6 literals keep their brackets, only a trailing `:port` goes
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    host.trim_end_matches('.').to_lowercase()
+}
+
+const PUBLICATION_COLUMNS: &str = "id, slug, name, host, senders, default_sender, created_at";
+
+fn from_row(row: &Row) -> Publication {
+    Publication {
+        id: row.get("id"),
+        slug: row.get("slug"),
+        name: row.get("name"),
+        host: row.get("host"),
+        senders: row.get("senders"),
+        default_sender: row.get("default_sender"),
+        created_at: row.get("created_at"),
+    }
+}
+
+pub async fn get_publication<C: GenericClient>(
+    client: &C,
+    id: PublicationId,
+) -> Result<Option<Publication>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            &format!(
+                "SELECT {} FROM publications WHERE id = $1",
+                PUBLICATION_COLUMNS
+            ),
+            &[&id.0],
+        )
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+pub async fn find_publication_by_slug<C: GenericClient>(
+    client: &C,
+    slug: &str,
+) -> Result<Option<Publication>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            &format!(
+                "SELECT {} FROM publications WHERE slug = $1",
+                PUBLICATION_COLUMNS
+            ),
+            &[&slug],
+        )
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+pub async fn find_publication_by_host<C: GenericClient>(
+    client: &C,
+    host: &str,
+) -> Result<Option<Publication>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            &format!(
+                "SELECT {} FROM publications WHERE host = $1",
+                PUBLICATION_COLUMNS
+            ),
+            &[&normalize_host(host)],
+        )
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+/// The publications `user_id` belongs to, by name.
+pub async fn list_user_publications<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Vec<Publication>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(
+            r#"
+    SELECT p.id, p.slug, p.name, p.host, p.senders, p.default_sender, p.created_at
+    FROM publications p
+    JOIN publication_members m ON m.publication_id = p.id
+    WHERE m.user_id = $1
+    ORDER BY p.name, p.slug
+    "#,
+            &[&user_id],
+        )
+        .await?;
+    Ok(rows.iter().map(from_row).collect())
+}
+
+pub struct NewPublication<'a> {
+    pub slug: &'a str,
+    pub name: &'a str,
+    pub host: Option<&'a str>,
+    pub senders: &'a [String],
+    pub default_sender: Option<&'a str>,
+}
+
+/// Create a publication and make `created_by` its first member.
+/// Returns `None` if the slug or host is taken.
+#[tracing::instrument(name = "Creating a publication", skip(client, publication))]
+pub async fn create_publication<C: GenericClient>(
+    client: &C,
+    publication: NewPublication<'_>,
+    created_by: Uuid,
+) -> Result<Option<PublicationId>, Box<dyn std::error::Error + Send + Sync>> {
+    let host = publication.host.map(normalize_host);
+    let row = client
+        .query_opt(
+            r#"
+    INSERT INTO publications (id, slug, name, host, senders, default_sender, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6, now())
+    ON CONFLICT DO NOTHING
+    RETURNING id
+    "#,
+            &[
+                &Uuid::new_v4(),
+                &publication.slug,
+                &publication.name,
+                &host,
+                &publication.senders,
+                &publication.default_sender,
+            ],
+        )
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let id = PublicationId(row.get("id"));
+    add_member(client, id, created_by).await?;
+    Ok(Some(id))
+}
+
+/// The users belonging to a publication.
+pub async fn list_member_ids<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+) -> Result<Vec<Uuid>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(
+            "SELECT user_id FROM publication_members WHERE publication_id = $1",
+            &[&publication_id.0],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get("user_id")).collect())
+}
+
+/// Returns `false` if the user already was a member.
+pub async fn add_member<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    user_id: Uuid,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let n_inserted = client
+        .execute(
+            r#"
+    INSERT INTO publication_members (publication_id, user_id, added_at)
+    VALUES ($1, $2, now())
+    ON CONFLICT DO NOTHING
+    "#,
+            &[&publication_id.0, &user_id],
+        )
+        .await?;
+    Ok(n_inserted > 0)
+}
+
+/// Returns `false` if the user was not a member. Their sessions stop
+/// working on the publication.
+pub async fn remove_member<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    user_id: Uuid,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let n_deleted = client
+        .execute(
+            "DELETE FROM publication_members WHERE publication_id = $1 AND user_id = $2",
+            &[&publication_id.0, &user_id],
+        )
+        .await?;
+    Ok(n_deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_host, parse_slug, Publication};
+    use crate::domain::SenderIdentity;
+    use crate::email_client::SenderIdentities;
+    use chrono::Utc;
+    use claims::{assert_err, assert_ok};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn identities() -> SenderIdentities {
+        let identities = ["newsletter", "digest", "accounts"]
+            .into_iter()
+            .map(|name| {
+                let identity =
+                    SenderIdentity::parse(name.into(), format!("{}@example.com", name), None)
+                        .unwrap();
+                (name.to_owned(), identity)
+            })
+            .collect();
+        SenderIdentities::new(identities, "newsletter".into(), HashMap::new()).unwrap()
+    }
+
+    fn publication(senders: &[&str], default_sender: Option<&str>) -> Publication {
+        Publication {
+            id: Uuid::new_v4(),
+            slug: "weekly".into(),
+            name: "Weekly".into(),
+            host: None,
+            senders: senders.iter().map(|s| s.to_string()).collect(),
+            default_sender: default_sender.map(str::to_owned),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn publications_without_senders_may_use_every_identity() {
+        let identities = identities();
+        let publication = publication(&[], None);
+        assert_eq!(
+            publication.sender_names(&identities),
+            vec!["accounts", "digest", "newsletter"]
+        );
+        assert_eq!(publication.default_sender_name(&identities), "newsletter");
+    }
+
+    #[test]
+    fn publications_only_send_as_their_own_identities() {
+        let identities = identities();
+        let publication = publication(&["digest", "gone"], Some("digest"));
+        assert_eq!(publication.sender_names(&identities), vec!["digest"]);
+        assert!(!publication.may_send_as(&identities, "newsletter"));
+        assert!(!publication.may_send_as(&identities, "gone"));
+        assert_eq!(publication.default_sender_name(&identities), "digest");
+
+        let misconfigured = super::Publication {
+            default_sender: Some("newsletter".into()),
+            ..publication
+        };
+        assert_eq!(misconfigured.default_sender_name(&identities), "digest");
+    }
+
+    #[test]
+    fn slugs_are_url_safe() {
+        assert_eq!(assert_ok!(parse_slug(" rust-weekly2 ")), "rust-weekly2");
+        for slug in ["", "Rust", "rust weekly", "-rust", "rust-", "über", "a/b"] {
+            assert_err!(parse_slug(slug));
+        }
+        assert_err!(parse_slug(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn hosts_are_compared_without_port_or_case() {
+        assert_eq!(normalize_host("News.Example.com:8443"), "news.example.com");
+        assert_eq!(normalize_host("news.example.com."), "news.example.com");
+        assert_eq!(normalize_host("127.0.0.1:8000"), "127.0.0.1");
+        assert_eq!(normalize_host("[::1]:8000"), "[::1]");
+        assert_eq!(normalize_host("[::1]"), "[::1]");
+    }
+}
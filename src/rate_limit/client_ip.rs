@@ -0,0 +1,153 @@
+use std::net::IpAddr;
+
+use actix_web::{web, HttpRequest};
+
+/// An address, or a range of them in CIDR notation, e.g. `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_length))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_length))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("{} is not a valid address or CIDR range.", s);
+        let (address, prefix_length) = match s.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (s.as_str(), None),
+        };
+        let address: IpAddr = address.trim().parse().map_err(|_| invalid())?;
+        let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length.trim().parse().map_err(|_| invalid())?,
+            None => max_prefix_length,
+        };
+        if prefix_length > max_prefix_length {
+            return Err(invalid());
+        }
+        Ok(Self {
+            address,
+            prefix_length,
+        })
+    }
+}
+
+/// The proxies we sit behind. Their `X-Forwarded-For` headers are believed,
+/// anybody else's are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(pub Vec<IpNetwork>);
+
+impl TrustedProxies {
+    /// Where `request` came from, once past our proxies.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let peer = request.peer_addr()?.ip();
+        let forwarded_for = request
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|value| value.to_str().ok());
+        Some(self.resolve(peer, forwarded_for))
+    }
+
+    /// Each proxy appends the address it got the request from: walk the
+    /// chain back from us to the first hop that is not ours.
+    fn resolve(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        let is_trusted = |address| self.0.iter().any(|network| network.contains(address));
+        if !is_trusted(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                // spoofed or mangled: the last hop we could read is it
+                break;
+            };
+            client = hop;
+            if !is_trusted(hop) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Where `request` came from, once past the proxies registered with the
+/// app. Without any, the peer it came from.
+pub fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(trusted_proxies) => trusted_proxies.client_ip(request),
+        None => request.peer_addr().map(|address| address.ip()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IpNetwork, TrustedProxies};
+    use claims::assert_err;
+    use std::net::IpAddr;
+
+    fn network(s: &str) -> IpNetwork {
+        IpNetwork::try_from(s.to_owned()).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn networks_match_the_addresses_in_their_range() {
+        assert!(network("10.0.0.0/8").contains(ip("10.1.2.3")));
+        assert!(!network("10.0.0.0/8").contains(ip("11.1.2.3")));
+        assert!(network("192.168.1.7").contains(ip("192.168.1.7")));
+        assert!(!network("192.168.1.7").contains(ip("192.168.1.8")));
+        assert!(network("0.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(network("fd00::/8").contains(ip("fd12::1")));
+        assert!(!network("fd00::/8").contains(ip("10.1.2.3")));
+        assert_err!(IpNetwork::try_from("10.0.0.0/33".to_owned()));
+        assert_err!(IpNetwork::try_from("proxy.internal".to_owned()));
+    }
+
+    #[test]
+    fn forwarded_headers_are_only_believed_from_trusted_proxies() {
+        let proxies = TrustedProxies(vec![network("10.0.0.0/8")]);
+        let header = Some("203.0.113.9");
+        assert_eq!(
+            proxies.resolve(ip("198.51.100.1"), header),
+            ip("198.51.100.1")
+        );
+        assert_eq!(proxies.resolve(ip("10.0.0.2"), header), ip("203.0.113.9"));
+        assert_eq!(proxies.resolve(ip("10.0.0.2"), None), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn the_client_is_the_first_hop_that_is_not_a_proxy() {
+        let proxies = TrustedProxies(vec![network("10.0.0.0/8")]);
+        // the client made up the first entry itself
+        let header = Some("1.2.3.4, 203.0.113.9, 10.0.0.5");
+        assert_eq!(proxies.resolve(ip("10.0.0.2"), header), ip("203.0.113.9"));
+        let header = Some("nonsense, 10.0.0.5");
+        assert_eq!(proxies.resolve(ip("10.0.0.2"), header), ip("10.0.0.5"));
+    }
+}
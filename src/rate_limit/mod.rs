@@ -0,0 +1,216 @@
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::{HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::configuration::{RateLimitBackend, SignupRateLimitSettings, TokenBucketSettings};
+use crate::suppression::email_hash;
+
+mod client_ip;
+mod postgres;
+
+pub use client_ip::{client_ip, IpNetwork, TrustedProxies};
+pub use postgres::{cleanup_loop, PostgresRateLimiter};
+
+/// How often full buckets are dropped from memory, at most.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token buckets, one per key, kept in memory.
+///
+/// Each bucket holds up to `capacity` tokens and regains one every
+/// `refill_interval`; an attempt takes a token or is turned away.
+pub struct RateLimiter {
+    capacity: u32,
+    refill_interval: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    swept_at: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, refill_interval: Duration) -> Self {
+        Self {
+            capacity,
+            refill_interval,
+            buckets: Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                swept_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token for `key`, or learn how long until one is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let capacity = self.capacity as f64;
+        let refill_interval = self.refill_interval.as_secs_f64();
+
+        // Full buckets carry no information; drop them so memory stays
+        // bounded, but not on every attempt: that walks every bucket.
+        if now.saturating_duration_since(buckets.swept_at) >= SWEEP_INTERVAL {
+            buckets.by_key.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+                bucket.tokens + elapsed / refill_interval < capacity
+            });
+            buckets.swept_at = now;
+        }
+
+        let bucket = buckets.by_key.entry(key.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed / refill_interval).min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) * refill_interval,
+            ))
+        }
+    }
+}
+
+/// Token buckets for one kind of attempt, wherever they are kept.
+pub enum TokenBuckets {
+    Memory(RateLimiter),
+    Postgres(PostgresRateLimiter),
+}
+
+impl TokenBuckets {
+    pub fn new(backend: RateLimitBackend, settings: TokenBucketSettings, pool: &Pool) -> Self {
+        match backend {
+            RateLimitBackend::Memory => Self::Memory(RateLimiter::new(
+                settings.capacity,
+                settings.refill_interval(),
+            )),
+            RateLimitBackend::Postgres => Self::Postgres(PostgresRateLimiter::new(
+                pool.clone(),
+                settings.capacity,
+                settings.refill_interval(),
+            )),
+        }
+    }
+
+    /// Take a token for `key`, or learn how long until one is available.
+    pub async fn check(
+        &self,
+        key: &str,
+    ) -> Result<Result<(), Duration>, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            TokenBuckets::Memory(limiter) => Ok(limiter.check(key)),
+            TokenBuckets::Postgres(limiter) => limiter.check(key).await,
+        }
+    }
+}
+
+/// Limits on signups, against people signing others up: per client, and
+/// per address signed up.
+pub struct SignupRateLimits {
+    per_ip: TokenBuckets,
+    per_email: TokenBuckets,
+    trusted_proxies: TrustedProxies,
+}
+
+impl SignupRateLimits {
+    pub fn new(
+        settings: &SignupRateLimitSettings,
+        trusted_proxies: TrustedProxies,
+        pool: &Pool,
+    ) -> Self {
+        Self {
+            per_ip: TokenBuckets::new(settings.backend, settings.per_ip, pool),
+            per_email: TokenBuckets::new(settings.backend, settings.per_email, pool),
+            trusted_proxies,
+        }
+    }
+
+    /// Take a token from both buckets the signup falls in. Addresses are
+    /// only kept as hashes: most are not subscribed.
+    pub async fn check(
+        &self,
+        request: &HttpRequest,
+        email: &str,
+    ) -> Result<Result<(), Duration>, Box<dyn std::error::Error + Send + Sync>> {
+        let ip = self.trusted_proxies.client_ip(request);
+        let ip_key = format!(
+            "signup:ip:{}",
+            ip.map(|ip| ip.to_string()).unwrap_or_default()
+        );
+        if let Err(retry_after) = self.per_ip.check(&ip_key).await? {
+            return Ok(Err(retry_after));
+        }
+        let email_key = format!("signup:email:{}", email_hash(email));
+        self.per_email.check(&email_key).await
+    }
+}
+
+/// The response for a turned away attempt.
+pub fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    // Round up: retrying a little early would only be turned away again
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, seconds.to_string()))
+        .body("Too many attempts, try again later.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, SWEEP_INTERVAL};
+    use claims::{assert_err, assert_ok};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn attempts_beyond_capacity_are_turned_away_until_refilled() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        assert_ok!(limiter.check_at("a", start));
+        assert_ok!(limiter.check_at("a", start));
+        let retry_after = assert_err!(limiter.check_at("a", start));
+        assert_eq!(retry_after, Duration::from_secs(60));
+
+        assert_err!(limiter.check_at("a", start + Duration::from_secs(59)));
+        assert_ok!(limiter.check_at("a", start + Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn keys_have_their_own_buckets() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+        assert_ok!(limiter.check_at("a", now));
+        assert_err!(limiter.check_at("a", now));
+        assert_ok!(limiter.check_at("b", now));
+    }
+
+    #[test]
+    fn full_buckets_are_swept_once_in_a_while() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(1));
+        let start = Instant::now();
+        let buckets = || limiter.buckets.lock().unwrap().by_key.len();
+        assert_ok!(limiter.check_at("a", start));
+        // refilled by now, but kept until the next sweep
+        assert_ok!(limiter.check_at("b", start + Duration::from_secs(2)));
+        assert_eq!(buckets(), 2);
+
+        assert_ok!(limiter.check_at("c", start + SWEEP_INTERVAL));
+        // "a" and "b" are full again and gone, "c" was just drained
+        assert_eq!(buckets(), 1);
+    }
+}
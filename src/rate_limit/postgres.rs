@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use deadpool_postgres::Pool;
+
+/// Token buckets kept in Postgres, so that replicas share them. They work
+/// like `RateLimiter`'s, one atomic statement per attempt.
+pub struct PostgresRateLimiter {
+    pool: Pool,
+    capacity: u32,
+    refill_interval: Duration,
+}
+
+impl PostgresRateLimiter {
+    pub fn new(pool: Pool, capacity: u32, refill_interval: Duration) -> Self {
+        Self {
+            pool,
+            capacity,
+            refill_interval,
+        }
+    }
+
+    /// Take a token for `key`, or learn how long until one is available.
+    pub async fn check(
+        &self,
+        key: &str,
+    ) -> Result<Result<(), Duration>, Box<dyn std::error::Error + Send + Sync>> {
+        let capacity = f64::from(self.capacity);
+        let refill_interval = self.refill_interval.as_secs_f64();
+        let row = self
+            .pool
+            .get()
+            .await?
+            .query_one(
+                r#"
+    INSERT INTO rate_limit_buckets AS bucket (key, tokens, allowed, updated_at, full_at)
+    VALUES ($1, $2::DOUBLE PRECISION - 1, true, now(),
+            now() + make_interval(secs => $3::DOUBLE PRECISION))
+    ON CONFLICT (key) DO UPDATE SET (tokens, allowed, updated_at, full_at) = (
+        SELECT
+            CASE WHEN refilled >= 1 THEN refilled - 1 ELSE refilled END,
+            refilled >= 1,
+            now(),
+            now() + make_interval(
+                secs => ($2 - CASE WHEN refilled >= 1 THEN refilled - 1 ELSE refilled END) * $3
+            )
+        FROM (
+            SELECT LEAST(
+                $2,
+                bucket.tokens
+                    + EXTRACT(EPOCH FROM now() - bucket.updated_at)::DOUBLE PRECISION / $3
+            ) AS refilled
+        ) AS refill
+    )
+    RETURNING tokens, allowed
+    "#,
+                &[&key, &capacity, &refill_interval],
+            )
+            .await?;
+        let tokens: f64 = row.get("tokens");
+        if row.get("allowed") {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(Duration::from_secs_f64(
+                (1.0 - tokens) * refill_interval,
+            )))
+        }
+    }
+}
+
+/// Full buckets carry no information: delete them every few minutes so
+/// that the table stays small.
+pub async fn cleanup_loop(pool: Pool) {
+    loop {
+        if let Err(e) = delete_full_buckets(&pool).await {
+            tracing::error!("Failed to delete full rate limit buckets: {:?}", e);
+        }
+        tokio::time::sleep(Duration::from_secs(300)).await;
+    }
+}
+
+async fn delete_full_buckets(pool: &Pool) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let client = pool.get().await?;
+    Ok(client
+        .execute("DELETE FROM rate_limit_buckets WHERE full_at < now()", &[])
+        .await?)
+}
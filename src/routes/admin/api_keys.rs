@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use htmlescape::{encode_attribute, encode_minimal};
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use super::{csrf_field, redirect_with_flash, render_page};
+use crate::audit::{record, AuditEvent};
+use crate::authentication::permissions::ManageApiKeys;
+use crate::authentication::{
+    create_api_key, list_api_keys, revoke_api_key, ApiKey, Authorized, FlashMessage, Scope, Session,
+};
+use crate::publication::PublicationId;
+
+#[tracing::instrument(name = "Showing API keys", skip_all)]
+pub async fn api_keys_page(
+    _: Authorized<ManageApiKeys>,
+    session: Session,
+    publication_id: PublicationId,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    render_api_keys_page(&pool, &session, publication_id, None).await
+}
+
+/// The form has a `name` field and one checkbox per scope, named after it.
+/// The key acts on the publication being worked on.
+#[tracing::instrument(name = "Creating an API key from the admin UI", skip_all)]
+pub async fn add_api_key(
+    request: HttpRequest,
+    _: Authorized<ManageApiKeys>,
+    session: Session,
+    publication_id: PublicationId,
+    form: web::Form<HashMap<String, String>>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let name = form.get("name").map(|n| n.trim()).unwrap_or_default();
+    if name.is_empty() {
+        let flash = FlashMessage::error("API keys need a name.");
+        return redirect_with_flash(&pool, &session, "/admin/api_keys", flash).await;
+    }
+    let scopes: Vec<Scope> = Scope::ALL
+        .into_iter()
+        .filter(|scope| form.contains_key(scope.as_str()))
+        .collect();
+
+    let audit = AuditEvent::new("api_key.created")
+        .actor(session.user_id)
+        .request(&request);
+    let outcome =
+        create_and_record(&pool, publication_id, name, &scopes, session.user_id, audit).await;
+    match outcome {
+        // The key is shown in the response itself: it must not outlive it.
+        Ok((api_key, secret)) => {
+            let notice = format!(
+                "<p>Created <b>{}</b>. Copy the key now, it will not be shown again:</p>\
+                 <pre>{}</pre>",
+                encode_minimal(&api_key.name),
+                encode_minimal(secret.expose_secret()),
+            );
+            render_api_keys_page(&pool, &session, publication_id, Some(notice)).await
+        }
+        Err(e) => {
+            tracing::error!("Failed to create an API key: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Revoking an API key from the admin UI",
+    skip(request, session, pool)
+)]
+pub async fn revoke_api_key_form(
+    request: HttpRequest,
+    _: Authorized<ManageApiKeys>,
+    session: Session,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    let audit = AuditEvent::new("api_key.revoked")
+        .actor(session.user_id)
+        .request(&request)
+        .target(format!("api_key:{}", id));
+    let flash = match revoke_and_record(&pool, publication_id, id, audit).await {
+        Ok(true) => FlashMessage::info("The API key has been revoked."),
+        Ok(false) => FlashMessage::error("There is no such API key."),
+        Err(e) => {
+            tracing::error!("Failed to revoke an API key: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    redirect_with_flash(&pool, &session, "/admin/api_keys", flash).await
+}
+
+/// The key and its audit entry commit together, or not at all.
+async fn create_and_record(
+    pool: &Pool,
+    publication_id: PublicationId,
+    name: &str,
+    scopes: &[Scope],
+    user_id: Uuid,
+    audit: AuditEvent<'_>,
+) -> Result<(ApiKey, Secret<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let (api_key, secret) =
+        create_api_key(&transaction, publication_id, name, scopes, Some(user_id)).await?;
+    let audit = audit
+        .target(format!("api_key:{}", api_key.id))
+        .details(serde_json::json!({
+            "name": api_key.name,
+            "scopes": api_key.scopes,
+            "publication_id": api_key.publication_id,
+        }));
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok((api_key, secret))
+}
+
+/// Returns `false` if there is no such active key, and nothing changed.
+async fn revoke_and_record(
+    pool: &Pool,
+    publication_id: PublicationId,
+    id: Uuid,
+    audit: AuditEvent<'_>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    if !revoke_api_key(&transaction, publication_id, id).await? {
+        return Ok(false);
+    }
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+async fn render_api_keys_page(
+    pool: &Pool,
+    session: &Session,
+    publication_id: PublicationId,
+    notice: Option<String>,
+) -> HttpResponse {
+    let api_keys = match pool.get().await {
+        Ok(client) => list_api_keys(&client, publication_id).await,
+        Err(e) => Err(e.into()),
+    };
+    let api_keys = match api_keys {
+        Ok(api_keys) => api_keys,
+        Err(e) => {
+            tracing::error!("Failed to list API keys: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let csrf = csrf_field(session);
+    let mut rows = String::new();
+    for api_key in &api_keys {
+        let scopes: Vec<&str> = api_key.scopes.iter().map(Scope::as_str).collect();
+        rows.push_str(&format!(
+            r#"<tr><td>{}</td><td><code>{}&hellip;</code></td><td>{}</td><td>{}</td><td>{}</td><td><form action="/admin/api_keys/{}/revoke" method="post">{}<button type="submit">Revoke</button></form></td></tr>"#,
+            encode_minimal(&api_key.name),
+            encode_minimal(&api_key.key_prefix),
+            scopes.join(", "),
+            api_key.created_at.format("%Y-%m-%d %H:%M"),
+            api_key
+                .last_used_at
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "never".into()),
+            api_key.id,
+            csrf,
+        ));
+    }
+    let mut checkboxes = String::new();
+    for scope in Scope::ALL {
+        checkboxes.push_str(&format!(
+            r#"<label><input type="checkbox" name="{0}"> {0}</label>"#,
+            encode_attribute(scope.as_str()),
+        ));
+    }
+
+    let body = format!(
+        r#"{notice}
+    <table>
+        <tr><th>Name</th><th>Key</th><th>Scopes</th><th>Created</th><th>Last used</th><th></th></tr>
+        {rows}
+    </table>
+    <h2>New key</h2>
+    <form action="/admin/api_keys" method="post">
+        {csrf}
+        <label>Name <input type="text" name="name"></label>
+        {checkboxes}
+        <button type="submit">Create</button>
+    </form>"#,
+        notice = notice.unwrap_or_default(),
+    );
+    render_page(pool, session, "API keys", &body).await
+}
@@ -0,0 +1,107 @@
+use actix_web::{web, HttpResponse};
+use deadpool_postgres::Pool;
+
+use crate::audit::{list_audit_entries, verify_audit_chain};
+use crate::authentication::permissions::ViewAuditLog;
+use crate::authentication::{Authorized, Principal};
+use crate::publication::PublicationId;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Deserialize)]
+pub struct AuditLogQuery {
+    /// Only entries after this one, oldest first; pass `next_after_id` to
+    /// get the next page.
+    #[serde(default)]
+    after_id: i64,
+    limit: Option<i64>,
+}
+
+/// API keys act on one publication, and only read its entries. Owners run
+/// every publication and read the whole log.
+fn readable_publication(
+    principal: &Principal,
+    publication_id: Option<PublicationId>,
+) -> Result<Option<PublicationId>, HttpResponse> {
+    match principal {
+        Principal::User { .. } => Ok(None),
+        Principal::ApiKey { .. } => publication_id
+            .map(Some)
+            .ok_or_else(|| HttpResponse::Forbidden().finish()),
+    }
+}
+
+#[tracing::instrument(name = "Paging through the audit log", skip_all)]
+pub async fn get_audit_log(
+    principal: Authorized<ViewAuditLog>,
+    publication_id: Option<PublicationId>,
+    query: web::Query<AuditLogQuery>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let publication_id = match readable_publication(principal.principal(), publication_id) {
+        Ok(publication_id) => publication_id,
+        Err(response) => return response,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let outcome = match pool.get().await {
+        Ok(client) => list_audit_entries(&client, query.after_id, limit, publication_id).await,
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(entries) => {
+            let next_after_id = if entries.len() as i64 == limit {
+                entries.last().map(|entry| entry.id)
+            } else {
+                None
+            };
+            HttpResponse::Ok().json(serde_json::json!({
+                "entries": entries,
+                "next_after_id": next_after_id,
+            }))
+        }
+        Err(e) => {
+            tracing::error!("Failed to list audit log entries: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The chain runs through every publication's entries: only owners can
+/// check it.
+#[tracing::instrument(name = "Verifying the audit log chain", skip_all)]
+pub async fn verify_audit_log(
+    principal: Authorized<ViewAuditLog>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    if let Principal::ApiKey { .. } = principal.principal() {
+        return HttpResponse::Forbidden().body("Only owners can verify the whole audit log.");
+    }
+    let outcome = match pool.get().await {
+        Ok(client) => verify_audit_chain(&client).await,
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(verification) => {
+            if !verification.is_intact() {
+                tracing::error!(
+                    first_broken_id = ?verification.first_broken_id,
+                    "The audit log chain is broken."
+                );
+            }
+            HttpResponse::Ok().json(serde_json::json!({
+                "intact": verification.is_intact(),
+                "verified_entries": verification.verified_entries,
+                "unchained_entries": verification.unchained_entries,
+                "first_broken_id": verification.first_broken_id,
+            }))
+        }
+        Err(e) => {
+            tracing::error!("Failed to verify the audit log: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
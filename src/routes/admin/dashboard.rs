@@ -0,0 +1,76 @@
+use actix_web::{web, HttpResponse};
+use deadpool_postgres::Pool;
+use htmlescape::encode_minimal;
+use uuid::Uuid;
+
+use super::render_page;
+use crate::authentication::permissions::ViewAnalytics;
+use crate::authentication::{Authorized, Session};
+use crate::publication::PublicationId;
+
+#[tracing::instrument(name = "Showing the admin dashboard", skip_all)]
+pub async fn admin_dashboard(
+    _: Authorized<ViewAnalytics>,
+    session: Session,
+    publication_id: PublicationId,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let overview = match pool.get().await {
+        Ok(client) => get_overview(&client, session.user_id, publication_id).await,
+        Err(e) => Err(e.into()),
+    };
+    let (username, counts) = match overview {
+        Ok(overview) => overview,
+        Err(e) => {
+            tracing::error!("Failed to load the dashboard: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let total: i64 = counts.iter().map(|(_, count)| count).sum();
+    let mut rows = String::new();
+    for (status, count) in &counts {
+        rows.push_str(&format!(
+            r#"<tr><td><a href="/admin/subscribers?status={status}">{status}</a></td><td>{count}</td></tr>"#,
+            status = encode_minimal(status),
+        ));
+    }
+
+    let body = format!(
+        r#"<p>Welcome {username}!</p>
+    <h2>Subscribers</h2>
+    <table>
+        <tr><th>Status</th><th>Count</th></tr>
+        {rows}
+        <tr><th>Total</th><th>{total}</th></tr>
+    </table>"#,
+        username = encode_minimal(&username),
+    );
+    render_page(&pool, &session, "Dashboard", &body).await
+}
+
+/// The username and the publication's subscriber counts by status.
+async fn get_overview(
+    client: &deadpool_postgres::Client,
+    user_id: Uuid,
+    publication_id: PublicationId,
+) -> Result<(String, Vec<(String, i64)>), Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_one("SELECT username FROM users WHERE user_id = $1", &[&user_id])
+        .await?;
+    let counts = client
+        .query(
+            r#"
+    SELECT status, count(*) FROM subscriptions
+    WHERE publication_id = $1
+    GROUP BY status
+    ORDER BY status
+    "#,
+            &[&publication_id.0],
+        )
+        .await?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+    Ok((row.get("username"), counts))
+}
@@ -0,0 +1,118 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+
+use crate::audit::{record, AuditEvent};
+use crate::authentication::permissions::{ReadSubscribers, WriteSubscribers};
+use crate::authentication::Authorized;
+use crate::domain::{parse_email_domain, DomainRule};
+use crate::email_domains::{delete_domain_rule, list_domain_rules, set_domain_rule};
+
+#[derive(serde::Deserialize)]
+pub struct EmailDomainRuleData {
+    domain: String,
+    rule: DomainRule,
+}
+
+#[tracing::instrument(name = "Listing email domain rules", skip(pool))]
+pub async fn get_email_domain_rules(
+    _: Authorized<ReadSubscribers>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let outcome = match pool.get().await {
+        Ok(client) => list_domain_rules(&client).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match outcome {
+        Ok(rules) => HttpResponse::Ok().json(rules),
+        Err(e) => {
+            tracing::error!("Failed to list email domain rules: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Setting an email domain rule",
+    skip(request, body, principal, pool),
+    fields(domain = %body.domain)
+)]
+pub async fn add_email_domain_rule(
+    request: HttpRequest,
+    body: web::Json<EmailDomainRuleData>,
+    principal: Authorized<WriteSubscribers>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let domain = match parse_email_domain(&body.domain) {
+        Ok(domain) => domain,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let audit = AuditEvent::new("email_domain_rule.set")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("email_domain:{}", domain))
+        .details(serde_json::json!({ "rule": body.rule }));
+
+    match set_and_record(&pool, &domain, body.rule, audit).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            tracing::error!("Failed to set email domain rule: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Removing an email domain rule", skip(request, principal, pool))]
+pub async fn delete_email_domain_rule(
+    request: HttpRequest,
+    domain: web::Path<String>,
+    principal: Authorized<WriteSubscribers>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let audit = AuditEvent::new("email_domain_rule.removed")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("email_domain:{}", domain));
+
+    match delete_and_record(&pool, &domain, audit).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to remove email domain rule: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The rule and its audit entry commit together, or not at all.
+async fn set_and_record(
+    pool: &Pool,
+    domain: &str,
+    rule: DomainRule,
+    audit: AuditEvent<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    set_domain_rule(&transaction, domain, rule).await?;
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Returns `false` if the domain had no rule, and nothing changed.
+async fn delete_and_record(
+    pool: &Pool,
+    domain: &str,
+    audit: AuditEvent<'_>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    if !delete_domain_rule(&transaction, domain).await? {
+        return Ok(false);
+    }
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
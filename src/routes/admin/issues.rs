@@ -0,0 +1,240 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use htmlescape::{encode_attribute, encode_minimal};
+use uuid::Uuid;
+
+use super::{csrf_field, publish_issue, redirect_with_flash, render_page};
+use crate::audit::AuditEvent;
+use crate::authentication::permissions::{PublishNewsletters, ViewAnalytics};
+use crate::authentication::{Authorized, FlashMessage, Session};
+use crate::email_client::EmailClient;
+use crate::publication::{get_publication, PublicationId};
+
+struct IssueProgress {
+    id: Uuid,
+    title: String,
+    sender: String,
+    published_at: DateTime<Utc>,
+    n_recipients: i32,
+    n_sent: i64,
+    n_suppressed: i64,
+    n_failed: i64,
+    n_queued: i64,
+}
+
+#[tracing::instrument(name = "Listing newsletter issues", skip_all)]
+pub async fn issues_page(
+    _: Authorized<ViewAnalytics>,
+    session: Session,
+    publication_id: PublicationId,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let issues = match pool.get().await {
+        Ok(client) => get_issue_progress(&client, publication_id).await,
+        Err(e) => Err(e.into()),
+    };
+    let issues = match issues {
+        Ok(issues) => issues,
+        Err(e) => {
+            tracing::error!("Failed to list newsletter issues: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut rows = String::new();
+    for issue in &issues {
+        let n_done = issue.n_sent + issue.n_suppressed + issue.n_failed;
+        rows.push_str(&format!(
+            r#"<tr><td>{published_at}</td><td title="{id}">{title}</td><td>{sender}</td><td><progress max="{max}" value="{n_done}"></progress> {n_done}/{n_recipients}</td><td>{n_sent}</td><td>{n_suppressed}</td><td>{n_failed}</td><td>{n_queued}</td></tr>"#,
+            published_at = issue.published_at.format("%Y-%m-%d %H:%M"),
+            id = issue.id,
+            title = encode_minimal(&issue.title),
+            sender = encode_minimal(&issue.sender),
+            max = issue.n_recipients.max(1),
+            n_recipients = issue.n_recipients,
+            n_sent = issue.n_sent,
+            n_suppressed = issue.n_suppressed,
+            n_failed = issue.n_failed,
+            n_queued = issue.n_queued,
+        ));
+    }
+
+    let body = format!(
+        r#"<table>
+        <tr><th>Published at</th><th>Title</th><th>Sender</th><th>Progress</th><th>Sent</th><th>Suppressed</th><th>Failed</th><th>Queued</th></tr>
+        {rows}
+    </table>"#
+    );
+    render_page(&pool, &session, "Issues", &body).await
+}
+
+#[tracing::instrument(name = "Showing the compose form", skip_all)]
+pub async fn compose_page(
+    _: Authorized<PublishNewsletters>,
+    session: Session,
+    publication_id: PublicationId,
+    pool: web::Data<Pool>,
+    email_client: web::Data<EmailClient>,
+) -> HttpResponse {
+    let publication = match pool.get().await {
+        Ok(client) => get_publication(&client, publication_id).await,
+        Err(e) => Err(e.into()),
+    };
+    let publication = match publication {
+        Ok(Some(publication)) => publication,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to load the publication: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let senders = email_client.senders();
+    let default_sender = publication.default_sender_name(senders);
+    let options: String = publication
+        .sender_names(senders)
+        .into_iter()
+        .map(|name| {
+            format!(
+                r#"<option value="{0}"{1}>{2}</option>"#,
+                encode_attribute(name),
+                if name == default_sender {
+                    " selected"
+                } else {
+                    ""
+                },
+                encode_minimal(&senders.get(name).unwrap().mailbox()),
+            )
+        })
+        .collect();
+
+    let body = format!(
+        r#"<form action="/admin/issues" method="post">
+        {csrf}
+        <p><label>Title<br><input type="text" name="title" size="80" required></label></p>
+        <p><label>Sender<br><select name="sender">{options}</select></label></p>
+        <p><label>Plain text<br><textarea name="text_content" rows="12" cols="80" required></textarea></label></p>
+        <p><label>HTML<br><textarea name="html_content" rows="12" cols="80" required></textarea></label></p>
+        <button type="submit">Publish</button>
+    </form>"#,
+        csrf = csrf_field(&session),
+    );
+    render_page(&pool, &session, "Compose an issue", &body).await
+}
+
+#[derive(serde::Deserialize)]
+pub struct IssueFormData {
+    title: String,
+    sender: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(
+    name = "Publishing a newsletter issue from the admin UI",
+    skip_all,
+    fields(title = %form.title, issue_id = tracing::field::Empty)
+)]
+pub async fn publish_issue_form(
+    request: HttpRequest,
+    principal: Authorized<PublishNewsletters>,
+    session: Session,
+    publication_id: PublicationId,
+    form: web::Form<IssueFormData>,
+    pool: web::Data<Pool>,
+    email_client: web::Data<EmailClient>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    if form.title.trim().is_empty() {
+        let flash = FlashMessage::error("Issues need a title.");
+        return redirect_with_flash(&pool, &session, "/admin/issues/new", flash).await;
+    }
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("Failed to get a database connection: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let may_send = match get_publication(&client, publication_id).await {
+        Ok(publication) => publication.is_some_and(|publication| {
+            publication.may_send_as(email_client.senders(), &form.sender)
+        }),
+        Err(e) => {
+            tracing::error!("Failed to load the publication: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if !may_send {
+        let flash = FlashMessage::error("The publication cannot send as this identity.");
+        return redirect_with_flash(&pool, &session, "/admin/issues/new", flash).await;
+    }
+
+    let outcome = publish_issue(
+        &mut client,
+        publication_id,
+        form.title.trim(),
+        &form.text_content,
+        &form.html_content,
+        &form.sender,
+        AuditEvent::new("newsletter.published")
+            .principal(principal.principal())
+            .request(&request),
+    )
+    .await;
+    match outcome {
+        Ok((issue_id, n_recipients)) => {
+            tracing::Span::current().record("issue_id", tracing::field::display(issue_id));
+            let flash = FlashMessage::info(format!(
+                "The issue has been queued for {} subscriber(s).",
+                n_recipients
+            ));
+            redirect_with_flash(&pool, &session, "/admin/issues", flash).await
+        }
+        Err(e) => {
+            tracing::error!("Failed to publish newsletter issue: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Most recent issues of a publication first, with how far their delivery
+/// has got.
+async fn get_issue_progress(
+    client: &deadpool_postgres::Client,
+    publication_id: PublicationId,
+) -> Result<Vec<IssueProgress>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(
+            r#"
+    SELECT i.id, i.title, i.sender, i.published_at, i.n_recipients,
+           count(*) FILTER (WHERE d.outcome = 'sent') AS n_sent,
+           count(*) FILTER (WHERE d.outcome = 'suppressed') AS n_suppressed,
+           count(*) FILTER (WHERE d.outcome = 'failed') AS n_failed,
+           (SELECT count(*) FROM email_delivery_queue q
+            WHERE q.newsletter_issue_id = i.id) AS n_queued
+    FROM newsletter_issues i
+    LEFT JOIN email_deliveries d ON d.newsletter_issue_id = i.id
+    WHERE i.publication_id = $1
+    GROUP BY i.id
+    ORDER BY i.published_at DESC
+    LIMIT 50
+    "#,
+            &[&publication_id.0],
+        )
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| IssueProgress {
+            id: row.get("id"),
+            title: row.get("title"),
+            sender: row.get("sender"),
+            published_at: row.get("published_at"),
+            n_recipients: row.get("n_recipients"),
+            n_sent: row.get("n_sent"),
+            n_suppressed: row.get("n_suppressed"),
+            n_failed: row.get("n_failed"),
+            n_queued: row.get("n_queued"),
+        })
+        .collect())
+}
@@ -0,0 +1,124 @@
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::HttpResponse;
+use deadpool_postgres::Pool;
+use htmlescape::{encode_attribute, encode_minimal};
+
+use crate::authentication::{set_flash, take_flash, FlashMessage, Permission, Session, CSRF_FIELD};
+use crate::publication::get_publication;
+
+/// The hidden input every admin form must carry.
+pub fn csrf_field(session: &Session) -> String {
+    format!(
+        r#"<input type="hidden" name="{}" value="{}">"#,
+        CSRF_FIELD,
+        encode_attribute(&session.csrf_token)
+    )
+}
+
+/// Wrap `body` in the admin layout: navigation, logout button, the
+/// publication being worked on and the pending flash message, if any.
+pub async fn render_page(pool: &Pool, session: &Session, title: &str, body: &str) -> HttpResponse {
+    let (flash, publication) = match pool.get().await {
+        Ok(client) => {
+            let publication = match session.publication_id {
+                Some(publication_id) => get_publication(&client, publication_id).await,
+                None => Ok(None),
+            };
+            (take_flash(&client, session).await, publication)
+        }
+        Err(e) => (Err(e.into()), Ok(None)),
+    };
+    let publication = match publication {
+        Ok(Some(publication)) => format!(
+            r#"<a href="/admin/publications">Publication: {}</a>"#,
+            encode_minimal(&publication.name)
+        ),
+        Ok(None) => r#"<a href="/admin/publications">Publications</a>"#.to_owned(),
+        Err(e) => {
+            tracing::error!("Failed to read the publication: {:?}", e);
+            r#"<a href="/admin/publications">Publications</a>"#.to_owned()
+        }
+    };
+    let flash = match flash {
+        Ok(Some(flash)) => format!(
+            r#"<p class="flash {}"><i>{}</i></p>"#,
+            flash.level.as_str(),
+            encode_minimal(&flash.message)
+        ),
+        Ok(None) => String::new(),
+        Err(e) => {
+            tracing::error!("Failed to read the flash message: {:?}", e);
+            String::new()
+        }
+    };
+
+    let mut links = vec![
+        publication.as_str(),
+        r#"<a href="/admin/dashboard">Dashboard</a>"#,
+    ];
+    let role = session.role;
+    if role.can(Permission::ReadSubscribers) {
+        links.push(r#"<a href="/admin/subscribers">Subscribers</a>"#);
+    }
+    if role.can(Permission::ViewAnalytics) {
+        links.push(r#"<a href="/admin/issues">Issues</a>"#);
+    }
+    if role.can(Permission::PublishNewsletters) {
+        links.push(r#"<a href="/admin/issues/new">Compose</a>"#);
+    }
+    if role.can(Permission::ManageUsers) {
+        links.push(r#"<a href="/admin/users">Users</a>"#);
+    }
+    if role.can(Permission::ManageApiKeys) {
+        links.push(r#"<a href="/admin/api_keys">API keys</a>"#);
+    }
+
+    links.push(r#"<a href="/admin/password">Change password</a>"#);
+    links.push(r#"<a href="/admin/two_factor">Two-factor</a>"#);
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <nav>
+        {links}
+        <form action="/admin/logout" method="post" style="display: inline">
+            {csrf}
+            <button type="submit">Logout</button>
+        </form>
+    </nav>
+    {flash}
+    <h1>{title}</h1>
+    {body}
+</body>
+</html>"#,
+            title = encode_minimal(title),
+            links = links.join(" | "),
+            csrf = csrf_field(session),
+        ))
+}
+
+/// Redirect after a form post, leaving a message for the next page.
+pub async fn redirect_with_flash(
+    pool: &Pool,
+    session: &Session,
+    location: &str,
+    flash: FlashMessage,
+) -> HttpResponse {
+    let outcome = match pool.get().await {
+        Ok(client) => set_flash(&client, session, &flash).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = outcome {
+        tracing::error!("Failed to store the flash message: {:?}", e);
+    }
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
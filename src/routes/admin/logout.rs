@@ -0,0 +1,33 @@
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+
+use crate::audit::{record_from_handler, AuditEvent};
+use crate::authentication::{delete_session, removal_cookie, Session, SESSION_COOKIE};
+
+#[tracing::instrument(name = "Logging out", skip(request, session, pool))]
+pub async fn log_out(
+    request: HttpRequest,
+    session: Session,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let audit = AuditEvent::new("logout")
+        .actor(session.user_id)
+        .request(&request);
+    record_from_handler(&pool, audit).await;
+    if let Some(cookie) = request.cookie(SESSION_COOKIE) {
+        let outcome = match pool.get().await {
+            Ok(client) => delete_session(&client, cookie.value()).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = outcome {
+            tracing::error!("Failed to delete the session: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .cookie(removal_cookie())
+        .finish()
+}
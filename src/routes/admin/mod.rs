@@ -0,0 +1,33 @@
+mod api_keys;
+mod audit_log;
+mod dashboard;
+mod email_domains;
+mod issues;
+mod layout;
+mod logout;
+mod newsletters;
+mod password;
+mod publications;
+mod subscriber_export;
+mod subscriber_import;
+mod subscribers;
+mod suppressions;
+mod two_factor;
+mod users;
+
+pub use api_keys::*;
+pub use audit_log::*;
+pub use dashboard::*;
+pub use email_domains::*;
+pub use issues::*;
+pub use layout::*;
+pub use logout::*;
+pub use newsletters::*;
+pub use password::*;
+pub use publications::*;
+pub use subscriber_export::*;
+pub use subscriber_import::*;
+pub use subscribers::*;
+pub use suppressions::*;
+pub use two_factor::*;
+pub use users::*;
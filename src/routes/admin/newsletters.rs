@@ -0,0 +1,155 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use deadpool_postgres::{Client, GenericClient, Pool};
+use uuid::Uuid;
+
+use crate::audit::{record, AuditEvent};
+use crate::authentication::permissions::PublishNewsletters;
+use crate::authentication::Authorized;
+use crate::delivery_worker::enqueue_newsletter_issue;
+use crate::email_client::EmailClient;
+use crate::publication::{get_publication, PublicationId};
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    /// The sender identity to publish as; the publication's default sender
+    /// if omitted.
+    sender: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+#[tracing::instrument(
+    name = "Publishing a newsletter issue",
+    skip(request, body, principal, pool, email_client),
+    fields(title = %body.title, issue_id = tracing::field::Empty)
+)]
+pub async fn publish_newsletter(
+    request: HttpRequest,
+    body: web::Json<BodyData>,
+    principal: Authorized<PublishNewsletters>,
+    publication_id: PublicationId,
+    pool: web::Data<Pool>,
+    email_client: web::Data<EmailClient>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("Failed to get a database connection: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let publication = match get_publication(&client, publication_id).await {
+        Ok(Some(publication)) => publication,
+        Ok(None) => return HttpResponse::NotFound().body("There is no such publication."),
+        Err(e) => {
+            tracing::error!("Failed to load the publication: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let senders = email_client.senders();
+    let sender_name = body
+        .sender
+        .unwrap_or_else(|| publication.default_sender_name(senders).to_owned());
+    if !publication.may_send_as(senders, &sender_name) {
+        return HttpResponse::BadRequest().body("The publication cannot send as this identity.");
+    }
+
+    let outcome = publish_issue(
+        &mut client,
+        publication_id,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+        &sender_name,
+        AuditEvent::new("newsletter.published")
+            .principal(principal.principal())
+            .request(&request),
+    )
+    .await;
+
+    match outcome {
+        Ok((issue_id, _)) => {
+            tracing::Span::current().record("issue_id", tracing::field::display(issue_id));
+            HttpResponse::Accepted().json(serde_json::json!({ "issue_id": issue_id }))
+        }
+        Err(e) => {
+            tracing::error!("Failed to publish newsletter issue: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Store the issue, queue it for every confirmed subscriber of the
+/// publication and write `audit` to the audit log, atomically.
+/// Returns the id of the issue and the number of recipients.
+pub(crate) async fn publish_issue(
+    client: &mut Client,
+    publication_id: PublicationId,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    sender: &str,
+    audit: AuditEvent<'_>,
+) -> Result<(Uuid, u64), Box<dyn std::error::Error + Send + Sync>> {
+    let transaction = client.transaction().await?;
+    let issue_id = insert_newsletter_issue(
+        &transaction,
+        publication_id,
+        title,
+        text_content,
+        html_content,
+        sender,
+    )
+    .await?;
+    let n_recipients = enqueue_newsletter_issue(&transaction, issue_id).await?;
+    let audit = audit
+        .target(format!("newsletter_issue:{}", issue_id))
+        .details(serde_json::json!({
+            "publication_id": publication_id.0,
+            "title": title,
+            "sender": sender,
+            "recipients": n_recipients,
+        }));
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok((issue_id, n_recipients))
+}
+
+#[tracing::instrument(name = "Saving newsletter issue", skip_all)]
+async fn insert_newsletter_issue<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    sender: &str,
+) -> Result<Uuid, Box<dyn std::error::Error + Send + Sync>> {
+    let issue_id = Uuid::new_v4();
+    client
+        .execute(
+            r#"
+    INSERT INTO newsletter_issues
+        (id, publication_id, title, text_content, html_content, sender, published_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    "#,
+            &[
+                &issue_id,
+                &publication_id.0,
+                &title,
+                &text_content,
+                &html_content,
+                &sender,
+                &Utc::now(),
+            ],
+        )
+        .await?;
+    Ok(issue_id)
+}
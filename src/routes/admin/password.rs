@@ -0,0 +1,139 @@
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use super::{csrf_field, redirect_with_flash, render_page};
+use crate::audit::{record, record_from_handler, AuditEvent};
+use crate::authentication::{
+    change_password, check_password_strength, get_username, removal_cookie, validate_credentials,
+    AuthError, Credentials, FlashMessage, Session,
+};
+use crate::rate_limit::{too_many_requests, RateLimiter};
+
+#[derive(serde::Deserialize)]
+pub struct ChangePasswordData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+/// Any logged in user may change their own password, whatever their role.
+pub async fn change_password_form(session: Session, pool: web::Data<Pool>) -> HttpResponse {
+    let body = format!(
+        r#"<form action="/admin/password" method="post">
+        {csrf}
+        <label>Current password <input type="password" name="current_password"></label>
+        <br>
+        <label>New password <input type="password" name="new_password"></label>
+        <br>
+        <label>Confirm new password <input type="password" name="new_password_check"></label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <p>At least 12 characters. You will be logged out everywhere.</p>"#,
+        csrf = csrf_field(&session),
+    );
+    render_page(&pool, &session, "Change password", &body).await
+}
+
+#[tracing::instrument(name = "Changing one's password", skip_all, fields(user_id = %session.user_id))]
+pub async fn change_password_submit(
+    request: HttpRequest,
+    session: Session,
+    form: web::Form<ChangePasswordData>,
+    pool: web::Data<Pool>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let audit = |action| {
+        AuditEvent::new(action)
+            .actor(session.user_id)
+            .request(&request)
+    };
+
+    if let Err(retry_after) = rate_limiter.check(&format!("password_change:{}", session.user_id)) {
+        record_from_handler(&pool, audit("password_change.rate_limited")).await;
+        return too_many_requests(retry_after);
+    }
+
+    let username = match pool.get().await {
+        Ok(client) => get_username(&client, session.user_id).await,
+        Err(e) => Err(e.into()),
+    };
+    let username = match username {
+        Ok(Some(username)) => username,
+        Ok(None) => return HttpResponse::Forbidden().finish(),
+        Err(e) => {
+            tracing::error!("Failed to look the user up: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let failure = |reason: String| {
+        let event =
+            audit("password_change.failed").details(serde_json::json!({ "reason": reason }));
+        (event, FlashMessage::error(reason))
+    };
+    let credentials = Credentials {
+        username: username.clone(),
+        password: form.current_password,
+    };
+    let rejection = match validate_credentials(credentials, &pool).await {
+        Ok(_) => None,
+        Err(AuthError::InvalidCredentials) => {
+            Some(failure("The current password is wrong.".into()))
+        }
+        Err(e) => {
+            tracing::error!("Failed to validate credentials: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let rejection = rejection.or_else(|| {
+        if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+            Some(failure("The new passwords do not match.".into()))
+        } else {
+            check_password_strength(&form.new_password, &username)
+                .err()
+                .map(failure)
+        }
+    });
+    if let Some((event, flash)) = rejection {
+        record_from_handler(&pool, event).await;
+        return redirect_with_flash(&pool, &session, "/admin/password", flash).await;
+    }
+
+    let outcome = change_and_record(
+        &pool,
+        session.user_id,
+        form.new_password,
+        audit("password_change.succeeded"),
+    )
+    .await;
+    if let Err(e) = outcome {
+        tracing::error!("Failed to change the password: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // The current session went with all the others
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login?notice=password_changed"))
+        .cookie(removal_cookie())
+        .finish()
+}
+
+/// The new password and its audit entry commit together, or not at all.
+async fn change_and_record(
+    pool: &Pool,
+    user_id: Uuid,
+    password: Secret<String>,
+    audit: AuditEvent<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    change_password(&transaction, user_id, password).await?;
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use htmlescape::{encode_attribute, encode_minimal};
+use uuid::Uuid;
+
+use super::{csrf_field, redirect_with_flash, render_page};
+use crate::audit::{record, AuditEvent};
+use crate::authentication::permissions::ManagePublications;
+use crate::authentication::{switch_publication, Authorized, FlashMessage, Permission, Session};
+use crate::email_client::EmailClient;
+use crate::publication::{
+    create_publication, list_user_publications, parse_slug, NewPublication, PublicationId,
+};
+
+/// Sender checkboxes are named after the identity, behind this prefix.
+const SENDER_FIELD_PREFIX: &str = "sender:";
+
+/// Every logged in user sees the publications they belong to.
+#[tracing::instrument(name = "Showing publications", skip_all)]
+pub async fn publications_page(
+    session: Session,
+    pool: web::Data<Pool>,
+    email_client: web::Data<EmailClient>,
+) -> HttpResponse {
+    let publications = match pool.get().await {
+        Ok(client) => list_user_publications(&client, session.user_id).await,
+        Err(e) => Err(e.into()),
+    };
+    let publications = match publications {
+        Ok(publications) => publications,
+        Err(e) => {
+            tracing::error!("Failed to list publications: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let csrf = csrf_field(&session);
+    let mut rows = String::new();
+    for publication in &publications {
+        let action = if session.publication_id == Some(PublicationId(publication.id)) {
+            "<i>working on it</i>".to_owned()
+        } else {
+            format!(
+                r#"<form action="/admin/publications/{}/switch" method="post">{}<button type="submit">Work on it</button></form>"#,
+                publication.id, csrf,
+            )
+        };
+        rows.push_str(&format!(
+            r#"<tr><td>{name}</td><td><code>/p/{slug}/subscriptions</code></td><td>{host}</td><td>{senders}</td><td>{action}</td></tr>"#,
+            name = encode_minimal(&publication.name),
+            slug = encode_minimal(&publication.slug),
+            host = encode_minimal(publication.host.as_deref().unwrap_or_default()),
+            senders = encode_minimal(&publication.sender_names(email_client.senders()).join(", ")),
+        ));
+    }
+
+    let create_form = if session.role.can(Permission::ManagePublications) {
+        let senders = email_client.senders();
+        let mut checkboxes = String::new();
+        let mut options = String::new();
+        for name in senders.names() {
+            checkboxes.push_str(&format!(
+                r#"<label><input type="checkbox" name="{prefix}{name}"> {name}</label>"#,
+                prefix = SENDER_FIELD_PREFIX,
+                name = encode_attribute(name),
+            ));
+            options.push_str(&format!(
+                r#"<option value="{0}"{1}>{0}</option>"#,
+                encode_attribute(name),
+                if name == senders.default_name() {
+                    " selected"
+                } else {
+                    ""
+                },
+            ));
+        }
+        format!(
+            r#"<h2>New publication</h2>
+    <form action="/admin/publications" method="post">
+        {csrf}
+        <p><label>Name <input type="text" name="name"></label></p>
+        <p><label>Slug <input type="text" name="slug" pattern="[a-z0-9-]+"></label></p>
+        <p><label>Host <input type="text" name="host" placeholder="news.example.com"></label></p>
+        <p>May send as (any identity if none is ticked): {checkboxes}</p>
+        <p><label>Default sender <select name="default_sender">{options}</select></label></p>
+        <button type="submit">Create</button>
+    </form>"#
+        )
+    } else {
+        String::new()
+    };
+
+    let body = format!(
+        r#"<table>
+        <tr><th>Name</th><th>Signup URL</th><th>Host</th><th>Senders</th><th></th></tr>
+        {rows}
+    </table>
+    {create_form}"#
+    );
+    render_page(&pool, &session, "Publications", &body).await
+}
+
+#[tracing::instrument(name = "Switching publications", skip(session, pool))]
+pub async fn switch_publication_form(
+    session: Session,
+    publication_id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let publication_id = PublicationId(publication_id.into_inner());
+    let outcome = match pool.get().await {
+        Ok(client) => switch_publication(&client, &session, publication_id).await,
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(true) => {
+            let flash = FlashMessage::info("You are now working on another publication.");
+            redirect_with_flash(&pool, &session, "/admin/dashboard", flash).await
+        }
+        Ok(false) => {
+            let flash = FlashMessage::error("You do not belong to that publication.");
+            redirect_with_flash(&pool, &session, "/admin/publications", flash).await
+        }
+        Err(e) => {
+            tracing::error!("Failed to switch publications: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The form has `name`, `slug`, `host` and `default_sender` fields and one
+/// checkbox per sender identity. The creator becomes the first member and
+/// starts working on the new publication.
+#[tracing::instrument(name = "Creating a publication from the admin UI", skip_all)]
+pub async fn add_publication(
+    request: HttpRequest,
+    _: Authorized<ManagePublications>,
+    session: Session,
+    form: web::Form<HashMap<String, String>>,
+    pool: web::Data<Pool>,
+    email_client: web::Data<EmailClient>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let field = |name: &str| form.get(name).map(|value| value.trim()).unwrap_or_default();
+    let name = field("name");
+    if name.is_empty() {
+        let flash = FlashMessage::error("Publications need a name.");
+        return redirect_with_flash(&pool, &session, "/admin/publications", flash).await;
+    }
+    let slug = match parse_slug(field("slug")) {
+        Ok(slug) => slug,
+        Err(e) => {
+            let flash = FlashMessage::error(e);
+            return redirect_with_flash(&pool, &session, "/admin/publications", flash).await;
+        }
+    };
+    let host = Some(field("host")).filter(|host| !host.is_empty());
+
+    let identities = email_client.senders();
+    let senders: Vec<String> = identities
+        .names()
+        .into_iter()
+        .filter(|name| form.contains_key(&format!("{}{}", SENDER_FIELD_PREFIX, name)))
+        .map(str::to_owned)
+        .collect();
+    let default_sender = field("default_sender");
+    let is_allowed = senders.is_empty() || senders.iter().any(|name| name == default_sender);
+    if identities.get(default_sender).is_none() || !is_allowed {
+        let flash = FlashMessage::error("The default sender must be one of the ticked identities.");
+        return redirect_with_flash(&pool, &session, "/admin/publications", flash).await;
+    }
+
+    let publication = NewPublication {
+        slug: &slug,
+        name,
+        host,
+        senders: &senders,
+        default_sender: Some(default_sender),
+    };
+    let audit = AuditEvent::new("publication.created")
+        .actor(session.user_id)
+        .request(&request)
+        .details(serde_json::json!({
+            "name": name,
+            "slug": slug,
+            "host": host,
+            "senders": senders,
+            "default_sender": default_sender,
+        }));
+    let outcome = create_and_switch(&pool, &session, publication, audit).await;
+    match outcome {
+        Ok(true) => {
+            let flash = FlashMessage::info("The publication has been created.");
+            redirect_with_flash(&pool, &session, "/admin/dashboard", flash).await
+        }
+        Ok(false) => {
+            let flash = FlashMessage::error("That slug or host is taken.");
+            redirect_with_flash(&pool, &session, "/admin/publications", flash).await
+        }
+        Err(e) => {
+            tracing::error!("Failed to create a publication: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Returns `false` if the slug or host is taken.
+async fn create_and_switch(
+    pool: &Pool,
+    session: &Session,
+    publication: NewPublication<'_>,
+    audit: AuditEvent<'_>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let Some(publication_id) =
+        create_publication(&transaction, publication, session.user_id).await?
+    else {
+        return Ok(false);
+    };
+    record(
+        &transaction,
+        audit.target(format!("publication:{}", publication_id)),
+    )
+    .await?;
+    switch_publication(&transaction, session, publication_id).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
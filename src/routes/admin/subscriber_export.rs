@@ -0,0 +1,458 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::audit::{record_from_handler, AuditEvent};
+use crate::authentication::permissions::ReadSubscribers;
+use crate::authentication::Authorized;
+use crate::publication::PublicationId;
+use crate::subscribers::{
+    declare_export_cursor, fetch_export_batch, ExportedSubscriber, SubscriberFilter,
+};
+use crate::suppression::SuppressionReason;
+
+/// Rows fetched from the cursor, and sent to the client, at a time.
+const BATCH_SIZE: u32 = 1000;
+/// Batches waiting for a slow client before we stop fetching more.
+const BUFFERED_BATCHES: usize = 4;
+
+type ExportError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+    /// What Mailchimp's audience import expects, statuses included.
+    Mailchimp,
+    /// What Substack's subscriber import expects: only the addresses we may
+    /// still send to, as it takes everyone in a file as subscribed.
+    Substack,
+}
+
+const CSV_HEADER: &str = "id,email,name,status,tags,subscribed_at,suppression_reason,\
+    suppressed_at,consent_source,consent_text_version,consented_at,consent_ip\r\n";
+const MAILCHIMP_HEADER: &str =
+    "Email Address,First Name,Last Name,TAGS,Status,OPTIN_TIME,OPTIN_IP\r\n";
+const SUBSTACK_HEADER: &str = "email,name,created_at\r\n";
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv | ExportFormat::Mailchimp | ExportFormat::Substack => {
+                "text/csv; charset=utf-8"
+            }
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv | ExportFormat::Mailchimp | ExportFormat::Substack => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    fn header(&self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Csv => Some(CSV_HEADER),
+            ExportFormat::Ndjson => None,
+            ExportFormat::Mailchimp => Some(MAILCHIMP_HEADER),
+            ExportFormat::Substack => Some(SUBSTACK_HEADER),
+        }
+    }
+
+    fn write_row(&self, row: &ExportedSubscriber, out: &mut Vec<u8>) -> Result<(), ExportError> {
+        match self {
+            ExportFormat::Csv => {
+                let timestamp = |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339());
+                let fields = [
+                    Some(row.subscriber.id.to_string()),
+                    Some(row.subscriber.email.clone()),
+                    Some(row.subscriber.name.clone()),
+                    Some(row.subscriber.status.clone()),
+                    // all of them in one column
+                    Some(row.subscriber.tags.join(";")),
+                    timestamp(Some(row.subscriber.subscribed_at)),
+                    row.suppression_reason.map(|r| r.as_str().to_owned()),
+                    timestamp(row.suppressed_at),
+                    row.consent_source.clone(),
+                    row.consent_text_version.clone(),
+                    timestamp(row.consented_at),
+                    row.consent_ip.clone(),
+                ];
+                write_csv_line(&fields, out);
+            }
+            ExportFormat::Ndjson => {
+                serde_json::to_writer(&mut *out, row)?;
+                out.push(b'\n');
+            }
+            ExportFormat::Mailchimp => {
+                let (first_name, last_name) = row
+                    .subscriber
+                    .name
+                    .split_once(' ')
+                    .unwrap_or((&row.subscriber.name, ""));
+                let tags: Vec<String> = row
+                    .subscriber
+                    .tags
+                    .iter()
+                    .map(|tag| format!("\"{}\"", tag.replace('"', "\"\"")))
+                    .collect();
+                let fields = [
+                    Some(row.subscriber.email.clone()),
+                    Some(first_name.to_owned()),
+                    Some(last_name.to_owned()),
+                    // a CSV line of its own, e.g. `"Tag A","Tag B"`
+                    Some(tags.join(",")),
+                    Some(mailchimp_status(row).to_owned()),
+                    Some(
+                        row.consented_at
+                            .unwrap_or(row.subscriber.subscribed_at)
+                            .format("%Y-%m-%d %H:%M:%S")
+                            .to_string(),
+                    ),
+                    row.consent_ip.clone(),
+                ];
+                write_csv_line(&fields, out);
+            }
+            ExportFormat::Substack => {
+                if row.subscriber.status == "confirmed" && row.suppression_reason.is_none() {
+                    let fields = [
+                        Some(row.subscriber.email.clone()),
+                        Some(row.subscriber.name.clone()),
+                        Some(row.subscriber.subscribed_at.to_rfc3339()),
+                    ];
+                    write_csv_line(&fields, out);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Our lifecycle in Mailchimp's words: `cleaned` is their hard bounce, and
+/// they have no other way to say an address must not be mailed.
+fn mailchimp_status(row: &ExportedSubscriber) -> &'static str {
+    match (row.subscriber.status.as_str(), row.suppression_reason) {
+        ("bounced", _) | (_, Some(SuppressionReason::HardBounce)) => "cleaned",
+        ("confirmed", None) => "subscribed",
+        _ => "unsubscribed",
+    }
+}
+
+fn write_csv_line(fields: &[Option<String>], out: &mut Vec<u8>) {
+    let line: Vec<String> = fields
+        .iter()
+        .map(|field| csv_field(field.as_deref().unwrap_or_default()))
+        .collect();
+    out.extend_from_slice(line.join(",").as_bytes());
+    out.extend_from_slice(b"\r\n");
+}
+
+/// Quote fields that need it, and defuse the ones a spreadsheet would
+/// otherwise run as a formula: names are whatever subscribers typed in.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_owned()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Streams every subscriber matching the listing filters, as CSV (the
+/// default), NDJSON, or ready to import into Mailchimp or Substack, fetching
+/// them from a cursor `BATCH_SIZE` at a time.
+#[tracing::instrument(
+    name = "Exporting subscribers",
+    skip(request, principal, filter, pool),
+    fields(filter = ?filter)
+)]
+pub async fn export_subscribers(
+    request: HttpRequest,
+    principal: Authorized<ReadSubscribers>,
+    publication_id: PublicationId,
+    filter: web::Query<SubscriberFilter>,
+    query: web::Query<ExportQuery>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let filter = filter.into_inner().without_blanks();
+    let format = query.format;
+    let audit = AuditEvent::new("subscribers.exported")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("publication:{}", publication_id))
+        .details(serde_json::json!({ "format": format, "filter": filter }));
+
+    let (ready, is_ready) = oneshot::channel();
+    let (sender, receiver) = mpsc::channel(BUFFERED_BATCHES);
+    tokio::spawn(stream_export(
+        pool.get_ref().clone(),
+        publication_id,
+        filter,
+        format,
+        ready,
+        sender,
+    ));
+    // once the cursor is open, failures can only cut the download short
+    match is_ready.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            tracing::error!("Failed to start the export: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+        Err(e) => {
+            tracing::error!("The export task went away: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    record_from_handler(&pool, audit).await;
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!(
+                r#"attachment; filename="subscribers-{}.{}""#,
+                Utc::now().format("%Y-%m-%d"),
+                format.extension()
+            ),
+        ))
+        .body(ExportBody(receiver))
+}
+
+async fn stream_export(
+    pool: Pool,
+    publication_id: PublicationId,
+    filter: SubscriberFilter,
+    format: ExportFormat,
+    ready: oneshot::Sender<Result<(), ExportError>>,
+    sender: mpsc::Sender<Result<Bytes, ExportError>>,
+) {
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            let _ = ready.send(Err(e.into()));
+            return;
+        }
+    };
+    // the cursor lives in this transaction, rolled back when it is dropped
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            let _ = ready.send(Err(e.into()));
+            return;
+        }
+    };
+    if let Err(e) = declare_export_cursor(&transaction, publication_id, &filter).await {
+        let _ = ready.send(Err(e));
+        return;
+    }
+    if ready.send(Ok(())).is_err() {
+        return;
+    }
+
+    if let Some(header) = format.header() {
+        if sender
+            .send(Ok(Bytes::from_static(header.as_bytes())))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+    loop {
+        let chunk = match fetch_export_batch(&transaction, BATCH_SIZE).await {
+            Ok(batch) if batch.is_empty() => return,
+            Ok(batch) => batch.iter().try_fold(Vec::new(), |mut chunk, row| {
+                format.write_row(row, &mut chunk).map(|_| chunk)
+            }),
+            Err(e) => Err(e),
+        };
+        let outcome = match chunk {
+            Ok(chunk) => sender.send(Ok(chunk.into())).await,
+            Err(e) => {
+                tracing::error!("Failed to export subscribers: {:?}", e);
+                let _ = sender.send(Err(e)).await;
+                return;
+            }
+        };
+        // the client hung up
+        if outcome.is_err() {
+            return;
+        }
+    }
+}
+
+/// The response body, fed batch by batch by `stream_export`.
+struct ExportBody(mpsc::Receiver<Result<Bytes, ExportError>>);
+
+impl MessageBody for ExportBody {
+    type Error = std::io::Error;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.0
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map_err(std::io::Error::other)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_field, ExportFormat, CSV_HEADER};
+    use crate::csv::CsvReader;
+    use crate::subscriber_import::{ImportFormat, ImportedStatus, RowLayout};
+    use crate::subscribers::{ExportedSubscriber, Subscriber};
+    use crate::suppression::SuppressionReason;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn exported() -> ExportedSubscriber {
+        ExportedSubscriber {
+            subscriber: Subscriber {
+                id: Uuid::new_v4(),
+                email: "ursula@example.com".into(),
+                name: "Le Guin, Ursula".into(),
+                status: "unsubscribed".into(),
+                tags: vec!["authors".into(), "vip".into()],
+                subscribed_at: Utc::now(),
+            },
+            suppression_reason: Some(SuppressionReason::Manual),
+            suppressed_at: Some(Utc::now()),
+            consent_source: Some("signup_form".into()),
+            consent_text_version: Some("2025-03".into()),
+            consented_at: Some(Utc::now()),
+            consent_ip: Some("203.0.113.7".into()),
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn csv_fields_never_start_a_formula() {
+        assert_eq!(csv_field("=HYPERLINK(1)"), "'=HYPERLINK(1)");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("+1,2"), "\"'+1,2\"");
+    }
+
+    #[test]
+    fn csv_rows_match_the_header() {
+        let mut out = Vec::new();
+        ExportFormat::Csv.write_row(&exported(), &mut out).unwrap();
+        let line = String::from_utf8(out).unwrap();
+        assert!(line.ends_with("\r\n"));
+        assert!(line.contains(",\"Le Guin, Ursula\",unsubscribed,authors;vip,"));
+        assert!(line.contains(",signup_form,2025-03,"));
+        assert!(line.ends_with(",203.0.113.7\r\n"));
+        // the only quoted comma is in the name
+        assert_eq!(
+            line.matches(',').count() - 1,
+            CSV_HEADER.matches(',').count()
+        );
+    }
+
+    #[test]
+    fn ndjson_rows_are_one_object_per_line() {
+        let mut out = Vec::new();
+        ExportFormat::Ndjson
+            .write_row(&exported(), &mut out)
+            .unwrap();
+        let line = String::from_utf8(out).unwrap();
+        assert_eq!(line.matches('\n').count(), 1);
+        let value: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(value["email"], "ursula@example.com");
+        assert_eq!(value["tags"], serde_json::json!(["authors", "vip"]));
+        assert_eq!(value["suppression_reason"], "manual");
+        assert_eq!(value["consent_source"], "signup_form");
+        assert_eq!(value["consent_ip"], "203.0.113.7");
+    }
+
+    fn export(format: ExportFormat, row: &ExportedSubscriber) -> String {
+        let mut out = format.header().unwrap().as_bytes().to_vec();
+        format.write_row(row, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn mailchimp_exports_import_back() {
+        let mut row = exported();
+        row.subscriber.name = "Ursula K. Le Guin".into();
+        row.subscriber.tags = vec!["authors".into(), "sci-fi, fantasy".into()];
+        row.suppression_reason = Some(SuppressionReason::HardBounce);
+        let content = export(ExportFormat::Mailchimp, &row);
+
+        let mut records = CsvReader::new(&content).map(Result::unwrap);
+        let header = records.next().unwrap();
+        let layout =
+            RowLayout::resolve(ImportFormat::Mailchimp, &Default::default(), &header).unwrap();
+        let record = records.next().unwrap();
+        assert_eq!(record.get(1), Some("Ursula"));
+        assert_eq!(record.get(4), Some("cleaned"));
+        let imported = layout.parse_row(&record).unwrap();
+        assert_eq!(imported.name.as_ref(), "Ursula K. Le Guin");
+        assert_eq!(imported.tags, Some(row.subscriber.tags));
+        assert_eq!(imported.status, Some(ImportedStatus::Bounced));
+    }
+
+    #[test]
+    fn mailchimp_statuses_follow_the_suppression_list() {
+        let mut row = exported();
+        for (status, reason, expected) in [
+            ("confirmed", None, "subscribed"),
+            ("confirmed", Some(SuppressionReason::Manual), "unsubscribed"),
+            ("unsubscribed", None, "unsubscribed"),
+            ("bounced", None, "cleaned"),
+            (
+                "complained",
+                Some(SuppressionReason::Complaint),
+                "unsubscribed",
+            ),
+        ] {
+            row.subscriber.status = status.into();
+            row.suppression_reason = reason;
+            assert_eq!(super::mailchimp_status(&row), expected);
+        }
+    }
+
+    #[test]
+    fn substack_exports_leave_out_who_we_cannot_mail() {
+        let mut row = exported();
+        assert_eq!(export(ExportFormat::Substack, &row).lines().count(), 1);
+        row.subscriber.status = "confirmed".into();
+        row.suppression_reason = None;
+        let content = export(ExportFormat::Substack, &row);
+        let line = content.lines().nth(1).unwrap();
+        assert!(line.starts_with("ursula@example.com,\"Le Guin, Ursula\","));
+    }
+}
@@ -0,0 +1,205 @@
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use uuid::Uuid;
+
+use crate::audit::{record, AuditEvent};
+use crate::authentication::permissions::{ReadSubscribers, WriteSubscribers};
+use crate::authentication::Authorized;
+use crate::publication::PublicationId;
+use crate::subscriber_import::{
+    create_import, get_import, inspect, list_rejections, ColumnMapping, DuplicatePolicy,
+    ImportFormat, NewImport,
+};
+
+/// Uploads larger than this are refused before they are read.
+pub const MAX_IMPORT_SIZE: usize = 64 * 1024 * 1024;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Missing columns default to the `email` and `name` headers, and no tags,
+/// status or subscription date. The columns of Mailchimp and Substack
+/// exports are fixed, so their formats ignore these.
+#[derive(serde::Deserialize, Debug)]
+pub struct ImportOptions {
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    on_duplicate: DuplicatePolicy,
+    #[serde(default)]
+    format: ImportFormat,
+    email_column: Option<String>,
+    name_column: Option<String>,
+    tags_column: Option<String>,
+    status_column: Option<String>,
+    subscribed_at_column: Option<String>,
+}
+
+impl ImportOptions {
+    fn columns(&self) -> ColumnMapping {
+        let defaults = ColumnMapping::default();
+        let present = |column: &Option<String>| {
+            column
+                .as_deref()
+                .map(str::trim)
+                .filter(|column| !column.is_empty())
+                .map(str::to_owned)
+        };
+        ColumnMapping {
+            email: present(&self.email_column).unwrap_or(defaults.email),
+            name: present(&self.name_column).unwrap_or(defaults.name),
+            tags: present(&self.tags_column),
+            status: present(&self.status_column),
+            subscribed_at: present(&self.subscribed_at_column),
+        }
+    }
+}
+
+/// The body is the CSV file itself. Its header is checked right away, the
+/// rows are imported in the background: poll the returned location.
+#[tracing::instrument(
+    name = "Uploading subscribers to import",
+    skip(request, principal, body, pool)
+)]
+pub async fn import_subscribers(
+    request: HttpRequest,
+    principal: Authorized<WriteSubscribers>,
+    publication_id: PublicationId,
+    options: web::Query<ImportOptions>,
+    body: web::Bytes,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let Ok(content) = std::str::from_utf8(&body) else {
+        return HttpResponse::BadRequest().body("The file must be encoded in UTF-8.");
+    };
+    let columns = options.columns();
+    let total_rows = match inspect(content, options.format, &columns) {
+        Ok(total_rows) => total_rows,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let import = NewImport {
+        content,
+        format: options.format,
+        columns: &columns,
+        on_duplicate: options.on_duplicate,
+        dry_run: options.dry_run,
+        total_rows,
+    };
+    let audit = AuditEvent::new("subscribers.import_started")
+        .principal(principal.principal())
+        .request(&request)
+        .details(serde_json::json!({
+            "dry_run": options.dry_run,
+            "on_duplicate": options.on_duplicate,
+            "format": options.format,
+            "columns": columns,
+            "total_rows": total_rows,
+        }));
+    match queue_import(&pool, publication_id, import, audit).await {
+        Ok(import_id) => HttpResponse::Accepted()
+            .insert_header((
+                LOCATION,
+                format!("/admin/subscribers/imports/{}", import_id),
+            ))
+            .json(serde_json::json!({
+                "import_id": import_id,
+                "total_rows": total_rows,
+            })),
+        Err(e) => {
+            tracing::error!("Failed to queue an import: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn queue_import(
+    pool: &Pool,
+    publication_id: PublicationId,
+    import: NewImport<'_>,
+    audit: AuditEvent<'_>,
+) -> Result<Uuid, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let import_id = create_import(&transaction, publication_id, import, false).await?;
+    record(
+        &transaction,
+        audit.target(format!("subscriber_import:{}", import_id)),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(import_id)
+}
+
+#[tracing::instrument(name = "Checking on an import", skip(pool))]
+pub async fn get_import_progress(
+    _: Authorized<ReadSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let outcome = match pool.get().await {
+        Ok(client) => get_import(&client, publication_id, *id).await,
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(Some(import)) => HttpResponse::Ok().json(import),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to get an import: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct RejectionsQuery {
+    /// Only lines after this one; pass `next_after_line` to get the next
+    /// page.
+    #[serde(default)]
+    after_line: i32,
+    limit: Option<i64>,
+}
+
+/// The lines that were not imported and why, in file order.
+#[tracing::instrument(name = "Listing rejected import lines", skip(pool))]
+pub async fn get_import_rejections(
+    _: Authorized<ReadSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    query: web::Query<RejectionsQuery>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let outcome = match pool.get().await {
+        Ok(client) => match get_import(&client, publication_id, *id).await {
+            Ok(Some(import)) => list_rejections(&client, import.id, query.after_line, limit)
+                .await
+                .map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(Some(rejections)) => {
+            let next_after_line = if rejections.len() as i64 == limit {
+                rejections.last().map(|rejection| rejection.line)
+            } else {
+                None
+            };
+            HttpResponse::Ok().json(serde_json::json!({
+                "rejections": rejections,
+                "next_after_line": next_after_line,
+            }))
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to list rejected import lines: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,486 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use htmlescape::{encode_attribute, encode_minimal};
+use uuid::Uuid;
+
+use super::render_page;
+use crate::audit::{record, AuditEvent};
+use crate::authentication::permissions::{ReadSubscribers, WriteSubscribers};
+use crate::authentication::{Authorized, Session};
+use crate::consent::{list_consent_events, ConsentContext};
+use crate::domain::{SubscriberName, TransitionError};
+use crate::erasure::{erase_address, Erasure, ErasureScope};
+use crate::publication::PublicationId;
+use crate::subscribers::{
+    confirm_subscriber, count_subscribers, delete_subscriber, get_subscriber, list_subscribers,
+    parse_tag, suppress_subscriber, update_subscriber, Subscriber, SubscriberCursor,
+    SubscriberFilter, SubscriberPage,
+};
+use crate::suppression::{email_hash, find_suppression, Suppression};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+/// The filters come in the same query string, see `SubscriberFilter`.
+#[derive(serde::Deserialize)]
+pub struct SubscriberPageQuery {
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+    limit: Option<i64>,
+    #[serde(default)]
+    format: ListFormat,
+}
+
+/// Browser sessions get a page unless they ask for `format=json`, API keys
+/// always get JSON.
+#[tracing::instrument(name = "Listing subscribers", skip_all, fields(filter = ?filter))]
+pub async fn subscribers_page(
+    _: Authorized<ReadSubscribers>,
+    session: Option<Session>,
+    publication_id: PublicationId,
+    filter: web::Query<SubscriberFilter>,
+    query: web::Query<SubscriberPageQuery>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let filter = filter.into_inner().without_blanks();
+    let cursor = match query.cursor.as_deref().filter(|c| !c.is_empty()) {
+        Some(cursor) => match SubscriberCursor::parse(cursor) {
+            Ok(cursor) => Some(cursor),
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        },
+        None => None,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let outcome = match pool.get().await {
+        Ok(client) => match list_subscribers(&client, publication_id, &filter, cursor, limit).await
+        {
+            Ok(page) => count_subscribers(&client, publication_id, &filter)
+                .await
+                .map(|total| (page, total)),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
+    let (page, total) = match outcome {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            tracing::error!("Failed to list subscribers: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match session {
+        Some(session) if query.format == ListFormat::Html => {
+            let body = render_subscribers(&filter, &page, total);
+            render_page(&pool, &session, "Subscribers", &body).await
+        }
+        _ => HttpResponse::Ok().json(serde_json::json!({
+            "subscribers": page.subscribers,
+            "total": total,
+            "next_cursor": page.next.map(|cursor| cursor.encode()),
+        })),
+    }
+}
+
+fn render_subscribers(filter: &SubscriberFilter, page: &SubscriberPage, total: i64) -> String {
+    let mut rows = String::new();
+    for subscriber in &page.subscribers {
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&subscriber.email),
+            encode_minimal(&subscriber.name),
+            encode_minimal(&subscriber.status),
+            encode_minimal(&subscriber.tags.join(", ")),
+            subscriber.subscribed_at.format("%Y-%m-%d %H:%M"),
+        ));
+    }
+    let filter_query = serde_urlencoded::to_string(filter).unwrap_or_default();
+    let next = match page.next {
+        Some(cursor) => {
+            let mut href = filter_query.clone();
+            if !href.is_empty() {
+                href.push('&');
+            }
+            href.push_str(&format!("cursor={}", cursor.encode()));
+            format!(
+                r#"<p><a href="/admin/subscribers?{}">Next page</a></p>"#,
+                encode_attribute(&href)
+            )
+        }
+        None => String::new(),
+    };
+    let export = format!(
+        r#"<p>Export these subscribers as <a href="/admin/subscribers/export?{0}">CSV</a> or <a href="/admin/subscribers/export?{0}&amp;format=ndjson">NDJSON</a>, or for <a href="/admin/subscribers/export?{0}&amp;format=mailchimp">Mailchimp</a> or <a href="/admin/subscribers/export?{0}&amp;format=substack">Substack</a></p>"#,
+        encode_attribute(&filter_query)
+    );
+    let date = |day: Option<chrono::NaiveDate>| day.map(|d| d.to_string()).unwrap_or_default();
+    let field = |value: &Option<String>| encode_attribute(value.as_deref().unwrap_or_default());
+
+    format!(
+        r#"<form action="/admin/subscribers" method="get">
+        <label>Email or name <input type="search" name="q" value="{q}"></label>
+        <label>Status <input type="text" name="status" value="{status}"></label>
+        <label>Tag <input type="text" name="tag" value="{tag}"></label>
+        <label>Subscribed from <input type="date" name="subscribed_from" value="{from}"></label>
+        <label>to <input type="date" name="subscribed_to" value="{to}"></label>
+        <button type="submit">Search</button>
+        <a href="/admin/subscribers">Clear</a>
+    </form>
+    <p>{total} subscriber(s)</p>
+    <table>
+        <tr><th>Email</th><th>Name</th><th>Status</th><th>Tags</th><th>Subscribed at</th></tr>
+        {rows}
+    </table>
+    {next}
+    {export}"#,
+        q = field(&filter.q),
+        status = field(&filter.status),
+        tag = field(&filter.tag),
+        from = date(filter.subscribed_from),
+        to = date(filter.subscribed_to),
+    )
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberDetails {
+    #[serde(flatten)]
+    subscriber: Subscriber,
+    /// Set if we do not email the address, whatever the status says.
+    suppression: Option<Suppression>,
+}
+
+#[tracing::instrument(name = "Viewing a subscriber", skip(pool))]
+pub async fn get_subscriber_details(
+    _: Authorized<ReadSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let outcome = match pool.get().await {
+        Ok(client) => match get_subscriber(&client, publication_id, *id).await {
+            Ok(Some(subscriber)) => {
+                find_suppression(&client, &subscriber.email, Some(publication_id))
+                    .await
+                    .map(|suppression| {
+                        Some(SubscriberDetails {
+                            subscriber,
+                            suppression,
+                        })
+                    })
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(Some(details)) => HttpResponse::Ok().json(details),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to get a subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Oldest first. Records outlive the subscription, so they can still be
+/// listed after the subscriber was deleted.
+#[tracing::instrument(name = "Viewing the consent records of a subscriber", skip(pool))]
+pub async fn get_consent_events(
+    _: Authorized<ReadSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let outcome = match pool.get().await {
+        Ok(client) => list_consent_events(&client, publication_id, *id).await,
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(e) => {
+            tracing::error!("Failed to list consent events: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Fields left out are kept; `tags` replaces all of them.
+#[derive(serde::Deserialize)]
+pub struct SubscriberUpdate {
+    name: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+#[tracing::instrument(name = "Editing a subscriber", skip(request, body, principal, pool))]
+pub async fn edit_subscriber(
+    request: HttpRequest,
+    principal: Authorized<WriteSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    body: web::Json<SubscriberUpdate>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let name = match body.name.map(SubscriberName::parse).transpose() {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let tags = match body
+        .tags
+        .map(|tags| tags.iter().map(|tag| parse_tag(tag)).collect())
+        .transpose()
+    {
+        Ok(tags) => tags.map(|mut tags: Vec<String>| {
+            tags.sort_unstable();
+            tags.dedup();
+            tags
+        }),
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let name = name.as_ref().map(AsRef::as_ref);
+    let audit = AuditEvent::new("subscriber.updated")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("subscriber:{}", id))
+        .details(serde_json::json!({ "name": name, "tags": tags }));
+    match update_and_record(&pool, publication_id, *id, name, tags.as_deref(), audit).await {
+        Ok(Some(subscriber)) => HttpResponse::Ok().json(subscriber),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to edit a subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Suppressed addresses stay unconfirmed: lift the suppression first.
+/// Subscribers who left cannot be confirmed back, they have to sign up.
+#[tracing::instrument(
+    name = "Manually confirming a subscriber",
+    skip(request, principal, pool)
+)]
+pub async fn confirm_subscriber_manually(
+    request: HttpRequest,
+    principal: Authorized<WriteSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let consent = ConsentContext::new("admin").request(&request);
+    let audit = AuditEvent::new("subscriber.confirmed")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("subscriber:{}", id));
+    let outcome = match pool.get().await {
+        Ok(mut client) => match get_subscriber(&client, publication_id, *id).await {
+            Ok(Some(subscriber)) => {
+                match find_suppression(&client, &subscriber.email, Some(publication_id)).await {
+                    Ok(Some(_)) => {
+                        return HttpResponse::Conflict()
+                            .body("The address is suppressed: lift the suppression first.")
+                    }
+                    Ok(None) => {
+                        confirm_and_record(&mut client, publication_id, *id, &consent, audit).await
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(Some(Ok(()))) => HttpResponse::Ok().finish(),
+        Ok(Some(Err(refusal))) => HttpResponse::Conflict().body(refusal.to_string()),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to confirm a subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The changes and their audit entry commit together, or not at all.
+async fn update_and_record(
+    pool: &Pool,
+    publication_id: PublicationId,
+    id: Uuid,
+    name: Option<&str>,
+    tags: Option<&[String]>,
+    audit: AuditEvent<'_>,
+) -> Result<Option<Subscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let Some(subscriber) = update_subscriber(&transaction, publication_id, id, name, tags).await?
+    else {
+        return Ok(None);
+    };
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(Some(subscriber))
+}
+
+/// The confirmation, its consent record and its audit entry commit
+/// together.
+async fn confirm_and_record(
+    client: &mut deadpool_postgres::Client,
+    publication_id: PublicationId,
+    id: Uuid,
+    consent: &ConsentContext,
+    audit: AuditEvent<'_>,
+) -> Result<Option<Result<(), TransitionError>>, Box<dyn std::error::Error + Send + Sync>> {
+    let transaction = client.transaction().await?;
+    let outcome = confirm_subscriber(&transaction, publication_id, id, consent).await?;
+    if let Some(Ok(())) = outcome {
+        record(&transaction, audit).await?;
+    }
+    transaction.commit().await?;
+    Ok(outcome)
+}
+
+#[tracing::instrument(
+    name = "Manually suppressing a subscriber",
+    skip(request, principal, pool)
+)]
+pub async fn suppress_subscriber_manually(
+    request: HttpRequest,
+    principal: Authorized<WriteSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let audit = AuditEvent::new("subscriber.suppressed")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("subscriber:{}", id));
+    let change = Change::Suppress(ConsentContext::new("admin").request(&request));
+    match change_and_record(&pool, publication_id, *id, change, audit).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to suppress a subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Deleting a subscriber", skip(request, principal, pool))]
+pub async fn remove_subscriber(
+    request: HttpRequest,
+    principal: Authorized<WriteSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let audit = AuditEvent::new("subscriber.deleted")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("subscriber:{}", id));
+    let change = Change::Delete(ConsentContext::new("admin").request(&request));
+    match change_and_record(&pool, publication_id, *id, change, audit).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to delete a subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// On the subscriber's behalf, e.g. for a request that reached us by mail:
+/// they can also do it themselves from `/erasure`.
+#[tracing::instrument(name = "Erasing a subscriber", skip(request, principal, pool))]
+pub async fn erase_subscriber(
+    request: HttpRequest,
+    principal: Authorized<WriteSubscribers>,
+    publication_id: PublicationId,
+    id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let audit = AuditEvent::new("subscriber.erased")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("subscriber:{}", id));
+    match erase_and_record(&pool, publication_id, *id, audit).await {
+        Ok(Some(erasure)) => HttpResponse::Ok().json(erasure),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to erase a subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The audit entry only keeps a hash of the address.
+async fn erase_and_record(
+    pool: &Pool,
+    publication_id: PublicationId,
+    id: Uuid,
+    audit: AuditEvent<'_>,
+) -> Result<Option<Erasure>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let Some(subscriber) = get_subscriber(&transaction, publication_id, id).await? else {
+        return Ok(None);
+    };
+    let scope = ErasureScope::Publication(publication_id);
+    let erasure = erase_address(&transaction, &subscriber.email, scope).await?;
+    let details = serde_json::json!({
+        "email": email_hash(&subscriber.email),
+        "via": "admin",
+        "erased": erasure,
+    });
+    record(&transaction, audit.details(details)).await?;
+    transaction.commit().await?;
+    Ok(Some(erasure))
+}
+
+enum Change {
+    Suppress(ConsentContext),
+    Delete(ConsentContext),
+}
+
+/// Both changes touch more than the subscription, so they and their audit
+/// entry commit together. Returns `false` if there is no such subscriber.
+async fn change_and_record(
+    pool: &Pool,
+    publication_id: PublicationId,
+    id: Uuid,
+    change: Change,
+    audit: AuditEvent<'_>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let subscriber = match change {
+        Change::Suppress(consent) => {
+            suppress_subscriber(&transaction, publication_id, id, &consent).await?
+        }
+        Change::Delete(consent) => {
+            delete_subscriber(&transaction, publication_id, id, &consent).await?
+        }
+    };
+    let Some(subscriber) = subscriber else {
+        return Ok(false);
+    };
+    record(
+        &transaction,
+        audit.details(serde_json::json!({ "email": subscriber.email })),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(true)
+}
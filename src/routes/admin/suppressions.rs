@@ -0,0 +1,191 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+
+use crate::audit::{record, AuditEvent};
+use crate::authentication::permissions::{ReadSubscribers, WriteSubscribers};
+use crate::authentication::{Authorized, Principal, Role};
+use crate::domain::SubscriberEmail;
+use crate::publication::PublicationId;
+use crate::suppression::{email_hash, list_suppressions, suppress, unsuppress, SuppressionReason};
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionData {
+    email: String,
+    reason: Option<SuppressionReason>,
+}
+
+/// Which suppressions a request works on.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionScope {
+    /// The publication's own.
+    #[default]
+    Publication,
+    /// Those stopping every publication's emails: bounces, complaints and
+    /// erasures.
+    Global,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SuppressionScopeQuery {
+    #[serde(default)]
+    scope: SuppressionScope,
+}
+
+/// The publication whose suppressions the request works on, or `None` for
+/// those of every publication. They name other publications' subscribers,
+/// so only owners get to them.
+fn suppression_publication(
+    principal: &Principal,
+    scope: SuppressionScope,
+    publication_id: Option<PublicationId>,
+) -> Result<Option<PublicationId>, HttpResponse> {
+    match scope {
+        SuppressionScope::Publication => publication_id.map(Some).ok_or_else(|| {
+            HttpResponse::Forbidden()
+                .body("You do not belong to any publication yet: ask an owner to add you.")
+        }),
+        SuppressionScope::Global => match principal {
+            Principal::User {
+                role: Role::Owner, ..
+            } => Ok(None),
+            _ => Err(HttpResponse::Forbidden()
+                .body("Only owners can see suppressions of every publication.")),
+        },
+    }
+}
+
+#[tracing::instrument(name = "Listing suppressed addresses", skip(principal, pool))]
+pub async fn get_suppressions(
+    principal: Authorized<ReadSubscribers>,
+    publication_id: Option<PublicationId>,
+    query: web::Query<SuppressionScopeQuery>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let publication_id =
+        match suppression_publication(principal.principal(), query.scope, publication_id) {
+            Ok(publication_id) => publication_id,
+            Err(response) => return response,
+        };
+    let outcome = match pool.get().await {
+        Ok(client) => list_suppressions(&client, publication_id).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match outcome {
+        Ok(suppressions) => HttpResponse::Ok().json(suppressions),
+        Err(e) => {
+            tracing::error!("Failed to list suppressions: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Manually suppressing an address",
+    skip(request, body, principal, pool),
+    fields(email = %body.email)
+)]
+pub async fn add_suppression(
+    request: HttpRequest,
+    body: web::Json<SuppressionData>,
+    principal: Authorized<WriteSubscribers>,
+    publication_id: PublicationId,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let email = match SubscriberEmail::parse(body.email) {
+        Ok(email) => email,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    // Bounces and complaints are the provider's to report, and erasures
+    // only ever keep a hash of the address
+    let reason = match body.reason.unwrap_or(SuppressionReason::Manual) {
+        reason @ (SuppressionReason::Manual | SuppressionReason::Unsubscribe) => reason,
+        reason => {
+            return HttpResponse::BadRequest().body(format!(
+                "Suppressions cannot be added with reason {}.",
+                reason.as_str()
+            ))
+        }
+    };
+    let audit = AuditEvent::new("suppression.added")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("email:{}", email_hash(email.as_ref())))
+        .details(serde_json::json!({ "reason": reason }));
+
+    match suppress_and_record(&pool, email.as_ref(), reason, publication_id, audit).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            tracing::error!("Failed to add suppression: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Lifting the suppression of an address",
+    skip(request, principal, pool)
+)]
+pub async fn delete_suppression(
+    request: HttpRequest,
+    email: web::Path<String>,
+    principal: Authorized<WriteSubscribers>,
+    publication_id: Option<PublicationId>,
+    query: web::Query<SuppressionScopeQuery>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let publication_id =
+        match suppression_publication(principal.principal(), query.scope, publication_id) {
+            Ok(publication_id) => publication_id,
+            Err(response) => return response,
+        };
+    let audit = AuditEvent::new("suppression.removed")
+        .principal(principal.principal())
+        .request(&request)
+        .target(format!("email:{}", email_hash(&email)))
+        .details(serde_json::json!({ "scope": query.scope }));
+
+    match unsuppress_and_record(&pool, &email, publication_id, audit).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to remove suppression: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The suppression and its audit entry commit together, or not at all.
+async fn suppress_and_record(
+    pool: &Pool,
+    email: &str,
+    reason: SuppressionReason,
+    publication_id: PublicationId,
+    audit: AuditEvent<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    suppress(&transaction, email, reason, Some(publication_id)).await?;
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Returns `false` if the address was not suppressed, and nothing changed.
+async fn unsuppress_and_record(
+    pool: &Pool,
+    email: &str,
+    publication_id: Option<PublicationId>,
+    audit: AuditEvent<'_>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    if !unsuppress(&transaction, email, publication_id).await? {
+        return Ok(false);
+    }
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
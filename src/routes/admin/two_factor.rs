@@ -0,0 +1,359 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use htmlescape::encode_minimal;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use super::{csrf_field, redirect_with_flash, render_page};
+use crate::audit::{record, record_from_handler, AuditEvent};
+use crate::authentication::permissions::ManageUsers;
+use crate::authentication::{
+    base32_encode, confirm_totp_enrollment, disable_two_factor, get_username, provisioning_uri,
+    replace_recovery_codes, set_two_factor_required, start_totp_enrollment, two_factor_required,
+    two_factor_status, verify_second_factor, Authorized, FlashMessage, Session, TwoFactorStatus,
+};
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorCode {
+    code: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorPolicyData {
+    /// A checkbox: only sent when ticked.
+    required: Option<String>,
+}
+
+/// Every logged in user manages their own second factor, whatever their role.
+#[tracing::instrument(name = "Showing two-factor authentication", skip_all)]
+pub async fn two_factor_page(session: Session, pool: web::Data<Pool>) -> HttpResponse {
+    let status = match pool.get().await {
+        Ok(client) => match two_factor_status(&client, session.user_id).await {
+            Ok(status) => get_username(&client, session.user_id)
+                .await
+                .map(|username| (status, username.unwrap_or_default())),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
+    let (status, username) = match status {
+        Ok(status) => status,
+        Err(e) => {
+            tracing::error!("Failed to read the two-factor status: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let csrf = csrf_field(&session);
+    let mandatory = if session.must_enroll_two_factor {
+        "<p><b>Two-factor authentication is mandatory: set it up to continue.</b></p>"
+    } else {
+        ""
+    };
+    let body = match status {
+        TwoFactorStatus::Disabled => format!(
+            r#"{mandatory}
+    <p>Two-factor authentication is off. Once on, logging in takes a code from an
+    authenticator app on top of the password.</p>
+    <form action="/admin/two_factor/setup" method="post">
+        {csrf}
+        <button type="submit">Set up</button>
+    </form>"#
+        ),
+        TwoFactorStatus::Enrolling { secret } => format!(
+            r#"{mandatory}
+    <p>Scan this provisioning URI as a QR code with your authenticator app:</p>
+    <pre>{uri}</pre>
+    <p>or enter the key <code>{key}</code> by hand. Then type in the code it shows.</p>
+    <form action="/admin/two_factor/enable" method="post">
+        {csrf}
+        <label>Code <input type="text" name="code" autocomplete="one-time-code"></label>
+        <button type="submit">Turn on</button>
+    </form>
+    <form action="/admin/two_factor/setup" method="post">
+        {csrf}
+        <button type="submit">Start over with a new key</button>
+    </form>"#,
+            uri = encode_minimal(&provisioning_uri(&username, &secret)),
+            key = base32_encode(&secret),
+        ),
+        TwoFactorStatus::Enabled {
+            unused_recovery_codes,
+        } => format!(
+            r#"<p>Two-factor authentication is on. {unused_recovery_codes} unused recovery code(s) left.</p>
+    <h2>New recovery codes</h2>
+    <form action="/admin/two_factor/recovery_codes" method="post">
+        {csrf}
+        <label>Current code <input type="text" name="code" autocomplete="one-time-code"></label>
+        <button type="submit">Replace recovery codes</button>
+    </form>
+    <h2>Turn off</h2>
+    <form action="/admin/two_factor/disable" method="post">
+        {csrf}
+        <label>Current code <input type="text" name="code" autocomplete="one-time-code"></label>
+        <button type="submit">Turn off</button>
+    </form>"#
+        ),
+    };
+    render_page(&pool, &session, "Two-factor authentication", &body).await
+}
+
+#[tracing::instrument(name = "Setting up two-factor authentication", skip_all)]
+pub async fn start_two_factor_setup(session: Session, pool: web::Data<Pool>) -> HttpResponse {
+    let outcome = match pool.get().await {
+        Ok(client) => start_totp_enrollment(&client, session.user_id).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = outcome {
+        tracing::error!("Failed to start the enrollment: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    redirect_with_flash(
+        &pool,
+        &session,
+        "/admin/two_factor",
+        FlashMessage::info("A new key has been generated."),
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Turning two-factor authentication on", skip_all)]
+pub async fn enable_two_factor(
+    request: HttpRequest,
+    session: Session,
+    form: web::Form<TwoFactorCode>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let audit = |action| {
+        AuditEvent::new(action)
+            .actor(session.user_id)
+            .request(&request)
+    };
+    let outcome = enable_and_record(
+        &pool,
+        session.user_id,
+        &form.code,
+        audit("two_factor.enabled"),
+    )
+    .await;
+    match outcome {
+        Ok(Some(codes)) => render_recovery_codes(&pool, &session, &codes).await,
+        Ok(None) => {
+            record_from_handler(&pool, audit("two_factor.enable_failed")).await;
+            let flash = FlashMessage::error("That code is not valid, try the next one.");
+            redirect_with_flash(&pool, &session, "/admin/two_factor", flash).await
+        }
+        Err(e) => {
+            tracing::error!("Failed to turn two-factor authentication on: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Replacing recovery codes", skip_all)]
+pub async fn regenerate_recovery_codes(
+    request: HttpRequest,
+    session: Session,
+    form: web::Form<TwoFactorCode>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let audit = |action| {
+        AuditEvent::new(action)
+            .actor(session.user_id)
+            .request(&request)
+    };
+    let outcome = replace_codes_and_record(
+        &pool,
+        session.user_id,
+        &form.code,
+        audit("two_factor.recovery_codes_replaced"),
+    )
+    .await;
+    match outcome {
+        Ok(Some(codes)) => render_recovery_codes(&pool, &session, &codes).await,
+        Ok(None) => {
+            record_from_handler(&pool, audit("two_factor.verification_failed")).await;
+            let flash = FlashMessage::error("That code is not valid.");
+            redirect_with_flash(&pool, &session, "/admin/two_factor", flash).await
+        }
+        Err(e) => {
+            tracing::error!("Failed to replace recovery codes: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Turning two-factor authentication off", skip_all)]
+pub async fn turn_off_two_factor(
+    request: HttpRequest,
+    session: Session,
+    form: web::Form<TwoFactorCode>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("Failed to get a database connection: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let audit = |action| {
+        AuditEvent::new(action)
+            .actor(session.user_id)
+            .request(&request)
+    };
+    let outcome = match two_factor_required(&client).await {
+        Ok(true) => Ok(Some(FlashMessage::error(
+            "Two-factor authentication is mandatory, it cannot be turned off.",
+        ))),
+        Ok(false) => {
+            let disabled = disable_and_record(
+                &pool,
+                session.user_id,
+                &form.code,
+                audit("two_factor.disabled"),
+            )
+            .await;
+            match disabled {
+                Ok(true) => Ok(None),
+                Ok(false) => {
+                    record_from_handler(&pool, audit("two_factor.verification_failed")).await;
+                    Ok(Some(FlashMessage::error("That code is not valid.")))
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    let flash = match outcome {
+        Ok(Some(rejection)) => rejection,
+        Ok(None) => FlashMessage::info("Two-factor authentication is off."),
+        Err(e) => {
+            tracing::error!("Failed to turn two-factor authentication off: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    redirect_with_flash(&pool, &session, "/admin/two_factor", flash).await
+}
+
+#[tracing::instrument(name = "Changing the two-factor policy", skip_all)]
+pub async fn change_two_factor_policy(
+    _: Authorized<ManageUsers>,
+    request: HttpRequest,
+    session: Session,
+    form: web::Form<TwoFactorPolicyData>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let required = form.required.is_some();
+    let event = AuditEvent::new("two_factor.policy_changed")
+        .actor(session.user_id)
+        .request(&request)
+        .details(serde_json::json!({ "required": required }));
+    if let Err(e) = set_policy_and_record(&pool, required, event).await {
+        tracing::error!("Failed to change the two-factor policy: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    let flash = if required {
+        FlashMessage::info("Two-factor authentication is now mandatory for everybody.")
+    } else {
+        FlashMessage::info("Two-factor authentication is now optional.")
+    };
+    redirect_with_flash(&pool, &session, "/admin/users", flash).await
+}
+
+// Each change commits with its audit entry, or not at all. A code that is
+// not valid changes nothing, and `None` or `false` comes back.
+
+async fn enable_and_record(
+    pool: &Pool,
+    user_id: Uuid,
+    code: &str,
+    audit: AuditEvent<'_>,
+) -> Result<Option<Vec<Secret<String>>>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let codes = confirm_totp_enrollment(&transaction, user_id, code).await?;
+    if codes.is_some() {
+        record(&transaction, audit).await?;
+    }
+    transaction.commit().await?;
+    Ok(codes)
+}
+
+async fn replace_codes_and_record(
+    pool: &Pool,
+    user_id: Uuid,
+    code: &str,
+    audit: AuditEvent<'_>,
+) -> Result<Option<Vec<Secret<String>>>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let codes = match verify_second_factor(&transaction, user_id, code).await? {
+        Some(_) => {
+            let codes = replace_recovery_codes(&transaction, user_id).await?;
+            record(&transaction, audit).await?;
+            Some(codes)
+        }
+        None => None,
+    };
+    // a recovery code used to verify stays used
+    transaction.commit().await?;
+    Ok(codes)
+}
+
+async fn disable_and_record(
+    pool: &Pool,
+    user_id: Uuid,
+    code: &str,
+    audit: AuditEvent<'_>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let is_valid = verify_second_factor(&transaction, user_id, code)
+        .await?
+        .is_some();
+    if is_valid {
+        disable_two_factor(&transaction, user_id).await?;
+        record(&transaction, audit).await?;
+    }
+    transaction.commit().await?;
+    Ok(is_valid)
+}
+
+async fn set_policy_and_record(
+    pool: &Pool,
+    required: bool,
+    audit: AuditEvent<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    set_two_factor_required(&transaction, required).await?;
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Recovery codes are shown once, in the response that created them.
+async fn render_recovery_codes(
+    pool: &Pool,
+    session: &Session,
+    codes: &[Secret<String>],
+) -> HttpResponse {
+    let codes: Vec<String> = codes
+        .iter()
+        .map(|code| {
+            format!(
+                "<li><code>{}</code></li>",
+                encode_minimal(code.expose_secret())
+            )
+        })
+        .collect();
+    let body = format!(
+        r#"<p>Keep these recovery codes somewhere safe, they will not be shown again.
+    Each of them can replace a code from your authenticator app once.</p>
+    <ul>{}</ul>
+    <p><a href="/admin/dashboard">Done</a></p>"#,
+        codes.join(""),
+    );
+    render_page(pool, session, "Recovery codes", &body).await
+}
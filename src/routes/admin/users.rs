@@ -0,0 +1,391 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use htmlescape::{encode_attribute, encode_minimal};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::{csrf_field, redirect_with_flash, render_page};
+use crate::audit::{record, AuditEvent};
+use crate::authentication::permissions::ManageUsers;
+use crate::authentication::{
+    check_password_strength, create_user, delete_user, list_users, set_user_role,
+    two_factor_required, Authorized, FlashMessage, Role, Session, User, UserChangeError,
+};
+use crate::publication::{
+    add_member, get_publication, list_member_ids, remove_member, PublicationId,
+};
+
+#[derive(serde::Deserialize)]
+pub struct NewUserData {
+    username: String,
+    #[serde(default)]
+    email: String,
+    password: Secret<String>,
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleData {
+    role: Role,
+}
+
+#[derive(serde::Deserialize)]
+pub struct MembershipData {
+    member: bool,
+}
+
+/// Users and roles are shared by all publications; membership is shown and
+/// changed for the publication being worked on.
+#[tracing::instrument(name = "Showing users", skip_all)]
+pub async fn users_page(
+    _: Authorized<ManageUsers>,
+    session: Session,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let overview = match pool.get().await {
+        Ok(client) => get_users_overview(&client, &session).await,
+        Err(e) => Err(e.into()),
+    };
+    let (users, two_factor_required, membership) = match overview {
+        Ok(overview) => overview,
+        Err(e) => {
+            tracing::error!("Failed to list users: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let csrf = csrf_field(&session);
+    let mut rows = String::new();
+    for user in &users {
+        let member = match &membership {
+            Some((_, member_ids)) => {
+                let is_member = member_ids.contains(&user.user_id);
+                format!(
+                    r#"<td><form action="/admin/users/{id}/membership" method="post">{csrf}<input type="hidden" name="member" value="{value}">{state} <button type="submit">{label}</button></form></td>"#,
+                    id = user.user_id,
+                    value = !is_member,
+                    state = if is_member { "yes" } else { "no" },
+                    label = if is_member { "Remove" } else { "Add" },
+                )
+            }
+            None => String::new(),
+        };
+        rows.push_str(&format!(
+            r#"<tr><td>{username}</td><td>{email}</td><td>{two_factor}</td><td><form action="/admin/users/{id}/role" method="post">{csrf}<select name="role">{options}</select><button type="submit">Change</button></form></td>{member}<td>{created_at}</td><td><form action="/admin/users/{id}/delete" method="post">{csrf}<button type="submit">Delete</button></form></td></tr>"#,
+            username = encode_minimal(&user.username),
+            email = encode_minimal(user.email.as_deref().unwrap_or_default()),
+            two_factor = if user.two_factor_enabled { "on" } else { "off" },
+            id = user.user_id,
+            options = role_options(Some(user.role)),
+            created_at = user.created_at.format("%Y-%m-%d %H:%M"),
+        ));
+    }
+
+    let member_header = match &membership {
+        Some((publication_name, _)) => {
+            format!("<th>Member of {}</th>", encode_minimal(publication_name))
+        }
+        None => String::new(),
+    };
+    let body = format!(
+        r#"<table>
+        <tr><th>Username</th><th>Email</th><th>Two-factor</th><th>Role</th>{member_header}<th>Created</th><th></th></tr>
+        {rows}
+    </table>
+    <h2>New user</h2>
+    <form action="/admin/users" method="post">
+        {csrf}
+        <label>Username <input type="text" name="username"></label>
+        <label>Email <input type="email" name="email"></label>
+        <label>Password <input type="password" name="password"></label>
+        <select name="role">{options}</select>
+        <button type="submit">Create</button>
+    </form>
+    <h2>Two-factor authentication</h2>
+    <form action="/admin/users/two_factor_policy" method="post">
+        {csrf}
+        <label><input type="checkbox" name="required"{checked}> Mandatory for everybody</label>
+        <button type="submit">Save</button>
+    </form>"#,
+        options = role_options(None),
+        checked = if two_factor_required { " checked" } else { "" },
+    );
+    render_page(&pool, &session, "Users", &body).await
+}
+
+type UsersOverview = (Vec<User>, bool, Option<(String, Vec<Uuid>)>);
+
+/// All users, the two-factor policy and, if the session works on a
+/// publication, its name and members.
+async fn get_users_overview(
+    client: &deadpool_postgres::Client,
+    session: &Session,
+) -> Result<UsersOverview, Box<dyn std::error::Error + Send + Sync>> {
+    let users = list_users(client).await?;
+    let two_factor_required = two_factor_required(client).await?;
+    let membership = match session.publication_id {
+        Some(publication_id) => match get_publication(client, publication_id).await? {
+            Some(publication) => Some((
+                publication.name,
+                list_member_ids(client, publication_id).await?,
+            )),
+            None => None,
+        },
+        None => None,
+    };
+    Ok((users, two_factor_required, membership))
+}
+
+#[tracing::instrument(
+    name = "Adding a user",
+    skip_all,
+    fields(username = %form.username, role = ?form.role)
+)]
+pub async fn add_user(
+    request: HttpRequest,
+    _: Authorized<ManageUsers>,
+    session: Session,
+    form: web::Form<NewUserData>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    if form.username.trim().is_empty() {
+        let flash = FlashMessage::error("Users need a username.");
+        return redirect_with_flash(&pool, &session, "/admin/users", flash).await;
+    }
+    let username = form.username.trim();
+    if let Err(e) = check_password_strength(&form.password, username) {
+        return redirect_with_flash(&pool, &session, "/admin/users", FlashMessage::error(e)).await;
+    }
+    // Without an email address the user cannot reset a forgotten password
+    let email = Some(form.email.trim()).filter(|e| !e.is_empty());
+    let audit = AuditEvent::new("user.created")
+        .actor(session.user_id)
+        .request(&request)
+        .details(serde_json::json!({
+            "username": username,
+            "email": email,
+            "role": form.role,
+            "publication_id": session.publication_id.map(|id| id.0),
+        }));
+    let new_user = NewUser {
+        username,
+        email,
+        password: form.password,
+        role: form.role,
+        publication_id: session.publication_id,
+    };
+    match create_and_record(&pool, new_user, audit).await {
+        Ok(()) => {
+            let flash = FlashMessage::info("The user has been created.");
+            redirect_with_flash(&pool, &session, "/admin/users", flash).await
+        }
+        Err(e) => {
+            tracing::error!("Failed to create a user: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Changing a user's role",
+    skip(request, session, pool, form),
+    fields(role = ?form.role)
+)]
+pub async fn change_user_role(
+    request: HttpRequest,
+    _: Authorized<ManageUsers>,
+    session: Session,
+    user_id: web::Path<Uuid>,
+    form: web::Form<RoleData>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let user_id = user_id.into_inner();
+    let outcome = match pool.get().await {
+        Ok(mut client) => match client.transaction().await {
+            Ok(transaction) => match set_user_role(&transaction, user_id, form.role).await {
+                Ok(previous_role) => {
+                    let audit = AuditEvent::new("user.role_changed")
+                        .actor(session.user_id)
+                        .request(&request)
+                        .target(format!("user:{}", user_id))
+                        .details(serde_json::json!({
+                            "role": { "from": previous_role, "to": form.role }
+                        }));
+                    match record(&transaction, audit).await {
+                        Ok(()) => transaction.commit().await.map_err(UserChangeError::from),
+                        Err(e) => Err(UserChangeError::Unexpected(e)),
+                    }
+                }
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(UserChangeError::Unexpected(e.into())),
+    };
+    user_change_response(&pool, &session, outcome).await
+}
+
+#[tracing::instrument(name = "Removing a user", skip(request, session, pool))]
+pub async fn remove_user(
+    request: HttpRequest,
+    _: Authorized<ManageUsers>,
+    session: Session,
+    user_id: web::Path<Uuid>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let user_id = user_id.into_inner();
+    let outcome = match pool.get().await {
+        Ok(mut client) => match client.transaction().await {
+            Ok(transaction) => match delete_user(&transaction, user_id).await {
+                Ok(()) => {
+                    let audit = AuditEvent::new("user.deleted")
+                        .actor(session.user_id)
+                        .request(&request)
+                        .target(format!("user:{}", user_id));
+                    match record(&transaction, audit).await {
+                        Ok(()) => transaction.commit().await.map_err(UserChangeError::from),
+                        Err(e) => Err(UserChangeError::Unexpected(e)),
+                    }
+                }
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(UserChangeError::Unexpected(e.into())),
+    };
+    user_change_response(&pool, &session, outcome).await
+}
+
+#[tracing::instrument(
+    name = "Changing a user's membership",
+    skip(request, session, pool, form),
+    fields(member = form.member)
+)]
+pub async fn change_membership(
+    request: HttpRequest,
+    _: Authorized<ManageUsers>,
+    session: Session,
+    user_id: web::Path<Uuid>,
+    form: web::Form<MembershipData>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let user_id = user_id.into_inner();
+    let Some(publication_id) = session.publication_id else {
+        let flash = FlashMessage::error("Pick a publication first.");
+        return redirect_with_flash(&pool, &session, "/admin/publications", flash).await;
+    };
+    let action = if form.member {
+        "publication.member_added"
+    } else {
+        "publication.member_removed"
+    };
+    let audit = AuditEvent::new(action)
+        .actor(session.user_id)
+        .request(&request)
+        .target(format!("publication:{}", publication_id))
+        .details(serde_json::json!({ "user_id": user_id }));
+    let outcome =
+        change_membership_and_record(&pool, publication_id, user_id, form.member, audit).await;
+    let flash = match outcome {
+        Ok(true) => FlashMessage::info("The user has been updated."),
+        Ok(false) => FlashMessage::info("Nothing to change."),
+        Err(e) => {
+            tracing::error!("Failed to change a membership: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    redirect_with_flash(&pool, &session, "/admin/users", flash).await
+}
+
+struct NewUser<'a> {
+    username: &'a str,
+    email: Option<&'a str>,
+    password: Secret<String>,
+    role: Role,
+    /// The publication they were created from, which they join.
+    publication_id: Option<PublicationId>,
+}
+
+/// The user, their membership and the audit entry commit together, or not
+/// at all.
+async fn create_and_record(
+    pool: &Pool,
+    new_user: NewUser<'_>,
+    audit: AuditEvent<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let user_id = create_user(
+        &transaction,
+        new_user.username,
+        new_user.email,
+        new_user.password,
+        new_user.role,
+    )
+    .await?;
+    if let Some(publication_id) = new_user.publication_id {
+        add_member(&transaction, publication_id, user_id).await?;
+    }
+    record(&transaction, audit.target(format!("user:{}", user_id))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Returns `false` if the user already was, or was not, a member.
+async fn change_membership_and_record(
+    pool: &Pool,
+    publication_id: PublicationId,
+    user_id: Uuid,
+    member: bool,
+    audit: AuditEvent<'_>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let changed = if member {
+        add_member(&transaction, publication_id, user_id).await?
+    } else {
+        remove_member(&transaction, publication_id, user_id).await?
+    };
+    if !changed {
+        return Ok(false);
+    }
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+async fn user_change_response(
+    pool: &Pool,
+    session: &Session,
+    outcome: Result<(), UserChangeError>,
+) -> HttpResponse {
+    let flash = match outcome {
+        Ok(()) => FlashMessage::info("The user has been updated."),
+        Err(e @ (UserChangeError::NotFound | UserChangeError::LastOwner)) => {
+            FlashMessage::error(e.to_string())
+        }
+        Err(e) => {
+            tracing::error!("Failed to change a user: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    redirect_with_flash(pool, session, "/admin/users", flash).await
+}
+
+fn role_options(selected: Option<Role>) -> String {
+    Role::ALL
+        .into_iter()
+        .map(|role| {
+            format!(
+                r#"<option value="{0}"{1}>{0}</option>"#,
+                encode_attribute(role.as_str()),
+                if Some(role) == selected {
+                    " selected"
+                } else {
+                    ""
+                },
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,252 @@
+use actix_web::http::header::{ContentType, CONTENT_DISPOSITION};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use deadpool_postgres::Pool;
+use htmlescape::encode_attribute;
+use uuid::Uuid;
+
+use super::password_reset::render_public_page;
+use crate::audit::{record, record_from_handler, AuditEvent};
+use crate::authentication::LinkSigner;
+use crate::data_export::{
+    get_archive, queue_data_export, ARCHIVE_LINK_PURPOSE, DATA_EXPORT_LINK_PURPOSE,
+    DATA_EXPORT_LINK_TTL_HOURS,
+};
+use crate::delivery_worker::enqueue_transactional_email;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::rate_limit::{client_ip, too_many_requests, RateLimiter};
+use crate::startup::ApplicationBaseUrl;
+use crate::subscribers::is_subscribed_anywhere;
+use crate::suppression::email_hash;
+
+#[derive(serde::Deserialize)]
+pub struct DataExportRequest {
+    email: String,
+}
+
+pub async fn data_export_form() -> HttpResponse {
+    render_public_page(
+        "Download my data",
+        r#"<form action="/data_export" method="post">
+        <label>Email <input type="email" name="email"></label>
+        <button type="submit">Send me a link</button>
+    </form>
+    <p>We will email you a link to confirm, then another to download everything we hold
+    about the address once it is ready.</p>"#,
+    )
+}
+
+/// The response is the same whether or not the address is subscribed, so
+/// the form cannot be used to find out who is.
+#[tracing::instrument(name = "Requesting a data export", skip_all)]
+pub async fn request_data_export(
+    request: HttpRequest,
+    form: web::Form<DataExportRequest>,
+    pool: web::Data<Pool>,
+    email_client: web::Data<EmailClient>,
+    signer: web::Data<LinkSigner>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    let Ok(email) = SubscriberEmail::parse(form.into_inner().email) else {
+        return HttpResponse::BadRequest().body("This is not a valid email address.");
+    };
+    let audit = |action| {
+        AuditEvent::new(action)
+            .request(&request)
+            .target(format!("email:{}", email_hash(email.as_ref())))
+    };
+
+    let ip = client_ip(&request).map(|ip| ip.to_string());
+    let limits = [
+        format!("data_export:ip:{}", ip.unwrap_or_default()),
+        format!("data_export:email:{}", email_hash(email.as_ref())),
+    ];
+    for key in &limits {
+        if let Err(retry_after) = rate_limiter.check(key) {
+            record_from_handler(&pool, audit("data_export.rate_limited")).await;
+            return too_many_requests(retry_after);
+        }
+    }
+
+    let outcome = match pool.get().await {
+        Ok(client) => {
+            send_data_export_link(&client, &email, &email_client, &signer, &base_url).await
+        }
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(true) => record_from_handler(&pool, audit("data_export.requested")).await,
+        Ok(false) => {}
+        Err(e) => {
+            tracing::error!("Failed to send a data export link: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    render_public_page(
+        "Download my data",
+        &format!(
+            "<p>If the address is subscribed to any of our newsletters, a link to confirm \
+             is on its way. It is valid for {} hours.</p>",
+            DATA_EXPORT_LINK_TTL_HOURS
+        ),
+    )
+}
+
+/// Returns `false` if we hold no subscription for the address, and no link
+/// was sent.
+async fn send_data_export_link(
+    client: &deadpool_postgres::Client,
+    email: &SubscriberEmail,
+    email_client: &EmailClient,
+    signer: &LinkSigner,
+    base_url: &ApplicationBaseUrl,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    if !is_subscribed_anywhere(client, email.as_ref()).await? {
+        return Ok(false);
+    }
+    let token = signer.sign(
+        DATA_EXPORT_LINK_PURPOSE,
+        email.as_ref(),
+        Utc::now() + Duration::hours(DATA_EXPORT_LINK_TTL_HOURS),
+    );
+    let link = format!("{}/data_export/{}", base_url.0, token);
+    enqueue_transactional_email(
+        client,
+        email_client.senders().transactional_name("data_export"),
+        email,
+        "Confirm the download of your data",
+        &format!(
+            "Hi,<br />Someone, hopefully you, asked for a copy of everything we hold about \
+             you. Follow <a href=\"{}\">this link</a> to confirm. It is valid for {} hours.<br />\
+             If you did not ask for it, you can ignore this email.",
+            encode_attribute(&link),
+            DATA_EXPORT_LINK_TTL_HOURS
+        ),
+        &format!(
+            "Hi,\nSomeone, hopefully you, asked for a copy of everything we hold about you. \
+             Visit {} to confirm. It is valid for {} hours.\n\
+             If you did not ask for it, you can ignore this email.",
+            link, DATA_EXPORT_LINK_TTL_HOURS
+        ),
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Links are opened by mail scanners too: the export takes a click on the
+/// page.
+pub async fn data_export_confirmation_form(
+    token: web::Path<String>,
+    signer: web::Data<LinkSigner>,
+) -> HttpResponse {
+    match signer.verify(DATA_EXPORT_LINK_PURPOSE, &token, Utc::now()) {
+        Some(_) => render_public_page(
+            "Download my data",
+            &format!(
+                r#"<form action="/data_export/{}" method="post">
+        <button type="submit">Prepare my data</button>
+    </form>"#,
+                encode_attribute(&token),
+            ),
+        ),
+        None => invalid_link(),
+    }
+}
+
+#[tracing::instrument(name = "Queuing a data export", skip_all)]
+pub async fn confirm_data_export(
+    request: HttpRequest,
+    token: web::Path<String>,
+    pool: web::Data<Pool>,
+    signer: web::Data<LinkSigner>,
+) -> HttpResponse {
+    let Some(email) = signer.verify(DATA_EXPORT_LINK_PURPOSE, &token, Utc::now()) else {
+        return invalid_link();
+    };
+    let audit = AuditEvent::new("data_export.queued")
+        .request(&request)
+        .target(format!("email:{}", email_hash(&email)));
+    match queue_and_record(&pool, &email, audit).await {
+        Ok(()) => render_public_page(
+            "Download my data",
+            "<p>We are putting your data together, and will email you a link to \
+                 download it when it is ready.</p>",
+        ),
+        Err(e) => {
+            tracing::error!("Failed to queue a data export: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The export and its audit entry commit together, or not at all. Nothing
+/// is recorded when an export of the address was already queued.
+async fn queue_and_record(
+    pool: &Pool,
+    email: &str,
+    audit: AuditEvent<'_>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    if queue_data_export(&transaction, email).await? {
+        record(&transaction, audit).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Downloading a data export", skip_all)]
+pub async fn download_data_export(
+    request: HttpRequest,
+    token: web::Path<String>,
+    pool: web::Data<Pool>,
+    signer: web::Data<LinkSigner>,
+) -> HttpResponse {
+    let id = signer
+        .verify(ARCHIVE_LINK_PURPOSE, &token, Utc::now())
+        .and_then(|id| Uuid::parse_str(&id).ok());
+    let Some(id) = id else {
+        return invalid_link();
+    };
+    let outcome = match pool.get().await {
+        Ok(client) => get_archive(&client, id).await,
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(Some(archive)) => {
+            let audit = AuditEvent::new("data_export.downloaded")
+                .request(&request)
+                .target(format!("data_export:{}", id));
+            record_from_handler(&pool, audit).await;
+            HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .insert_header((
+                    CONTENT_DISPOSITION,
+                    format!(
+                        r#"attachment; filename="my-data-{}.json""#,
+                        Utc::now().format("%Y-%m-%d")
+                    ),
+                ))
+                .json(archive)
+        }
+        // deleted once expired, or erased since
+        Ok(None) => invalid_link(),
+        Err(e) => {
+            tracing::error!("Failed to get a data export: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn invalid_link() -> HttpResponse {
+    let mut response = render_public_page(
+        "Download my data",
+        r#"<p>This link is invalid or has expired.
+    <a href="/data_export">Ask for a new one</a>.</p>"#,
+    );
+    *response.status_mut() = actix_web::http::StatusCode::BAD_REQUEST;
+    response
+}
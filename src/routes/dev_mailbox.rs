@@ -0,0 +1,129 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use htmlescape::{encode_attribute, encode_minimal};
+use uuid::Uuid;
+
+use crate::outbox::{CapturedEmail, Outbox};
+
+#[derive(serde::Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailboxFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+#[derive(serde::Deserialize)]
+pub struct MailboxQuery {
+    #[serde(default)]
+    format: MailboxFormat,
+}
+
+#[tracing::instrument(name = "Listing captured emails", skip_all)]
+pub async fn mailbox(query: web::Query<MailboxQuery>, outbox: web::Data<Outbox>) -> HttpResponse {
+    let emails = match outbox.list().await {
+        Ok(emails) => emails,
+        Err(e) => {
+            tracing::error!("Failed to read the outbox: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if query.format == MailboxFormat::Json {
+        return HttpResponse::Ok().json(emails);
+    }
+
+    let mut rows = String::new();
+    for email in &emails {
+        rows.push_str(&format!(
+            r#"<tr><td>{}</td><td>{}</td><td><a href="/_dev/mailbox/{}">{}</a></td></tr>"#,
+            email.captured_at.format("%Y-%m-%d %H:%M:%S"),
+            encode_minimal(&email.to),
+            email.id,
+            encode_minimal(&email.subject),
+        ));
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Mailbox</title>
+</head>
+<body>
+    <h1>Mailbox ({count})</h1>
+    <p><a href="/_dev/mailbox?format=json">JSON</a></p>
+    <table>
+        <tr><th>Captured at</th><th>To</th><th>Subject</th></tr>
+        {rows}
+    </table>
+</body>
+</html>"#,
+            count = emails.len(),
+        ))
+}
+
+#[tracing::instrument(name = "Showing a captured email", skip(query, outbox))]
+pub async fn mailbox_message(
+    id: web::Path<Uuid>,
+    query: web::Query<MailboxQuery>,
+    outbox: web::Data<Outbox>,
+) -> HttpResponse {
+    let email = match outbox.get(id.into_inner()).await {
+        Ok(Some(email)) => email,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to read the outbox: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if query.format == MailboxFormat::Json {
+        return HttpResponse::Ok().json(email);
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render_message(&email))
+}
+
+fn render_message(email: &CapturedEmail) -> String {
+    // Links open outside of the sandboxed frame, so that confirmation
+    // links can be followed as if clicked from a real mail client.
+    let html_body = format!(r#"<base target="_blank">{}"#, email.html_body);
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{subject}</title>
+</head>
+<body>
+    <p><a href="/_dev/mailbox">&larr; Mailbox</a></p>
+    <dl>
+        <dt>From</dt><dd>{from}</dd>
+        <dt>Reply-To</dt><dd>{reply_to}</dd>
+        <dt>To</dt><dd>{to}</dd>
+        <dt>Subject</dt><dd>{subject}</dd>
+        <dt>Stream</dt><dd>{stream}</dd>
+        <dt>Captured at</dt><dd>{captured_at}</dd>
+    </dl>
+    <h2>HTML</h2>
+    <iframe sandbox="allow-popups allow-popups-to-escape-sandbox" style="width: 100%; height: 480px;" srcdoc="{html_body}"></iframe>
+    <h2>Text</h2>
+    <pre>{text_body}</pre>
+</body>
+</html>"#,
+        subject = encode_minimal(&email.subject),
+        from = encode_minimal(&email.from),
+        reply_to = encode_minimal(email.reply_to.as_deref().unwrap_or("-")),
+        to = encode_minimal(&email.to),
+        stream = email.message_stream.map(|s| s.as_str()).unwrap_or("-"),
+        captured_at = email.captured_at.format("%Y-%m-%d %H:%M:%S"),
+        html_body = encode_attribute(&html_body),
+        text_body = encode_minimal(&email.text_body),
+    )
+}
@@ -0,0 +1,205 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use deadpool_postgres::Pool;
+use htmlescape::{encode_attribute, encode_minimal};
+
+use super::password_reset::render_public_page;
+use crate::audit::{record, record_from_handler, AuditEvent};
+use crate::authentication::LinkSigner;
+use crate::delivery_worker::enqueue_transactional_email;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::erasure::{
+    erase_address, Erasure, ErasureScope, ERASURE_LINK_PURPOSE, ERASURE_LINK_TTL_HOURS,
+};
+use crate::rate_limit::{client_ip, too_many_requests, RateLimiter};
+use crate::startup::ApplicationBaseUrl;
+use crate::subscribers::is_subscribed_anywhere;
+use crate::suppression::email_hash;
+
+#[derive(serde::Deserialize)]
+pub struct ErasureRequest {
+    email: String,
+}
+
+pub async fn erasure_form() -> HttpResponse {
+    render_public_page(
+        "Erase my data",
+        r#"<form action="/erasure" method="post">
+        <label>Email <input type="email" name="email"></label>
+        <button type="submit">Send me a link</button>
+    </form>
+    <p>We will email you a link to confirm: everything we hold about the address is then
+    erased, and it is unsubscribed from every newsletter.</p>"#,
+    )
+}
+
+/// The response is the same whether or not the address is subscribed, so
+/// the form cannot be used to find out who is.
+#[tracing::instrument(name = "Requesting an erasure", skip_all)]
+pub async fn request_erasure(
+    request: HttpRequest,
+    form: web::Form<ErasureRequest>,
+    pool: web::Data<Pool>,
+    email_client: web::Data<EmailClient>,
+    signer: web::Data<LinkSigner>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    let Ok(email) = SubscriberEmail::parse(form.into_inner().email) else {
+        return HttpResponse::BadRequest().body("This is not a valid email address.");
+    };
+    let audit = |action| {
+        AuditEvent::new(action)
+            .request(&request)
+            .target(format!("email:{}", email_hash(email.as_ref())))
+    };
+
+    let ip = client_ip(&request).map(|ip| ip.to_string());
+    let limits = [
+        format!("erasure:ip:{}", ip.unwrap_or_default()),
+        format!("erasure:email:{}", email_hash(email.as_ref())),
+    ];
+    for key in &limits {
+        if let Err(retry_after) = rate_limiter.check(key) {
+            record_from_handler(&pool, audit("erasure.rate_limited")).await;
+            return too_many_requests(retry_after);
+        }
+    }
+
+    let outcome = match pool.get().await {
+        Ok(client) => send_erasure_link(&client, &email, &email_client, &signer, &base_url).await,
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(true) => record_from_handler(&pool, audit("erasure.requested")).await,
+        Ok(false) => {}
+        Err(e) => {
+            tracing::error!("Failed to send an erasure link: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    render_public_page(
+        "Erase my data",
+        &format!(
+            "<p>If the address is subscribed to any of our newsletters, a link to confirm \
+             the erasure is on its way. It is valid for {} hours.</p>",
+            ERASURE_LINK_TTL_HOURS
+        ),
+    )
+}
+
+/// Returns `false` if there was nothing to erase, and no link was sent.
+async fn send_erasure_link(
+    client: &deadpool_postgres::Client,
+    email: &SubscriberEmail,
+    email_client: &EmailClient,
+    signer: &LinkSigner,
+    base_url: &ApplicationBaseUrl,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    if !is_subscribed_anywhere(client, email.as_ref()).await? {
+        return Ok(false);
+    }
+    let token = signer.sign(
+        ERASURE_LINK_PURPOSE,
+        email.as_ref(),
+        Utc::now() + Duration::hours(ERASURE_LINK_TTL_HOURS),
+    );
+    let link = format!("{}/erasure/{}", base_url.0, token);
+    enqueue_transactional_email(
+        client,
+        email_client.senders().transactional_name("erasure"),
+        email,
+        "Confirm the erasure of your data",
+        &format!(
+            "Hi,<br />Someone, hopefully you, asked us to erase everything we hold about \
+             {}. Follow <a href=\"{}\">this link</a> to confirm. It is valid for {} hours.<br />\
+             If you did not ask for it, you can ignore this email.",
+            encode_minimal(email.as_ref()),
+            encode_attribute(&link),
+            ERASURE_LINK_TTL_HOURS
+        ),
+        &format!(
+            "Hi,\nSomeone, hopefully you, asked us to erase everything we hold about {}. \
+             Visit {} to confirm. It is valid for {} hours.\n\
+             If you did not ask for it, you can ignore this email.",
+            email.as_ref(),
+            link,
+            ERASURE_LINK_TTL_HOURS
+        ),
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Links are opened by mail scanners too: erasing takes a click on the page.
+pub async fn erasure_confirmation_form(
+    token: web::Path<String>,
+    signer: web::Data<LinkSigner>,
+) -> HttpResponse {
+    match signer.verify(ERASURE_LINK_PURPOSE, &token, Utc::now()) {
+        Some(email) => render_public_page(
+            "Erase my data",
+            &format!(
+                r#"<p>Everything we hold about {} will be erased, and the address unsubscribed
+    from every newsletter. This cannot be undone.</p>
+    <form action="/erasure/{}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>"#,
+                encode_minimal(&email),
+                encode_attribute(&token),
+            ),
+        ),
+        None => invalid_link(),
+    }
+}
+
+#[tracing::instrument(name = "Erasing a subscriber on their request", skip_all)]
+pub async fn confirm_erasure(
+    request: HttpRequest,
+    token: web::Path<String>,
+    pool: web::Data<Pool>,
+    signer: web::Data<LinkSigner>,
+) -> HttpResponse {
+    let Some(email) = signer.verify(ERASURE_LINK_PURPOSE, &token, Utc::now()) else {
+        return invalid_link();
+    };
+    let audit = AuditEvent::new("subscriber.erased")
+        .request(&request)
+        .target(format!("email:{}", email_hash(&email)));
+    match erase_and_record(&pool, &email, audit).await {
+        Ok(_) => render_public_page(
+            "Erase my data",
+            "<p>Your data has been erased. We will not email you again.</p>",
+        ),
+        Err(e) => {
+            tracing::error!("Failed to erase a subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn erase_and_record(
+    pool: &Pool,
+    email: &str,
+    audit: AuditEvent<'_>,
+) -> Result<Erasure, Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let erasure = erase_address(&transaction, email, ErasureScope::Everywhere).await?;
+    let details = serde_json::json!({ "via": "link", "erased": erasure });
+    record(&transaction, audit.details(details)).await?;
+    transaction.commit().await?;
+    Ok(erasure)
+}
+
+fn invalid_link() -> HttpResponse {
+    let mut response = render_public_page(
+        "Erase my data",
+        r#"<p>This link is invalid or has expired.
+    <a href="/erasure">Ask for a new one</a>.</p>"#,
+    );
+    *response.status_mut() = actix_web::http::StatusCode::BAD_REQUEST;
+    response
+}
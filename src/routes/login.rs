@@ -0,0 +1,278 @@
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use htmlescape::encode_minimal;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use crate::audit::{record_from_handler, AuditEvent};
+use crate::authentication::{
+    create_pending_login, create_session, delete_pending_login, load_pending_login,
+    record_failed_second_factor, session_cookie, two_factor_enabled, validate_credentials,
+    verify_second_factor, AuthError, Credentials, PENDING_LOGIN_TTL_MINUTES,
+};
+use crate::configuration::AdminSettings;
+
+const PENDING_LOGIN_COOKIE: &str = "pending_login";
+
+#[derive(serde::Deserialize)]
+pub struct LoginData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct LoginQuery {
+    notice: Option<String>,
+}
+
+/// Only known notices are shown: the query string is attacker-controlled.
+pub async fn login_form(query: web::Query<LoginQuery>) -> HttpResponse {
+    let notice = match query.notice.as_deref() {
+        Some("password_changed") => Some("Your password has been changed, please log in again."),
+        Some("password_reset") => Some("Your password has been reset, you can log in now."),
+        _ => None,
+    };
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render_login_form(notice))
+}
+
+#[tracing::instrument(
+    name = "Logging in",
+    skip(request, form, pool, settings),
+    fields(username = %form.username, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    request: HttpRequest,
+    form: web::Form<LoginData>,
+    pool: web::Data<Pool>,
+    settings: web::Data<AdminSettings>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let username = form.username.clone();
+    let credentials = Credentials {
+        username: form.username,
+        password: form.password,
+    };
+
+    let user_id = match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => user_id,
+        Err(AuthError::InvalidCredentials) => {
+            let audit = AuditEvent::new("login.failed")
+                .request(&request)
+                .details(serde_json::json!({ "username": username }));
+            record_from_handler(&pool, audit).await;
+            return HttpResponse::Unauthorized()
+                .content_type(ContentType::html())
+                .body(render_login_form(Some("Invalid username or password.")));
+        }
+        Err(e) => {
+            tracing::error!("Failed to validate credentials: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(user_id));
+
+    let pending_login = match pool.get().await {
+        Ok(client) => match two_factor_enabled(&client, user_id).await {
+            Ok(true) => create_pending_login(&client, user_id).await.map(Some),
+            Ok(false) => Ok(None),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
+    match pending_login {
+        Ok(Some(token)) => HttpResponse::SeeOther()
+            .insert_header((LOCATION, "/login/two_factor"))
+            .cookie(pending_login_cookie(&token, &settings))
+            .finish(),
+        Ok(None) => {
+            let audit = AuditEvent::new("login.succeeded")
+                .actor(user_id)
+                .request(&request)
+                .details(serde_json::json!({ "second_factor": null }));
+            record_from_handler(&pool, audit).await;
+            start_session(&pool, user_id, &settings).await
+        }
+        Err(e) => {
+            tracing::error!("Failed to check for a second factor: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SecondFactorData {
+    code: String,
+}
+
+pub async fn second_factor_form(request: HttpRequest) -> HttpResponse {
+    if request.cookie(PENDING_LOGIN_COOKIE).is_none() {
+        return redirect_to_login();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render_second_factor_form(None))
+}
+
+#[tracing::instrument(
+    name = "Checking the second factor",
+    skip_all,
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn second_factor(
+    request: HttpRequest,
+    form: web::Form<SecondFactorData>,
+    pool: web::Data<Pool>,
+    settings: web::Data<AdminSettings>,
+) -> HttpResponse {
+    let Some(token) = request
+        .cookie(PENDING_LOGIN_COOKIE)
+        .map(|cookie| Secret::new(cookie.value().to_owned()))
+    else {
+        return redirect_to_login();
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("Failed to get a database connection: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let user_id = match load_pending_login(&client, &token).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return redirect_to_login(),
+        Err(e) => {
+            tracing::error!("Failed to load a pending login: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(user_id));
+    let audit = |action| AuditEvent::new(action).actor(user_id).request(&request);
+
+    match verify_second_factor(&client, user_id, &form.code).await {
+        Ok(Some(second_factor)) => {
+            let audit = audit("login.succeeded")
+                .details(serde_json::json!({ "second_factor": second_factor }));
+            record_from_handler(&pool, audit).await;
+            if let Err(e) = delete_pending_login(&client, &token).await {
+                tracing::error!("Failed to delete a pending login: {:?}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+            let mut response = start_session(&pool, user_id, &settings).await;
+            if let Err(e) = response.add_removal_cookie(&pending_login_cookie(&token, &settings)) {
+                tracing::error!("Failed to remove the pending login cookie: {:?}", e);
+            }
+            response
+        }
+        Ok(None) => {
+            record_from_handler(&pool, audit("login.second_factor_failed")).await;
+            match record_failed_second_factor(&client, &token).await {
+                Ok(true) => HttpResponse::Unauthorized()
+                    .content_type(ContentType::html())
+                    .body(render_second_factor_form(Some("That code is not valid."))),
+                // Too many wrong codes: back to the password
+                Ok(false) => redirect_to_login(),
+                Err(e) => {
+                    tracing::error!("Failed to count a failed attempt: {:?}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!("Failed to verify the second factor: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn start_session(pool: &Pool, user_id: Uuid, settings: &AdminSettings) -> HttpResponse {
+    let token = match pool.get().await {
+        Ok(client) => create_session(&client, user_id, settings.session_ttl()).await,
+        Err(e) => Err(e.into()),
+    };
+    match token {
+        Ok(token) => HttpResponse::SeeOther()
+            .insert_header((LOCATION, "/admin/dashboard"))
+            .cookie(session_cookie(&token, settings))
+            .finish(),
+        Err(e) => {
+            tracing::error!("Failed to create a session: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn redirect_to_login() -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .finish()
+}
+
+/// Only sent back to the second login step.
+fn pending_login_cookie(token: &Secret<String>, settings: &AdminSettings) -> Cookie<'static> {
+    Cookie::build(PENDING_LOGIN_COOKIE, token.expose_secret().clone())
+        .path("/login")
+        .http_only(true)
+        .secure(settings.secure_cookies)
+        .same_site(SameSite::Strict)
+        .max_age(actix_web::cookie::time::Duration::minutes(
+            PENDING_LOGIN_TTL_MINUTES,
+        ))
+        .finish()
+}
+
+fn render_second_factor_form(error: Option<&str>) -> String {
+    let error = error
+        .map(|e| format!("<p><i>{}</i></p>", encode_minimal(e)))
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {error}
+    <form action="/login/two_factor" method="post">
+        <label>Code from your authenticator app, or a recovery code
+            <input type="text" name="code" autocomplete="one-time-code" autofocus>
+        </label>
+        <button type="submit">Verify</button>
+    </form>
+</body>
+</html>"#
+    )
+}
+
+fn render_login_form(message: Option<&str>) -> String {
+    let message = message
+        .map(|e| format!("<p><i>{}</i></p>", encode_minimal(e)))
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {message}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+    <p><a href="/password_reset">Forgot your password?</a></p>
+</body>
+</html>"#
+    )
+}
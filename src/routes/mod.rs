@@ -1,5 +1,19 @@
+mod admin;
+mod data_export;
+mod dev_mailbox;
+mod erasure;
 mod health_check;
+mod login;
+mod password_reset;
 mod subscriptions;
+mod webhooks;
 
+pub use admin::*;
+pub use data_export::*;
+pub use dev_mailbox::*;
+pub use erasure::*;
 pub use health_check::*;
+pub use login::*;
+pub use password_reset::*;
 pub use subscriptions::*;
+pub use webhooks::*;
@@ -0,0 +1,300 @@
+use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::{web, HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use htmlescape::{encode_attribute, encode_minimal};
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use crate::audit::{record, record_from_handler, AuditEvent};
+use crate::authentication::{
+    change_password, check_password_reset_token, check_password_strength,
+    consume_password_reset_token, create_password_reset_token, find_user_by_login, get_username,
+    PASSWORD_RESET_TTL_MINUTES,
+};
+use crate::delivery_worker::enqueue_transactional_email;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::rate_limit::{client_ip, too_many_requests, RateLimiter};
+use crate::startup::ApplicationBaseUrl;
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequest {
+    login: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewPasswordData {
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+pub async fn password_reset_form() -> HttpResponse {
+    render_public_page(
+        "Reset your password",
+        r#"<form action="/password_reset" method="post">
+        <label>Username or email <input type="text" name="login"></label>
+        <button type="submit">Send me a link</button>
+    </form>"#,
+    )
+}
+
+/// The response is the same whether or not an account matched, so the form
+/// cannot be used to find out who has one.
+#[tracing::instrument(name = "Requesting a password reset", skip_all)]
+pub async fn request_password_reset(
+    request: HttpRequest,
+    form: web::Form<PasswordResetRequest>,
+    pool: web::Data<Pool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    let login = form.into_inner().login.trim().to_owned();
+    let audit = |action| {
+        AuditEvent::new(action)
+            .request(&request)
+            .details(serde_json::json!({ "login": login }))
+    };
+
+    let ip = client_ip(&request).map(|ip| ip.to_string());
+    let limits = [
+        format!("password_reset:ip:{}", ip.unwrap_or_default()),
+        format!("password_reset:login:{}", login.to_lowercase()),
+    ];
+    for key in &limits {
+        if let Err(retry_after) = rate_limiter.check(key) {
+            record_from_handler(&pool, audit("password_reset.rate_limited")).await;
+            return too_many_requests(retry_after);
+        }
+    }
+
+    let outcome = match pool.get().await {
+        Ok(mut client) => send_reset_link(&mut client, &login, &email_client, &base_url).await,
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(Some(user_id)) => {
+            record_from_handler(&pool, audit("password_reset.requested").actor(user_id)).await
+        }
+        Ok(None) => record_from_handler(&pool, audit("password_reset.unknown_login")).await,
+        Err(e) => {
+            tracing::error!("Failed to send a password reset link: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    render_public_page(
+        "Reset your password",
+        &format!(
+            "<p>If an account with an email address matches, a link to reset its password \
+             is on its way. It is valid for {} minutes.</p>",
+            PASSWORD_RESET_TTL_MINUTES
+        ),
+    )
+}
+
+/// Returns who the link was sent to, if anybody.
+async fn send_reset_link(
+    client: &mut deadpool_postgres::Client,
+    login: &str,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+) -> Result<Option<Uuid>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(user) = find_user_by_login(&*client, login).await? else {
+        return Ok(None);
+    };
+    let Some(email) = user
+        .email
+        .and_then(|email| SubscriberEmail::parse(email).ok())
+    else {
+        return Ok(None);
+    };
+
+    let transaction = client.transaction().await?;
+    let token = create_password_reset_token(&transaction, user.user_id).await?;
+    let link = format!("{}/password_reset/{}", base_url.0, token.expose_secret());
+    enqueue_transactional_email(
+        &transaction,
+        email_client.senders().transactional_name("password_reset"),
+        &email,
+        "Reset your password",
+        &format!(
+            "Hi {},<br />Follow <a href=\"{}\">this link</a> to choose a new password. \
+             It is valid for {} minutes and can be used once.<br />\
+             If you did not ask for it, you can ignore this email.",
+            encode_minimal(&user.username),
+            encode_attribute(&link),
+            PASSWORD_RESET_TTL_MINUTES
+        ),
+        &format!(
+            "Hi {},\nVisit {} to choose a new password. \
+             It is valid for {} minutes and can be used once.\n\
+             If you did not ask for it, you can ignore this email.",
+            user.username, link, PASSWORD_RESET_TTL_MINUTES
+        ),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(Some(user.user_id))
+}
+
+pub async fn new_password_form(token: web::Path<String>, pool: web::Data<Pool>) -> HttpResponse {
+    let token = Secret::new(token.into_inner());
+    let user_id = match pool.get().await {
+        Ok(client) => check_password_reset_token(&client, &token).await,
+        Err(e) => Err(e.into()),
+    };
+    match user_id {
+        Ok(Some(_)) => render_new_password_form(&token, None),
+        Ok(None) => invalid_link(),
+        Err(e) => {
+            tracing::error!("Failed to check a password reset token: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Resetting a password", skip_all, fields(user_id = tracing::field::Empty))]
+pub async fn reset_password(
+    request: HttpRequest,
+    token: web::Path<String>,
+    form: web::Form<NewPasswordData>,
+    pool: web::Data<Pool>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    let token = Secret::new(token.into_inner());
+    let form = form.into_inner();
+    let ip = client_ip(&request).map(|ip| ip.to_string());
+
+    if let Err(retry_after) =
+        rate_limiter.check(&format!("new_password:ip:{}", ip.unwrap_or_default()))
+    {
+        let event = AuditEvent::new("password_reset.rate_limited").request(&request);
+        record_from_handler(&pool, event).await;
+        return too_many_requests(retry_after);
+    }
+
+    // Check first, so that a typo in the new password does not burn the link
+    let user = match pool.get().await {
+        Ok(client) => match check_password_reset_token(&client, &token).await {
+            Ok(Some(user_id)) => get_username(&client, user_id)
+                .await
+                .map(|username| username.map(|username| (user_id, username))),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
+    let (user_id, username) = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            let event = AuditEvent::new("password_reset.invalid_link").request(&request);
+            record_from_handler(&pool, event).await;
+            return invalid_link();
+        }
+        Err(e) => {
+            tracing::error!("Failed to check a password reset token: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(user_id));
+    let audit = |action| AuditEvent::new(action).actor(user_id).request(&request);
+
+    let rejection = if form.new_password.expose_secret() != form.new_password_check.expose_secret()
+    {
+        Err("The new passwords do not match.".to_string())
+    } else {
+        check_password_strength(&form.new_password, &username)
+    };
+    if let Err(reason) = rejection {
+        let event = audit("password_reset.failed").details(serde_json::json!({ "reason": reason }));
+        record_from_handler(&pool, event).await;
+        return render_new_password_form(&token, Some(&reason));
+    }
+
+    let outcome = match pool.get().await {
+        Ok(mut client) => {
+            let audit = audit("password_reset.succeeded");
+            reset_with_token(&mut client, &token, form.new_password, audit).await
+        }
+        Err(e) => Err(e.into()),
+    };
+    match outcome {
+        Ok(true) => HttpResponse::SeeOther()
+            .insert_header((LOCATION, "/login?notice=password_reset"))
+            .finish(),
+        // Used concurrently, or expired in the meantime
+        Ok(false) => invalid_link(),
+        Err(e) => {
+            tracing::error!("Failed to reset a password: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The token is used up, and the password and audit entry written, together.
+async fn reset_with_token(
+    client: &mut deadpool_postgres::Client,
+    token: &Secret<String>,
+    password: Secret<String>,
+    audit: AuditEvent<'_>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let transaction = client.transaction().await?;
+    let Some(user_id) = consume_password_reset_token(&transaction, token).await? else {
+        return Ok(false);
+    };
+    change_password(&transaction, user_id, password).await?;
+    record(&transaction, audit).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+fn invalid_link() -> HttpResponse {
+    let mut response = render_public_page(
+        "Reset your password",
+        r#"<p>This link is invalid, expired or has already been used.
+    <a href="/password_reset">Ask for a new one</a>.</p>"#,
+    );
+    *response.status_mut() = actix_web::http::StatusCode::BAD_REQUEST;
+    response
+}
+
+fn render_new_password_form(token: &Secret<String>, error: Option<&str>) -> HttpResponse {
+    let error = error
+        .map(|e| format!("<p><i>{}</i></p>", encode_minimal(e)))
+        .unwrap_or_default();
+    render_public_page(
+        "Choose a new password",
+        &format!(
+            r#"{error}
+    <form action="/password_reset/{token}" method="post">
+        <label>New password <input type="password" name="new_password"></label>
+        <br>
+        <label>Confirm new password <input type="password" name="new_password_check"></label>
+        <br>
+        <button type="submit">Set password</button>
+    </form>
+    <p>At least 12 characters. You will be logged out everywhere.</p>"#,
+            token = encode_attribute(token.expose_secret()),
+        ),
+    )
+}
+
+pub(super) fn render_public_page(title: &str, body: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    {body}
+</body>
+</html>"#,
+            title = encode_minimal(title),
+        ))
+}
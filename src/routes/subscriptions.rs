@@ -1,14 +1,57 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use chrono::Utc;
-use deadpool_postgres::Pool;
-use uuid::Uuid;
+use deadpool_postgres::{GenericClient, Pool};
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::bot_protection::{BotProtection, SignupChallenge};
+
+use crate::consent::{parse_form_field, ConsentContext};
+use crate::domain::{EmailPolicy, NewSubscriber, Subscriber, SubscriberEmail, SubscriberName};
+use crate::email_domains::find_domain_rule;
+use crate::publication::{
+    find_publication_by_host, find_publication_by_slug, Publication, PublicationId,
+};
+use crate::rate_limit::{too_many_requests, SignupRateLimits};
+use crate::startup::DefaultPublication;
+use crate::subscribers::{
+    insert_subscription, load_subscription_by_email, save_subscription, update_subscriber,
+};
+use crate::suppression::{find_suppression, unsuppress_on_opt_in, SuppressionReason};
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Explicit consent to be emailed again after a spam complaint.
+    #[serde(default)]
+    reconsent: bool,
+    /// Which form it is, when a site has several; kept as proof of consent.
+    source: Option<String>,
+    /// Of the wording next to the form.
+    consent_text_version: Option<String>,
+    /// A honeypot: the form hides it from people, only bots fill it in.
+    website: Option<String>,
+    /// From `GET /subscriptions/form_token`, as the form was loaded.
+    form_token: Option<String>,
+    #[serde(
+        alias = "h-captcha-response",
+        alias = "g-recaptcha-response",
+        alias = "cf-turnstile-response"
+    )]
+    captcha_response: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct FormToken {
+    form_token: String,
+}
+
+/// For signup forms to send back, see `BotProtection`.
+pub async fn subscription_form_token(bot_protection: web::Data<BotProtection>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(FormToken {
+            form_token: bot_protection.issue_form_token(Utc::now()),
+        })
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -20,21 +63,144 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
+/// Serves both `/subscriptions` and `/p/{slug}/subscriptions`.
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(request, form, pool, default_publication, rate_limits, bot_protection, email_policy),
     fields(
         subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_name = %form.name,
+        publication = tracing::field::Empty
     )
 )]
-pub async fn subscribe(form: web::Form<FormData>, pool: web::Data<Pool>) -> HttpResponse {
-    let new_subscriber = match form.0.try_into() {
+pub async fn subscribe(
+    request: HttpRequest,
+    mut form: web::Form<FormData>,
+    pool: web::Data<Pool>,
+    default_publication: web::Data<DefaultPublication>,
+    rate_limits: web::Data<SignupRateLimits>,
+    bot_protection: web::Data<BotProtection>,
+    email_policy: web::Data<EmailPolicy>,
+) -> HttpResponse {
+    let website = form.website.take();
+    let form_token = form.form_token.take();
+    let captcha_response = form.captcha_response.take();
+    let reconsent = form.reconsent;
+    let consent = ConsentContext::new(
+        parse_form_field(form.source.take()).unwrap_or_else(|| "signup_form".into()),
+    )
+    .consent_text_version(parse_form_field(form.consent_text_version.take()))
+    .request(&request);
+    let new_subscriber: NewSubscriber = match form.0.try_into() {
         Ok(form) => form,
-        Err(_) => return HttpResponse::BadRequest().finish(),
+        Err(e) => {
+            // Shaped like the policy rejections below, for clients to tell
+            // them apart by code
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "code": "invalid_syntax",
+                "message": e,
+            }));
+        }
+    };
+
+    let challenge = SignupChallenge {
+        honeypot: website.as_deref(),
+        form_token: form_token.as_deref(),
+        captcha_response: captcha_response.as_deref(),
+    };
+    match bot_protection.check(&challenge, Utc::now()).await {
+        Ok(None) => {}
+        Ok(Some(signal)) => {
+            // Bots are told they succeeded, so there is nothing to learn
+            // from their failures.
+            tracing::warn!(signal = signal.as_str(), "Ignoring a suspected bot signup.");
+            return HttpResponse::Ok().finish();
+        }
+        Err(e) => {
+            tracing::error!("Failed to verify the CAPTCHA: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    // Before anything is stored or sent: the form is public, and could be
+    // used to flood somebody's inbox.
+    match rate_limits
+        .check(&request, new_subscriber.email.as_ref())
+        .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(retry_after)) => {
+            tracing::warn!("Turning away a signup over the rate limit.");
+            return too_many_requests(retry_after);
+        }
+        Err(e) => {
+            tracing::error!("Failed to check the signup rate limits: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let rule = match pool.get().await {
+        Ok(client) => find_domain_rule(&client, &new_subscriber.email).await,
+        Err(e) => Err(e.into()),
+    };
+    let rejection = match rule {
+        Ok(rule) => email_policy.check(&new_subscriber.email, rule).err(),
+        Err(e) => {
+            tracing::error!("Failed to look up the email domain rules: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if let Some(rejection) = rejection {
+        tracing::warn!(code = rejection.code(), "Rejecting the address.");
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "code": rejection.code(),
+            "message": rejection.to_string(),
+        }));
+    }
+
+    let publication = match pool.get().await {
+        Ok(client) => resolve_publication(&client, &request, &default_publication).await,
+        Err(e) => Err(e.into()),
+    };
+    let publication_id = match publication {
+        Ok(Some(publication)) => {
+            tracing::Span::current().record("publication", &publication.slug);
+            PublicationId(publication.id)
+        }
+        Ok(None) => return HttpResponse::NotFound().body("There is no such publication."),
+        Err(e) => {
+            tracing::error!("Failed to find the publication: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let suppression = match lookup_suppression(&pool, publication_id, &new_subscriber).await {
+        Ok(suppression) => suppression,
+        Err(e) => {
+            tracing::error!("Failed to check the suppression list: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
     };
 
-    match insert_subscriber(&pool, &new_subscriber).await {
+    let outcome = match suppression {
+        Some(SuppressionReason::Complaint) if !reconsent => {
+            tracing::warn!("Refusing to resubscribe an address that filed a spam complaint.");
+            return HttpResponse::Conflict().finish();
+        }
+        // Signing up again is a fresh opt-in: lift the suppression. Imports
+        // still cannot bring erased addresses back.
+        Some(
+            SuppressionReason::Complaint
+            | SuppressionReason::Unsubscribe
+            | SuppressionReason::Erased,
+        ) => resubscribe(&pool, publication_id, &new_subscriber, &consent).await,
+        // Bounced or manually suppressed addresses stay suppressed.
+        Some(SuppressionReason::HardBounce | SuppressionReason::Manual) | None => {
+            insert_subscriber(&pool, publication_id, &new_subscriber, &consent).await
+        }
+    };
+
+    match outcome {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(e) => {
             tracing::error!("Failed to execute query: {:?}", e);
@@ -43,32 +209,94 @@ pub async fn subscribe(form: web::Form<FormData>, pool: web::Data<Pool>) -> Http
     }
 }
 
+/// The publication named in the path or, without one, the publication
+/// claiming the request's host, falling back to the default publication.
+async fn resolve_publication<C: GenericClient>(
+    client: &C,
+    request: &HttpRequest,
+    default_publication: &DefaultPublication,
+) -> Result<Option<Publication>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(slug) = request.match_info().get("slug") {
+        return find_publication_by_slug(client, slug).await;
+    }
+    let host = request.connection_info().host().to_owned();
+    match find_publication_by_host(client, &host).await? {
+        Some(publication) => Ok(Some(publication)),
+        None => find_publication_by_slug(client, &default_publication.0).await,
+    }
+}
+
+async fn lookup_suppression(
+    pool: &Pool,
+    publication_id: PublicationId,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<SuppressionReason>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = pool.get().await?;
+    let suppression =
+        find_suppression(&client, new_subscriber.email.as_ref(), Some(publication_id)).await?;
+    Ok(suppression.map(|s| s.reason))
+}
+
+#[tracing::instrument(
+    name = "Resubscribing a previously suppressed subscriber",
+    skip(new_subscriber, pool, consent)
+)]
+pub async fn resubscribe(
+    pool: &Pool,
+    publication_id: PublicationId,
+    new_subscriber: &NewSubscriber,
+    consent: &ConsentContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    let email = new_subscriber.email.as_ref();
+    unsuppress_on_opt_in(&transaction, email, publication_id).await?;
+    match load_subscription_by_email(&transaction, publication_id, email).await? {
+        Some(mut subscriber) => {
+            subscriber.resubscribe()?;
+            // There is no confirmation flow yet: signing up confirms
+            subscriber.confirm()?;
+            let name = Some(new_subscriber.name.as_ref());
+            update_subscriber(&transaction, publication_id, subscriber.id(), name, None).await?;
+            save_subscription(&transaction, &mut subscriber, consent).await?;
+        }
+        None => store_new_subscriber(&transaction, publication_id, new_subscriber, consent).await?,
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, pool)
+    skip(new_subscriber, pool, consent)
 )]
 pub async fn insert_subscriber(
     pool: &Pool,
+    publication_id: PublicationId,
     new_subscriber: &NewSubscriber,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = pool.get().await?;
-    client
-        .execute(
-            r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at)
-    VALUES ($1, $2, $3, $4)
-    "#,
-            &[
-                &Uuid::new_v4(),
-                &new_subscriber.email.as_ref(),
-                &new_subscriber.name.as_ref(),
-                &Utc::now(),
-            ],
-        )
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to execute query: {:?}", e);
-            e
-        })?;
+    consent: &ConsentContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+    store_new_subscriber(&transaction, publication_id, new_subscriber, consent).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Signing up an address that is already subscribed changes nothing, and
+/// is not an error: we do not tell who is subscribed.
+async fn store_new_subscriber<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    new_subscriber: &NewSubscriber,
+    consent: &ConsentContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut subscriber = Subscriber::subscribe(publication_id, &new_subscriber.email);
+    // There is no confirmation flow yet: signing up confirms
+    subscriber.confirm()?;
+    let name = new_subscriber.name.as_ref();
+    if !insert_subscription(client, &mut subscriber, name, None, None, consent).await? {
+        tracing::info!("The address is already subscribed.");
+    }
     Ok(())
 }
@@ -0,0 +1,287 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use uuid::Uuid;
+
+use crate::authentication::verify_webhook;
+use crate::configuration::WebhookSettings;
+use crate::consent::ConsentContext;
+use crate::domain::SubscriptionStatus;
+use crate::subscribers::{load_subscriptions_of_address, save_subscription};
+use crate::suppression::{suppress, SuppressionReason};
+
+/// The subset of Postmark webhook payloads we act upon, or keep for the
+/// recipient's data export. Every other record type is stored for audit
+/// purposes and otherwise ignored.
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "RecordType", rename_all_fields = "PascalCase")]
+pub enum EmailEvent {
+    Bounce {
+        #[serde(rename = "Type")]
+        bounce_type: String,
+        email: String,
+    },
+    SpamComplaint {
+        email: String,
+    },
+    SubscriptionChange {
+        recipient: String,
+        suppress_sending: bool,
+        suppression_reason: Option<String>,
+    },
+    Delivery {
+        recipient: String,
+    },
+    Open {
+        recipient: String,
+    },
+    Click {
+        recipient: String,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+impl EmailEvent {
+    fn record_type(&self) -> &'static str {
+        match self {
+            EmailEvent::Bounce { .. } => "Bounce",
+            EmailEvent::SpamComplaint { .. } => "SpamComplaint",
+            EmailEvent::SubscriptionChange { .. } => "SubscriptionChange",
+            EmailEvent::Delivery { .. } => "Delivery",
+            EmailEvent::Open { .. } => "Open",
+            EmailEvent::Click { .. } => "Click",
+            EmailEvent::Unsupported => "Unsupported",
+        }
+    }
+
+    fn email(&self) -> Option<&str> {
+        match self {
+            EmailEvent::Bounce { email, .. } | EmailEvent::SpamComplaint { email } => Some(email),
+            EmailEvent::SubscriptionChange { recipient, .. }
+            | EmailEvent::Delivery { recipient }
+            | EmailEvent::Open { recipient }
+            | EmailEvent::Click { recipient } => Some(recipient),
+            EmailEvent::Unsupported => None,
+        }
+    }
+
+    /// The status the matching subscription should be moved to, and the reason
+    /// its address goes on the suppression list, if any.
+    fn suppression(&self) -> Option<(SubscriptionStatus, SuppressionReason)> {
+        match self {
+            EmailEvent::Bounce { bounce_type, .. } if bounce_type == "HardBounce" => {
+                Some((SubscriptionStatus::Bounced, SuppressionReason::HardBounce))
+            }
+            EmailEvent::SpamComplaint { .. } => {
+                Some((SubscriptionStatus::Complained, SuppressionReason::Complaint))
+            }
+            EmailEvent::SubscriptionChange {
+                suppress_sending: true,
+                suppression_reason,
+                ..
+            } => match suppression_reason.as_deref() {
+                Some("HardBounce") => {
+                    Some((SubscriptionStatus::Bounced, SuppressionReason::HardBounce))
+                }
+                Some("SpamComplaint") => {
+                    Some((SubscriptionStatus::Complained, SuppressionReason::Complaint))
+                }
+                _ => Some((
+                    SubscriptionStatus::Unsubscribed,
+                    SuppressionReason::Unsubscribe,
+                )),
+            },
+            _ => None,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Receiving an email provider webhook", skip_all)]
+pub async fn email_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<Pool>,
+    settings: web::Data<WebhookSettings>,
+) -> HttpResponse {
+    // Signatures cover the raw bytes, so verify before deserializing anything.
+    if let Err(rejection) = verify_webhook(request.headers(), &body, &settings, Utc::now()) {
+        tracing::warn!(reason = %rejection, "Rejected webhook request");
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let payload: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            tracing::warn!("Failed to parse webhook payload: {:?}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let event: EmailEvent = match serde_json::from_value(payload.clone()) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::warn!("Failed to parse webhook payload: {:?}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+
+    match handle_email_event(&pool, &event, &payload).await {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            tracing::error!("Failed to process webhook event: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Handling an email event",
+    skip(pool, payload),
+    fields(record_type = %event.record_type())
+)]
+async fn handle_email_event(
+    pool: &Pool,
+    event: &EmailEvent,
+    payload: &serde_json::Value,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut client = pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    transaction
+        .execute(
+            r#"
+    INSERT INTO email_events (id, record_type, email, payload, received_at)
+    VALUES ($1, $2, $3, $4, $5)
+    "#,
+            &[
+                &Uuid::new_v4(),
+                &event.record_type(),
+                &event.email(),
+                payload,
+                &Utc::now(),
+            ],
+        )
+        .await?;
+
+    if let (Some(email), Some((status, reason))) = (event.email(), event.suppression()) {
+        suppress(&transaction, email, reason, None).await?;
+
+        let subscriptions = load_subscriptions_of_address(&transaction, email, None).await?;
+        if subscriptions.is_empty() {
+            tracing::info!("No subscription matches the email event recipient.");
+        }
+        let consent = ConsentContext::new(format!("email_provider:{}", event.record_type()));
+        for mut subscription in subscriptions {
+            let change = match status {
+                SubscriptionStatus::Bounced => subscription.bounce(),
+                SubscriptionStatus::Complained => subscription.complain(),
+                _ => subscription.unsubscribe(),
+            };
+            if let Err(refusal) = change {
+                tracing::warn!("Ignoring an email event: {}", refusal);
+                continue;
+            }
+            save_subscription(&transaction, &mut subscription, &consent).await?;
+        }
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailEvent;
+    use crate::domain::SubscriptionStatus;
+    use crate::suppression::SuppressionReason;
+
+    fn parse(payload: serde_json::Value) -> EmailEvent {
+        serde_json::from_value(payload).unwrap()
+    }
+
+    #[test]
+    fn hard_bounces_suppress_the_subscription() {
+        let event = parse(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "HardBounce",
+            "Email": "ursula@example.com",
+        }));
+        assert_eq!(event.email(), Some("ursula@example.com"));
+        assert_eq!(
+            event.suppression(),
+            Some((SubscriptionStatus::Bounced, SuppressionReason::HardBounce))
+        );
+    }
+
+    #[test]
+    fn soft_bounces_do_not_suppress_the_subscription() {
+        let event = parse(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "SoftBounce",
+            "Email": "ursula@example.com",
+        }));
+        assert_eq!(event.suppression(), None);
+    }
+
+    #[test]
+    fn spam_complaints_suppress_the_subscription() {
+        let event = parse(serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Email": "ursula@example.com",
+        }));
+        assert_eq!(
+            event.suppression(),
+            Some((SubscriptionStatus::Complained, SuppressionReason::Complaint))
+        );
+    }
+
+    #[test]
+    fn subscription_changes_follow_the_suppression_reason() {
+        let event = parse(serde_json::json!({
+            "RecordType": "SubscriptionChange",
+            "Recipient": "ursula@example.com",
+            "SuppressSending": true,
+            "SuppressionReason": "ManualSuppression",
+        }));
+        assert_eq!(event.email(), Some("ursula@example.com"));
+        assert_eq!(
+            event.suppression(),
+            Some((
+                SubscriptionStatus::Unsubscribed,
+                SuppressionReason::Unsubscribe
+            ))
+        );
+
+        let event = parse(serde_json::json!({
+            "RecordType": "SubscriptionChange",
+            "Recipient": "ursula@example.com",
+            "SuppressSending": false,
+            "SuppressionReason": null,
+        }));
+        assert_eq!(event.suppression(), None);
+    }
+
+    #[test]
+    fn deliveries_opens_and_clicks_keep_their_recipient() {
+        for record_type in ["Delivery", "Open", "Click"] {
+            let event = parse(serde_json::json!({
+                "RecordType": record_type,
+                "Recipient": "ursula@example.com",
+                "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            }));
+            assert_eq!(event.record_type(), record_type);
+            assert_eq!(event.email(), Some("ursula@example.com"));
+            assert_eq!(event.suppression(), None);
+        }
+    }
+
+    #[test]
+    fn other_record_types_are_accepted_but_ignored() {
+        let event = parse(serde_json::json!({
+            "RecordType": "Inbound",
+            "From": "ursula@example.com",
+        }));
+        assert_eq!(event.email(), None);
+        assert_eq!(event.suppression(), None);
+    }
+}
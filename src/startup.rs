@@ -1,15 +1,52 @@
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::{ensure_admin_user, reject_anonymous_users, LinkSigner};
+use crate::bot_protection::BotProtection;
+use crate::configuration::{
+    AdminSettings, ApplicationSettings, DatabaseSettings, EmailBackend, EmailPolicySettings,
+    RateLimitBackend, Settings, WebhookSettings,
+};
+use crate::data_export::{self, ArchiveNotifier};
+use crate::delivery_worker::worker_loop;
+use crate::domain::{parse_domain_list, EmailPolicy};
 use crate::email_client::EmailClient;
-use crate::routes::{health_check, subscribe};
+use crate::outbox::Outbox;
+use crate::rate_limit::{self, RateLimiter, SignupRateLimits, TrustedProxies};
+use crate::routes::{
+    add_api_key, add_email_domain_rule, add_publication, add_suppression, add_user,
+    admin_dashboard, api_keys_page, change_membership, change_password_form,
+    change_password_submit, change_two_factor_policy, change_user_role, compose_page,
+    confirm_data_export, confirm_erasure, confirm_subscriber_manually,
+    data_export_confirmation_form, data_export_form, delete_email_domain_rule, delete_suppression,
+    download_data_export, edit_subscriber, email_webhook, enable_two_factor, erase_subscriber,
+    erasure_confirmation_form, erasure_form, export_subscribers, get_audit_log, get_consent_events,
+    get_email_domain_rules, get_import_progress, get_import_rejections, get_subscriber_details,
+    get_suppressions, health_check, import_subscribers, issues_page, log_out, login, login_form,
+    mailbox, mailbox_message, new_password_form, password_reset_form, publications_page,
+    publish_issue_form, publish_newsletter, regenerate_recovery_codes, remove_subscriber,
+    remove_user, request_data_export, request_erasure, request_password_reset, reset_password,
+    revoke_api_key_form, second_factor, second_factor_form, start_two_factor_setup, subscribe,
+    subscribers_page, subscription_form_token, suppress_subscriber_manually,
+    switch_publication_form, turn_off_two_factor, two_factor_page, users_page, verify_audit_log,
+    MAX_IMPORT_SIZE,
+};
+use crate::subscriber_import;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::web::Data;
 use actix_web::{web, App, HttpServer};
 use deadpool_postgres::{Config, Pool, Runtime};
 use secrecy::ExposeSecret;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio_postgres::NoTls;
 use tracing_actix_web::TracingLogger;
 
+/// Where users reach us, for links in emails.
+pub struct ApplicationBaseUrl(pub String);
+
+/// The slug of the publication signups on unclaimed hosts go to.
+pub struct DefaultPublication(pub String);
+
 pub struct Application {
     port: u16,
     server: Server,
@@ -18,21 +55,59 @@ pub struct Application {
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, Box<dyn std::error::Error>> {
         // create database connection pool
-        let connection_pool = create_configuration_pool(&configuration.database)?;
+        let connection_pool = get_connection_pool(&configuration.database)?;
+        ensure_admin_user(
+            &connection_pool,
+            &configuration.admin.username,
+            configuration.admin.email.as_deref(),
+            &configuration.admin.password,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
 
         // Build an `EmailClient` using `configuration`
-        let sender_email = configuration
+        let senders = configuration
             .email_client
-            .sender()
-            .expect("Invalid sender email address");
+            .senders()
+            .expect("Invalid sender identities");
+
+        let outbox = match configuration.email_client.backend {
+            EmailBackend::Postmark => None,
+            EmailBackend::Capture => Some(configuration.email_client.outbox()?),
+        };
 
         let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let email_client = match &outbox {
+            Some(outbox) => EmailClient::capture(senders, outbox.clone()),
+            None => EmailClient::new(
+                configuration.email_client.base_url,
+                senders,
+                configuration.email_client.message_streams,
+                configuration.email_client.authorization_token,
+                timeout,
+            ),
+        }
+        .with_suppression_list(connection_pool.clone());
+        let email_client = Arc::new(email_client);
+
+        // Deliveries are drained in the background, next to the server
+        tokio::spawn(worker_loop(connection_pool.clone(), email_client.clone()));
+        tokio::spawn(subscriber_import::worker_loop(connection_pool.clone()));
+        let archive_notifier = ArchiveNotifier {
+            signer: LinkSigner::new(configuration.application.link_signing_secret.clone()),
+            base_url: configuration.application.base_url.clone(),
+            sender: email_client
+                .senders()
+                .transactional_name("data_export")
+                .to_owned(),
+        };
+        tokio::spawn(data_export::worker_loop(
+            connection_pool.clone(),
+            archive_notifier,
+        ));
+        if configuration.application.signup_rate_limits.backend == RateLimitBackend::Postgres {
+            tokio::spawn(rate_limit::cleanup_loop(connection_pool.clone()));
+        }
 
         let address = format!(
             "{}:{}",
@@ -41,7 +116,15 @@ impl Application {
 
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(listener, connection_pool, email_client)?;
+        let server = run(
+            listener,
+            connection_pool,
+            email_client,
+            configuration.webhooks,
+            configuration.admin,
+            outbox,
+            configuration.application,
+        )?;
 
         Ok(Self { port, server })
     }
@@ -55,7 +138,7 @@ impl Application {
     }
 }
 
-fn create_configuration_pool(
+pub fn get_connection_pool(
     db_config: &DatabaseSettings,
 ) -> Result<Pool, Box<dyn std::error::Error>> {
     let mut cfg = Config::new();
@@ -69,22 +152,198 @@ fn create_configuration_pool(
         .map_err(|e| e.into())
 }
 
+/// A missing or malformed blocklist fails startup, rather than letting
+/// every disposable address in.
+fn load_email_policy(
+    settings: &EmailPolicySettings,
+) -> Result<EmailPolicy, Box<dyn std::error::Error>> {
+    let disposable_domains = match &settings.disposable_domains_file {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            parse_domain_list(&text).map_err(|e| format!("In {}: {}", path, e))?
+        }
+        None => Vec::new(),
+    };
+    Ok(EmailPolicy::new(
+        disposable_domains,
+        settings.role_addresses.iter().cloned(),
+    ))
+}
+
 pub fn run(
     listener: TcpListener,
     db_pool: Pool,
-    email_client: EmailClient,
+    email_client: Arc<EmailClient>,
+    webhook_settings: WebhookSettings,
+    admin_settings: AdminSettings,
+    outbox: Option<Outbox>,
+    application_settings: ApplicationSettings,
 ) -> Result<Server, Box<dyn std::error::Error>> {
     let db_pool = web::Data::new(db_pool);
 
-    let email_client = Data::new(email_client);
+    let email_client = Data::from(email_client);
+    let webhook_settings = Data::new(webhook_settings);
+    let admin_settings = Data::new(admin_settings);
+    let outbox = outbox.map(Data::new);
+    let base_url = Data::new(ApplicationBaseUrl(application_settings.base_url));
+    let default_publication =
+        Data::new(DefaultPublication(application_settings.default_publication));
+    let email_policy = Data::new(load_email_policy(&application_settings.email_policy)?);
+    let bot_protection = Data::new(BotProtection::new(
+        &application_settings.bot_protection,
+        application_settings.link_signing_secret.clone(),
+    )?);
+    let link_signer = Data::new(LinkSigner::new(application_settings.link_signing_secret));
+    // Password changes and resets: a burst of 5, then one every 3 minutes
+    let rate_limiter = Data::new(RateLimiter::new(5, Duration::from_secs(180)));
+    let trusted_proxies = Data::new(TrustedProxies(application_settings.trusted_proxies));
+    let signup_rate_limits = Data::new(SignupRateLimits::new(
+        &application_settings.signup_rate_limits,
+        trusted_proxies.get_ref().clone(),
+        &db_pool,
+    ));
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
+            .route(
+                "/subscriptions/form_token",
+                web::get().to(subscription_form_token),
+            )
+            .route("/p/{slug}/subscriptions", web::post().to(subscribe))
+            .route("/webhooks/email", web::post().to(email_webhook))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .route("/login/two_factor", web::get().to(second_factor_form))
+            .route("/login/two_factor", web::post().to(second_factor))
+            .route("/password_reset", web::get().to(password_reset_form))
+            .route("/password_reset", web::post().to(request_password_reset))
+            .route("/password_reset/{token}", web::get().to(new_password_form))
+            .route("/password_reset/{token}", web::post().to(reset_password))
+            .route("/data_export", web::get().to(data_export_form))
+            .route("/data_export", web::post().to(request_data_export))
+            .route(
+                "/data_export/download/{token}",
+                web::get().to(download_data_export),
+            )
+            .route(
+                "/data_export/{token}",
+                web::get().to(data_export_confirmation_form),
+            )
+            .route("/data_export/{token}", web::post().to(confirm_data_export))
+            .route("/erasure", web::get().to(erasure_form))
+            .route("/erasure", web::post().to(request_erasure))
+            .route("/erasure/{token}", web::get().to(erasure_confirmation_form))
+            .route("/erasure/{token}", web::post().to(confirm_erasure))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/subscribers", web::get().to(subscribers_page))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .service(
+                        web::resource("/subscribers/imports")
+                            .app_data(web::PayloadConfig::new(MAX_IMPORT_SIZE))
+                            .route(web::post().to(import_subscribers)),
+                    )
+                    .route(
+                        "/subscribers/imports/{id}",
+                        web::get().to(get_import_progress),
+                    )
+                    .route(
+                        "/subscribers/imports/{id}/rejections",
+                        web::get().to(get_import_rejections),
+                    )
+                    .route("/subscribers/{id}", web::get().to(get_subscriber_details))
+                    .route("/subscribers/{id}", web::patch().to(edit_subscriber))
+                    .route("/subscribers/{id}", web::delete().to(remove_subscriber))
+                    .route(
+                        "/subscribers/{id}/confirm",
+                        web::post().to(confirm_subscriber_manually),
+                    )
+                    .route(
+                        "/subscribers/{id}/suppress",
+                        web::post().to(suppress_subscriber_manually),
+                    )
+                    .route("/subscribers/{id}/erase", web::post().to(erase_subscriber))
+                    .route(
+                        "/subscribers/{id}/consent",
+                        web::get().to(get_consent_events),
+                    )
+                    .route("/issues", web::get().to(issues_page))
+                    .route("/issues", web::post().to(publish_issue_form))
+                    .route("/issues/new", web::get().to(compose_page))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password_submit))
+                    .route("/two_factor", web::get().to(two_factor_page))
+                    .route("/two_factor/setup", web::post().to(start_two_factor_setup))
+                    .route("/two_factor/enable", web::post().to(enable_two_factor))
+                    .route(
+                        "/two_factor/recovery_codes",
+                        web::post().to(regenerate_recovery_codes),
+                    )
+                    .route("/two_factor/disable", web::post().to(turn_off_two_factor))
+                    .route("/publications", web::get().to(publications_page))
+                    .route("/publications", web::post().to(add_publication))
+                    .route(
+                        "/publications/{id}/switch",
+                        web::post().to(switch_publication_form),
+                    )
+                    .route("/api_keys", web::get().to(api_keys_page))
+                    .route("/api_keys", web::post().to(add_api_key))
+                    .route("/api_keys/{id}/revoke", web::post().to(revoke_api_key_form))
+                    .route("/users", web::get().to(users_page))
+                    .route("/users", web::post().to(add_user))
+                    .route("/users/{id}/role", web::post().to(change_user_role))
+                    .route("/users/{id}/membership", web::post().to(change_membership))
+                    .route("/users/{id}/delete", web::post().to(remove_user))
+                    .route(
+                        "/users/two_factor_policy",
+                        web::post().to(change_two_factor_policy),
+                    )
+                    .route("/newsletters", web::post().to(publish_newsletter))
+                    .route("/audit_log", web::get().to(get_audit_log))
+                    .route("/audit_log/verify", web::get().to(verify_audit_log))
+                    .route("/email_domains", web::get().to(get_email_domain_rules))
+                    .route("/email_domains", web::post().to(add_email_domain_rule))
+                    .route(
+                        "/email_domains/{domain}",
+                        web::delete().to(delete_email_domain_rule),
+                    )
+                    .route("/suppressions", web::get().to(get_suppressions))
+                    .route("/suppressions", web::post().to(add_suppression))
+                    .route(
+                        "/suppressions/{email}",
+                        web::delete().to(delete_suppression),
+                    ),
+            )
+            // the development mailbox only exists when emails are captured
+            .configure(|cfg| {
+                if let Some(outbox) = &outbox {
+                    cfg.app_data(outbox.clone())
+                        .route("/_dev/mailbox", web::get().to(mailbox))
+                        .route("/_dev/mailbox/{id}", web::get().to(mailbox_message));
+                }
+            })
+            // newsletter issues are posted as forms from the admin area
+            .app_data(web::FormConfig::default().limit(1024 * 1024))
+            .app_data(web::PayloadConfig::default().limit(1024 * 1024))
             // register db connection as part of application state
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(admin_settings.clone())
+            .app_data(base_url.clone())
+            .app_data(default_publication.clone())
+            .app_data(link_signer.clone())
+            .app_data(bot_protection.clone())
+            .app_data(email_policy.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(signup_rate_limits.clone())
+            .app_data(trusted_proxies.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,470 @@
+//! The CSV layouts we import: our own, with configurable columns, and the
+//! audience exports of Mailchimp and Substack.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+use crate::csv::{CsvReader, CsvRecord};
+use crate::domain::{Subscriber, SubscriberEmail, SubscriberName, TransitionError};
+use crate::subscribers::parse_tag;
+use crate::suppression::SuppressionReason;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// Ours, with the columns named by a `ColumnMapping`.
+    #[default]
+    Csv,
+    /// An audience export: one file per status, or a `Status` column.
+    Mailchimp,
+    /// A subscriber export: `email_disabled` marks the unsubscribed.
+    Substack,
+}
+
+impl ImportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv",
+            ImportFormat::Mailchimp => "mailchimp",
+            ImportFormat::Substack => "substack",
+        }
+    }
+}
+
+impl TryFrom<String> for ImportFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "csv" => Ok(Self::Csv),
+            "mailchimp" => Ok(Self::Mailchimp),
+            "substack" => Ok(Self::Substack),
+            other => Err(format!(
+                "{} is not a valid import format: use csv, mailchimp or substack.",
+                other
+            )),
+        }
+    }
+}
+
+/// Where an imported row lands in our subscriber lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportedStatus {
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+}
+
+impl ImportedStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportedStatus::Confirmed => "confirmed",
+            ImportedStatus::Unsubscribed => "unsubscribed",
+            ImportedStatus::Bounced => "bounced",
+            ImportedStatus::Complained => "complained",
+        }
+    }
+
+    /// Move a subscription to this state, the way the other tool had it.
+    pub fn apply(&self, subscriber: &mut Subscriber) -> Result<(), TransitionError> {
+        match self {
+            ImportedStatus::Confirmed => subscriber.confirm(),
+            ImportedStatus::Unsubscribed => subscriber.unsubscribe(),
+            ImportedStatus::Bounced => subscriber.bounce(),
+            ImportedStatus::Complained => subscriber.complain(),
+        }
+    }
+
+    /// Addresses arriving in this state also go on the suppression list.
+    pub fn suppression(&self) -> Option<SuppressionReason> {
+        match self {
+            ImportedStatus::Confirmed => None,
+            ImportedStatus::Unsubscribed => Some(SuppressionReason::Unsubscribe),
+            ImportedStatus::Bounced => Some(SuppressionReason::HardBounce),
+            ImportedStatus::Complained => Some(SuppressionReason::Complaint),
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "confirmed" => Ok(Self::Confirmed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            "bounced" => Ok(Self::Bounced),
+            "complained" => Ok(Self::Complained),
+            _ => Err(format!("{} is not a valid subscriber status.", s)),
+        }
+    }
+
+    fn parse_mailchimp(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "subscribed" => Ok(Self::Confirmed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            "cleaned" => Ok(Self::Bounced),
+            "pending" => Err("The contact never confirmed their subscription.".into()),
+            _ => Err(format!("Mailchimp {} contacts are not subscribers.", s)),
+        }
+    }
+}
+
+/// The headers of the columns holding each field of our own format,
+/// compared without case.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ColumnMapping {
+    pub email: String,
+    pub name: String,
+    /// Tags separated by `;`, if the file has any.
+    #[serde(default)]
+    pub tags: Option<String>,
+    /// One of our statuses; new subscribers are confirmed without it.
+    #[serde(default)]
+    pub status: Option<String>,
+    /// When they subscribed; now without it.
+    #[serde(default)]
+    pub subscribed_at: Option<String>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            email: "email".into(),
+            name: "name".into(),
+            tags: None,
+            status: None,
+            subscribed_at: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportRow {
+    pub email: SubscriberEmail,
+    pub name: SubscriberName,
+    pub tags: Option<Vec<String>>,
+    /// `None` if the file does not say.
+    pub status: Option<ImportedStatus>,
+    pub subscribed_at: Option<DateTime<Utc>>,
+}
+
+/// Where the fields are in the records of a file, found from its header.
+pub enum RowLayout {
+    Csv {
+        email: usize,
+        name: usize,
+        tags: Option<usize>,
+        status: Option<usize>,
+        subscribed_at: Option<usize>,
+    },
+    Mailchimp {
+        email: usize,
+        first_name: Option<usize>,
+        last_name: Option<usize>,
+        tags: Option<usize>,
+        status: Option<usize>,
+        /// Mailchimp exports each status to its own file.
+        file_status: &'static str,
+        optin_time: Option<usize>,
+        confirm_time: Option<usize>,
+    },
+    Substack {
+        email: usize,
+        name: Option<usize>,
+        email_disabled: Option<usize>,
+        created_at: Option<usize>,
+    },
+}
+
+impl RowLayout {
+    pub fn resolve(
+        format: ImportFormat,
+        columns: &ColumnMapping,
+        header: &CsvRecord,
+    ) -> Result<Self, String> {
+        let find = |name: &str| {
+            header
+                .fields
+                .iter()
+                .position(|field| field.trim().eq_ignore_ascii_case(name.trim()))
+        };
+        let require =
+            |name: &str| find(name).ok_or_else(|| format!("The file has no {} column.", name));
+        let require_mapped = |name: &Option<String>| name.as_deref().map(require).transpose();
+
+        Ok(match format {
+            ImportFormat::Csv => RowLayout::Csv {
+                email: require(&columns.email)?,
+                name: require(&columns.name)?,
+                tags: require_mapped(&columns.tags)?,
+                status: require_mapped(&columns.status)?,
+                subscribed_at: require_mapped(&columns.subscribed_at)?,
+            },
+            ImportFormat::Mailchimp => RowLayout::Mailchimp {
+                email: require("Email Address")?,
+                first_name: find("First Name"),
+                last_name: find("Last Name"),
+                tags: find("TAGS"),
+                status: find("Status"),
+                file_status: if find("CLEAN_TIME").is_some() {
+                    "cleaned"
+                } else if find("UNSUB_TIME").is_some() {
+                    "unsubscribed"
+                } else {
+                    "subscribed"
+                },
+                optin_time: find("OPTIN_TIME"),
+                confirm_time: find("CONFIRM_TIME"),
+            },
+            ImportFormat::Substack => RowLayout::Substack {
+                email: require("email")?,
+                name: find("name"),
+                email_disabled: find("email_disabled"),
+                created_at: find("created_at"),
+            },
+        })
+    }
+
+    pub fn parse_row(&self, record: &CsvRecord) -> Result<ImportRow, String> {
+        let field = |column: usize| record.get(column).unwrap_or_default().trim();
+        let optional = |column: Option<usize>| column.map(field).filter(|value| !value.is_empty());
+
+        match *self {
+            RowLayout::Csv {
+                email,
+                name,
+                tags,
+                status,
+                subscribed_at,
+            } => Ok(ImportRow {
+                email: SubscriberEmail::parse(field(email).to_owned())?,
+                name: SubscriberName::parse(field(name).to_owned())?,
+                tags: tags
+                    .map(|column| parse_tags(field(column).split(';')))
+                    .transpose()?,
+                status: optional(status).map(ImportedStatus::parse).transpose()?,
+                subscribed_at: optional(subscribed_at).map(parse_timestamp).transpose()?,
+            }),
+            RowLayout::Mailchimp {
+                email,
+                first_name,
+                last_name,
+                tags,
+                status,
+                file_status,
+                optin_time,
+                confirm_time,
+            } => {
+                let email = SubscriberEmail::parse(field(email).to_owned())?;
+                let full_name = [optional(first_name), optional(last_name)]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                Ok(ImportRow {
+                    name: name_or_local_part(full_name, &email)?,
+                    email,
+                    // a CSV line of its own, e.g. `"Tag A","Tag B"`
+                    tags: tags
+                        .map(|column| {
+                            let tags = CsvReader::new(field(column))
+                                .next()
+                                .transpose()
+                                .map_err(|e| e.message.to_owned())?
+                                .map(|record| record.fields)
+                                .unwrap_or_default();
+                            parse_tags(tags.iter().map(String::as_str))
+                        })
+                        .transpose()?,
+                    status: Some(ImportedStatus::parse_mailchimp(
+                        optional(status).unwrap_or(file_status),
+                    )?),
+                    subscribed_at: optional(optin_time)
+                        .or(optional(confirm_time))
+                        .map(parse_timestamp)
+                        .transpose()?,
+                })
+            }
+            RowLayout::Substack {
+                email,
+                name,
+                email_disabled,
+                created_at,
+            } => {
+                let email = SubscriberEmail::parse(field(email).to_owned())?;
+                let is_disabled = optional(email_disabled)
+                    .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+                Ok(ImportRow {
+                    name: name_or_local_part(optional(name).unwrap_or_default().into(), &email)?,
+                    email,
+                    tags: None,
+                    status: Some(if is_disabled {
+                        ImportedStatus::Unsubscribed
+                    } else {
+                        ImportedStatus::Confirmed
+                    }),
+                    subscribed_at: optional(created_at).map(parse_timestamp).transpose()?,
+                })
+            }
+        }
+    }
+}
+
+fn parse_tags<'a>(tags: impl Iterator<Item = &'a str>) -> Result<Vec<String>, String> {
+    let mut tags = tags
+        .filter(|tag| !tag.trim().is_empty())
+        .map(parse_tag)
+        .collect::<Result<Vec<_>, _>>()?;
+    tags.sort_unstable();
+    tags.dedup();
+    Ok(tags)
+}
+
+/// Other tools do not require names: the local part of the address stands
+/// in for a missing one.
+fn name_or_local_part(name: String, email: &SubscriberEmail) -> Result<SubscriberName, String> {
+    if name.trim().is_empty() {
+        let local_part = email.as_ref().split('@').next().unwrap_or_default();
+        SubscriberName::parse(local_part.to_owned())
+    } else {
+        SubscriberName::parse(name)
+    }
+}
+
+/// RFC 3339 as we export it, `2019-05-03 14:15:27` as Mailchimp does
+/// (UTC), or a bare date.
+fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.to_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").map(|t| t.and_utc()))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .map(|d| d.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        })
+        .map_err(|_| format!("{} is not a valid date.", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColumnMapping, ImportFormat, ImportedStatus, RowLayout};
+    use crate::csv::CsvReader;
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+
+    fn rows(
+        format: ImportFormat,
+        columns: &ColumnMapping,
+        content: &str,
+    ) -> Vec<Result<super::ImportRow, String>> {
+        let mut records = CsvReader::new(content).map(Result::unwrap);
+        let layout = RowLayout::resolve(format, columns, &records.next().unwrap()).unwrap();
+        records.map(|record| layout.parse_row(&record)).collect()
+    }
+
+    #[test]
+    fn our_format_follows_the_mapping() {
+        let columns = ColumnMapping {
+            email: "E-mail".into(),
+            tags: Some("labels".into()),
+            status: Some("status".into()),
+            subscribed_at: Some("subscribed_at".into()),
+            ..Default::default()
+        };
+        let rows = rows(
+            ImportFormat::Csv,
+            &columns,
+            "e-mail,name,labels,status,subscribed_at\n\
+             a@example.com,Ursula,VIP; authors;vip,unsubscribed,2025-03-01T10:00:00+01:00\n\
+             b@example.com,Octavia,,,\n\
+             c@example.com,Ted,,gone,\n",
+        );
+        let row = assert_ok!(&rows[0]);
+        assert_eq!(
+            row.tags,
+            Some(vec!["authors".to_string(), "vip".to_string()])
+        );
+        assert_eq!(row.status, Some(ImportedStatus::Unsubscribed));
+        assert_eq!(
+            row.subscribed_at,
+            Some(Utc.with_ymd_and_hms(2025, 3, 1, 9, 0, 0).unwrap())
+        );
+        let row = assert_ok!(&rows[1]);
+        assert_eq!((row.status, row.subscribed_at), (None, None));
+        assert_err!(&rows[2]);
+    }
+
+    #[test]
+    fn mailchimp_exports_map_onto_our_lifecycle() {
+        let subscribed = rows(
+            ImportFormat::Mailchimp,
+            &Default::default(),
+            "Email Address,First Name,Last Name,OPTIN_TIME,TAGS\n\
+             a@example.com,Ursula,Le Guin,2019-05-03 14:15:27,\"\"\"VIP\"\",\"\"Authors\"\"\"\n\
+             octavia@example.com,,,,\n",
+        );
+        let row = assert_ok!(&subscribed[0]);
+        assert_eq!(row.name.as_ref(), "Ursula Le Guin");
+        assert_eq!(row.status, Some(ImportedStatus::Confirmed));
+        assert_eq!(
+            row.tags,
+            Some(vec!["authors".to_string(), "vip".to_string()])
+        );
+        assert_eq!(
+            row.subscribed_at,
+            Some(Utc.with_ymd_and_hms(2019, 5, 3, 14, 15, 27).unwrap())
+        );
+        assert_eq!(assert_ok!(&subscribed[1]).name.as_ref(), "octavia");
+
+        let cleaned = rows(
+            ImportFormat::Mailchimp,
+            &Default::default(),
+            "Email Address,First Name,CLEAN_TIME\na@example.com,Ursula,2020-01-01 00:00:00\n",
+        );
+        assert_eq!(
+            assert_ok!(&cleaned[0]).status,
+            Some(ImportedStatus::Bounced)
+        );
+
+        let mixed = rows(
+            ImportFormat::Mailchimp,
+            &Default::default(),
+            "Email Address,Status\na@example.com,unsubscribed\nb@example.com,pending\nc@example.com,archived\n",
+        );
+        assert_eq!(
+            assert_ok!(&mixed[0]).status,
+            Some(ImportedStatus::Unsubscribed)
+        );
+        assert_err!(&mixed[1]);
+        assert_err!(&mixed[2]);
+    }
+
+    #[test]
+    fn substack_exports_map_onto_our_lifecycle() {
+        let rows = rows(
+            ImportFormat::Substack,
+            &Default::default(),
+            "email,active_subscription,email_disabled,created_at\n\
+             ursula@example.com,false,false,2021-03-04T05:06:07.000Z\n\
+             octavia@example.com,false,true,2021-03-04T05:06:07.000Z\n",
+        );
+        let row = assert_ok!(&rows[0]);
+        assert_eq!(row.name.as_ref(), "ursula");
+        assert_eq!(row.status, Some(ImportedStatus::Confirmed));
+        assert_eq!(
+            assert_ok!(&rows[1]).status,
+            Some(ImportedStatus::Unsubscribed)
+        );
+    }
+
+    #[test]
+    fn formats_round_trip_through_their_string_representation() {
+        for format in [
+            ImportFormat::Csv,
+            ImportFormat::Mailchimp,
+            ImportFormat::Substack,
+        ] {
+            assert_eq!(
+                ImportFormat::try_from(format.as_str().to_owned()),
+                Ok(format)
+            );
+        }
+        assert_err!(ImportFormat::try_from("convertkit".to_owned()));
+    }
+}
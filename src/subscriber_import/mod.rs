@@ -0,0 +1,636 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{GenericClient, Pool};
+use uuid::Uuid;
+
+use crate::consent::ConsentContext;
+use crate::csv::{CsvError, CsvReader, CsvRecord};
+use crate::domain::Subscriber;
+use crate::publication::PublicationId;
+use crate::subscribers::{insert_subscription, load_subscription_by_email, save_subscription};
+use crate::suppression::{email_hash, normalize_email, suppress};
+
+mod formats;
+
+use formats::ImportRow;
+pub use formats::{ColumnMapping, ImportFormat, ImportedStatus, RowLayout};
+
+/// Rows imported per transaction, and between two progress updates.
+const CHUNK_SIZE: usize = 500;
+/// Running imports whose progress has not moved for this long are taken
+/// over by another worker.
+const STALE_AFTER_SECONDS: f64 = 60.0;
+
+/// What to do with a row whose address is already subscribed, or appears
+/// earlier in the file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    #[default]
+    Skip,
+    /// Overwrite the name, and the tags if the file has them.
+    Update,
+    /// Reject the row.
+    Error,
+}
+
+impl DuplicatePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DuplicatePolicy::Skip => "skip",
+            DuplicatePolicy::Update => "update",
+            DuplicatePolicy::Error => "error",
+        }
+    }
+}
+
+impl TryFrom<String> for DuplicatePolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        match s.as_str() {
+            "skip" => Ok(Self::Skip),
+            "update" => Ok(Self::Update),
+            "error" => Ok(Self::Error),
+            other => Err(format!(
+                "{} is not a valid duplicate policy: use skip, update or error.",
+                other
+            )),
+        }
+    }
+}
+
+/// Check the header of a file against its format and count its rows.
+pub fn inspect(
+    content: &str,
+    format: ImportFormat,
+    columns: &ColumnMapping,
+) -> Result<i32, String> {
+    let mut records = CsvReader::new(content);
+    let header = match records.next() {
+        Some(header) => header.map_err(|e| e.to_string())?,
+        None => return Err("The file is empty.".into()),
+    };
+    RowLayout::resolve(format, columns, &header)?;
+    i32::try_from(records.count()).map_err(|_| "The file has too many rows.".into())
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SubscriberImport {
+    pub id: Uuid,
+    pub status: String,
+    pub dry_run: bool,
+    pub on_duplicate: DuplicatePolicy,
+    pub format: ImportFormat,
+    /// Only used by the `csv` format.
+    pub columns: ColumnMapping,
+    pub total_rows: i32,
+    pub processed_rows: i32,
+    /// New subscribers, or that would be on a dry run.
+    pub imported: i32,
+    pub updated: i32,
+    pub skipped: i32,
+    pub rejected: i32,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Rejection {
+    pub line: i32,
+    pub reason: String,
+}
+
+pub struct NewImport<'a> {
+    pub content: &'a str,
+    pub format: ImportFormat,
+    pub columns: &'a ColumnMapping,
+    pub on_duplicate: DuplicatePolicy,
+    pub dry_run: bool,
+    /// As counted by `inspect`.
+    pub total_rows: i32,
+}
+
+/// Queue a file for the import worker. With `claimed`, the caller processes
+/// it itself with `process_import` and the worker leaves it alone.
+#[tracing::instrument(name = "Queuing a subscriber import", skip(client, import))]
+pub async fn create_import<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    import: NewImport<'_>,
+    claimed: bool,
+) -> Result<Uuid, Box<dyn std::error::Error + Send + Sync>> {
+    let id = Uuid::new_v4();
+    client
+        .execute(
+            r#"
+    INSERT INTO subscriber_imports
+        (id, publication_id, status, dry_run, on_duplicate, format, columns, content,
+         total_rows, created_at, heartbeat_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now(), CASE WHEN $10 THEN now() END)
+    "#,
+            &[
+                &id,
+                &publication_id.0,
+                &if claimed { "running" } else { "pending" },
+                &import.dry_run,
+                &import.on_duplicate.as_str(),
+                &import.format.as_str(),
+                &serde_json::to_value(import.columns)?,
+                &import.content,
+                &import.total_rows,
+                &claimed,
+            ],
+        )
+        .await?;
+    Ok(id)
+}
+
+pub async fn get_import<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    id: Uuid,
+) -> Result<Option<SubscriberImport>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT id, status, dry_run, on_duplicate, format, columns, total_rows, processed_rows,
+           imported, updated, skipped, rejected, created_at, finished_at
+    FROM subscriber_imports
+    WHERE publication_id = $1 AND id = $2
+    "#,
+            &[&publication_id.0, &id],
+        )
+        .await?;
+    row.map(|row| {
+        Ok(SubscriberImport {
+            id: row.get("id"),
+            status: row.get("status"),
+            dry_run: row.get("dry_run"),
+            on_duplicate: row.get::<_, String>("on_duplicate").try_into()?,
+            format: row.get::<_, String>("format").try_into()?,
+            columns: serde_json::from_value(row.get("columns"))?,
+            total_rows: row.get("total_rows"),
+            processed_rows: row.get("processed_rows"),
+            imported: row.get("imported"),
+            updated: row.get("updated"),
+            skipped: row.get("skipped"),
+            rejected: row.get("rejected"),
+            created_at: row.get("created_at"),
+            finished_at: row.get("finished_at"),
+        })
+    })
+    .transpose()
+}
+
+/// The rejected lines of an import after `after_line`, in file order.
+pub async fn list_rejections<C: GenericClient>(
+    client: &C,
+    import_id: Uuid,
+    after_line: i32,
+    limit: i64,
+) -> Result<Vec<Rejection>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(
+            r#"
+    SELECT line, reason FROM subscriber_import_rejections
+    WHERE import_id = $1 AND line > $2
+    ORDER BY line
+    LIMIT $3
+    "#,
+            &[&import_id, &after_line, &limit],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| Rejection {
+            line: row.get("line"),
+            reason: row.get("reason"),
+        })
+        .collect())
+}
+
+/// Import files forever, one at a time.
+pub async fn worker_loop(pool: Pool) {
+    loop {
+        match try_import_next(&pool).await {
+            Ok(true) => {}
+            Ok(false) => tokio::time::sleep(Duration::from_secs(5)).await,
+            Err(e) => {
+                tracing::error!("Failed to import subscribers: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Returns `false` if there was nothing to import.
+async fn try_import_next(pool: &Pool) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let client = pool.get().await?;
+    let row = client
+        .query_opt(
+            r#"
+    UPDATE subscriber_imports SET status = 'running', heartbeat_at = now()
+    WHERE id = (
+        SELECT id FROM subscriber_imports
+        WHERE status = 'pending'
+           OR (status = 'running' AND heartbeat_at < now() - make_interval(secs => $1))
+        ORDER BY created_at
+        FOR UPDATE SKIP LOCKED
+        LIMIT 1
+    )
+    RETURNING id
+    "#,
+            &[&STALE_AFTER_SECONDS],
+        )
+        .await?;
+    drop(client);
+    match row {
+        Some(row) => process_import(pool, row.get("id")).await.map(|_| true),
+        None => Ok(false),
+    }
+}
+
+#[derive(Default)]
+struct ChunkOutcome {
+    imported: i32,
+    updated: i32,
+    skipped: i32,
+    rejections: Vec<(usize, String)>,
+}
+
+/// Work through an import, `CHUNK_SIZE` rows per transaction, from where
+/// it was left off. Rows are only ever processed once: a chunk is rolled
+/// back if another worker got there first.
+#[tracing::instrument(name = "Importing subscribers", skip(pool))]
+pub async fn process_import(
+    pool: &Pool,
+    import_id: Uuid,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let row = pool
+        .get()
+        .await?
+        .query_one(
+            r#"
+    SELECT publication_id, dry_run, on_duplicate, format, columns, content, processed_rows
+    FROM subscriber_imports WHERE id = $1
+    "#,
+            &[&import_id],
+        )
+        .await?;
+    let Some(content) = row.get::<_, Option<String>>("content") else {
+        return Ok(());
+    };
+    let publication_id = PublicationId(row.get("publication_id"));
+    let dry_run: bool = row.get("dry_run");
+    let on_duplicate: DuplicatePolicy = row.get::<_, String>("on_duplicate").try_into()?;
+    let format: ImportFormat = row.get::<_, String>("format").try_into()?;
+    let columns: ColumnMapping = serde_json::from_value(row.get("columns"))?;
+    let mut processed_rows: i32 = row.get("processed_rows");
+
+    let settings = ImportSettings {
+        publication_id,
+        on_duplicate,
+        dry_run,
+        consent: ConsentContext::new(format!("import:{}", import_id)),
+    };
+
+    let mut records = CsvReader::new(&content);
+    let layout = match records.next() {
+        Some(Ok(header)) => RowLayout::resolve(format, &columns, &header)?,
+        _ => return Err("The import has no header.".into()),
+    };
+    // the lines each address was first seen on, to spot duplicates within
+    // the file; the rows processed before we took over count too
+    let mut seen = HashMap::new();
+    for record in (&mut records).take(processed_rows as usize).flatten() {
+        if let Ok(row) = layout.parse_row(&record) {
+            seen.entry(normalize_email(row.email.as_ref()))
+                .or_insert(record.line);
+        }
+    }
+
+    loop {
+        let chunk: Vec<_> = (&mut records).take(CHUNK_SIZE).collect();
+        let mut client = pool.get().await?;
+        let transaction = client.transaction().await?;
+        if chunk.is_empty() {
+            transaction
+                .execute(
+                    r#"
+    UPDATE subscriber_imports
+    SET status = 'completed', content = NULL, finished_at = now()
+    WHERE id = $1 AND processed_rows = $2
+    "#,
+                    &[&import_id, &processed_rows],
+                )
+                .await?;
+            transaction.commit().await?;
+            return Ok(());
+        }
+
+        let outcome = import_chunk(&transaction, &chunk, &layout, &settings, &mut seen).await?;
+        for (line, reason) in &outcome.rejections {
+            transaction
+                .execute(
+                    r#"
+    INSERT INTO subscriber_import_rejections (import_id, line, reason)
+    VALUES ($1, $2, $3)
+    ON CONFLICT DO NOTHING
+    "#,
+                    &[&import_id, &(*line as i32), reason],
+                )
+                .await?;
+        }
+        let n_rows = chunk.len() as i32;
+        let n_updated = transaction
+            .execute(
+                r#"
+    UPDATE subscriber_imports
+    SET processed_rows = processed_rows + $3, imported = imported + $4,
+        updated = updated + $5, skipped = skipped + $6, rejected = rejected + $7,
+        heartbeat_at = now()
+    WHERE id = $1 AND processed_rows = $2
+    "#,
+                &[
+                    &import_id,
+                    &processed_rows,
+                    &n_rows,
+                    &outcome.imported,
+                    &outcome.updated,
+                    &outcome.skipped,
+                    &(outcome.rejections.len() as i32),
+                ],
+            )
+            .await?;
+        if n_updated == 0 {
+            return Err("Another worker took the import over.".into());
+        }
+        transaction.commit().await?;
+        processed_rows += n_rows;
+    }
+}
+
+/// How every chunk of an import is written.
+struct ImportSettings {
+    publication_id: PublicationId,
+    on_duplicate: DuplicatePolicy,
+    dry_run: bool,
+    /// Names the import as the source of the subscriptions.
+    consent: ConsentContext,
+}
+
+async fn import_chunk<C: GenericClient>(
+    client: &C,
+    chunk: &[Result<CsvRecord, CsvError>],
+    layout: &RowLayout,
+    settings: &ImportSettings,
+    seen: &mut HashMap<String, usize>,
+) -> Result<ChunkOutcome, Box<dyn std::error::Error + Send + Sync>> {
+    let &ImportSettings {
+        publication_id,
+        on_duplicate,
+        dry_run,
+        ref consent,
+    } = settings;
+    let mut outcome = ChunkOutcome::default();
+    let mut rows = Vec::with_capacity(chunk.len());
+    for record in chunk {
+        let parsed = match record {
+            Ok(record) => layout
+                .parse_row(record)
+                .map(|row| (record.line, row))
+                .map_err(|reason| (record.line, reason)),
+            Err(e) => Err((e.line, e.message.to_owned())),
+        };
+        match parsed {
+            Ok(row) => rows.push(row),
+            Err(rejection) => outcome.rejections.push(rejection),
+        }
+    }
+
+    let addresses: Vec<String> = rows
+        .iter()
+        .map(|(_, row)| normalize_email(row.email.as_ref()))
+        .collect();
+    let existing: HashSet<String> = client
+        .query(
+            r#"
+    SELECT lower(email) AS email FROM subscriptions
+    WHERE publication_id = $1 AND lower(email) = ANY($2)
+    "#,
+            &[&publication_id.0, &addresses],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get("email"))
+        .collect();
+    // erased addresses are only on the list as hashes
+    let hashes: Vec<String> = addresses
+        .iter()
+        .map(|address| email_hash(address))
+        .collect();
+    let on_list: HashSet<String> = client
+        .query(
+            r#"
+    SELECT email FROM suppressions
+    WHERE (email = ANY($1) OR email = ANY($2))
+      AND (publication_id IS NULL OR publication_id = $3)
+    "#,
+            &[&addresses, &hashes, &publication_id.0],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get("email"))
+        .collect();
+    let suppressed: HashSet<String> = addresses
+        .iter()
+        .zip(&hashes)
+        .filter(|(address, hash)| on_list.contains(*address) || on_list.contains(*hash))
+        .map(|(address, _)| address.clone())
+        .collect();
+
+    for ((line, row), address) in rows.into_iter().zip(addresses) {
+        // unsubscribed, bounced and complained rows can still be recorded
+        let is_confirmed = matches!(row.status, None | Some(ImportedStatus::Confirmed));
+        if is_confirmed && suppressed.contains(&address) {
+            outcome
+                .rejections
+                .push((line, "The address is on the suppression list.".into()));
+            continue;
+        }
+        let duplicate = match seen.get(&address) {
+            Some(first_line) => Some(format!("The address already is on line {}.", first_line)),
+            None if existing.contains(&address) => {
+                Some("The address is already subscribed.".into())
+            }
+            None => None,
+        };
+        let is_written = match (duplicate, on_duplicate) {
+            (None, _) => {
+                if !dry_run {
+                    insert_subscriber(client, publication_id, &row, consent).await?;
+                }
+                outcome.imported += 1;
+                true
+            }
+            (Some(_), DuplicatePolicy::Skip) => {
+                outcome.skipped += 1;
+                false
+            }
+            (Some(_), DuplicatePolicy::Update) => {
+                if !dry_run {
+                    update_subscriber(client, publication_id, &row, consent).await?;
+                }
+                outcome.updated += 1;
+                true
+            }
+            (Some(reason), DuplicatePolicy::Error) => {
+                outcome.rejections.push((line, reason));
+                continue;
+            }
+        };
+        // the other tool stopped sending to them, so must we; an existing
+        // suppression keeps its reason
+        let reason = row.status.and_then(|status| status.suppression());
+        if let (Some(reason), true, false) = (reason, is_written, dry_run) {
+            if !suppressed.contains(&address) {
+                suppress(client, &address, reason, Some(publication_id)).await?;
+            }
+        }
+        seen.entry(address).or_insert(line);
+    }
+    outcome.rejections.sort_unstable();
+    Ok(outcome)
+}
+
+async fn insert_subscriber<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    row: &ImportRow,
+    consent: &ConsentContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut subscriber = Subscriber::subscribe(publication_id, &row.email);
+    row.status
+        .unwrap_or(ImportedStatus::Confirmed)
+        .apply(&mut subscriber)?;
+    let name = row.name.as_ref();
+    let tags = row.tags.as_deref();
+    insert_subscription(
+        client,
+        &mut subscriber,
+        name,
+        tags,
+        row.subscribed_at,
+        consent,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn update_subscriber<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    row: &ImportRow,
+    consent: &ConsentContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let email = row.email.as_ref();
+    let Some(mut subscriber) = load_subscription_by_email(client, publication_id, email).await?
+    else {
+        return Ok(());
+    };
+    client
+        .execute(
+            "UPDATE subscriptions SET name = $2, tags = COALESCE($3, tags) WHERE id = $1",
+            &[&subscriber.id(), &row.name.as_ref(), &row.tags],
+        )
+        .await?;
+    // an import never confirms anyone who left
+    let status = row
+        .status
+        .filter(|status| *status != ImportedStatus::Confirmed);
+    if let Some(status) = status {
+        status.apply(&mut subscriber)?;
+        save_subscription(client, &mut subscriber, consent).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inspect, ColumnMapping, DuplicatePolicy, ImportFormat, RowLayout};
+    use crate::csv::CsvReader;
+    use claims::{assert_err, assert_ok};
+
+    fn mapping(email: &str, name: &str, tags: Option<&str>) -> ColumnMapping {
+        ColumnMapping {
+            email: email.into(),
+            name: name.into(),
+            tags: tags.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn inspecting_counts_rows_after_the_header() {
+        let content = "Email Address,First Name\na@example.com,A\n\nb@example.com,B\n";
+        let columns = mapping("email address", "first name", None);
+        assert_eq!(assert_ok!(inspect(content, ImportFormat::Csv, &columns)), 2);
+    }
+
+    #[test]
+    fn inspecting_requires_the_mapped_columns() {
+        let content = "email,name\na@example.com,A\n";
+        assert_err!(inspect(
+            content,
+            ImportFormat::Csv,
+            &mapping("email", "full name", None)
+        ));
+        assert_err!(inspect(
+            content,
+            ImportFormat::Csv,
+            &mapping("email", "name", Some("tags"))
+        ));
+        assert_err!(inspect("", ImportFormat::Csv, &ColumnMapping::default()));
+    }
+
+    #[test]
+    fn rows_are_validated_like_signups() {
+        let content = "email,name,labels\n a@example.com ,Ursula,VIP; authors;vip\nnot-an-email,X,\nb@example.com,<script>,\n";
+        let mut records = CsvReader::new(content).map(Result::unwrap);
+        let layout = RowLayout::resolve(
+            ImportFormat::Csv,
+            &mapping("email", "name", Some("labels")),
+            &records.next().unwrap(),
+        )
+        .unwrap();
+
+        let row = assert_ok!(layout.parse_row(&records.next().unwrap()));
+        assert_eq!(row.email.as_ref(), "a@example.com");
+        assert_eq!(row.name.as_ref(), "Ursula");
+        assert_eq!(
+            row.tags,
+            Some(vec!["authors".to_string(), "vip".to_string()])
+        );
+
+        assert_err!(layout.parse_row(&records.next().unwrap()));
+        assert_err!(layout.parse_row(&records.next().unwrap()));
+    }
+
+    #[test]
+    fn duplicate_policies_round_trip_through_their_string_representation() {
+        for policy in [
+            DuplicatePolicy::Skip,
+            DuplicatePolicy::Update,
+            DuplicatePolicy::Error,
+        ] {
+            assert_eq!(
+                DuplicatePolicy::try_from(policy.as_str().to_owned()),
+                Ok(policy)
+            );
+        }
+        assert_err!(DuplicatePolicy::try_from("merge".to_owned()));
+    }
+}
@@ -0,0 +1,723 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use deadpool_postgres::GenericClient;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::consent::{record_consent, ConsentAction, ConsentContext};
+use crate::domain::{self, SubscriberEvent, SubscriptionStatus, TransitionError};
+use crate::publication::PublicationId;
+use crate::suppression::{normalize_email, suppress, SuppressionReason};
+
+/// A subscription, as administrators see it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub tags: Vec<String>,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+/// Narrows a listing down; every field is optional and they all apply.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SubscriberFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// A substring of the email address or of the name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Subscribed on this day or later, UTC.
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub subscribed_from: Option<NaiveDate>,
+    /// Subscribed on this day or earlier, UTC.
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub subscribed_to: Option<NaiveDate>,
+}
+
+/// Date inputs left empty are submitted as `subscribed_from=`.
+fn blank_as_none<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(day) => day.parse().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+impl SubscriberFilter {
+    /// Forms submit empty fields for "any".
+    pub fn without_blanks(self) -> Self {
+        let present = |value: Option<String>| {
+            value
+                .map(|value| value.trim().to_owned())
+                .filter(|value| !value.is_empty())
+        };
+        Self {
+            status: present(self.status),
+            q: present(self.q),
+            tag: present(self.tag).map(|tag| tag.to_lowercase()),
+            ..self
+        }
+    }
+
+    /// The bounds of the date range as timestamps, the upper one exclusive.
+    fn bounds(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        let midnight = |day: NaiveDate| day.and_hms_opt(0, 0, 0).map(|t| t.and_utc());
+        (
+            self.subscribed_from.and_then(midnight),
+            self.subscribed_to
+                .and_then(|day| day.checked_add_days(Days::new(1)))
+                .and_then(midnight),
+        )
+    }
+
+    /// `%` and `_` in the search are meant literally.
+    fn pattern(&self) -> Option<String> {
+        self.q.as_deref().map(|q| {
+            format!(
+                "%{}%",
+                q.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            )
+        })
+    }
+}
+
+/// Where a page of subscribers ends, so the next one can pick up after it
+/// even if subscribers sign up in between. Opaque to API clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriberCursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl SubscriberCursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!(
+            "{}.{}",
+            self.subscribed_at.timestamp_micros(),
+            self.id
+        ))
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("{} is not a valid cursor.", s);
+        let decoded = URL_SAFE_NO_PAD.decode(s).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (micros, id) = decoded.split_once('.').ok_or_else(invalid)?;
+        let subscribed_at = micros
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(invalid)?;
+        let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+        Ok(Self { subscribed_at, id })
+    }
+}
+
+pub struct SubscriberPage {
+    pub subscribers: Vec<Subscriber>,
+    /// `None` on the last page.
+    pub next: Option<SubscriberCursor>,
+}
+
+/// Tags are short lowercase labels.
+pub fn parse_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.chars().count() > 64 || tag.chars().any(char::is_control) {
+        Err(format!("{} is not a valid tag.", tag))
+    } else {
+        Ok(tag)
+    }
+}
+
+const SUBSCRIBER_COLUMNS: &str = "id, email, name, status, tags, subscribed_at";
+
+fn from_row(row: &Row) -> Subscriber {
+    Subscriber {
+        id: row.get("id"),
+        email: row.get("email"),
+        name: row.get("name"),
+        status: row.get("status"),
+        tags: row.get("tags"),
+        subscribed_at: row.get("subscribed_at"),
+    }
+}
+
+/// The conditions of a `SubscriberFilter` on `subscriptions s`, bound to
+/// `$1` to `$6` by `FilterParams`.
+const FILTER_CONDITIONS: &str = r#"s.publication_id = $1
+      AND ($2::TEXT IS NULL OR s.status = $2)
+      AND ($3::TEXT IS NULL OR s.email ILIKE $3 OR s.name ILIKE $3)
+      AND ($4::TEXT IS NULL OR $4 = ANY(s.tags))
+      AND ($5::TIMESTAMPTZ IS NULL OR s.subscribed_at >= $5)
+      AND ($6::TIMESTAMPTZ IS NULL OR s.subscribed_at < $6)"#;
+
+struct FilterParams<'a> {
+    publication_id: Uuid,
+    status: Option<&'a str>,
+    pattern: Option<String>,
+    tag: Option<&'a str>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl<'a> FilterParams<'a> {
+    fn new(publication_id: PublicationId, filter: &'a SubscriberFilter) -> Self {
+        let (from, to) = filter.bounds();
+        Self {
+            publication_id: publication_id.0,
+            status: filter.status.as_deref(),
+            pattern: filter.pattern(),
+            tag: filter.tag.as_deref(),
+            from,
+            to,
+        }
+    }
+
+    fn bind(&self) -> Vec<&(dyn ToSql + Sync)> {
+        vec![
+            &self.publication_id,
+            &self.status,
+            &self.pattern,
+            &self.tag,
+            &self.from,
+            &self.to,
+        ]
+    }
+}
+
+/// Newest first, `limit` at a time, after `after` if given.
+#[tracing::instrument(name = "Listing subscribers", skip(client))]
+pub async fn list_subscribers<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    filter: &SubscriberFilter,
+    after: Option<SubscriberCursor>,
+    limit: i64,
+) -> Result<SubscriberPage, Box<dyn std::error::Error + Send + Sync>> {
+    let filter = FilterParams::new(publication_id, filter);
+    let after_subscribed_at = after.map(|cursor| cursor.subscribed_at);
+    let after_id = after.map(|cursor| cursor.id);
+    // one more than asked for tells us whether there is a next page
+    let n_rows = limit + 1;
+    let mut params = filter.bind();
+    params.extend([
+        &after_subscribed_at as &(dyn ToSql + Sync),
+        &after_id,
+        &n_rows,
+    ]);
+    let rows = client
+        .query(
+            &format!(
+                r#"
+    SELECT {}
+    FROM subscriptions s
+    WHERE {}
+      AND ($7::TIMESTAMPTZ IS NULL OR (s.subscribed_at, s.id) < ($7, $8))
+    ORDER BY s.subscribed_at DESC, s.id DESC
+    LIMIT $9
+    "#,
+                SUBSCRIBER_COLUMNS, FILTER_CONDITIONS
+            ),
+            &params,
+        )
+        .await?;
+    let mut subscribers: Vec<Subscriber> = rows.iter().map(from_row).collect();
+    let next = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| SubscriberCursor {
+            subscribed_at: last.subscribed_at,
+            id: last.id,
+        })
+    } else {
+        None
+    };
+    Ok(SubscriberPage { subscribers, next })
+}
+
+/// How many subscribers match the filter, across all pages.
+#[tracing::instrument(name = "Counting subscribers", skip(client))]
+pub async fn count_subscribers<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    filter: &SubscriberFilter,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    let filter = FilterParams::new(publication_id, filter);
+    let row = client
+        .query_one(
+            &format!(
+                "SELECT COUNT(*) AS total FROM subscriptions s WHERE {}",
+                FILTER_CONDITIONS
+            ),
+            &filter.bind(),
+        )
+        .await?;
+    Ok(row.get("total"))
+}
+
+/// A subscriber as exported, with whether we may still email them.
+#[derive(Debug, serde::Serialize)]
+pub struct ExportedSubscriber {
+    #[serde(flatten)]
+    pub subscriber: Subscriber,
+    pub suppression_reason: Option<SuppressionReason>,
+    pub suppressed_at: Option<DateTime<Utc>>,
+    /// Of the latest opt-in, from the consent records; `None` for
+    /// subscriptions older than them.
+    pub consent_source: Option<String>,
+    pub consent_text_version: Option<String>,
+    pub consented_at: Option<DateTime<Utc>>,
+    pub consent_ip: Option<String>,
+}
+
+const EXPORT_CURSOR: &str = "subscriber_export";
+
+/// Open a cursor over the subscribers matching the filter, oldest first.
+/// It only lives as long as the transaction `client` must be in.
+#[tracing::instrument(name = "Opening a subscriber export cursor", skip(client))]
+pub async fn declare_export_cursor<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    filter: &SubscriberFilter,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = FilterParams::new(publication_id, filter);
+    client
+        .execute(
+            &format!(
+                r#"
+    DECLARE {} NO SCROLL CURSOR FOR
+    SELECT s.id, s.email, s.name, s.status, s.tags, s.subscribed_at,
+           x.reason AS suppression_reason, x.created_at AS suppressed_at,
+           c.source AS consent_source, c.consent_text_version,
+           c.occurred_at AS consented_at, c.ip AS consent_ip
+    FROM subscriptions s
+    LEFT JOIN LATERAL (
+        SELECT reason, created_at FROM suppressions
+        WHERE email = lower(btrim(s.email))
+          AND (publication_id IS NULL OR publication_id = s.publication_id)
+        ORDER BY publication_id IS NULL DESC
+        LIMIT 1
+    ) x ON true
+    LEFT JOIN LATERAL (
+        SELECT source, consent_text_version, occurred_at, ip FROM consent_events
+        WHERE subscriber_id = s.id AND action = 'subscribe'
+        ORDER BY occurred_at DESC, id DESC
+        LIMIT 1
+    ) c ON true
+    WHERE {}
+    ORDER BY s.subscribed_at, s.id
+    "#,
+                EXPORT_CURSOR, FILTER_CONDITIONS
+            ),
+            &filter.bind(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// The next `n` rows of the export cursor, fewer once it runs out.
+pub async fn fetch_export_batch<C: GenericClient>(
+    client: &C,
+    n: u32,
+) -> Result<Vec<ExportedSubscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(&format!("FETCH {} FROM {}", n, EXPORT_CURSOR), &[])
+        .await?;
+    rows.iter()
+        .map(|row| {
+            Ok(ExportedSubscriber {
+                subscriber: from_row(row),
+                suppression_reason: row
+                    .get::<_, Option<String>>("suppression_reason")
+                    .map(TryInto::try_into)
+                    .transpose()?,
+                suppressed_at: row.get("suppressed_at"),
+                consent_source: row.get("consent_source"),
+                consent_text_version: row.get("consent_text_version"),
+                consented_at: row.get("consented_at"),
+                consent_ip: row.get("consent_ip"),
+            })
+        })
+        .collect()
+}
+
+/// In any publication.
+#[tracing::instrument(name = "Looking for subscriptions of an address", skip_all)]
+pub async fn is_subscribed_anywhere<C: GenericClient>(
+    client: &C,
+    email: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM subscriptions WHERE lower(btrim(email)) = $1)",
+            &[&normalize_email(email)],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+pub async fn get_subscriber<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    id: Uuid,
+) -> Result<Option<Subscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            &format!(
+                "SELECT {} FROM subscriptions WHERE publication_id = $1 AND id = $2",
+                SUBSCRIBER_COLUMNS
+            ),
+            &[&publication_id.0, &id],
+        )
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+/// Fields left `None` are kept. Returns the updated subscriber, `None` if
+/// there is no such subscriber.
+#[tracing::instrument(name = "Updating a subscriber", skip(client))]
+pub async fn update_subscriber<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    id: Uuid,
+    name: Option<&str>,
+    tags: Option<&[String]>,
+) -> Result<Option<Subscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            &format!(
+                r#"
+    UPDATE subscriptions
+    SET name = COALESCE($3, name), tags = COALESCE($4, tags)
+    WHERE publication_id = $1 AND id = $2
+    RETURNING {}
+    "#,
+                SUBSCRIBER_COLUMNS
+            ),
+            &[&publication_id.0, &id, &name, &tags],
+        )
+        .await?;
+    Ok(row.as_ref().map(from_row))
+}
+
+/// Locked until the transaction ends.
+pub async fn load_subscription<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    id: Uuid,
+) -> Result<Option<domain::Subscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT id, publication_id, email, status FROM subscriptions
+    WHERE publication_id = $1 AND id = $2
+    FOR UPDATE
+    "#,
+            &[&publication_id.0, &id],
+        )
+        .await?;
+    row.as_ref().map(restore).transpose()
+}
+
+/// Locked until the transaction ends.
+pub async fn load_subscription_by_email<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    email: &str,
+) -> Result<Option<domain::Subscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    let row = client
+        .query_opt(
+            r#"
+    SELECT id, publication_id, email, status FROM subscriptions
+    WHERE publication_id = $1 AND lower(email) = lower($2)
+    FOR UPDATE
+    "#,
+            &[&publication_id.0, &email],
+        )
+        .await?;
+    row.as_ref().map(restore).transpose()
+}
+
+/// In `publication_id` only, if set. Locked until the transaction ends.
+pub async fn load_subscriptions_of_address<C: GenericClient>(
+    client: &C,
+    email: &str,
+    publication_id: Option<PublicationId>,
+) -> Result<Vec<domain::Subscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .query(
+            r#"
+    SELECT id, publication_id, email, status FROM subscriptions
+    WHERE lower(btrim(email)) = $1 AND ($2::uuid IS NULL OR publication_id = $2)
+    FOR UPDATE
+    "#,
+            &[&normalize_email(email), &publication_id.map(|id| id.0)],
+        )
+        .await?
+        .iter()
+        .map(restore)
+        .collect()
+}
+
+fn restore(row: &Row) -> Result<domain::Subscriber, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(domain::Subscriber::restore(
+        row.get("id"),
+        PublicationId(row.get("publication_id")),
+        row.get("email"),
+        SubscriptionStatus::try_from(row.get::<_, String>("status"))?,
+    ))
+}
+
+/// `subscribed_at` defaults to now. Returns `false` if the address was
+/// already subscribed, and nothing was stored.
+#[tracing::instrument(
+    name = "Saving a new subscription",
+    skip(client, subscriber, name, tags, consent)
+)]
+pub async fn insert_subscription<C: GenericClient>(
+    client: &C,
+    subscriber: &mut domain::Subscriber,
+    name: &str,
+    tags: Option<&[String]>,
+    subscribed_at: Option<DateTime<Utc>>,
+    consent: &ConsentContext,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let inserted = client
+        .execute(
+            r#"
+    INSERT INTO subscriptions (id, publication_id, email, name, tags, status, subscribed_at)
+    VALUES ($1, $2, $3, $4, COALESCE($5::TEXT[], '{}'), $6, COALESCE($7, now()))
+    ON CONFLICT DO NOTHING
+    "#,
+            &[
+                &subscriber.id(),
+                &subscriber.publication_id().0,
+                &subscriber.email(),
+                &name,
+                &tags,
+                &subscriber.status().as_str(),
+                &subscribed_at,
+            ],
+        )
+        .await?;
+    if inserted == 0 {
+        return Ok(false);
+    }
+    record_events(client, subscriber, consent).await?;
+    Ok(true)
+}
+
+/// Store the status the subscription moved to, deleting it once erased.
+#[tracing::instrument(name = "Saving a subscription", skip(client, subscriber, consent))]
+pub async fn save_subscription<C: GenericClient>(
+    client: &C,
+    subscriber: &mut domain::Subscriber,
+    consent: &ConsentContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if subscriber.status() == SubscriptionStatus::Erased {
+        client
+            .execute(
+                "DELETE FROM subscriptions WHERE id = $1",
+                &[&subscriber.id()],
+            )
+            .await?;
+    } else {
+        client
+            .execute(
+                "UPDATE subscriptions SET status = $2 WHERE id = $1",
+                &[&subscriber.id(), &subscriber.status().as_str()],
+            )
+            .await?;
+    }
+    record_events(client, subscriber, consent).await
+}
+
+/// The subscriber's own decisions are kept as proof of consent.
+async fn record_events<C: GenericClient>(
+    client: &C,
+    subscriber: &mut domain::Subscriber,
+    consent: &ConsentContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for event in subscriber.take_events() {
+        let action = match event {
+            SubscriberEvent::Subscribed => ConsentAction::Subscribe,
+            SubscriberEvent::Confirmed => ConsentAction::Confirm,
+            SubscriberEvent::Unsubscribed | SubscriberEvent::Complained => {
+                ConsentAction::Unsubscribe
+            }
+            SubscriberEvent::Bounced | SubscriberEvent::Erased => continue,
+        };
+        record_consent(
+            client,
+            action,
+            subscriber.publication_id(),
+            subscriber.id(),
+            subscriber.email(),
+            consent,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Returns `None` if there is no such subscriber, the refusal if their
+/// status does not allow it.
+#[tracing::instrument(name = "Confirming a subscriber", skip(client, consent))]
+pub async fn confirm_subscriber<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    id: Uuid,
+    consent: &ConsentContext,
+) -> Result<Option<Result<(), TransitionError>>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(mut subscription) = load_subscription(client, publication_id, id).await? else {
+        return Ok(None);
+    };
+    if let Err(refusal) = subscription.confirm() {
+        return Ok(Some(Err(refusal)));
+    }
+    save_subscription(client, &mut subscription, consent).await?;
+    Ok(Some(Ok(())))
+}
+
+/// Stop emailing the subscriber: the address goes on this publication's
+/// suppression list and the subscription is marked unsubscribed.
+#[tracing::instrument(name = "Suppressing a subscriber", skip(client, consent))]
+pub async fn suppress_subscriber<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    id: Uuid,
+    consent: &ConsentContext,
+) -> Result<Option<Subscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(mut subscription) = load_subscription(client, publication_id, id).await? else {
+        return Ok(None);
+    };
+    subscription.unsubscribe()?;
+    save_subscription(client, &mut subscription, consent).await?;
+    suppress(
+        client,
+        subscription.email(),
+        SuppressionReason::Manual,
+        Some(publication_id),
+    )
+    .await?;
+    get_subscriber(client, publication_id, id).await
+}
+
+/// Erases the subscription and drops the deliveries of this publication
+/// still queued for it. Returns the subscriber as they were.
+#[tracing::instrument(name = "Deleting a subscriber", skip(client, consent))]
+pub async fn delete_subscriber<C: GenericClient>(
+    client: &C,
+    publication_id: PublicationId,
+    id: Uuid,
+    consent: &ConsentContext,
+) -> Result<Option<Subscriber>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(mut subscription) = load_subscription(client, publication_id, id).await? else {
+        return Ok(None);
+    };
+    let subscriber = get_subscriber(client, publication_id, id).await?;
+    subscription.erase();
+    save_subscription(client, &mut subscription, consent).await?;
+    client
+        .execute(
+            r#"
+    DELETE FROM email_delivery_queue
+    WHERE lower(btrim(recipient_email)) = $2
+      AND newsletter_issue_id IN (SELECT id FROM newsletter_issues WHERE publication_id = $1)
+    "#,
+            &[&publication_id.0, &normalize_email(subscription.email())],
+        )
+        .await?;
+    Ok(subscriber)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_tag, SubscriberCursor, SubscriberFilter};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = SubscriberCursor {
+            subscribed_at: Utc.with_ymd_and_hms(2025, 3, 14, 9, 26, 53).unwrap()
+                + chrono::Duration::microseconds(589_793),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(
+            assert_ok!(SubscriberCursor::parse(&cursor.encode())),
+            cursor
+        );
+    }
+
+    #[test]
+    fn garbage_cursors_are_rejected() {
+        for cursor in ["", "not base64!", "MTIz", "YWJjLmRlZg"] {
+            assert_err!(SubscriberCursor::parse(cursor));
+        }
+    }
+
+    #[test]
+    fn the_date_range_includes_both_days() {
+        let filter = SubscriberFilter {
+            subscribed_from: NaiveDate::from_ymd_opt(2025, 3, 1),
+            subscribed_to: NaiveDate::from_ymd_opt(2025, 3, 31),
+            ..Default::default()
+        };
+        let (from, to) = filter.bounds();
+        assert_eq!(
+            from,
+            Some(Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap())
+        );
+        assert_eq!(to, Some(Utc.with_ymd_and_hms(2025, 4, 1, 0, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn blank_filters_mean_any() {
+        let filter = SubscriberFilter {
+            status: Some("".into()),
+            q: Some("  ".into()),
+            tag: Some(" VIP ".into()),
+            ..Default::default()
+        }
+        .without_blanks();
+        assert_eq!(filter.status, None);
+        assert_eq!(filter.pattern(), None);
+        assert_eq!(filter.tag.as_deref(), Some("vip"));
+
+        let filter: SubscriberFilter =
+            serde_urlencoded::from_str("subscribed_from=&subscribed_to=2025-03-31").unwrap();
+        assert_eq!(filter.subscribed_from, None);
+        assert_eq!(filter.subscribed_to, NaiveDate::from_ymd_opt(2025, 3, 31));
+        assert_err!(serde_urlencoded::from_str::<SubscriberFilter>(
+            "subscribed_to=march"
+        ));
+    }
+
+    #[test]
+    fn tags_are_short_lowercase_labels() {
+        assert_eq!(assert_ok!(parse_tag(" VIP ")), "vip");
+        assert_err!(parse_tag(" "));
+        assert_err!(parse_tag(&"a".repeat(65)));
+        assert_err!(parse_tag("a\nb"));
+    }
+}
@@ -0,0 +1,285 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use sha2::{Digest, Sha256};
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::publication::PublicationId;
+
+/// Why we stopped sending email to an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    HardBounce,
+    Complaint,
+    Manual,
+    Unsubscribe,
+    /// The subscriber had their data erased; only a hash of the address is
+    /// kept, see `email_hash`.
+    Erased,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::HardBounce => "hard_bounce",
+            SuppressionReason::Complaint => "complaint",
+            SuppressionReason::Manual => "manual",
+            SuppressionReason::Unsubscribe => "unsubscribe",
+            SuppressionReason::Erased => "erased",
+        }
+    }
+}
+
+impl TryFrom<String> for SuppressionReason {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "hard_bounce" => Ok(Self::HardBounce),
+            "complaint" => Ok(Self::Complaint),
+            "manual" => Ok(Self::Manual),
+            "unsubscribe" => Ok(Self::Unsubscribe),
+            "erased" => Ok(Self::Erased),
+            other => Err(format!("{} is not a valid suppression reason.", other)),
+        }
+    }
+}
+
+/// An address we stop emailing. Bounces, complaints and erasures stop every
+/// publication's emails; administrators and imports only stop their own
+/// publication's.
+#[derive(Debug, serde::Serialize)]
+pub struct Suppression {
+    pub email: String,
+    pub reason: SuppressionReason,
+    /// `None` for every publication.
+    pub publication_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The key of the suppression list: providers and subscribers do not agree
+/// on casing or surrounding whitespace, so neither should we.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// What stands in for an erased address on the suppression list: enough to
+/// recognise it if it comes back, without keeping it.
+pub fn email_hash(email: &str) -> String {
+    format!(
+        "sha256:{}",
+        hex::encode(Sha256::digest(normalize_email(email).as_bytes()))
+    )
+}
+
+const SUPPRESSION_COLUMNS: &str = "email, reason, publication_id, created_at";
+
+fn from_row(row: &Row) -> Result<Suppression, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(Suppression {
+        email: row.get("email"),
+        reason: row.get::<_, String>("reason").try_into()?,
+        publication_id: row.get("publication_id"),
+        created_at: row.get("created_at"),
+    })
+}
+
+/// The suppression stopping emails to the address, from `publication_id`
+/// or, with `None`, emails belonging to no publication.
+#[tracing::instrument(name = "Looking up the suppression list", skip(client))]
+pub async fn find_suppression<C: GenericClient>(
+    client: &C,
+    email: &str,
+    publication_id: Option<PublicationId>,
+) -> Result<Option<Suppression>, Box<dyn std::error::Error + Send + Sync>> {
+    // suppressions of every publication come from providers and say more;
+    // the address itself wins over its hash, its reason says more too
+    let row = client
+        .query_opt(
+            &format!(
+                r#"
+    SELECT {} FROM suppressions
+    WHERE (email = $1 OR email = $2) AND (publication_id IS NULL OR publication_id = $3)
+    ORDER BY publication_id IS NULL DESC, email = $1 DESC
+    LIMIT 1
+    "#,
+                SUPPRESSION_COLUMNS
+            ),
+            &[
+                &normalize_email(email),
+                &email_hash(email),
+                &publication_id.map(|id| id.0),
+            ],
+        )
+        .await?;
+    row.as_ref().map(from_row).transpose()
+}
+
+/// In every publication.
+pub async fn suppressions_of_address<C: GenericClient>(
+    client: &C,
+    email: &str,
+) -> Result<Vec<Suppression>, Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .query(
+            &format!(
+                "SELECT {} FROM suppressions WHERE email = $1 OR email = $2 ORDER BY created_at",
+                SUPPRESSION_COLUMNS
+            ),
+            &[&normalize_email(email), &email_hash(email)],
+        )
+        .await?
+        .iter()
+        .map(from_row)
+        .collect()
+}
+
+/// `publication_id` is `None` to stop every publication's emails. A spam
+/// complaint stays one: later reasons do not replace it, only an explicit
+/// opt-in lifts it, see `unsuppress_on_opt_in`.
+#[tracing::instrument(name = "Adding an address to the suppression list", skip(client))]
+pub async fn suppress<C: GenericClient>(
+    client: &C,
+    email: &str,
+    reason: SuppressionReason,
+    publication_id: Option<PublicationId>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            r#"
+    INSERT INTO suppressions (email, reason, publication_id, created_at)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (email, publication_id)
+    DO UPDATE SET reason = EXCLUDED.reason, created_at = EXCLUDED.created_at
+    WHERE suppressions.reason <> 'complaint'
+    "#,
+            &[
+                &normalize_email(email),
+                &reason.as_str(),
+                &publication_id.map(|id| id.0),
+                &Utc::now(),
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Lifts the publication's own suppression of the address, or of its hash,
+/// or with `None` the one stopping every publication's emails. Returns
+/// `false` if there was none.
+#[tracing::instrument(name = "Removing an address from the suppression list", skip(client))]
+pub async fn unsuppress<C: GenericClient>(
+    client: &C,
+    email: &str,
+    publication_id: Option<PublicationId>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let deleted = client
+        .execute(
+            r#"
+    DELETE FROM suppressions
+    WHERE (email = $1 OR email = $2) AND publication_id IS NOT DISTINCT FROM $3
+    "#,
+            &[
+                &normalize_email(email),
+                &email_hash(email),
+                &publication_id.map(|id| id.0),
+            ],
+        )
+        .await?;
+    Ok(deleted > 0)
+}
+
+/// A fresh opt-in to the publication lifts what the subscriber did
+/// themselves: unsubscribes, complaints and erasures, of every publication
+/// or of this one. Bounces and administrators' suppressions stay; owners
+/// lift a mistaken bounce through `/admin/suppressions?scope=global`.
+#[tracing::instrument(name = "Lifting suppressions on a new opt-in", skip(client))]
+pub async fn unsuppress_on_opt_in<C: GenericClient>(
+    client: &C,
+    email: &str,
+    publication_id: PublicationId,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .execute(
+            r#"
+    DELETE FROM suppressions
+    WHERE (email = $1 OR email = $2)
+      AND (publication_id IS NULL OR publication_id = $3)
+      AND reason IN ('unsubscribe', 'complaint', 'erased')
+    "#,
+            &[
+                &normalize_email(email),
+                &email_hash(email),
+                &publication_id.0,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// The publication's own suppressions, or with `None` those stopping every
+/// publication's emails. The two are never mixed: the latter name other
+/// publications' subscribers, and only owners may see them.
+#[tracing::instrument(name = "Listing the suppression list", skip(client))]
+pub async fn list_suppressions<C: GenericClient>(
+    client: &C,
+    publication_id: Option<PublicationId>,
+) -> Result<Vec<Suppression>, Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .query(
+            &format!(
+                r#"
+    SELECT {} FROM suppressions
+    WHERE publication_id IS NOT DISTINCT FROM $1
+    ORDER BY created_at DESC
+    "#,
+                SUPPRESSION_COLUMNS
+            ),
+            &[&publication_id.map(|id| id.0)],
+        )
+        .await?
+        .iter()
+        .map(from_row)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{email_hash, normalize_email, SuppressionReason};
+    use claims::assert_err;
+
+    #[test]
+    fn emails_are_normalized_to_trimmed_lowercase() {
+        assert_eq!(
+            normalize_email("  Ursula.Le.Guin@Example.COM "),
+            "ursula.le.guin@example.com"
+        );
+    }
+
+    #[test]
+    fn hashes_ignore_casing_and_whitespace() {
+        let hash = email_hash(" Ursula@Example.com");
+        assert_eq!(hash, email_hash("ursula@example.com"));
+        assert!(hash.starts_with("sha256:"));
+        assert!(!hash.contains("ursula"));
+    }
+
+    #[test]
+    fn reasons_round_trip_through_their_string_representation() {
+        for reason in [
+            SuppressionReason::HardBounce,
+            SuppressionReason::Complaint,
+            SuppressionReason::Manual,
+            SuppressionReason::Unsubscribe,
+            SuppressionReason::Erased,
+        ] {
+            let parsed = SuppressionReason::try_from(reason.as_str().to_string()).unwrap();
+            assert_eq!(parsed, reason);
+        }
+    }
+
+    #[test]
+    fn unknown_reasons_are_rejected() {
+        assert_err!(SuppressionReason::try_from("bored".to_string()));
+    }
+}
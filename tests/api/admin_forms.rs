@@ -0,0 +1,105 @@
+use crate::helpers::{assert_is_redirect_to, csrf_form, TestApp};
+
+async fn api_key_count(app: &TestApp) -> i64 {
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    client
+        .query_one("SELECT count(*) AS n FROM api_keys", &[])
+        .await
+        .expect("Failed to count API keys.")
+        .get("n")
+}
+
+#[tokio::test]
+async fn forms_without_a_valid_csrf_token_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let cookie = app.log_in_as_admin().await;
+    let csrf_token = app.get_csrf_token(&cookie).await;
+    let test_cases = vec![
+        (
+            "/admin/api_keys".to_owned(),
+            "name=ci".to_owned(),
+            "no token",
+        ),
+        (
+            "/admin/api_keys".to_owned(),
+            csrf_form("not-the-token", &[("name", "ci")]),
+            "a wrong token",
+        ),
+        (
+            // URLs end up in logs and Referer headers
+            format!(
+                "/admin/api_keys?{}",
+                serde_urlencoded::to_string([("csrf_token", &csrf_token)]).unwrap()
+            ),
+            "name=ci".to_owned(),
+            "a token in the query string",
+        ),
+    ];
+
+    for (path, body, case) in test_cases {
+        // Act
+        let response = app.post_admin_form(&path, &cookie, &body).await;
+
+        // Assert
+        assert_eq!(403, response.status().as_u16(), "For {}.", case);
+    }
+    assert_eq!(0, api_key_count(&app).await);
+}
+
+#[tokio::test]
+async fn forms_with_the_sessions_csrf_token_are_accepted() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let cookie = app.log_in_as_admin().await;
+    let csrf_token = app.get_csrf_token(&cookie).await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/api_keys",
+            &cookie,
+            &csrf_form(&csrf_token, &[("name", "ci")]),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, api_key_count(&app).await);
+}
+
+#[tokio::test]
+async fn flash_messages_are_shown_once_after_the_redirect() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let cookie = app.log_in_as_admin().await;
+    let csrf_token = app.get_csrf_token(&cookie).await;
+
+    // Act - Part 1 - A form that fails
+    let response = app
+        .post_admin_form(
+            "/admin/api_keys",
+            &cookie,
+            &csrf_form(&csrf_token, &[("name", "")]),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/api_keys");
+
+    // Act - Part 2 - Follow the redirect
+    let page = app
+        .get_admin_page("/admin/api_keys", &cookie)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("<p class=\"flash error\"><i>API keys need a name.</i></p>"));
+
+    // Act - Part 3 - Reload the page
+    let page = app
+        .get_admin_page("/admin/api_keys", &cookie)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(!page.contains("API keys need a name."));
+}
@@ -0,0 +1,114 @@
+use zero2prod::authentication::Scope;
+
+use crate::helpers::TestApp;
+
+async fn suppress(app: &TestApp, api_key: &str, email: &str) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/suppressions", &app.address))
+        .bearer_auth(api_key)
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+async fn audit_actions(app: &TestApp, api_key: &str) -> Vec<String> {
+    let body: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/audit_log", &app.address))
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .expect("Failed to read the audit log");
+    body["entries"]
+        .as_array()
+        .expect("No entries in the audit log")
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn keys_only_read_the_audit_entries_of_their_publication() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let scopes = [Scope::SubscribersWrite, Scope::AuditLogRead];
+    let default_key = app
+        .create_api_key(app.default_publication_id().await, &scopes)
+        .await;
+    let other = app.create_publication("other", None).await;
+    let other_key = app.create_api_key(other, &scopes).await;
+
+    // Act
+    suppress(&app, &default_key, "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(
+        vec!["suppression.added"],
+        audit_actions(&app, &default_key).await
+    );
+    assert!(audit_actions(&app, &other_key).await.is_empty());
+}
+
+#[tokio::test]
+async fn the_audit_entry_keeps_a_hash_of_the_address_only() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let scopes = [Scope::SubscribersWrite, Scope::AuditLogRead];
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &scopes)
+        .await;
+
+    // Act
+    suppress(&app, &api_key, "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    let body = reqwest::Client::new()
+        .get(format!("{}/admin/audit_log", &app.address))
+        .bearer_auth(&api_key)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(!body.contains("ursula_le_guin"));
+}
+
+#[tokio::test]
+async fn only_owners_can_verify_the_audit_log() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(
+            app.default_publication_id().await,
+            &[Scope::SubscribersWrite, Scope::AuditLogRead],
+        )
+        .await;
+    suppress(&app, &api_key, "ursula_le_guin@gmail.com").await;
+    let session_cookie = app.log_in_as_admin().await;
+    let verify = format!("{}/admin/audit_log/verify", &app.address);
+
+    // Act
+    let as_key = reqwest::Client::new()
+        .get(&verify)
+        .bearer_auth(&api_key)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let as_owner = reqwest::Client::new()
+        .get(&verify)
+        .header(reqwest::header::COOKIE, session_cookie)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(403, as_key.status().as_u16());
+    assert_eq!(200, as_owner.status().as_u16());
+    let verification: serde_json::Value = as_owner.json().await.unwrap();
+    assert_eq!(true, verification["intact"]);
+}
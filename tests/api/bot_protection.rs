@@ -0,0 +1,151 @@
+use secrecy::Secret;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::CaptchaSettings;
+
+use crate::helpers::TestApp;
+
+async fn saved_subscriptions(app: &TestApp) -> usize {
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    client
+        .query("SELECT email FROM subscriptions", &[])
+        .await
+        .expect("Failed to fetch saved subscriptions.")
+        .len()
+}
+
+async fn spawn_with_captcha(captcha_server: &MockServer) -> TestApp {
+    let verify_url = format!("{}/siteverify", captcha_server.uri());
+    TestApp::spawn_with(|settings| {
+        settings.application.bot_protection.captcha = Some(CaptchaSettings {
+            verify_url,
+            secret: Secret::new("my-captcha-secret".to_owned()),
+            timeout_milliseconds: 1000,
+        });
+    })
+    .await
+}
+
+async fn captcha_answers(captcha_server: &MockServer, success: bool) {
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": success,
+        })))
+        .mount(captcha_server)
+        .await;
+}
+
+#[tokio::test]
+async fn a_filled_in_honeypot_is_answered_with_a_200_and_ignored() {
+    // Arrange
+    let app = TestApp::spawn().await;
+
+    // Act
+    let response = app
+        .post_signup_form("name=le%20guin&email=ursula_le_guin%40gmail.com&website=spam.example")
+        .await;
+
+    // Assert
+    // Bots are not told they were found out
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, saved_subscriptions(&app).await);
+}
+
+#[tokio::test]
+async fn forms_sent_too_quickly_are_ignored() {
+    // Arrange
+    let app = TestApp::spawn_with(|settings| {
+        settings.application.bot_protection.min_fill_time_seconds = Some(60);
+    })
+    .await;
+
+    // Act
+    let response = app
+        .post_signup_form("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, saved_subscriptions(&app).await);
+}
+
+#[tokio::test]
+async fn forms_without_a_token_are_ignored_when_one_is_required() {
+    // Arrange
+    let app = TestApp::spawn_with(|settings| {
+        settings.application.bot_protection.min_fill_time_seconds = Some(0);
+    })
+    .await;
+
+    // Act
+    let without_token = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let with_forged_token = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token=forged".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, without_token.status().as_u16());
+    assert_eq!(200, with_forged_token.status().as_u16());
+    assert_eq!(0, saved_subscriptions(&app).await);
+}
+
+#[tokio::test]
+async fn forms_with_a_valid_token_are_accepted() {
+    // Arrange
+    let app = TestApp::spawn_with(|settings| {
+        settings.application.bot_protection.min_fill_time_seconds = Some(0);
+    })
+    .await;
+
+    // Act
+    let response = app
+        .post_signup_form("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, saved_subscriptions(&app).await);
+}
+
+#[tokio::test]
+async fn a_failed_captcha_is_ignored() {
+    // Arrange
+    let captcha_server = MockServer::start().await;
+    captcha_answers(&captcha_server, false).await;
+    let app = spawn_with_captcha(&captcha_server).await;
+
+    // Act
+    let response = app
+        .post_signup_form(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&h-captcha-response=wrong",
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, saved_subscriptions(&app).await);
+}
+
+#[tokio::test]
+async fn a_solved_captcha_is_accepted() {
+    // Arrange
+    let captcha_server = MockServer::start().await;
+    captcha_answers(&captcha_server, true).await;
+    let app = spawn_with_captcha(&captcha_server).await;
+
+    // Act
+    let response = app
+        .post_signup_form(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&h-captcha-response=right",
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, saved_subscriptions(&app).await);
+}
@@ -0,0 +1,90 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{accept_all_emails, TestApp};
+
+/// Asks for a copy of the data of a subscribed address and returns the link
+/// emailed to confirm it.
+async fn confirmation_link(app: &TestApp) -> String {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    accept_all_emails(&app.email_server).await;
+    app.post_form("/data_export", "email=ursula_le_guin%40gmail.com")
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_links(email_request).html
+}
+
+async fn pending_exports(app: &TestApp) -> i64 {
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    client
+        .query_one(
+            "SELECT count(*) AS n FROM data_exports WHERE status = 'pending'",
+            &[],
+        )
+        .await
+        .expect("Failed to count data exports.")
+        .get("n")
+}
+
+#[tokio::test]
+async fn requesting_a_data_export_of_an_unknown_address_sends_nothing() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_form("/data_export", "email=ursula_le_guin%40gmail.com")
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, pending_exports(&app).await);
+}
+
+#[tokio::test]
+async fn confirming_twice_queues_a_single_export() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let link = confirmation_link(&app).await;
+    assert_eq!(0, pending_exports(&app).await);
+
+    // Act
+    for _ in 0..2 {
+        let response = reqwest::Client::new().post(&link).send().await.unwrap();
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Assert
+    assert_eq!(1, pending_exports(&app).await);
+}
+
+#[tokio::test]
+async fn the_archive_is_emailed_once_ready() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let link = confirmation_link(&app).await;
+    reqwest::Client::new().post(&link).send().await.unwrap();
+
+    // Act
+    app.build_pending_data_exports().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(2, email_requests.len());
+    let download_link = app.get_links(&email_requests[1]).html;
+    let response = reqwest::get(&download_link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let archive: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("ursula_le_guin@gmail.com", archive["email"]);
+    assert_eq!(1, archive["subscriptions"].as_array().unwrap().len());
+}
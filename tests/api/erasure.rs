@@ -0,0 +1,148 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{accept_all_emails, TestApp};
+
+async fn subscription_count(app: &TestApp, email: &str) -> i64 {
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    client
+        .query_one(
+            "SELECT count(*) AS n FROM subscriptions WHERE email = $1",
+            &[&email],
+        )
+        .await
+        .expect("Failed to count subscriptions.")
+        .get("n")
+}
+
+/// Asks for an erasure of a subscribed address and returns the link emailed
+/// to confirm it.
+async fn erasure_link(app: &TestApp) -> String {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    accept_all_emails(&app.email_server).await;
+    app.post_form("/erasure", "email=ursula_le_guin%40gmail.com")
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let links = app.get_links(email_request);
+    assert_eq!(links.html, links.plain_text);
+    links.html
+}
+
+#[tokio::test]
+async fn requesting_an_erasure_emails_a_confirmation_link() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_form("/erasure", "email=ursula_le_guin%40gmail.com")
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    // Nothing is erased before the link is followed
+    assert_eq!(
+        1,
+        subscription_count(&app, "ursula_le_guin@gmail.com").await
+    );
+}
+
+#[tokio::test]
+async fn requesting_an_erasure_of_an_unknown_address_sends_nothing() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_form("/erasure", "email=ursula_le_guin%40gmail.com")
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // The same answer as for subscribed addresses
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn opening_the_link_does_not_erase_anything() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let link = erasure_link(&app).await;
+
+    // Act
+    let response = reqwest::get(&link).await.unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        1,
+        subscription_count(&app, "ursula_le_guin@gmail.com").await
+    );
+}
+
+#[tokio::test]
+async fn confirming_an_erasure_removes_the_subscriber() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let link = erasure_link(&app).await;
+
+    // Act
+    let response = reqwest::Client::new().post(&link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        0,
+        subscription_count(&app, "ursula_le_guin@gmail.com").await
+    );
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    let suppressions = client
+        .query("SELECT email, reason FROM suppressions", &[])
+        .await
+        .expect("Failed to fetch the suppressions.");
+    assert_eq!(1, suppressions.len());
+    assert_eq!("erased", suppressions[0].get::<_, String>("reason"));
+    // Only a hash of the address is kept
+    assert_ne!(
+        "ursula_le_guin@gmail.com",
+        suppressions[0].get::<_, String>("email")
+    );
+}
+
+#[tokio::test]
+async fn an_erasure_link_that_was_tampered_with_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let link = erasure_link(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}x", link))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        1,
+        subscription_count(&app, "ursula_le_guin@gmail.com").await
+    );
+}
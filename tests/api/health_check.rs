@@ -1,3 +1,5 @@
+use crate::helpers::TestApp;
+
 #[tokio::test]
 async fn health_check_works() {
     // Arrange
@@ -6,7 +8,7 @@ async fn health_check_works() {
 
     // Act
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed to execute request");
@@ -14,4 +16,4 @@ async fn health_check_works() {
     // Assert
     assert!(response.status().is_success());
     assert_eq!(response.content_length(), Some(0));
-}
\ No newline at end of file
+}
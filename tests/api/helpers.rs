@@ -1,7 +1,22 @@
+use std::sync::LazyLock;
+
 use deadpool_postgres::{Config, Pool, Runtime};
+use htmlescape::decode_html;
+use secrecy::ExposeSecret;
 use tokio_postgres::NoTls;
 use uuid::Uuid;
-use zero2prod::{configuration::get_configuration, email_client::EmailClient};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::authentication::{create_api_key, LinkSigner, Scope};
+use zero2prod::configuration::{get_configuration, EmailBackend, Settings};
+use zero2prod::data_export::{try_export_next, ArchiveNotifier};
+use zero2prod::delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::email_client::EmailClient;
+use zero2prod::publication::{
+    create_publication, find_publication_by_slug, NewPublication, PublicationId,
+};
+use zero2prod::startup::Application;
+use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 pub struct TestDatabase {
     pub database_name: String,
@@ -78,14 +93,22 @@ async fn create_test_pool(database_name: &str) -> Pool {
 async fn run_migrations(pool: &Pool) {
     let client = pool.get().await.expect("Failed to get client");
 
-    let migration_sql =
-        std::fs::read_to_string("migrations/20250130200119_create_subscriptions_table.sql")
-            .expect("Failed to read migration file");
+    // Apply every migration in lexicographic (i.e. chronological) order
+    let mut migrations: Vec<_> = std::fs::read_dir("migrations")
+        .expect("Failed to read migrations directory")
+        .map(|entry| entry.expect("Failed to read migration entry").path())
+        .collect();
+    migrations.sort();
 
-    client
-        .execute(&migration_sql, &[])
-        .await
-        .expect("Failed to execute migration");
+    for migration in migrations {
+        let migration_sql =
+            std::fs::read_to_string(&migration).expect("Failed to read migration file");
+
+        client
+            .batch_execute(&migration_sql)
+            .await
+            .expect("Failed to execute migration");
+    }
 }
 
 async fn cleanup_test_database(database_name: &str) {
@@ -121,18 +144,26 @@ async fn cleanup_test_database(database_name: &str) {
 pub struct TestApp {
     pub address: String,
     pub db_pool: Pool,
+    /// Stands in for Postmark: the app sends its emails here.
+    pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub archive_notifier: ArchiveNotifier,
     _db: TestDatabase, // Keep database alive for the test duration
 }
 
+/// The links found in an email, in its HTML and text bodies.
+pub struct EmailLinks {
+    pub html: String,
+    pub plain_text: String,
+}
+
 impl TestApp {
     pub async fn spawn() -> TestApp {
-        use std::net::TcpListener;
-        use std::sync::LazyLock;
-        use zero2prod::{
-            startup::run,
-            telemetry::{get_subscriber, init_subscriber},
-        };
+        Self::spawn_with(|_| {}).await
+    }
 
+    /// Spawns the app with the test's own settings on top of the defaults.
+    pub async fn spawn_with(customize: impl FnOnce(&mut Settings)) -> TestApp {
         // Initialize tracing once
         static TRACING: LazyLock<()> = LazyLock::new(|| {
             let default_filter_level = "info".to_string();
@@ -149,50 +180,278 @@ impl TestApp {
         });
         LazyLock::force(&TRACING);
 
-        let configuration = get_configuration().expect("Failed to read configuration");
         // Setup test database
         let db = TestDatabase::new().await;
+        let email_server = MockServer::start().await;
 
-        // Build a new email client
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
+        let mut configuration = get_configuration().expect("Failed to read configuration");
+        configuration.database.database_name = db.database_name.clone();
+        // A random port, so tests can run in parallel
+        configuration.application.port = 0;
+        configuration.email_client.backend = EmailBackend::Postmark;
+        configuration.email_client.base_url = email_server.uri();
+        customize(&mut configuration);
 
-        let timeout = configuration.email_client.timeout();
+        // Sends what the app queued, as its delivery worker would
         let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
+            configuration.email_client.base_url.clone(),
+            configuration
+                .email_client
+                .senders()
+                .expect("Invalid sender identities."),
+            configuration.email_client.message_streams.clone(),
+            configuration.email_client.authorization_token.clone(),
+            configuration.email_client.timeout(),
         );
+        let archive_notifier = ArchiveNotifier {
+            signer: LinkSigner::new(configuration.application.link_signing_secret.clone()),
+            base_url: configuration.application.base_url.clone(),
+            sender: email_client
+                .senders()
+                .transactional_name("data_export")
+                .to_owned(),
+        };
 
-        // Setup application
-        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
-        let port = listener.local_addr().unwrap().port();
-
-        // let server = run(listener, db.pool.clone(), email_client).expect("Failed to bind address");
-        let application = Application::build(configuration.clone())
+        let application = Application::build(configuration)
             .await
             .expect("Failed to build application");
-
         let address = format!("http://127.0.0.1:{}", application.port());
-        let _ = tokio::spawn(application.run_until_stopped());
+        tokio::spawn(application.run_until_stopped());
 
         TestApp {
             address,
             db_pool: db.pool.clone(),
+            email_server,
+            email_client,
+            archive_notifier,
             _db: db, // Database will be cleaned up when TestApp is dropped
         }
     }
 
+    /// A token for a signup form, as a browser fetches it when the form loads.
+    pub async fn get_form_token(&self) -> String {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(format!("{}/subscriptions/form_token", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .json()
+            .await
+            .expect("Failed to read the form token");
+        body["form_token"]
+            .as_str()
+            .expect("No form token in the response")
+            .to_owned()
+    }
+
+    /// Posts the form as a browser would, with a freshly issued form token.
+    pub async fn post_signup_form(&self, body: &str) -> reqwest::Response {
+        let form_token = self.get_form_token().await;
+        // URL safe base64 already, no need to encode it
+        let body = format!("{}&form_token={}", body, form_token);
+        self.post_subscriptions(body).await
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
             .await
             .expect("Failed to execute request")
     }
+
+    pub async fn post_form(&self, path: &str, body: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}{}", &self.address, path))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Posts a provider event as Postmark would, with its Basic credentials.
+    pub async fn post_email_event(&self, event: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email", &self.address))
+            .basic_auth("postmark", Some("my-webhook-secret"))
+            .json(&event)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// A key for the publication, as `api-keys create` prints it.
+    pub async fn create_api_key(&self, publication_id: PublicationId, scopes: &[Scope]) -> String {
+        let client = self.db_pool.get().await.expect("Failed to get client");
+        let (_, key) = create_api_key(&client, publication_id, "test", scopes, None)
+            .await
+            .expect("Failed to create an API key");
+        key.expose_secret().clone()
+    }
+
+    /// Redirects are left for tests to follow, or not.
+    fn admin_client() -> reqwest::Client {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+    }
+
+    pub async fn post_login(&self, username: &str, password: &str) -> reqwest::Response {
+        Self::admin_client()
+            .post(format!("{}/login", &self.address))
+            .form(&[("username", username), ("password", password)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Logs in as the administrator created on startup, returning the
+    /// session cookie to send along.
+    pub async fn log_in_as_admin(&self) -> String {
+        let response = self.post_login("admin", "my-admin-password").await;
+        session_cookie(&response).expect("No session cookie in the response")
+    }
+
+    /// An admin page, as the browser holding `cookie` would get it.
+    pub async fn get_admin_page(&self, path: &str, cookie: &str) -> reqwest::Response {
+        Self::admin_client()
+            .get(format!("{}{}", &self.address, path))
+            .header(reqwest::header::COOKIE, cookie)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Posts an admin form, as the browser holding `cookie` would.
+    pub async fn post_admin_form(&self, path: &str, cookie: &str, body: &str) -> reqwest::Response {
+        Self::admin_client()
+            .post(format!("{}{}", &self.address, path))
+            .header(reqwest::header::COOKIE, cookie)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// The CSRF token of the session, as admin pages embed it in their forms.
+    pub async fn get_csrf_token(&self, cookie: &str) -> String {
+        let page = self
+            .get_admin_page("/admin/dashboard", cookie)
+            .await
+            .text()
+            .await
+            .expect("Failed to read the dashboard");
+        let (_, rest) = page
+            .split_once(r#"name="csrf_token" value=""#)
+            .expect("No CSRF token on the dashboard");
+        let token = rest.split('"').next().unwrap();
+        decode_html(token).unwrap()
+    }
+
+    pub async fn default_publication_id(&self) -> PublicationId {
+        let client = self.db_pool.get().await.expect("Failed to get client");
+        let publication = find_publication_by_slug(&client, "default")
+            .await
+            .expect("Failed to fetch the default publication")
+            .expect("There is no default publication");
+        PublicationId(publication.id)
+    }
+
+    /// Another publication, run by the administrator created on startup.
+    pub async fn create_publication(&self, slug: &str, host: Option<&str>) -> PublicationId {
+        let client = self.db_pool.get().await.expect("Failed to get client");
+        let admin = client
+            .query_one("SELECT user_id FROM users WHERE username = 'admin'", &[])
+            .await
+            .expect("Failed to fetch the administrator");
+        let publication = NewPublication {
+            slug,
+            name: slug,
+            host,
+            senders: &[],
+            default_sender: None,
+        };
+        create_publication(&client, publication, admin.get("user_id"))
+            .await
+            .expect("Failed to create a publication")
+            .expect("The publication already exists")
+    }
+
+    /// Sends everything queued, as the delivery worker would.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, &self.email_client)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Puts together every archive asked for, as the export worker would.
+    pub async fn build_pending_data_exports(&self) {
+        while try_export_next(&self.db_pool, &self.archive_notifier)
+            .await
+            .unwrap()
+        {}
+    }
+
+    /// The links in an email sent to the mock email server.
+    pub fn get_links(&self, email_request: &wiremock::Request) -> EmailLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        // Links point at the configured base URL, not at this test's port
+        let get_link = |s: &str| {
+            let link = s
+                .split(|c: char| c.is_whitespace() || c == '"')
+                .find(|word| word.starts_with("http://127.0.0.1:8000/"))
+                .expect("No link in the email");
+            link.replacen("http://127.0.0.1:8000", &self.address, 1)
+        };
+        EmailLinks {
+            // Attributes are escaped down to the slashes
+            html: get_link(&decode_html(body["HtmlBody"].as_str().unwrap()).unwrap()),
+            plain_text: get_link(body["TextBody"].as_str().unwrap()),
+        }
+    }
+}
+
+/// The `name=value` pair of the session cookie a response sets, to send it
+/// back.
+pub fn session_cookie(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find(|value| value.starts_with("session_id="))
+        .map(|value| value.split(';').next().unwrap().to_owned())
+}
+
+/// An url-encoded admin form body, carrying the session's CSRF token.
+pub fn csrf_form(csrf_token: &str, fields: &[(&str, &str)]) -> String {
+    let mut fields = fields.to_vec();
+    fields.push(("csrf_token", csrf_token));
+    serde_urlencoded::to_string(fields).unwrap()
+}
+
+/// Checks that the response redirects the browser to `location`.
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(location, response.headers().get("Location").unwrap());
+}
+
+/// Answers every email the app sends with a 200, as Postmark does.
+pub async fn accept_all_emails(email_server: &MockServer) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(email_server)
+        .await;
 }
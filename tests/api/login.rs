@@ -0,0 +1,76 @@
+use crate::helpers::{assert_is_redirect_to, csrf_form, session_cookie, TestApp};
+
+#[tokio::test]
+async fn a_wrong_password_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn().await;
+
+    // Act
+    let response = app.post_login("admin", "not-the-password").await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(None, session_cookie(&response));
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Invalid username or password."));
+}
+
+#[tokio::test]
+async fn logging_in_redirects_to_the_dashboard_with_a_session() {
+    // Arrange
+    let app = TestApp::spawn().await;
+
+    // Act
+    let response = app.post_login("admin", "my-admin-password").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let cookie = session_cookie(&response).expect("No session cookie in the response");
+    let dashboard = app.get_admin_page("/admin/dashboard", &cookie).await;
+    assert_eq!(200, dashboard.status().as_u16());
+    assert!(dashboard.text().await.unwrap().contains("Welcome admin!"));
+}
+
+#[tokio::test]
+async fn anonymous_visitors_are_sent_to_the_login_form() {
+    // Arrange
+    let app = TestApp::spawn().await;
+
+    // Act
+    let response = app.get_admin_page("/admin/dashboard", "").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_unknown_session_is_sent_to_the_login_form() {
+    // Arrange
+    let app = TestApp::spawn().await;
+
+    // Act
+    let response = app
+        .get_admin_page("/admin/dashboard", "session_id=made-up")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logging_out_ends_the_session() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let cookie = app.log_in_as_admin().await;
+    let csrf_token = app.get_csrf_token(&cookie).await;
+
+    // Act
+    let response = app
+        .post_admin_form("/admin/logout", &cookie, &csrf_form(&csrf_token, &[]))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let dashboard = app.get_admin_page("/admin/dashboard", &cookie).await;
+    assert_is_redirect_to(&dashboard, "/login");
+}
@@ -0,0 +1,16 @@
+mod admin_forms;
+mod audit_log;
+mod bot_protection;
+mod data_export;
+mod erasure;
+mod health_check;
+mod helpers;
+mod login;
+mod password;
+mod rate_limits;
+mod subscriber_import;
+mod subscribers;
+mod subscriptions;
+mod suppressions;
+mod two_factor;
+mod webhooks;
@@ -0,0 +1,184 @@
+use crate::helpers::{accept_all_emails, assert_is_redirect_to, csrf_form, TestApp};
+
+const NEW_PASSWORD: &str = "a-much-longer-passphrase";
+
+async fn change_password(
+    app: &TestApp,
+    cookie: &str,
+    current: &str,
+    new: &str,
+) -> reqwest::Response {
+    let csrf_token = app.get_csrf_token(cookie).await;
+    let body = csrf_form(
+        &csrf_token,
+        &[
+            ("current_password", current),
+            ("new_password", new),
+            ("new_password_check", new),
+        ],
+    );
+    app.post_admin_form("/admin/password", cookie, &body).await
+}
+
+/// The password page, showing why the last change was rejected.
+async fn password_page(app: &TestApp, cookie: &str) -> String {
+    app.get_admin_page("/admin/password", cookie)
+        .await
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_current_password_is_required_to_change_it() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let cookie = app.log_in_as_admin().await;
+
+    // Act
+    let response = change_password(&app, &cookie, "not-the-password", NEW_PASSWORD).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    assert!(password_page(&app, &cookie)
+        .await
+        .contains("The current password is wrong."));
+    let login = app.post_login("admin", NEW_PASSWORD).await;
+    assert_eq!(401, login.status().as_u16());
+}
+
+#[tokio::test]
+async fn weak_new_passwords_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let cookie = app.log_in_as_admin().await;
+    let test_cases = vec![
+        ("short", "at least 12 characters"),
+        ("aaaaaaaaaaaaaaaa", "must not repeat a single character"),
+        ("admin-is-my-name", "must not contain the username"),
+    ];
+
+    for (new_password, reason) in test_cases {
+        // Act
+        let response = change_password(&app, &cookie, "my-admin-password", new_password).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/password");
+        assert!(
+            password_page(&app, &cookie).await.contains(reason),
+            "{} was not rejected",
+            new_password
+        );
+    }
+}
+
+#[tokio::test]
+async fn changing_the_password_ends_every_session() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let cookie = app.log_in_as_admin().await;
+    let other_cookie = app.log_in_as_admin().await;
+
+    // Act
+    let response = change_password(&app, &cookie, "my-admin-password", NEW_PASSWORD).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login?notice=password_changed");
+    for cookie in [cookie, other_cookie] {
+        let dashboard = app.get_admin_page("/admin/dashboard", &cookie).await;
+        assert_is_redirect_to(&dashboard, "/login");
+    }
+    let old_login = app.post_login("admin", "my-admin-password").await;
+    assert_eq!(401, old_login.status().as_u16());
+    let new_login = app.post_login("admin", NEW_PASSWORD).await;
+    assert_is_redirect_to(&new_login, "/admin/dashboard");
+}
+
+/// Asks for a reset of the administrator's password and returns the link
+/// emailed to them.
+async fn password_reset_link(app: &TestApp) -> String {
+    accept_all_emails(&app.email_server).await;
+    let response = app.post_form("/password_reset", "login=admin").await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let links = app.get_links(email_request);
+    assert_eq!(links.html, links.plain_text);
+    links.html
+}
+
+fn new_password_form(password: &str) -> String {
+    serde_urlencoded::to_string([("new_password", password), ("new_password_check", password)])
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_password_reset_link_works_once() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let link = password_reset_link(&app).await;
+    let path = link.strip_prefix(&app.address).unwrap();
+    let other_cookie = app.log_in_as_admin().await;
+
+    // Act
+    let reset = app.post_form(path, &new_password_form(NEW_PASSWORD)).await;
+    let reset_again = app
+        .post_form(path, &new_password_form("yet-another-passphrase"))
+        .await;
+
+    // Assert
+    assert_eq!(200, reset.status().as_u16());
+    assert!(reset
+        .url()
+        .as_str()
+        .ends_with("/login?notice=password_reset"));
+    assert_eq!(400, reset_again.status().as_u16());
+    let dashboard = app.get_admin_page("/admin/dashboard", &other_cookie).await;
+    assert_is_redirect_to(&dashboard, "/login");
+    let login = app.post_login("admin", NEW_PASSWORD).await;
+    assert_is_redirect_to(&login, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_weak_password_does_not_use_up_the_reset_link() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let link = password_reset_link(&app).await;
+    let path = link.strip_prefix(&app.address).unwrap();
+
+    // Act
+    let weak = app.post_form(path, &new_password_form("short")).await;
+    let strong = app.post_form(path, &new_password_form(NEW_PASSWORD)).await;
+
+    // Assert
+    assert_eq!(200, weak.status().as_u16());
+    assert!(weak
+        .text()
+        .await
+        .unwrap()
+        .contains("at least 12 characters"));
+    assert!(strong
+        .url()
+        .as_str()
+        .ends_with("/login?notice=password_reset"));
+}
+
+#[tokio::test]
+async fn unknown_logins_get_the_same_answer_and_no_email() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    accept_all_emails(&app.email_server).await;
+
+    // Act
+    let response = app.post_form("/password_reset", "login=nobody").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,99 @@
+use zero2prod::configuration::RateLimitBackend;
+use zero2prod::rate_limit::IpNetwork;
+
+use crate::helpers::TestApp;
+
+async fn sign_up_until_turned_away(app: &TestApp, bodies: &[String]) -> reqwest::Response {
+    let (last, first) = bodies.split_last().unwrap();
+    for body in first {
+        let response = app.post_subscriptions(body.clone()).await;
+        assert_eq!(200, response.status().as_u16(), "For {}.", body);
+    }
+    app.post_subscriptions(last.clone()).await
+}
+
+#[tokio::test]
+async fn signing_up_one_address_too_often_returns_a_429() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    // A burst of 3 per address
+    let bodies = vec!["name=le%20guin&email=ursula_le_guin%40gmail.com".to_owned(); 4];
+
+    // Act
+    let response = sign_up_until_turned_away(&app, &bodies).await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn signing_up_too_often_from_one_client_returns_a_429() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    // A burst of 10 per client
+    let bodies: Vec<String> = (0..11)
+        .map(|i| format!("name=user&email=user{}%40example.com", i))
+        .collect();
+
+    // Act
+    let response = sign_up_until_turned_away(&app, &bodies).await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    let saved = client
+        .query("SELECT email FROM subscriptions", &[])
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(10, saved.len());
+}
+
+#[tokio::test]
+async fn the_postgres_backend_enforces_the_same_limits() {
+    // Arrange
+    let app = TestApp::spawn_with(|settings| {
+        settings.application.signup_rate_limits.backend = RateLimitBackend::Postgres;
+    })
+    .await;
+    let bodies = vec!["name=le%20guin&email=ursula_le_guin%40gmail.com".to_owned(); 4];
+
+    // Act
+    let response = sign_up_until_turned_away(&app, &bodies).await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    let buckets = client
+        .query("SELECT key FROM rate_limit_buckets", &[])
+        .await
+        .expect("Failed to fetch the rate limit buckets.");
+    assert!(!buckets.is_empty());
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_are_told_apart() {
+    // Arrange
+    let app = TestApp::spawn_with(|settings| {
+        settings.application.trusted_proxies =
+            vec![IpNetwork::try_from("127.0.0.1".to_owned()).unwrap()];
+    })
+    .await;
+
+    for i in 0..11 {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("X-Forwarded-For", format!("203.0.113.{}", i))
+            .form(&[
+                ("name", "user"),
+                ("email", &format!("user{}@example.com", i)),
+            ])
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        // Assert
+        assert_eq!(200, response.status().as_u16(), "For client {}.", i);
+    }
+}
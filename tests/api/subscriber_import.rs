@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use zero2prod::authentication::Scope;
+
+use crate::helpers::TestApp;
+
+const READ_WRITE: [Scope; 2] = [Scope::SubscribersRead, Scope::SubscribersWrite];
+
+async fn upload(app: &TestApp, api_key: &str, query: &str, file: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/imports?{}",
+            &app.address, query
+        ))
+        .bearer_auth(api_key)
+        .header("Content-Type", "text/csv")
+        .body(file.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn get_json(app: &TestApp, api_key: &str, path: &str) -> serde_json::Value {
+    reqwest::Client::new()
+        .get(format!("{}{}", &app.address, path))
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .expect("Failed to read the response")
+}
+
+/// Uploads the file and polls the import until the worker is done with it.
+async fn import(app: &TestApp, api_key: &str, query: &str, file: &str) -> serde_json::Value {
+    let response = upload(app, api_key, query, file).await;
+    assert_eq!(202, response.status().as_u16());
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    for _ in 0..100 {
+        let progress = get_json(app, api_key, &location).await;
+        if progress["status"] == "completed" {
+            return progress;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    panic!("The import did not complete in time.");
+}
+
+async fn names_by_email(app: &TestApp) -> Vec<(String, String)> {
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    client
+        .query("SELECT email, name FROM subscriptions ORDER BY email", &[])
+        .await
+        .expect("Failed to fetch the subscriptions.")
+        .iter()
+        .map(|row| (row.get("email"), row.get("name")))
+        .collect()
+}
+
+fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected
+        .iter()
+        .map(|(email, name)| (email.to_string(), name.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn uploaded_subscribers_are_imported_in_the_background() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+
+    // Act
+    let progress = import(
+        &app,
+        &api_key,
+        "",
+        "email,name\nada@example.com,Ada\ngrace@example.com,Grace\n",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(2, progress["total_rows"]);
+    assert_eq!(2, progress["processed_rows"]);
+    assert_eq!(2, progress["imported"]);
+    assert_eq!(
+        pairs(&[("ada@example.com", "Ada"), ("grace@example.com", "Grace")]),
+        names_by_email(&app).await
+    );
+}
+
+#[tokio::test]
+async fn a_dry_run_writes_nothing() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+
+    // Act
+    let progress = import(
+        &app,
+        &api_key,
+        "dry_run=true",
+        "email,name\nada@example.com,Ada\ngrace@example.com,Grace\n",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(true, progress["dry_run"]);
+    assert_eq!(2, progress["imported"]);
+    assert!(names_by_email(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn files_that_do_not_match_the_format_are_rejected_upfront() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+
+    // Act
+    let response = upload(&app, &api_key, "", "address,name\nada@example.com,Ada\n").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+/// Imports a file naming an existing subscriber, Ada, with `policy`.
+async fn import_duplicate(app: &TestApp, api_key: &str, policy: &str) -> serde_json::Value {
+    app.post_subscriptions("name=Ada&email=ada%40example.com".into())
+        .await;
+    import(
+        app,
+        api_key,
+        &format!("on_duplicate={}", policy),
+        "email,name\nADA@example.com,Ada Lovelace\ngrace@example.com,Grace\n",
+    )
+    .await
+}
+
+#[tokio::test]
+async fn duplicates_are_skipped_by_default() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+
+    // Act
+    let progress = import_duplicate(&app, &api_key, "skip").await;
+
+    // Assert
+    assert_eq!(1, progress["imported"]);
+    assert_eq!(1, progress["skipped"]);
+    assert_eq!(
+        pairs(&[("ada@example.com", "Ada"), ("grace@example.com", "Grace")]),
+        names_by_email(&app).await
+    );
+}
+
+#[tokio::test]
+async fn duplicates_can_update_the_existing_subscriber() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+
+    // Act
+    let progress = import_duplicate(&app, &api_key, "update").await;
+
+    // Assert
+    assert_eq!(1, progress["imported"]);
+    assert_eq!(1, progress["updated"]);
+    assert_eq!(
+        pairs(&[
+            ("ada@example.com", "Ada Lovelace"),
+            ("grace@example.com", "Grace")
+        ]),
+        names_by_email(&app).await
+    );
+}
+
+#[tokio::test]
+async fn duplicates_can_be_rejected() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+
+    // Act
+    let progress = import_duplicate(&app, &api_key, "error").await;
+
+    // Assert
+    assert_eq!(1, progress["imported"]);
+    assert_eq!(1, progress["rejected"]);
+    let report = get_json(
+        &app,
+        &api_key,
+        &format!(
+            "/admin/subscribers/imports/{}/rejections",
+            progress["id"].as_str().unwrap()
+        ),
+    )
+    .await;
+    assert_eq!(
+        serde_json::json!([{ "line": 2, "reason": "The address is already subscribed." }]),
+        report["rejections"]
+    );
+    assert_eq!(
+        pairs(&[("ada@example.com", "Ada"), ("grace@example.com", "Grace")]),
+        names_by_email(&app).await
+    );
+}
+
+#[tokio::test]
+async fn the_rejection_report_lists_every_line_left_out_and_why() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    app.post_email_event(serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "Email": "barbara@example.com",
+    }))
+    .await;
+
+    // Act
+    let progress = import(
+        &app,
+        &api_key,
+        "on_duplicate=error",
+        "email,name\n\
+         ada@example.com,Ada\n\
+         not-an-email,Nobody\n\
+         barbara@example.com,Barbara\n\
+         ada@example.com,Ada again\n",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(1, progress["imported"]);
+    assert_eq!(3, progress["rejected"]);
+    let report = get_json(
+        &app,
+        &api_key,
+        &format!(
+            "/admin/subscribers/imports/{}/rejections?limit=2",
+            progress["id"].as_str().unwrap()
+        ),
+    )
+    .await;
+    let lines: Vec<_> = report["rejections"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|rejection| rejection["line"].as_i64().unwrap())
+        .collect();
+    assert_eq!(vec![3, 4], lines);
+    assert_eq!(
+        "The address is on the suppression list.",
+        report["rejections"][1]["reason"]
+    );
+    let next_page = get_json(
+        &app,
+        &api_key,
+        &format!(
+            "/admin/subscribers/imports/{}/rejections?after_line={}",
+            progress["id"].as_str().unwrap(),
+            report["next_after_line"]
+        ),
+    )
+    .await;
+    assert_eq!(
+        serde_json::json!([{ "line": 5, "reason": "The address already is on line 2." }]),
+        next_page["rejections"]
+    );
+    assert_eq!(
+        pairs(&[("ada@example.com", "Ada")]),
+        names_by_email(&app).await
+    );
+}
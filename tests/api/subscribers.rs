@@ -0,0 +1,257 @@
+use zero2prod::authentication::Scope;
+
+use crate::helpers::TestApp;
+
+const READ_WRITE: [Scope; 2] = [Scope::SubscribersRead, Scope::SubscribersWrite];
+
+async fn list_subscribers(app: &TestApp, api_key: &str, query: &str) -> serde_json::Value {
+    reqwest::Client::new()
+        .get(format!("{}/admin/subscribers?{}", &app.address, query))
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .expect("Failed to read the subscribers")
+}
+
+async fn delete_subscriber(app: &TestApp, api_key: &str, id: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .delete(format!("{}/admin/subscribers/{}", &app.address, id))
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_a_page_at_a_time() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    for name in ["ada", "grace", "barbara"] {
+        let body = format!("name={}&email={}%40example.com", name, name);
+        app.post_subscriptions(body).await;
+    }
+
+    // Act
+    let first_page = list_subscribers(&app, &api_key, "limit=2").await;
+    let cursor = first_page["next_cursor"].as_str().unwrap();
+    let second_page = list_subscribers(&app, &api_key, &format!("limit=2&cursor={}", cursor)).await;
+
+    // Assert
+    assert_eq!(3, first_page["total"]);
+    assert_eq!(2, first_page["subscribers"].as_array().unwrap().len());
+    assert_eq!(1, second_page["subscribers"].as_array().unwrap().len());
+    assert!(second_page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn subscribers_are_filtered_by_search() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    for name in ["ada", "grace"] {
+        let body = format!("name={}&email={}%40example.com", name, name);
+        app.post_subscriptions(body).await;
+    }
+
+    // Act
+    let page = list_subscribers(&app, &api_key, "q=grace").await;
+
+    // Assert
+    assert_eq!(1, page["total"]);
+    assert_eq!("grace@example.com", page["subscribers"][0]["email"]);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_it() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let page = list_subscribers(&app, &api_key, "").await;
+    let id = page["subscribers"][0]["id"].as_str().unwrap();
+
+    // Act
+    let deleted = delete_subscriber(&app, &api_key, id).await;
+    let deleted_again = delete_subscriber(&app, &api_key, id).await;
+
+    // Assert
+    assert_eq!(200, deleted.status().as_u16());
+    assert_eq!(404, deleted_again.status().as_u16());
+    assert_eq!(0, list_subscribers(&app, &api_key, "").await["total"]);
+}
+
+#[tokio::test]
+async fn subscribers_of_other_publications_cannot_be_deleted() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let default_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    let other = app.create_publication("other", None).await;
+    let other_key = app.create_api_key(other, &READ_WRITE).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let page = list_subscribers(&app, &default_key, "").await;
+    let id = page["subscribers"][0]["id"].as_str().unwrap();
+
+    // Act
+    let response = delete_subscriber(&app, &other_key, id).await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+    assert_eq!(1, list_subscribers(&app, &default_key, "").await["total"]);
+}
+
+async fn export_subscribers(app: &TestApp, api_key: &str, format: &str) -> String {
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/admin/subscribers/export?format={}",
+            &app.address, format
+        ))
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.expect("Failed to read the export")
+}
+
+#[tokio::test]
+async fn the_csv_export_carries_the_consent_of_each_subscriber() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&source=footer&consent_text_version=v2"
+            .into(),
+    )
+    .await;
+
+    // Act
+    let export = export_subscribers(&app, &api_key, "csv").await;
+
+    // Assert
+    let lines: Vec<&str> = export.lines().collect();
+    assert_eq!(2, lines.len());
+    assert!(lines[0].starts_with("id,email,name,status,"));
+    let fields: Vec<&str> = lines[1].split(',').collect();
+    assert_eq!("ursula_le_guin@gmail.com", fields[1]);
+    assert_eq!("confirmed", fields[3]);
+    assert_eq!("footer", fields[8]);
+    assert_eq!("v2", fields[9]);
+}
+
+#[tokio::test]
+async fn the_ndjson_export_tells_suppressed_subscribers_apart() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    for name in ["ada", "grace"] {
+        let body = format!("name={}&email={}%40example.com", name, name);
+        app.post_subscriptions(body).await;
+    }
+    app.post_email_event(serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "Email": "grace@example.com",
+    }))
+    .await;
+
+    // Act
+    let export = export_subscribers(&app, &api_key, "ndjson").await;
+
+    // Assert
+    let rows: Vec<serde_json::Value> = export
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(2, rows.len());
+    let reason = |email: &str| {
+        rows.iter()
+            .find(|row| row["email"] == email)
+            .map(|row| row["suppression_reason"].clone())
+            .unwrap()
+    };
+    assert!(reason("ada@example.com").is_null());
+    assert_eq!("complaint", reason("grace@example.com"));
+}
+
+#[tokio::test]
+async fn the_substack_export_leaves_out_who_we_may_not_email() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    for name in ["ada", "grace"] {
+        let body = format!("name={}&email={}%40example.com", name, name);
+        app.post_subscriptions(body).await;
+    }
+    app.post_email_event(serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": "HardBounce",
+        "Email": "grace@example.com",
+    }))
+    .await;
+
+    // Act
+    let export = export_subscribers(&app, &api_key, "substack").await;
+
+    // Assert
+    assert_eq!("email,name,created_at", export.lines().next().unwrap());
+    assert!(export.contains("ada@example.com"));
+    assert!(!export.contains("grace@example.com"));
+}
+
+#[tokio::test]
+async fn consent_records_outlive_the_subscriber() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&source=footer&consent_text_version=v2"
+            .into(),
+    )
+    .await;
+    let page = list_subscribers(&app, &api_key, "").await;
+    let id = page["subscribers"][0]["id"].as_str().unwrap();
+    delete_subscriber(&app, &api_key, id).await;
+
+    // Act
+    let events: Vec<serde_json::Value> = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers/{}/consent", &app.address, id))
+        .bearer_auth(&api_key)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .expect("Failed to read the consent records");
+
+    // Assert
+    let actions: Vec<&str> = events
+        .iter()
+        .map(|event| event["action"].as_str().unwrap())
+        .collect();
+    // Deleting is not the subscriber's decision, and leaves no record
+    assert_eq!(vec!["subscribe", "confirm"], actions);
+    assert_eq!("footer", events[0]["source"]);
+    assert_eq!("v2", events[0]["consent_text_version"]);
+    assert_eq!("127.0.0.1", events[0]["ip"]);
+}
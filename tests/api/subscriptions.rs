@@ -1,12 +1,15 @@
+use zero2prod::authentication::Scope;
+
+use crate::helpers::TestApp;
+
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
     // Arrange
     let app = TestApp::spawn().await;
-    let client = reqwest::Client::new();
 
     // Act
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let response = app.post_subscriptions(body.into());
+    let response = app.post_signup_form(body).await;
 
     // Assert
     assert_eq!(200, response.status().as_u16());
@@ -27,7 +30,6 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
 async fn subscribe_returns_a_400_when_data_is_missing() {
     // Arrange
     let app = TestApp::spawn().await;
-    let client = reqwest::Client::new();
     let test_cases = vec![
         ("name=le%20guin", "missing the email"),
         ("email=ursula_le_guin%40gmail.com", "missing the name"),
@@ -36,7 +38,7 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
 
     for (invalid_body, error_message) in test_cases {
         // Act
-        let response = app.post_subscriptions(invalid_body.into());
+        let response = app.post_subscriptions(invalid_body.into()).await;
 
         // Assert
         assert_eq!(
@@ -52,7 +54,6 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
 async fn subscribe_returns_400_when_fields_are_present_but_empty() {
     // Arrange
     let app = TestApp::spawn().await;
-    let client = reqwest::Client::new();
 
     let test_cases = vec![
         ("name=&email=ursula_le_guin%40gmail.com", "empty name"),
@@ -62,13 +63,268 @@ async fn subscribe_returns_400_when_fields_are_present_but_empty() {
 
     for (body, description) in test_cases {
         //act
-        let response = app.post_subscriptions(body.into());
+        let response = app.post_signup_form(body).await;
         // assert
         assert_eq!(
             400,
             response.status().as_u16(),
             "The API did not return a 400 OK when the payload was {}.",
             description
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "invalid_syntax", "For the {}.", description);
+    }
+}
+
+async fn subscribed_publications(app: &TestApp, email: &str) -> Vec<String> {
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    client
+        .query(
+            r#"
+    SELECT p.slug FROM subscriptions s JOIN publications p ON p.id = s.publication_id
+    WHERE s.email = $1 ORDER BY p.slug
+    "#,
+            &[&email],
+        )
+        .await
+        .expect("Failed to fetch saved subscriptions.")
+        .iter()
+        .map(|row| row.get("slug"))
+        .collect()
+}
+
+#[tokio::test]
+async fn subscribe_to_a_publication_by_its_slug() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    app.create_publication("poetry", None).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act
+    let to_poetry = app.post_form("/p/poetry/subscriptions", body).await;
+    let to_default = app.post_form("/subscriptions", body).await;
+
+    // Assert
+    assert_eq!(200, to_poetry.status().as_u16());
+    assert_eq!(200, to_default.status().as_u16());
+    assert_eq!(
+        vec!["default", "poetry"],
+        subscribed_publications(&app, "ursula_le_guin@gmail.com").await
+    );
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_404_for_an_unknown_publication() {
+    // Arrange
+    let app = TestApp::spawn().await;
+
+    // Act
+    let response = app
+        .post_form(
+            "/p/no-such-publication/subscriptions",
+            "name=le%20guin&email=ursula_le_guin%40gmail.com",
         )
+        .await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+    assert!(subscribed_publications(&app, "ursula_le_guin@gmail.com")
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn subscribe_on_a_claimed_host_goes_to_its_publication() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    app.create_publication("poetry", Some("poetry.example.com"))
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Host", "poetry.example.com")
+        .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        vec!["poetry"],
+        subscribed_publications(&app, "ursula_le_guin@gmail.com").await
+    );
+}
+
+#[tokio::test]
+async fn subscribe_twice_returns_a_200_and_keeps_one_subscription() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_signup_form(body).await;
+
+    // Act
+    let response = app
+        .post_signup_form("name=le%20guin&email=Ursula_Le_Guin%40gmail.com")
+        .await;
+
+    // Assert
+    // The same answer as for new addresses: we do not tell who is subscribed
+    assert_eq!(200, response.status().as_u16());
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    let saved = client
+        .query("SELECT email FROM subscriptions", &[])
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(1, saved.len());
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_409_after_a_spam_complaint() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_signup_form(body).await;
+    app.post_email_event(serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "Email": "ursula_le_guin@gmail.com",
+    }))
+    .await;
+
+    // Act
+    let response = app.post_signup_form(body).await;
+
+    // Assert
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_with_renewed_consent_lifts_a_complaint() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_signup_form(body).await;
+    app.post_email_event(serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "Email": "ursula_le_guin@gmail.com",
+    }))
+    .await;
+
+    // Act
+    let response = app
+        .post_signup_form(&format!("{}&reconsent=true", body))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    let saved = client
+        .query_one("SELECT status FROM subscriptions", &[])
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!("confirmed", saved.get::<_, String>("status"));
+    let suppressions = client
+        .query("SELECT reason FROM suppressions", &[])
+        .await
+        .expect("Failed to fetch the suppressions.");
+    assert!(suppressions.is_empty());
+}
+
+#[tokio::test]
+async fn subscribe_again_after_unsubscribing_confirms_the_subscription() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_signup_form(body).await;
+    app.post_email_event(serde_json::json!({
+        "RecordType": "SubscriptionChange",
+        "Recipient": "ursula_le_guin@gmail.com",
+        "SuppressSending": true,
+        "SuppressionReason": "ManualSuppression",
+    }))
+    .await;
+
+    // Act
+    let response = app.post_signup_form(body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    let saved = client
+        .query_one("SELECT status FROM subscriptions", &[])
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!("confirmed", saved.get::<_, String>("status"));
+}
+
+async fn set_domain_rule(app: &TestApp, domain: &str, rule: &str) {
+    let api_key = app
+        .create_api_key(
+            app.default_publication_id().await,
+            &[Scope::SubscribersWrite],
+        )
+        .await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/email_domains", &app.address))
+        .bearer_auth(api_key)
+        .json(&serde_json::json!({ "domain": domain, "rule": rule }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_rejects_addresses_against_the_email_policy() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    set_domain_rule(&app, "blocked.example", "deny").await;
+    let test_cases = vec![
+        (
+            "name=Ursula&email=ursula%4010minutemail.com",
+            "disposable_domain",
+        ),
+        (
+            "name=Ursula&email=ursula%40mail.10minutemail.com",
+            "disposable_domain",
+        ),
+        ("name=Ursula&email=noreply%40gmail.com", "role_address"),
+        (
+            "name=Ursula&email=ursula%40blocked.example",
+            "denied_domain",
+        ),
+    ];
+
+    for (body, code) in test_cases {
+        // Act
+        let response = app.post_signup_form(body).await;
+
+        // Assert
+        assert_eq!(400, response.status().as_u16(), "For {}.", body);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(code, body["code"]);
     }
+    assert!(subscribed_publications(&app, "ursula@blocked.example")
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn subscribe_accepts_disposable_domains_administrators_allowed() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    set_domain_rule(&app, "10minutemail.com", "allow").await;
+
+    // Act
+    let response = app
+        .post_signup_form("name=Ursula&email=ursula%4010minutemail.com")
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        vec!["default"],
+        subscribed_publications(&app, "ursula@10minutemail.com").await
+    );
 }
@@ -0,0 +1,221 @@
+use zero2prod::authentication::Scope;
+
+use crate::helpers::TestApp;
+
+const READ_WRITE: [Scope; 2] = [Scope::SubscribersRead, Scope::SubscribersWrite];
+
+async fn list_suppressions(app: &TestApp, api_key: &str) -> Vec<serde_json::Value> {
+    reqwest::Client::new()
+        .get(format!("{}/admin/suppressions", &app.address))
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .expect("Failed to read the suppressions")
+}
+
+async fn add_suppression(app: &TestApp, api_key: &str, email: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/suppressions", &app.address))
+        .bearer_auth(api_key)
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn suppressions_only_apply_to_the_publication_of_the_key() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let default_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    let other = app.create_publication("other", None).await;
+    let other_key = app.create_api_key(other, &READ_WRITE).await;
+
+    // Act
+    let response = add_suppression(&app, &default_key, "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let suppressions = list_suppressions(&app, &default_key).await;
+    assert_eq!(1, suppressions.len());
+    assert_eq!("ursula_le_guin@gmail.com", suppressions[0]["email"]);
+    assert_eq!("manual", suppressions[0]["reason"]);
+    assert!(list_suppressions(&app, &other_key).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_manual_suppression_survives_a_new_signup() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    add_suppression(&app, &api_key, "ursula_le_guin@gmail.com").await;
+
+    // Act
+    let response = app
+        .post_signup_form("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, list_suppressions(&app, &api_key).await.len());
+}
+
+#[tokio::test]
+async fn a_suppression_can_only_be_lifted_once() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    add_suppression(&app, &api_key, "ursula_le_guin@gmail.com").await;
+    let delete = |email: &'static str| {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/suppressions/{}", &app.address, email))
+            .bearer_auth(&api_key)
+            .send()
+    };
+
+    // Act
+    let lifted = delete("ursula_le_guin@gmail.com").await.unwrap();
+    let lifted_again = delete("ursula_le_guin@gmail.com").await.unwrap();
+
+    // Assert
+    assert_eq!(200, lifted.status().as_u16());
+    assert_eq!(404, lifted_again.status().as_u16());
+    assert!(list_suppressions(&app, &api_key).await.is_empty());
+}
+
+#[tokio::test]
+async fn keys_without_the_write_scope_cannot_suppress_addresses() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(
+            app.default_publication_id().await,
+            &[Scope::SubscribersRead],
+        )
+        .await;
+
+    // Act
+    let response = add_suppression(&app, &api_key, "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+    assert!(list_suppressions(&app, &api_key).await.is_empty());
+}
+
+#[tokio::test]
+async fn owners_can_lift_a_hard_bounce() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    app.post_email_event(serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": "HardBounce",
+        "Email": "ursula_le_guin@gmail.com",
+    }))
+    .await;
+    let cookie = app.log_in_as_admin().await;
+    let csrf_token = app.get_csrf_token(&cookie).await;
+    let list_global = || async {
+        reqwest::Client::new()
+            .get(format!("{}/admin/suppressions?scope=global", &app.address))
+            .header(reqwest::header::COOKIE, &cookie)
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .json::<Vec<serde_json::Value>>()
+            .await
+            .expect("Failed to read the suppressions")
+    };
+    let suppressions = list_global().await;
+    assert_eq!(1, suppressions.len());
+    assert_eq!("hard_bounce", suppressions[0]["reason"]);
+
+    // Act
+    let response = reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/suppressions/ursula_le_guin@gmail.com?scope=global",
+            &app.address
+        ))
+        .header(reqwest::header::COOKIE, &cookie)
+        .header("X-CSRF-Token", &csrf_token)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(list_global().await.is_empty());
+}
+
+#[tokio::test]
+async fn api_keys_cannot_reach_suppressions_of_every_publication() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+    app.post_email_event(serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": "HardBounce",
+        "Email": "ursula_le_guin@gmail.com",
+    }))
+    .await;
+
+    // Act
+    let listed = reqwest::Client::new()
+        .get(format!("{}/admin/suppressions?scope=global", &app.address))
+        .bearer_auth(&api_key)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let lifted = reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/suppressions/ursula_le_guin@gmail.com?scope=global",
+            &app.address
+        ))
+        .bearer_auth(&api_key)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(403, listed.status().as_u16());
+    assert_eq!(403, lifted.status().as_u16());
+    // Nor are they mixed into the publication's own
+    assert!(list_suppressions(&app, &api_key).await.is_empty());
+}
+
+#[tokio::test]
+async fn administrators_cannot_record_provider_or_erasure_reasons() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let api_key = app
+        .create_api_key(app.default_publication_id().await, &READ_WRITE)
+        .await;
+
+    for reason in ["hard_bounce", "complaint", "erased"] {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}/admin/suppressions", &app.address))
+            .bearer_auth(&api_key)
+            .json(&serde_json::json!({
+                "email": "ursula_le_guin@gmail.com",
+                "reason": reason,
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        // Assert
+        assert_eq!(400, response.status().as_u16(), "{} was accepted", reason);
+    }
+    assert!(list_suppressions(&app, &api_key).await.is_empty());
+}
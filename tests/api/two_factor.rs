@@ -0,0 +1,190 @@
+use zero2prod::authentication::{totp_code, totp_step};
+
+use crate::helpers::{assert_is_redirect_to, csrf_form, session_cookie, TestApp};
+
+/// The administrator's key and recovery codes, once they turned two-factor
+/// authentication on.
+struct Enrollment {
+    secret: Vec<u8>,
+    recovery_codes: Vec<String>,
+}
+
+impl Enrollment {
+    /// The code the authenticator app shows `steps` steps from now.
+    fn code(&self, steps: i64) -> String {
+        let now = chrono::Utc::now().timestamp() as u64;
+        totp_code(&self.secret, totp_step(now).saturating_add_signed(steps))
+    }
+}
+
+async fn enable_two_factor(app: &TestApp) -> Enrollment {
+    let cookie = app.log_in_as_admin().await;
+    let csrf_token = app.get_csrf_token(&cookie).await;
+    app.post_admin_form(
+        "/admin/two_factor/setup",
+        &cookie,
+        &csrf_form(&csrf_token, &[]),
+    )
+    .await;
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    let secret: Vec<u8> = client
+        .query_one(
+            "SELECT totp_pending_secret FROM users WHERE username = 'admin'",
+            &[],
+        )
+        .await
+        .expect("Failed to fetch the pending secret.")
+        .get("totp_pending_secret");
+    let mut enrollment = Enrollment {
+        secret,
+        recovery_codes: vec![],
+    };
+
+    let response = app
+        .post_admin_form(
+            "/admin/two_factor/enable",
+            &cookie,
+            &csrf_form(&csrf_token, &[("code", &enrollment.code(0))]),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let page = response.text().await.unwrap();
+    enrollment.recovery_codes = page
+        .split("<li><code>")
+        .skip(1)
+        .map(|item| item.split("</code>").next().unwrap().to_owned())
+        .collect();
+    assert_eq!(10, enrollment.recovery_codes.len());
+    enrollment
+}
+
+/// Gets the password right, returning the cookie of the pending login.
+async fn enter_password(app: &TestApp) -> String {
+    let response = app.post_login("admin", "my-admin-password").await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    assert_eq!(None, session_cookie(&response));
+    response
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .find(|value| value.starts_with("pending_login="))
+        .map(|value| value.split(';').next().unwrap().to_owned())
+        .expect("No pending login cookie in the response")
+}
+
+async fn enter_code(app: &TestApp, pending_login: &str, code: &str) -> reqwest::Response {
+    app.post_admin_form(
+        "/login/two_factor",
+        pending_login,
+        &serde_urlencoded::to_string([("code", code)]).unwrap(),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn logging_in_takes_a_code_on_top_of_the_password() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let enrollment = enable_two_factor(&app).await;
+
+    // Act
+    let pending_login = enter_password(&app).await;
+    let response = enter_code(&app, &pending_login, &enrollment.code(1)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let cookie = session_cookie(&response).expect("No session cookie in the response");
+    let dashboard = app.get_admin_page("/admin/dashboard", &cookie).await;
+    assert_eq!(200, dashboard.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_code_cannot_be_used_twice() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let enrollment = enable_two_factor(&app).await;
+    let code = enrollment.code(1);
+    let pending_login = enter_password(&app).await;
+    let response = enter_code(&app, &pending_login, &code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act
+    let pending_login = enter_password(&app).await;
+    let response = enter_code(&app, &pending_login, &code).await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(None, session_cookie(&response));
+}
+
+#[tokio::test]
+async fn a_recovery_code_can_be_used_once() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let enrollment = enable_two_factor(&app).await;
+    // Typed in by hand
+    let code = enrollment.recovery_codes[0].to_uppercase();
+
+    // Act
+    let pending_login = enter_password(&app).await;
+    let first = enter_code(&app, &pending_login, &code).await;
+    let pending_login = enter_password(&app).await;
+    let second = enter_code(&app, &pending_login, &code).await;
+
+    // Assert
+    assert_is_redirect_to(&first, "/admin/dashboard");
+    assert_eq!(401, second.status().as_u16());
+}
+
+#[tokio::test]
+async fn too_many_wrong_codes_send_the_user_back_to_the_password() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let enrollment = enable_two_factor(&app).await;
+    let pending_login = enter_password(&app).await;
+
+    // Act - Part 1 - Guess
+    for _ in 0..4 {
+        let response = enter_code(&app, &pending_login, "000000").await;
+        assert_eq!(401, response.status().as_u16());
+    }
+    let response = enter_code(&app, &pending_login, "000000").await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - The right code is too late
+    let response = enter_code(&app, &pending_login, &enrollment.code(1)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(None, session_cookie(&response));
+}
+
+#[tokio::test]
+async fn users_without_two_factor_must_enroll_once_it_is_mandatory() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    let cookie = app.log_in_as_admin().await;
+    let csrf_token = app.get_csrf_token(&cookie).await;
+    let response = app
+        .post_admin_form(
+            "/admin/users/two_factor_policy",
+            &cookie,
+            &csrf_form(&csrf_token, &[("required", "on")]),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Act
+    let dashboard = app.get_admin_page("/admin/dashboard", &cookie).await;
+    let enrollment_page = app.get_admin_page("/admin/two_factor", &cookie).await;
+
+    // Assert
+    assert_is_redirect_to(&dashboard, "/admin/two_factor");
+    assert_eq!(200, enrollment_page.status().as_u16());
+    assert!(enrollment_page
+        .text()
+        .await
+        .unwrap()
+        .contains("Two-factor authentication is mandatory"));
+}
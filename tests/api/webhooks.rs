@@ -0,0 +1,160 @@
+use crate::helpers::TestApp;
+
+async fn suppression_reason(app: &TestApp, email: &str) -> Option<String> {
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    client
+        .query_opt(
+            "SELECT reason FROM suppressions WHERE email = $1 AND publication_id IS NULL",
+            &[&email],
+        )
+        .await
+        .expect("Failed to fetch the suppression.")
+        .map(|row| row.get("reason"))
+}
+
+async fn subscription_status(app: &TestApp, email: &str) -> String {
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    client
+        .query_one(
+            "SELECT status FROM subscriptions WHERE email = $1",
+            &[&email],
+        )
+        .await
+        .expect("Failed to fetch the subscription.")
+        .get("status")
+}
+
+#[tokio::test]
+async fn webhooks_without_credentials_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email", &app.address))
+        .json(&serde_json::json!({ "RecordType": "SpamComplaint", "Email": "a@example.com" }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(None, suppression_reason(&app, "a@example.com").await);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_suppresses_the_address_everywhere() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    app.post_signup_form("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = app
+        .post_email_event(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "HardBounce",
+            "Email": "Ursula_Le_Guin@gmail.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        Some("hard_bounce".to_owned()),
+        suppression_reason(&app, "ursula_le_guin@gmail.com").await
+    );
+    assert_eq!(
+        "bounced",
+        subscription_status(&app, "ursula_le_guin@gmail.com").await
+    );
+}
+
+#[tokio::test]
+async fn a_soft_bounce_does_not_suppress_the_address() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    app.post_signup_form("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = app
+        .post_email_event(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "SoftBounce",
+            "Email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        None,
+        suppression_reason(&app, "ursula_le_guin@gmail.com").await
+    );
+    assert_eq!(
+        "confirmed",
+        subscription_status(&app, "ursula_le_guin@gmail.com").await
+    );
+}
+
+#[tokio::test]
+async fn a_complaint_is_not_downgraded_by_a_later_unsubscribe() {
+    // Arrange
+    let app = TestApp::spawn().await;
+    app.post_signup_form("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    app.post_email_event(serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "Email": "ursula_le_guin@gmail.com",
+    }))
+    .await;
+
+    // Act
+    let response = app
+        .post_email_event(serde_json::json!({
+            "RecordType": "SubscriptionChange",
+            "Recipient": "ursula_le_guin@gmail.com",
+            "SuppressSending": true,
+            "SuppressionReason": "ManualSuppression",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        Some("complaint".to_owned()),
+        suppression_reason(&app, "ursula_le_guin@gmail.com").await
+    );
+    assert_eq!(
+        "complained",
+        subscription_status(&app, "ursula_le_guin@gmail.com").await
+    );
+}
+
+#[tokio::test]
+async fn opens_are_stored_with_their_recipient() {
+    // Arrange
+    let app = TestApp::spawn().await;
+
+    // Act
+    let response = app
+        .post_email_event(serde_json::json!({
+            "RecordType": "Open",
+            "Recipient": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let client = app.db_pool.get().await.expect("Failed to get client");
+    let saved = client
+        .query_one("SELECT record_type, email FROM email_events", &[])
+        .await
+        .expect("Failed to fetch the email event.");
+    assert_eq!("Open", saved.get::<_, String>("record_type"));
+    assert_eq!(
+        Some("ursula_le_guin@gmail.com".to_owned()),
+        saved.get::<_, Option<String>>("email")
+    );
+}